@@ -20,6 +20,11 @@
 #include <stdlib.h>
 #include "chewing_rs.h"
 
+/**
+ * TODO: doc
+ */
+typedef struct ChewingConversionEngine ChewingConversionEngine;
+
 #define MAX_UTF8_SIZE 4
 
 #define MAX_UTF8_BUF (MAX_UTF8_SIZE + 1)
@@ -207,11 +212,6 @@ enum UserUpdate
 typedef uint8_t UserUpdate;
 #endif // __cplusplus
 
-/**
- * TODO: doc
- */
-typedef struct ChewingConversionEngine ChewingConversionEngine;
-
 /**
  * A collection of dictionaries that returns the union of the lookup results.
  * # Examples
@@ -397,7 +397,7 @@ typedef struct ChewingData {
   void (*logger)(void *data, int level, const char *fmt);
   void *loggerData;
   const struct LayeredDictionary *dict;
-  struct ChewingConversionEngine *ce;
+  ChewingConversionEngine *ce;
   struct UserphraseDbAndEstimate *ue;
   void *phraseIter;
   void *phraseEnumIter;
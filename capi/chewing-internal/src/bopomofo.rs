@@ -156,7 +156,7 @@ pub unsafe extern "C" fn BopomofoPhoInx(bopomofo_data: &BopomofoData, pho_inx: *
         None => 0,
     };
     pho_inx[3] = match syllable.tone() {
-        Some(b) => b.tone_index() as i32,
+        Some(tone) => tone.index() as i32,
         None => 0,
     };
 }
@@ -181,7 +181,7 @@ pub unsafe extern "C" fn BopomofoPhoInxAlt(bopomofo_data: &BopomofoData, pho_inx
         None => 0,
     };
     pho_inx[3] = match syllable.tone() {
-        Some(b) => b.tone_index() as i32,
+        Some(tone) => tone.index() as i32,
         None => 0,
     };
 }
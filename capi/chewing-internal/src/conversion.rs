@@ -1,7 +1,8 @@
 use std::{ffi::CStr, rc::Rc};
 
-use chewing::conversion::{
-    Break, ChewingConversionEngine, ChineseSequence, ConversionEngine, Interval,
+use chewing::{
+    conversion::{Break, ChewingConversionEngine, ChineseSequence, ConversionEngine, Interval},
+    dictionary::Dictionary,
 };
 use chewing_public::types::IntervalType;
 use libc::{c_char, c_int};
@@ -14,7 +15,9 @@ pub extern "C" fn InitTree(pgdata: &mut ChewingData, _prefix: *const c_char) ->
         Rc::increment_strong_count(pgdata.dict);
         Rc::from_raw(pgdata.dict)
     };
-    pgdata.ce = Some(Box::new(ChewingConversionEngine::new(dict)));
+    pgdata.ce = Some(Box::new(ChewingConversionEngine::new(
+        dict as Rc<dyn Dictionary>,
+    )));
     0
 }
 
@@ -47,10 +50,13 @@ pub extern "C" fn Phrasing(pgdata: &mut ChewingData, _all_phrasing: bool) {
 
     let selections = select_intervals
         .zip(select_strs)
-        .map(|(interval, str)| Interval {
-            start: interval.from as usize,
-            end: interval.to as usize,
-            phrase: str.to_string_lossy().to_string(),
+        .filter_map(|(interval, str)| {
+            Interval::new(
+                interval.from as usize,
+                interval.to as usize,
+                str.to_string_lossy().to_string(),
+            )
+            .ok()
         })
         .collect();
 
@@ -61,8 +67,11 @@ pub extern "C" fn Phrasing(pgdata: &mut ChewingData, _all_phrasing: bool) {
     };
     let intervals = match pgdata.phr_out.n_num_cut {
         0 => ce.convert(&sequence),
-        _ => ce.convert_next(&sequence, pgdata.phr_out.n_num_cut as usize),
-    };
+        _ => ce
+            .convert_next(&sequence, pgdata.phr_out.n_num_cut as usize)
+            .or_else(|_| ce.convert(&sequence)),
+    }
+    .unwrap_or_default();
 
     pgdata.phr_out.n_disp_interval = intervals.len() as c_int;
     for (i, interval) in intervals.into_iter().enumerate() {
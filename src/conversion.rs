@@ -1,9 +1,29 @@
 //! TODO: docs
 
-use crate::zhuyin::Syllable;
+use std::{
+    collections::BTreeSet,
+    fmt::{Debug, Display},
+    ops::{Deref, Range},
+    rc::Rc,
+};
+
+use thiserror::Error;
+
+use crate::{
+    dictionary::{Dictionary, DictionaryUpdateError, DEFAULT_MAX_PHRASE_LEN},
+    zhuyin::{ParseSyllableError, Syllable, ToneSystem},
+};
 
 /// TODO: doc
+///
+/// Prefer [`Interval::new`] over building the struct by hand, it checks that
+/// the interval is not empty.
+///
+/// An interval owns its phrase and never borrows from the dictionary, so
+/// conversion results can be stored or returned freely after the engine is
+/// gone.
 #[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Interval {
     /// TODO: doc
     pub start: usize,
@@ -11,9 +31,64 @@ pub struct Interval {
     pub end: usize,
     /// TODO: doc
     pub phrase: String,
+    /// Whether the dictionary has nothing for the syllable of the interval.
+    ///
+    /// Unknown intervals cover a single syllable and carry the zhuyin of the
+    /// syllable as the phrase, so conversion never drops any input.
+    #[cfg_attr(feature = "serde", serde(default))]
+    unknown: bool,
+}
+
+/// Errors returned by [`Interval::new`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum IntervalError {
+    /// The start of the interval is not before the end.
+    #[error("interval start {start} is not before end {end}")]
+    InvalidBounds {
+        /// Start of the interval.
+        start: usize,
+        /// End of the interval.
+        end: usize,
+    },
+    /// The phrase of the interval is empty.
+    #[error("interval phrase is empty")]
+    EmptyPhrase,
 }
 
 impl Interval {
+    /// Creates a new interval covering `start..end` with `phrase`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `start` is not less than `end` or if `phrase` is
+    /// empty.
+    pub fn new(
+        start: usize,
+        end: usize,
+        phrase: impl Into<String>,
+    ) -> Result<Interval, IntervalError> {
+        if start >= end {
+            return Err(IntervalError::InvalidBounds { start, end });
+        }
+        let phrase = phrase.into();
+        if phrase.is_empty() {
+            return Err(IntervalError::EmptyPhrase);
+        }
+        Ok(Interval {
+            start,
+            end,
+            phrase,
+            unknown: false,
+        })
+    }
+    /// Returns whether the dictionary has nothing for the syllable of the
+    /// interval.
+    ///
+    /// Unknown intervals carry the zhuyin of the syllable as the phrase, and
+    /// are not pinned as selections.
+    pub fn is_unknown(&self) -> bool {
+        self.unknown
+    }
     /// TODO: doc
     pub fn contains(&self, other: &Interval) -> bool {
         self.start <= other.start && self.end >= other.end
@@ -28,28 +103,1960 @@ impl Interval {
     }
 }
 
+/// The intervals of a conversion with readable formatting.
+///
+/// `Display` renders the segmented sentence, e.g. `國民 | 大會 | 代表`, and
+/// `Debug` lists each span with its phrase compactly.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::{ConversionResult, Interval};
+///
+/// let result = ConversionResult::from(vec![
+///     Interval::new(0, 2, "國民").unwrap(),
+///     Interval::new(2, 4, "大會").unwrap(),
+/// ]);
+/// assert_eq!("國民 | 大會", result.to_string());
+/// assert_eq!("[0..2 國民, 2..4 大會]", format!("{:?}", result));
+/// ```
+#[derive(Default, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct ConversionResult(pub Vec<Interval>);
+
+impl ConversionResult {
+    /// Returns the intervals of the result.
+    pub fn into_inner(self) -> Vec<Interval> {
+        self.0
+    }
+}
+
+impl From<Vec<Interval>> for ConversionResult {
+    fn from(intervals: Vec<Interval>) -> Self {
+        ConversionResult(intervals)
+    }
+}
+
+impl Deref for ConversionResult {
+    type Target = [Interval];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Display for ConversionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, interval) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", interval.phrase)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for ConversionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (i, interval) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(
+                f,
+                "{}..{} {}",
+                interval.start, interval.end, interval.phrase
+            )?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// A change between two conversion results reported by [`diff`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IntervalChange {
+    /// The interval only exists in the new result.
+    Added(Interval),
+    /// The interval only exists in the old result.
+    Removed(Interval),
+    /// The interval exists in both results at the same position.
+    Unchanged(Interval),
+}
+
+/// Compares two conversion results and reports the changed intervals.
+///
+/// Intervals are matched by their position and phrase. The changes are
+/// ordered by position, with removed intervals before the added intervals
+/// that replace them. When only the tail of the result changed, for example
+/// after appending a syllable, the leading intervals are all
+/// [`IntervalChange::Unchanged`].
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::{diff, Interval, IntervalChange};
+///
+/// let old = vec![Interval::new(0, 1, "大").unwrap()];
+/// let new = vec![Interval::new(0, 2, "大會").unwrap()];
+/// assert_eq!(
+///     vec![
+///         IntervalChange::Removed(Interval::new(0, 1, "大").unwrap()),
+///         IntervalChange::Added(Interval::new(0, 2, "大會").unwrap()),
+///     ],
+///     diff(&old, &new)
+/// );
+/// ```
+pub fn diff(old: &[Interval], new: &[Interval]) -> Vec<IntervalChange> {
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut changes: Vec<IntervalChange> = new[..prefix]
+        .iter()
+        .cloned()
+        .map(IntervalChange::Unchanged)
+        .collect();
+    let mut rest = vec![];
+    for interval in &old[prefix..] {
+        if new[prefix..].contains(interval) {
+            rest.push((
+                interval.start,
+                1,
+                IntervalChange::Unchanged(interval.clone()),
+            ));
+        } else {
+            rest.push((interval.start, 0, IntervalChange::Removed(interval.clone())));
+        }
+    }
+    for interval in &new[prefix..] {
+        if !old[prefix..].contains(interval) {
+            rest.push((interval.start, 2, IntervalChange::Added(interval.clone())));
+        }
+    }
+    rest.sort_by_key(|(start, order, _)| (*start, *order));
+    changes.extend(rest.into_iter().map(|(_, _, change)| change));
+    changes
+}
+
+/// Returns whether `intervals` tile `0..len`: they are sorted, non-empty,
+/// don't overlap, and cover every position without gaps.
+///
+/// Every conversion result is a tiling of its sequence, so this is a cheap
+/// sanity check of results.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::{is_valid_tiling, Interval};
+///
+/// let intervals = vec![
+///     Interval::new(0, 2, "國民").unwrap(),
+///     Interval::new(2, 4, "大會").unwrap(),
+/// ];
+/// assert!(is_valid_tiling(&intervals, 4));
+/// assert!(!is_valid_tiling(&intervals, 5));
+/// ```
+pub fn is_valid_tiling(intervals: &[Interval], len: usize) -> bool {
+    let mut pos = 0;
+    for interval in intervals {
+        if interval.start != pos || interval.end <= interval.start {
+            return false;
+        }
+        pos = interval.end;
+    }
+    pos == len
+}
+
+/// Returns the number of display cells taken by `text`.
+///
+/// ASCII characters are half-width and take one cell, all other characters
+/// are assumed to be full-width and take two.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::rendered_width;
+///
+/// assert_eq!(5, rendered_width("1號樓"));
+/// ```
+pub fn rendered_width(text: &str) -> usize {
+    text.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum()
+}
+
+/// An interval with an identity that is carried across conversions.
+///
+/// See [`track_intervals`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TrackedInterval {
+    /// The identity of the segment.
+    pub id: u64,
+    /// The converted interval.
+    pub interval: Interval,
+}
+
+/// Assigns identities to the intervals of a new conversion result, reusing
+/// the identities of `prev` for the same logical segments.
+///
+/// A new interval takes over the identity of a previous interval with the
+/// same phrase that overlaps it, or failing that of a previous interval
+/// covering exactly the same syllables, for example one whose phrase was
+/// re-selected. Each previous identity is reused at most once. The other
+/// intervals get fresh identities larger than any in `prev`, saturating at
+/// [`u64::MAX`].
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::{track_intervals, Interval};
+///
+/// let old = track_intervals(&[], vec![Interval::new(0, 2, "國民").unwrap()]);
+/// let new = track_intervals(
+///     &old,
+///     vec![
+///         Interval::new(0, 2, "國民").unwrap(),
+///         Interval::new(2, 3, "大").unwrap(),
+///     ],
+/// );
+/// assert_eq!(old[0].id, new[0].id);
+/// assert_ne!(old[0].id, new[1].id);
+/// ```
+pub fn track_intervals(prev: &[TrackedInterval], intervals: Vec<Interval>) -> Vec<TrackedInterval> {
+    let mut used = vec![false; prev.len()];
+    let mut ids: Vec<Option<u64>> = vec![None; intervals.len()];
+    let rule = |pass: usize, a: &Interval, b: &Interval| match pass {
+        0 => a == b,
+        1 => a.phrase == b.phrase && a.start < b.end && b.start < a.end,
+        _ => a.start == b.start && a.end == b.end,
+    };
+    for pass in 0..3 {
+        for (interval, id) in intervals.iter().zip(ids.iter_mut()) {
+            if id.is_some() {
+                continue;
+            }
+            let found = prev
+                .iter()
+                .zip(used.iter())
+                .position(|(tracked, used)| !used && rule(pass, &tracked.interval, interval));
+            if let Some(i) = found {
+                used[i] = true;
+                *id = Some(prev[i].id);
+            }
+        }
+    }
+    let mut next = prev
+        .iter()
+        .map(|tracked| tracked.id.saturating_add(1))
+        .max()
+        .unwrap_or(0);
+    intervals
+        .into_iter()
+        .zip(ids)
+        .map(|(interval, id)| {
+            let id = id.unwrap_or_else(|| {
+                let id = next;
+                next = next.saturating_add(1);
+                id
+            });
+            TrackedInterval { id, interval }
+        })
+        .collect()
+}
+
+/// The normalizations applied to phrases by [`intervals_equivalent`].
+///
+/// The default applies none, so phrases are compared exactly.
+#[derive(Debug, Default, Clone)]
+pub struct NormOptions {
+    /// Folds the full-width forms of ASCII characters and the ideographic
+    /// space to their half-width forms.
+    pub fold_width: bool,
+    /// Replaces phrases with an alias by their canonical form.
+    pub aliases: Option<AliasMap>,
+    /// Maps the characters to their simplified form.
+    pub simplified: Option<SimplifiedMapper>,
+}
+
+impl NormOptions {
+    /// Returns `phrase` after the normalizations, aliases first.
+    pub fn normalize(&self, phrase: &str) -> String {
+        let mut phrase = self
+            .aliases
+            .as_ref()
+            .and_then(|aliases| aliases.get(phrase))
+            .unwrap_or(phrase)
+            .to_string();
+        if let Some(simplified) = &self.simplified {
+            phrase = simplified.map_str(&phrase);
+        }
+        if self.fold_width {
+            phrase = phrase
+                .chars()
+                .map(|c| match c {
+                    '\u{3000}' => ' ',
+                    '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0).unwrap_or(c),
+                    _ => c,
+                })
+                .collect();
+        }
+        phrase
+    }
+}
+
+/// Returns whether two conversion results have the same intervals, when
+/// phrases are compared after the normalizations of `norm`.
+///
+/// The positions of the intervals are compared exactly.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::{intervals_equivalent, Interval, NormOptions};
+///
+/// let a = vec![Interval::new(0, 1, "！").unwrap()];
+/// let b = vec![Interval::new(0, 1, "!").unwrap()];
+/// let norm = NormOptions {
+///     fold_width: true,
+///     ..Default::default()
+/// };
+/// assert!(intervals_equivalent(&a, &b, &norm));
+/// ```
+pub fn intervals_equivalent(a: &[Interval], b: &[Interval], norm: &NormOptions) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            a.start == b.start
+                && a.end == b.end
+                && norm.normalize(&a.phrase) == norm.normalize(&b.phrase)
+        })
+}
+
+/// Returns the selections that keep the phrases of a chosen segmentation.
+///
+/// Every interval becomes a selection of its phrase, except the unknown
+/// intervals whose phrase is only a placeholder for the syllable. See
+/// [`ChewingConversionEngine::pin_segmentation`] to install them on a
+/// sequence.
+///
+/// # Examples
+///
+/// ```
+/// use std::{collections::HashMap, rc::Rc};
+///
+/// use chewing::{
+///     conversion::{
+///         segmentation_to_selections, ChewingConversionEngine, ChineseSequence, ConversionEngine,
+///         Interval,
+///     },
+///     dictionary::Phrase,
+///     syl,
+///     zhuyin::{Bopomofo::*, Syllable},
+/// };
+///
+/// let dict: HashMap<Vec<Syllable>, Vec<Phrase<'static>>> = HashMap::from([(
+///     vec![syl![C, E, TONE4], syl![SH, TONE4]],
+///     vec![("測試", 1).into()],
+/// )]);
+/// let engine = ChewingConversionEngine::new(Rc::new(dict));
+/// let seq = ChineseSequence::builder()
+///     .syllables([syl![C, E, TONE4], syl![SH, TONE4], syl![I, TONE2]])
+///     .build();
+/// let intervals = engine.convert(&seq).unwrap();
+/// assert!(intervals[1].is_unknown());
+/// assert_eq!(
+///     vec![Interval::new(0, 2, "測試").unwrap()],
+///     segmentation_to_selections(&intervals)
+/// );
+/// ```
+pub fn segmentation_to_selections(intervals: &[Interval]) -> Vec<Interval> {
+    intervals
+        .iter()
+        .filter(|interval| !interval.unknown)
+        .cloned()
+        .collect()
+}
+
+/// Returns the number of boundaries present in only one of the two
+/// segmentations.
+///
+/// The boundaries of a segmentation are the start and end positions of its
+/// intervals, and the distance is the size of the symmetric difference of the
+/// two boundary sets. Phrases are ignored, so two segmentations with the same
+/// boundaries have distance 0 even if their phrases differ.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::{segmentation_distance, Interval};
+///
+/// let a = vec![
+///     Interval::new(0, 2, "國民").unwrap(),
+///     Interval::new(2, 4, "大會").unwrap(),
+/// ];
+/// let b = vec![
+///     Interval::new(0, 1, "國").unwrap(),
+///     Interval::new(1, 4, "民大會").unwrap(),
+/// ];
+/// assert_eq!(2, segmentation_distance(&a, &b));
+/// ```
+pub fn segmentation_distance(a: &[Interval], b: &[Interval]) -> usize {
+    let boundaries = |intervals: &[Interval]| -> BTreeSet<usize> {
+        intervals
+            .iter()
+            .flat_map(|interval| [interval.start, interval.end])
+            .collect()
+    };
+    boundaries(a).symmetric_difference(&boundaries(b)).count()
+}
+
 /// TODO: doc
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Break(pub usize);
 
+impl Break {
+    /// Creates a break before the syllable at `pos`.
+    pub const fn new(pos: usize) -> Break {
+        Break(pos)
+    }
+}
+
+/// A break before the syllable at the position that only discourages
+/// phrases spanning it.
+///
+/// Unlike a [`Break`], phrases may still cross a soft break, but the penalty
+/// is subtracted from their score, so they win only when their frequency gain
+/// is large enough.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SoftBreak(pub usize, pub i32);
+
 /// TODO: doc
+///
+/// With the `serde` feature the syllables are serialized as zhuyin strings
+/// and missing selections or breaks deserialize as empty.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChineseSequence {
     /// TODO: doc
     pub syllables: Vec<Syllable>,
     /// TODO: doc
+    #[cfg_attr(feature = "serde", serde(default))]
     pub selections: Vec<Interval>,
     /// TODO: doc
+    #[cfg_attr(feature = "serde", serde(default))]
     pub breaks: Vec<Break>,
 }
 
+/// The maximum number of syllables a conversion engine accepts.
+///
+/// Longer sequences could overflow the scores of the phrasings.
+pub const MAX_SEQUENCE_LEN: usize = 65535;
+
+/// How strongly a selection constrains the conversion.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelectionPriority {
+    /// The user locked the selection, it is never changed.
+    #[default]
+    Locked,
+    /// The selection was suggested, for example by an earlier conversion,
+    /// and only gets a bonus. A clearly better phrasing replaces it.
+    Suggested,
+}
+
+/// Errors returned by conversion engines.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    /// No phrasing covers the whole sequence.
+    #[error("no phrasing covers all {len} syllables")]
+    EmptyPath {
+        /// Number of syllables in the sequence.
+        len: usize,
+    },
+    /// The sequence is too long to be scored without overflow.
+    #[error("sequence of {len} syllables is longer than {max}")]
+    ScoreOverflow {
+        /// Number of syllables in the sequence.
+        len: usize,
+        /// The maximum number of syllables accepted.
+        max: usize,
+    },
+    /// There is no candidate phrasing to choose from.
+    #[error("no candidate phrasing for the sequence")]
+    MissingCandidate,
+    /// The dictionary returned a phrase whose length doesn't match the
+    /// syllables it was looked up with.
+    #[error("dictionary phrase {phrase} doesn't have {len} characters")]
+    InconsistentPhrase {
+        /// The phrase returned by the dictionary.
+        phrase: String,
+        /// Number of syllables looked up.
+        len: usize,
+    },
+    /// The best phrasing has more intervals than the output buffer holds.
+    #[error("phrasing of {needed} intervals doesn't fit in buffer of {capacity}")]
+    BufferTooSmall {
+        /// Number of intervals of the best phrasing.
+        needed: usize,
+        /// Number of intervals the buffer holds.
+        capacity: usize,
+    },
+    /// A position of a lattice has no candidate syllable.
+    #[error("position {position} of the lattice has no candidate syllable")]
+    MissingSyllable {
+        /// The position without candidates.
+        position: usize,
+    },
+}
+
+/// Errors returned by [`ChewingConversionEngine::convert_and_commit`].
+#[derive(Error, Debug)]
+pub enum CommitError {
+    /// The sequence could not be converted.
+    #[error("conversion failed")]
+    Conversion(#[from] ConversionError),
+    /// The dictionary of the engine has no mutable layer to learn into.
+    #[error("dictionary is read-only")]
+    ReadOnly,
+    /// The dictionary failed to learn a committed phrase.
+    #[error("learning the committed phrases failed")]
+    Update(#[from] DictionaryUpdateError),
+}
+
+/// Errors found by [`ChineseSequence::validate`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SequenceError {
+    /// A selection extends past the end of the syllables.
+    #[error("selection {start}..{end} is out of range of {len} syllables")]
+    SelectionOutOfRange {
+        /// Start of the selection.
+        start: usize,
+        /// End of the selection.
+        end: usize,
+        /// Number of syllables in the sequence.
+        len: usize,
+    },
+    /// Two selections cover the same syllable.
+    #[error("selection {first:?} overlaps selection {second:?}")]
+    OverlappingSelections {
+        /// The selection that starts first.
+        first: Range<usize>,
+        /// The selection overlapping the first one.
+        second: Range<usize>,
+    },
+    /// The phrase of a selection doesn't have one character per syllable.
+    #[error("selection {start}..{end} has phrase of {chars} characters")]
+    PhraseLengthMismatch {
+        /// Start of the selection.
+        start: usize,
+        /// End of the selection.
+        end: usize,
+        /// Number of characters in the phrase.
+        chars: usize,
+    },
+    /// A break is past the end of the syllables.
+    #[error("break at {pos} is out of range of {len} syllables")]
+    BreakOutOfRange {
+        /// Position of the break.
+        pos: usize,
+        /// Number of syllables in the sequence.
+        len: usize,
+    },
+    /// A selection relative to the end doesn't fit in the syllables.
+    #[error(
+        "selection of {from_end_len} syllables starting {from_end_start} from the end \
+         is out of range of {len} syllables"
+    )]
+    SelectionFromEndOutOfRange {
+        /// Number of syllables from the start of the selection to the end.
+        from_end_start: usize,
+        /// Number of syllables of the selection.
+        from_end_len: usize,
+        /// Number of syllables in the sequence.
+        len: usize,
+    },
+}
+
+/// Error returned by
+/// [`pin_text`][ChewingConversionEngine::pin_text] when no span starting at
+/// the position has the text as a phrase.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("no phrase {text:?} starting at syllable {start}")]
+pub struct PinTextError {
+    /// The position the span had to start at.
+    pub start: usize,
+    /// The requested text.
+    pub text: String,
+}
+
+/// Errors returned by [`ChineseSequence::from_zhuyin_str`].
+#[derive(Error, Debug)]
+#[error("invalid syllable {token:?} at token {index}")]
+pub struct ParseSequenceError {
+    /// The index of the offending token, starting from 0.
+    pub index: usize,
+    /// The offending token.
+    pub token: String,
+    source: ParseSyllableError,
+}
+
+impl ChineseSequence {
+    /// Parses a whitespace separated zhuyin string into a sequence without
+    /// selections or breaks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use chewing::{conversion::ChineseSequence, syl, zhuyin::Bopomofo::*};
+    ///
+    /// let seq = ChineseSequence::from_zhuyin_str("ㄍㄨㄛˊ ㄇㄧㄣˊ").unwrap();
+    /// assert_eq!(vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]], seq.syllables);
+    /// ```
+    pub fn from_zhuyin_str(text: &str) -> Result<ChineseSequence, ParseSequenceError> {
+        ChineseSequence::parse_tokens(text, |token| token.parse())
+    }
+    /// Parses a whitespace separated string of syllables written in
+    /// `system` into a sequence without selections or breaks.
+    ///
+    /// This accepts input schemes other than zhuyin, see [`ToneSystem`].
+    pub fn from_str_with(
+        text: &str,
+        system: &dyn ToneSystem,
+    ) -> Result<ChineseSequence, ParseSequenceError> {
+        ChineseSequence::parse_tokens(text, |token| system.parse_syllable(token))
+    }
+    fn parse_tokens<F>(text: &str, parse: F) -> Result<ChineseSequence, ParseSequenceError>
+    where
+        F: Fn(&str) -> Result<Syllable, ParseSyllableError>,
+    {
+        let syllables = text
+            .split_whitespace()
+            .enumerate()
+            .map(|(index, token)| {
+                parse(token).map_err(|source| ParseSequenceError {
+                    index,
+                    token: token.to_string(),
+                    source,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ChineseSequence {
+            syllables,
+            selections: vec![],
+            breaks: vec![],
+        })
+    }
+    /// Returns the syllables covered by `interval`.
+    ///
+    /// Returns `None` if the interval is empty or out of range of the
+    /// syllables.
+    pub fn syllables_for(&self, interval: &Interval) -> Option<&[Syllable]> {
+        if interval.start >= interval.end {
+            return None;
+        }
+        self.syllables.get(interval.start..interval.end)
+    }
+    /// Adds a user selection, replacing any existing selection that overlaps
+    /// it.
+    ///
+    /// This keeps the selections pairwise disjoint, like re-selecting a span
+    /// in an editor replaces the previous choice.
+    pub fn add_selection(&mut self, selection: Interval) {
+        self.selections
+            .retain(|sel| sel.end <= selection.start || sel.start >= selection.end);
+        self.selections.push(selection);
+    }
+    /// Adds a user selection given relative to the end of the syllables.
+    ///
+    /// The selection starts `from_end_start` syllables before the end and
+    /// covers `from_end_len` syllables, so the last two syllables are
+    /// `(2, 2)`. The coordinates are converted to absolute positions before
+    /// storing, thus the selection stays on the same syllables when more
+    /// syllables are appended later. Overlapping selections are replaced
+    /// like [`add_selection`][Self::add_selection].
+    ///
+    /// Returns [`SequenceError::SelectionFromEndOutOfRange`] and leaves the
+    /// selections unchanged if the selection doesn't fit in the syllables.
+    pub fn add_selection_from_end(
+        &mut self,
+        from_end_start: usize,
+        from_end_len: usize,
+        phrase: String,
+    ) -> Result<(), SequenceError> {
+        let len = self.syllables.len();
+        if from_end_start > len || from_end_len > from_end_start {
+            return Err(SequenceError::SelectionFromEndOutOfRange {
+                from_end_start,
+                from_end_len,
+                len,
+            });
+        }
+        let start = len - from_end_start;
+        self.add_selection(Interval {
+            start,
+            end: start + from_end_len,
+            phrase,
+            unknown: false,
+        });
+        Ok(())
+    }
+    /// Checks that the selections and breaks are consistent with the
+    /// syllables.
+    ///
+    /// Selections must be within the syllables, must not overlap each other,
+    /// and must have one character per syllable. Breaks must be within
+    /// `0..=syllables.len()`.
+    pub fn validate(&self) -> Result<(), SequenceError> {
+        let len = self.syllables.len();
+        for sel in &self.selections {
+            check_selection(sel, len)?;
+        }
+        let mut sorted: Vec<&Interval> = self.selections.iter().collect();
+        sorted.sort_by_key(|sel| sel.start);
+        for pair in sorted.windows(2) {
+            if pair[1].start < pair[0].end {
+                return Err(SequenceError::OverlappingSelections {
+                    first: pair[0].start..pair[0].end,
+                    second: pair[1].start..pair[1].end,
+                });
+            }
+        }
+        if let Some(br) = self.breaks.iter().find(|br| br.0 > len) {
+            return Err(SequenceError::BreakOutOfRange { pos: br.0, len });
+        }
+        Ok(())
+    }
+    /// Returns a builder that checks the selections and breaks as they are
+    /// added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use chewing::{
+    ///     conversion::{ChineseSequence, Interval},
+    ///     syl,
+    ///     zhuyin::Bopomofo::*,
+    /// };
+    ///
+    /// let seq = ChineseSequence::builder()
+    ///     .syllables([syl![C, E, TONE4], syl![SH, TONE4]])
+    ///     .selection(Interval::new(0, 2, "測試").unwrap())
+    ///     .unwrap()
+    ///     .build();
+    /// assert!(seq.validate().is_ok());
+    /// ```
+    pub fn builder() -> ChineseSequenceBuilder {
+        ChineseSequenceBuilder::default()
+    }
+    /// Returns a new sequence containing only the syllables in `range`.
+    ///
+    /// Selections and breaks are re-based to the start of the range.
+    /// Selections not fully inside the range are dropped. Breaks on either
+    /// boundary of the range are kept.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds of the syllables.
+    pub fn slice(&self, range: Range<usize>) -> ChineseSequence {
+        let start = range.start;
+        let end = range.end;
+        ChineseSequence {
+            syllables: self.syllables[range].to_vec(),
+            selections: self
+                .selections
+                .iter()
+                .filter(|sel| sel.start >= start && sel.end <= end)
+                .map(|sel| Interval {
+                    start: sel.start - start,
+                    end: sel.end - start,
+                    phrase: sel.phrase.clone(),
+                    unknown: sel.unknown,
+                })
+                .collect(),
+            breaks: self
+                .breaks
+                .iter()
+                .filter(|br| br.0 >= start && br.0 <= end)
+                .map(|br| Break(br.0 - start))
+                .collect(),
+        }
+    }
+}
+
+/// Checks a single selection against a sequence of `len` syllables.
+fn check_selection(sel: &Interval, len: usize) -> Result<(), SequenceError> {
+    if sel.start >= sel.end || sel.end > len {
+        return Err(SequenceError::SelectionOutOfRange {
+            start: sel.start,
+            end: sel.end,
+            len,
+        });
+    }
+    let chars = sel.phrase.chars().count();
+    if chars != sel.len() {
+        return Err(SequenceError::PhraseLengthMismatch {
+            start: sel.start,
+            end: sel.end,
+            chars,
+        });
+    }
+    Ok(())
+}
+
+/// Builds a [`ChineseSequence`] that always passes
+/// [`ChineseSequence::validate`].
+///
+/// Selections and breaks are checked against the syllables added so far, so
+/// add the syllables first. Rejected components leave the builder unchanged,
+/// which lets generated inputs, for example from a fuzzer, skip the invalid
+/// parts and keep going.
+#[derive(Debug, Default, Clone)]
+pub struct ChineseSequenceBuilder {
+    syllables: Vec<Syllable>,
+    selections: Vec<Interval>,
+    breaks: Vec<Break>,
+}
+
+impl ChineseSequenceBuilder {
+    /// Appends syllables to the sequence.
+    pub fn syllables(mut self, syllables: impl IntoIterator<Item = Syllable>) -> Self {
+        self.syllables.extend(syllables);
+        self
+    }
+    /// Adds a selection.
+    ///
+    /// Returns an error if the selection is out of range of the syllables,
+    /// doesn't have one character per syllable, or overlaps an earlier
+    /// selection.
+    pub fn selection(mut self, selection: Interval) -> Result<Self, SequenceError> {
+        self.try_add_selection(selection)?;
+        Ok(self)
+    }
+    /// Adds a break before the syllable at `pos`.
+    ///
+    /// Returns an error if the break is past the end of the syllables.
+    pub fn break_at(mut self, pos: usize) -> Result<Self, SequenceError> {
+        self.try_add_break(pos)?;
+        Ok(self)
+    }
+    /// Same as [`selection`][Self::selection] but borrows the builder, which
+    /// is kept unchanged on errors.
+    pub fn try_add_selection(&mut self, selection: Interval) -> Result<(), SequenceError> {
+        check_selection(&selection, self.syllables.len())?;
+        if let Some(sel) = self
+            .selections
+            .iter()
+            .find(|sel| sel.start < selection.end && selection.start < sel.end)
+        {
+            let (first, second) = if sel.start <= selection.start {
+                (sel, &selection)
+            } else {
+                (&selection, sel)
+            };
+            return Err(SequenceError::OverlappingSelections {
+                first: first.start..first.end,
+                second: second.start..second.end,
+            });
+        }
+        self.selections.push(selection);
+        Ok(())
+    }
+    /// Same as [`break_at`][Self::break_at] but borrows the builder, which is
+    /// kept unchanged on errors.
+    pub fn try_add_break(&mut self, pos: usize) -> Result<(), SequenceError> {
+        let len = self.syllables.len();
+        if pos > len {
+            return Err(SequenceError::BreakOutOfRange { pos, len });
+        }
+        self.breaks.push(Break(pos));
+        Ok(())
+    }
+    /// Returns the built sequence.
+    pub fn build(self) -> ChineseSequence {
+        ChineseSequence {
+            syllables: self.syllables,
+            selections: self.selections,
+            breaks: self.breaks,
+        }
+    }
+}
+
 /// TODO: doc
 pub trait ConversionEngine {
-    /// TODO: doc
-    fn convert(&self, segment: &ChineseSequence) -> Vec<Interval>;
-    /// TODO: doc
-    fn convert_next(&self, segment: &ChineseSequence, next: usize) -> Vec<Interval>;
+    /// Converts the sequence into its best phrasing.
+    ///
+    /// The sequence is not validated, call [`ChineseSequence::validate`]
+    /// first to reject malformed input. A sequence without syllables always
+    /// converts to no intervals, even if it still has selections or breaks
+    /// left over from editing; `validate` reports those as out of range.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence cannot be converted into a complete
+    /// phrasing.
+    fn convert(&self, segment: &ChineseSequence) -> Result<Vec<Interval>, ConversionError>;
+    /// Returns the `next`-th alternative phrasing of the sequence.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence cannot be converted into a complete
+    /// phrasing.
+    fn convert_next(
+        &self,
+        segment: &ChineseSequence,
+        next: usize,
+    ) -> Result<Vec<Interval>, ConversionError>;
+    /// Same as [`convert`][ConversionEngine::convert] but returns a
+    /// [`ConversionResult`] for display.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence cannot be converted into a complete
+    /// phrasing.
+    fn convert_display(
+        &self,
+        segment: &ChineseSequence,
+    ) -> Result<ConversionResult, ConversionError> {
+        self.convert(segment).map(ConversionResult::from)
+    }
+    /// Converts the sequence into the text of its phrases.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence cannot be converted into a complete
+    /// phrasing.
+    fn convert_to_string(&self, segment: &ChineseSequence) -> Result<String, ConversionError> {
+        self.convert_to_string_with_sep(segment, "")
+    }
+    /// Same as [`convert_to_string`][ConversionEngine::convert_to_string] but
+    /// puts `sep` between phrases, which shows the segmentation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence cannot be converted into a complete
+    /// phrasing.
+    fn convert_to_string_with_sep(
+        &self,
+        segment: &ChineseSequence,
+        sep: &str,
+    ) -> Result<String, ConversionError> {
+        Ok(self
+            .convert(segment)?
+            .iter()
+            .map(|interval| interval.phrase.as_str())
+            .collect::<Vec<_>>()
+            .join(sep))
+    }
+    /// Converts the sequence and pairs each interval with the range of its
+    /// phrase in the converted text, counted in characters.
+    ///
+    /// Intervals count syllables, which only match the characters of the
+    /// text while every phrase has one character per syllable. Editors
+    /// placing a cursor in the text can use the character ranges instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence cannot be converted into a complete
+    /// phrasing.
+    fn convert_char_spans(
+        &self,
+        segment: &ChineseSequence,
+    ) -> Result<Vec<(Range<usize>, Interval)>, ConversionError> {
+        let mut offset = 0;
+        Ok(self
+            .convert(segment)?
+            .into_iter()
+            .map(|interval| {
+                let start = offset;
+                offset += interval.phrase.chars().count();
+                (start..offset, interval)
+            })
+            .collect())
+    }
+    /// Converts every case and compares the result with the expected
+    /// phrasing.
+    ///
+    /// This measures how well a dictionary and engine handle a test corpus.
+    /// A case whose conversion fails counts as a mismatch.
+    fn coverage(&self, cases: &[(ChineseSequence, Vec<Interval>)]) -> CoverageReport {
+        let mut report = CoverageReport {
+            total: cases.len(),
+            ..Default::default()
+        };
+        for (index, (segment, expected)) in cases.iter().enumerate() {
+            let actual = self.convert(segment);
+            if actual.as_ref() == Ok(expected) {
+                report.matched += 1;
+            } else {
+                report.mismatches.push(CoverageMismatch {
+                    index,
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        report
+    }
+    /// Counts the intervals of each length chosen when converting
+    /// `sequences`.
+    ///
+    /// The count of intervals with `n` syllables is at index `n`. Intervals
+    /// longer than [`DEFAULT_MAX_PHRASE_LEN`] are counted in the last bin.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of converting the sequences.
+    fn length_histogram(
+        &self,
+        sequences: &[ChineseSequence],
+    ) -> Result<[usize; DEFAULT_MAX_PHRASE_LEN + 1], ConversionError> {
+        let mut histogram = [0; DEFAULT_MAX_PHRASE_LEN + 1];
+        for segment in sequences {
+            for interval in self.convert(segment)? {
+                histogram[interval.len().min(DEFAULT_MAX_PHRASE_LEN)] += 1;
+            }
+        }
+        Ok(histogram)
+    }
 }
 
+/// The result of [`ConversionEngine::coverage`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CoverageReport {
+    /// Number of cases converted.
+    pub total: usize,
+    /// Number of cases converted to exactly the expected phrasing.
+    pub matched: usize,
+    /// The cases that didn't convert to the expected phrasing.
+    pub mismatches: Vec<CoverageMismatch>,
+}
+
+impl CoverageReport {
+    /// Returns the fraction of cases that matched, or 1.0 without cases.
+    pub fn match_rate(&self) -> f64 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        self.matched as f64 / self.total as f64
+    }
+}
+
+/// A case of [`CoverageReport`] that didn't convert as expected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageMismatch {
+    /// Position of the case in the input.
+    pub index: usize,
+    /// The expected phrasing.
+    pub expected: Vec<Interval>,
+    /// The phrasing the engine produced, or the conversion error.
+    pub actual: Result<Vec<Interval>, ConversionError>,
+}
+
+impl Display for CoverageMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "case {}: expected {}, ",
+            self.index,
+            ConversionResult(self.expected.clone())
+        )?;
+        match &self.actual {
+            Ok(actual) => write!(f, "got {}", ConversionResult(actual.clone())),
+            Err(error) => write!(f, "failed: {}", error),
+        }
+    }
+}
+
+/// Converts every case with both engines and returns the cases where they
+/// disagree.
+///
+/// This vets that a change to an engine doesn't change the conversions
+/// unintentionally. A case that fails with the same error in both engines
+/// is not a divergence.
+pub fn compare(
+    a: &dyn ConversionEngine,
+    b: &dyn ConversionEngine,
+    cases: &[ChineseSequence],
+) -> Vec<Divergence> {
+    cases
+        .iter()
+        .enumerate()
+        .filter_map(|(index, segment)| {
+            let (a, b) = (a.convert(segment), b.convert(segment));
+            (a != b).then_some(Divergence { index, a, b })
+        })
+        .collect()
+}
+
+/// A case converted differently by the two engines of [`compare`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Position of the case in the input.
+    pub index: usize,
+    /// The phrasing of the first engine, or the conversion error.
+    pub a: Result<Vec<Interval>, ConversionError>,
+    /// The phrasing of the second engine, or the conversion error.
+    pub b: Result<Vec<Interval>, ConversionError>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "case {}: ", self.index)?;
+        for (i, result) in [&self.a, &self.b].into_iter().enumerate() {
+            if i > 0 {
+                write!(f, " vs ")?;
+            }
+            match result {
+                Ok(intervals) => write!(f, "{}", ConversionResult(intervals.clone()))?,
+                Err(error) => write!(f, "failed: {}", error)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The kinds of conversion engines that can be selected at runtime.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConversionEngineKind {
+    /// The classic dynamic programming engine, [`ChewingConversionEngine`].
+    #[default]
+    Chewing,
+    /// The per-syllable frequency engine, [`SimpleConversionEngine`].
+    Simple,
+}
+
+/// A conversion engine chosen at runtime by [`ConversionEngineKind`].
+///
+/// All calls are delegated to the wrapped engine, so applications can switch
+/// engines through configuration without changing call sites.
+///
+/// # Examples
+///
+/// ```
+/// use std::{collections::HashMap, rc::Rc};
+///
+/// use chewing::conversion::{AnyConversionEngine, ConversionEngineKind};
+///
+/// let engine = AnyConversionEngine::new(ConversionEngineKind::Simple, Rc::new(HashMap::new()));
+/// assert_eq!(ConversionEngineKind::Simple, engine.kind());
+/// ```
+#[derive(Debug)]
+pub enum AnyConversionEngine {
+    /// See [`ChewingConversionEngine`].
+    Chewing(Box<ChewingConversionEngine>),
+    /// See [`SimpleConversionEngine`].
+    Simple(SimpleConversionEngine),
+}
+
+impl AnyConversionEngine {
+    /// Creates the conversion engine of `kind` using `dict` for lookups.
+    pub fn new(kind: ConversionEngineKind, dict: Rc<dyn Dictionary>) -> AnyConversionEngine {
+        match kind {
+            ConversionEngineKind::Chewing => {
+                AnyConversionEngine::Chewing(Box::new(ChewingConversionEngine::new(dict)))
+            }
+            ConversionEngineKind::Simple => {
+                AnyConversionEngine::Simple(SimpleConversionEngine::new(dict))
+            }
+        }
+    }
+    /// Returns the kind of the wrapped engine.
+    pub fn kind(&self) -> ConversionEngineKind {
+        match self {
+            AnyConversionEngine::Chewing(_) => ConversionEngineKind::Chewing,
+            AnyConversionEngine::Simple(_) => ConversionEngineKind::Simple,
+        }
+    }
+}
+
+impl ConversionEngine for AnyConversionEngine {
+    fn convert(&self, segment: &ChineseSequence) -> Result<Vec<Interval>, ConversionError> {
+        match self {
+            AnyConversionEngine::Chewing(engine) => engine.convert(segment),
+            AnyConversionEngine::Simple(engine) => engine.convert(segment),
+        }
+    }
+
+    fn convert_next(
+        &self,
+        segment: &ChineseSequence,
+        next: usize,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        match self {
+            AnyConversionEngine::Chewing(engine) => engine.convert_next(segment, next),
+            AnyConversionEngine::Simple(engine) => engine.convert_next(segment, next),
+        }
+    }
+}
+
+mod alias;
+mod bias;
+mod bigram;
 mod chewing_conversion;
-pub use chewing_conversion::ChewingConversionEngine;
+mod fallback;
+mod homophone;
+mod interner;
+mod overlay;
+mod simple;
+mod simplified;
+mod span_cache;
+
+pub use alias::AliasMap;
+pub use bias::{UserBias, DEFAULT_MAX_USER_BIAS};
+pub use bigram::Bigram;
+pub use chewing_conversion::{
+    BenchReport, CacheSnapshot, ChewingConversionEngine, ConversionCache, ConversionConfig,
+    Correction, FreqRule, LanguageModel, PhraseCost, PhrasingScore, Preview, Reconciliation,
+    ScoreMode, SequenceBench, SingleCharPenalty, SyncChewingConversionEngine, DEFAULT_SEED,
+    DEFAULT_SINGLE_CHAR_PENALTY,
+};
+pub use fallback::{FallbackRenderer, ZhuyinFallback};
+pub use homophone::HomophoneMemory;
+pub use interner::{InternedInterval, PhraseInterner};
+pub use overlay::FrequencyOverlay;
+pub use simple::SimpleConversionEngine;
+pub use simplified::SimplifiedMapper;
+pub use span_cache::SpanCache;
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, rc::Rc};
+
+    use crate::{
+        dictionary::{Dictionary, Phrase},
+        syl,
+        zhuyin::{Bopomofo::*, Syllable},
+    };
+
+    use super::{
+        compare, diff, intervals_equivalent, is_valid_tiling, segmentation_distance,
+        track_intervals, AliasMap, AnyConversionEngine, Break, ChewingConversionEngine,
+        ChineseSequence, ChineseSequenceBuilder, ConversionEngine, ConversionEngineKind,
+        ConversionResult, CoverageReport, Interval, IntervalChange, IntervalError, NormOptions,
+        SequenceError, TrackedInterval,
+    };
+
+    fn test_dictionary() -> Rc<dyn Dictionary> {
+        Rc::new(test_map())
+    }
+
+    fn test_map() -> HashMap<Vec<Syllable>, Vec<Phrase<'static>>> {
+        HashMap::from([
+            (vec![syl![D, AI, TONE4]], vec![("代", 10).into()]),
+            (vec![syl![B, I, AU, TONE3]], vec![("表", 10).into()]),
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 200).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 200).into()],
+            ),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 200).into()],
+            ),
+        ])
+    }
+
+    #[test]
+    fn diff_after_appending_syllable() {
+        let old = vec![
+            Interval::new(0, 2, "國民").unwrap(),
+            Interval::new(2, 3, "大").unwrap(),
+        ];
+        let new = vec![
+            Interval::new(0, 2, "國民").unwrap(),
+            Interval::new(2, 4, "大會").unwrap(),
+        ];
+        assert_eq!(
+            vec![
+                IntervalChange::Unchanged(Interval::new(0, 2, "國民").unwrap()),
+                IntervalChange::Removed(Interval::new(2, 3, "大").unwrap()),
+                IntervalChange::Added(Interval::new(2, 4, "大會").unwrap()),
+            ],
+            diff(&old, &new)
+        );
+    }
+
+    #[test]
+    fn diff_appended_segment() {
+        let old = vec![Interval::new(0, 2, "國民").unwrap()];
+        let new = vec![
+            Interval::new(0, 2, "國民").unwrap(),
+            Interval::new(2, 3, "大").unwrap(),
+        ];
+        assert_eq!(
+            vec![
+                IntervalChange::Unchanged(Interval::new(0, 2, "國民").unwrap()),
+                IntervalChange::Added(Interval::new(2, 3, "大").unwrap()),
+            ],
+            diff(&old, &new)
+        );
+        assert_eq!(
+            vec![IntervalChange::Unchanged(
+                Interval::new(0, 2, "國民").unwrap()
+            )],
+            diff(&old, &old)
+        );
+    }
+
+    #[test]
+    fn format_conversion_result() {
+        let engine = AnyConversionEngine::new(ConversionEngineKind::Chewing, test_dictionary());
+        let seq = ChineseSequence::from_zhuyin_str("ㄍㄨㄛˊ ㄇㄧㄣˊ ㄉㄚˋ ㄏㄨㄟˋ ㄉㄞˋ ㄅㄧㄠˇ")
+            .unwrap();
+        let result = engine.convert_display(&seq).unwrap();
+        assert_eq!("國民 | 大會 | 代表", result.to_string());
+        assert_eq!("[0..2 國民, 2..4 大會, 4..6 代表]", format!("{:?}", result));
+        assert_eq!(engine.convert(&seq).unwrap(), result.into_inner());
+        assert_eq!("", ConversionResult::default().to_string());
+    }
+
+    #[test]
+    fn convert_to_string_joins_phrases() {
+        let engine = AnyConversionEngine::new(ConversionEngineKind::Chewing, test_dictionary());
+        let seq = ChineseSequence::from_zhuyin_str("ㄍㄨㄛˊ ㄇㄧㄣˊ ㄉㄚˋ ㄏㄨㄟˋ ㄉㄞˋ ㄅㄧㄠˇ")
+            .unwrap();
+        assert_eq!("國民大會代表", engine.convert_to_string(&seq).unwrap());
+        assert_eq!(
+            "國民/大會/代表",
+            engine.convert_to_string_with_sep(&seq, "/").unwrap()
+        );
+    }
+
+    #[test]
+    fn coverage_reports_mismatches() {
+        let engine = AnyConversionEngine::new(ConversionEngineKind::Chewing, test_dictionary());
+        let seq = |zhuyin: &str| ChineseSequence::from_zhuyin_str(zhuyin).unwrap();
+        let cases = vec![
+            (
+                seq("ㄍㄨㄛˊ ㄇㄧㄣˊ"),
+                vec![Interval::new(0, 2, "國民").unwrap()],
+            ),
+            (
+                seq("ㄉㄞˋ ㄅㄧㄠˇ"),
+                vec![Interval::new(0, 2, "代表").unwrap()],
+            ),
+            (
+                seq("ㄍㄨㄛˊ ㄇㄧㄣˊ ㄉㄞˋ ㄅㄧㄠˇ"),
+                vec![
+                    Interval::new(0, 2, "國民").unwrap(),
+                    Interval::new(2, 3, "代").unwrap(),
+                    Interval::new(3, 4, "表").unwrap(),
+                ],
+            ),
+        ];
+        let report = engine.coverage(&cases);
+        assert_eq!(3, report.total);
+        assert_eq!(2, report.matched);
+        assert_eq!(1, report.mismatches.len());
+        let mismatch = &report.mismatches[0];
+        assert_eq!(2, mismatch.index);
+        assert_eq!(
+            "case 2: expected 國民 | 代 | 表, got 國民 | 代表",
+            mismatch.to_string()
+        );
+        assert!((report.match_rate() - 2.0 / 3.0).abs() < f64::EPSILON);
+        assert_eq!(1.0, CoverageReport::default().match_rate());
+    }
+
+    #[test]
+    fn compare_engines() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict.clone());
+        let misweighted = ChewingConversionEngine::new(dict).with_phrase_cost(|phrase, _, _| {
+            if phrase.as_str() == "代表" {
+                -100_000
+            } else {
+                0
+            }
+        });
+        let seq = |zhuyin: &str| ChineseSequence::from_zhuyin_str(zhuyin).unwrap();
+        let cases = vec![
+            seq("ㄍㄨㄛˊ ㄇㄧㄣˊ"),
+            seq("ㄉㄞˋ ㄅㄧㄠˇ"),
+            seq("ㄍㄨㄛˊ ㄇㄧㄣˊ ㄉㄞˋ ㄅㄧㄠˇ"),
+        ];
+        assert!(compare(&engine, &engine, &cases).is_empty());
+        let divergences = compare(&engine, &misweighted, &cases);
+        assert_eq!(
+            vec![1, 2],
+            divergences.iter().map(|it| it.index).collect::<Vec<_>>()
+        );
+        assert_eq!("case 1: 代表 vs 代 | 表", divergences[0].to_string());
+    }
+
+    #[test]
+    fn equivalent_under_width_folding() {
+        let a = vec![
+            Interval::new(0, 2, "國民").unwrap(),
+            Interval::new(2, 3, "，").unwrap(),
+            Interval::new(3, 5, "大會").unwrap(),
+        ];
+        let b = vec![
+            Interval::new(0, 2, "國民").unwrap(),
+            Interval::new(2, 3, ",").unwrap(),
+            Interval::new(3, 5, "大會").unwrap(),
+        ];
+        let strict = NormOptions::default();
+        let folded = NormOptions {
+            fold_width: true,
+            ..Default::default()
+        };
+        assert!(!intervals_equivalent(&a, &b, &strict));
+        assert!(intervals_equivalent(&a, &b, &folded));
+        assert!(!intervals_equivalent(&a[..2], &b, &folded));
+
+        let mut aliases = AliasMap::new();
+        aliases.insert("大會", "大会");
+        let aliased = NormOptions {
+            aliases: Some(aliases),
+            ..Default::default()
+        };
+        let c = vec![Interval::new(0, 2, "大會").unwrap()];
+        let d = vec![Interval::new(0, 2, "大会").unwrap()];
+        assert!(intervals_equivalent(&c, &d, &aliased));
+        assert!(!intervals_equivalent(&c, &d, &folded));
+    }
+
+    #[test]
+    fn histogram_of_interval_lengths() {
+        let mut dict = test_map();
+        dict.insert(
+            vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ],
+            vec![("國民大會", 200).into()],
+        );
+        let engine = AnyConversionEngine::new(ConversionEngineKind::Chewing, Rc::new(dict));
+        let seq = |zhuyin: &str| ChineseSequence::from_zhuyin_str(zhuyin).unwrap();
+        let sequences = [
+            seq("ㄍㄨㄛˊ ㄇㄧㄣˊ ㄉㄞˋ"),
+            seq("ㄉㄞˋ ㄅㄧㄠˇ"),
+            seq("ㄍㄨㄛˊ ㄇㄧㄣˊ ㄉㄚˋ ㄏㄨㄟˋ"),
+        ];
+        let histogram = engine.length_histogram(&sequences).unwrap();
+        assert_eq!([0, 1, 2, 0, 1], histogram[..5]);
+        assert!(histogram[5..].iter().all(|&count| count == 0));
+        assert_eq!([0; 12], engine.length_histogram(&[]).unwrap());
+    }
+
+    #[test]
+    fn any_conversion_engine_from_kind() {
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::from([
+            (
+                vec![syl![D, AI, TONE4]],
+                vec![("代", 10).into(), ("戴", 20).into()],
+            ),
+            (vec![syl![B, I, AU, TONE3]], vec![("表", 10).into()]),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 200).into()],
+            ),
+        ]));
+        let seq = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+
+        let engine = AnyConversionEngine::new(ConversionEngineKind::Chewing, dict.clone());
+        assert_eq!(ConversionEngineKind::Chewing, engine.kind());
+        assert_eq!(
+            vec![Interval::new(0, 2, "代表").unwrap()],
+            engine.convert(&seq).unwrap()
+        );
+
+        let engine = AnyConversionEngine::new(ConversionEngineKind::Simple, dict);
+        assert_eq!(ConversionEngineKind::Simple, engine.kind());
+        assert_eq!(
+            vec![
+                Interval::new(0, 1, "戴").unwrap(),
+                Interval::new(1, 2, "表").unwrap()
+            ],
+            engine.convert(&seq).unwrap()
+        );
+        assert_eq!(engine.convert(&seq), engine.convert_next(&seq, 1));
+    }
+
+    #[test]
+    fn new_interval() {
+        assert_eq!(
+            Ok(Interval {
+                start: 1,
+                end: 3,
+                phrase: "代表".to_string(),
+                unknown: false
+            }),
+            Interval::new(1, 3, "代表")
+        );
+        assert_eq!(Break(2), Break::new(2));
+    }
+
+    #[test]
+    fn new_interval_reversed_bounds() {
+        assert_eq!(
+            Err(IntervalError::InvalidBounds { start: 3, end: 1 }),
+            Interval::new(3, 1, "代表")
+        );
+        assert_eq!(
+            Err(IntervalError::InvalidBounds { start: 2, end: 2 }),
+            Interval::new(2, 2, "代")
+        );
+    }
+
+    #[test]
+    fn new_interval_empty_phrase() {
+        assert_eq!(Err(IntervalError::EmptyPhrase), Interval::new(0, 1, ""));
+    }
+
+    fn sequence(selections: Vec<Interval>, breaks: Vec<Break>) -> ChineseSequence {
+        ChineseSequence {
+            syllables: vec![
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections,
+            breaks,
+        }
+    }
+
+    #[test]
+    fn add_selection_evicts_overlapping() {
+        let mut seq = sequence(
+            vec![
+                Interval::new(1, 2, "會").unwrap(),
+                Interval::new(3, 4, "表").unwrap(),
+            ],
+            vec![],
+        );
+        seq.add_selection(Interval::new(0, 3, "大會代").unwrap());
+        assert_eq!(
+            vec![
+                Interval::new(3, 4, "表").unwrap(),
+                Interval::new(0, 3, "大會代").unwrap(),
+            ],
+            seq.selections
+        );
+        assert_eq!(Ok(()), seq.validate());
+
+        seq.add_selection(Interval::new(2, 4, "代表").unwrap());
+        assert_eq!(vec![Interval::new(2, 4, "代表").unwrap()], seq.selections);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let seq = ChineseSequence::from_zhuyin_str("ㄍㄨㄛˊ ㄇㄧㄣˊ ㄉㄚˋ ㄏㄨㄟˋ").unwrap();
+        let json = serde_json::to_string(&seq).unwrap();
+        assert_eq!(
+            r#"{"syllables":["ㄍㄨㄛˊ","ㄇㄧㄣˊ","ㄉㄚˋ","ㄏㄨㄟˋ"],"selections":[],"breaks":[]}"#,
+            json
+        );
+        let seq: ChineseSequence = serde_json::from_str(&json).unwrap();
+        let result = ConversionResult(engine.convert(&seq).unwrap());
+        assert_eq!(
+            concat!(
+                r#"[{"start":0,"end":2,"phrase":"國民","unknown":false},"#,
+                r#"{"start":2,"end":4,"phrase":"大會","unknown":false}]"#
+            ),
+            serde_json::to_string(&result).unwrap()
+        );
+
+        let request: ChineseSequence =
+            serde_json::from_str(r#"{"syllables":["ㄉㄚˋ"],"breaks":[1]}"#).unwrap();
+        assert_eq!(vec![syl![D, A, TONE4]], request.syllables);
+        assert!(request.selections.is_empty());
+        assert_eq!(vec![Break(1)], request.breaks);
+    }
+
+    #[test]
+    fn add_selection_relative_to_end() {
+        let mut seq = sequence(vec![], vec![]);
+        assert_eq!(Ok(()), seq.add_selection_from_end(2, 2, "戴錶".to_string()));
+        assert_eq!(vec![Interval::new(2, 4, "戴錶").unwrap()], seq.selections);
+        assert_eq!(Ok(()), seq.validate());
+
+        seq.syllables.push(syl![D, A, TONE4]);
+        assert_eq!(vec![Interval::new(2, 4, "戴錶").unwrap()], seq.selections);
+        assert_eq!(Ok(()), seq.add_selection_from_end(1, 1, "大".to_string()));
+        assert_eq!(
+            vec![
+                Interval::new(2, 4, "戴錶").unwrap(),
+                Interval::new(4, 5, "大").unwrap(),
+            ],
+            seq.selections
+        );
+        assert_eq!(Ok(()), seq.validate());
+
+        assert_eq!(
+            Err(SequenceError::SelectionFromEndOutOfRange {
+                from_end_start: 6,
+                from_end_len: 1,
+                len: 5
+            }),
+            seq.add_selection_from_end(6, 1, "大".to_string())
+        );
+        assert_eq!(
+            Err(SequenceError::SelectionFromEndOutOfRange {
+                from_end_start: 1,
+                from_end_len: 2,
+                len: 5
+            }),
+            seq.add_selection_from_end(1, 2, "大會".to_string())
+        );
+        assert_eq!(2, seq.selections.len());
+    }
+
+    #[test]
+    fn syllables_for_interval() {
+        let seq = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            Some(&[syl![G, U, O, TONE2], syl![M, I, EN, TONE2]][..]),
+            seq.syllables_for(&Interval::new(0, 2, "國民").unwrap())
+        );
+        assert_eq!(
+            None,
+            seq.syllables_for(&Interval::new(2, 4, "大會").unwrap())
+        );
+    }
+
+    #[test]
+    fn from_zhuyin_str() {
+        let seq = ChineseSequence::from_zhuyin_str(" ㄍㄨㄛˊ  ㄇㄧㄣˊ\n").unwrap();
+        assert_eq!(
+            vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+            seq.syllables
+        );
+        assert!(seq.selections.is_empty());
+        assert!(seq.breaks.is_empty());
+    }
+
+    #[test]
+    fn from_zhuyin_str_malformed_token() {
+        let err = ChineseSequence::from_zhuyin_str("ㄍㄨㄛˊ ㄇㄧㄣˊ ㄉxˋ").unwrap_err();
+        assert_eq!(2, err.index);
+        assert_eq!("ㄉxˋ", err.token);
+        let err = ChineseSequence::from_zhuyin_str("ㄍㄍ ㄇㄧㄣˊ").unwrap_err();
+        assert_eq!(0, err.index);
+    }
+
+    #[test]
+    fn validate_consistent_sequence() {
+        let seq = sequence(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "大會".to_string(),
+                    unknown: false,
+                },
+                Interval {
+                    start: 2,
+                    end: 3,
+                    phrase: "代".to_string(),
+                    unknown: false,
+                },
+            ],
+            vec![Break(0), Break(4)],
+        );
+        assert_eq!(Ok(()), seq.validate());
+    }
+
+    #[test]
+    fn validate_overlapping_selections() {
+        let seq = sequence(
+            vec![
+                Interval {
+                    start: 1,
+                    end: 3,
+                    phrase: "會代".to_string(),
+                    unknown: false,
+                },
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "大會".to_string(),
+                    unknown: false,
+                },
+            ],
+            vec![],
+        );
+        assert_eq!(
+            Err(SequenceError::OverlappingSelections {
+                first: 0..2,
+                second: 1..3
+            }),
+            seq.validate()
+        );
+    }
+
+    #[test]
+    fn validate_phrase_length_mismatch() {
+        let seq = sequence(
+            vec![Interval {
+                start: 2,
+                end: 4,
+                phrase: "代".to_string(),
+                unknown: false,
+            }],
+            vec![],
+        );
+        assert_eq!(
+            Err(SequenceError::PhraseLengthMismatch {
+                start: 2,
+                end: 4,
+                chars: 1
+            }),
+            seq.validate()
+        );
+    }
+
+    #[test]
+    fn validate_selection_out_of_range() {
+        let seq = sequence(
+            vec![Interval {
+                start: 3,
+                end: 5,
+                phrase: "表示".to_string(),
+                unknown: false,
+            }],
+            vec![],
+        );
+        assert_eq!(
+            Err(SequenceError::SelectionOutOfRange {
+                start: 3,
+                end: 5,
+                len: 4
+            }),
+            seq.validate()
+        );
+    }
+
+    #[test]
+    fn stray_selection_on_empty_sequence() {
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::new());
+        let engine = ChewingConversionEngine::new(dict);
+        let seq = ChineseSequence {
+            syllables: vec![],
+            selections: vec![Interval::new(0, 1, "大").unwrap()],
+            breaks: vec![Break(1)],
+        };
+        assert_eq!(
+            Err(SequenceError::SelectionOutOfRange {
+                start: 0,
+                end: 1,
+                len: 0
+            }),
+            seq.validate()
+        );
+        assert_eq!(Ok(vec![]), engine.convert(&seq));
+        assert_eq!(Ok(vec![]), engine.convert_next(&seq, 1));
+    }
+
+    #[test]
+    fn validate_break_out_of_range() {
+        let seq = sequence(vec![], vec![Break(5)]);
+        assert_eq!(
+            Err(SequenceError::BreakOutOfRange { pos: 5, len: 4 }),
+            seq.validate()
+        );
+    }
+
+    #[test]
+    fn builder_matches_direct_construction() {
+        let syllables = vec![
+            syl![G, U, O, TONE2],
+            syl![M, I, EN, TONE2],
+            syl![D, A, TONE4],
+            syl![H, U, EI, TONE4],
+        ];
+        let built = ChineseSequence::builder()
+            .syllables(syllables.clone())
+            .selection(Interval::new(0, 2, "國民").unwrap())
+            .unwrap()
+            .break_at(2)
+            .unwrap()
+            .build();
+        let direct = ChineseSequence {
+            syllables,
+            selections: vec![Interval::new(0, 2, "國民").unwrap()],
+            breaks: vec![Break(2)],
+        };
+        assert_eq!(direct.syllables, built.syllables);
+        assert_eq!(direct.selections, built.selections);
+        assert_eq!(direct.breaks, built.breaks);
+        assert_eq!(Ok(()), built.validate());
+    }
+
+    #[test]
+    fn builder_rejects_invalid_components() {
+        let mut builder = ChineseSequenceBuilder::default()
+            .syllables([syl![G, U, O, TONE2], syl![M, I, EN, TONE2]]);
+        builder
+            .try_add_selection(Interval::new(1, 2, "民").unwrap())
+            .unwrap();
+        assert_eq!(
+            Err(SequenceError::OverlappingSelections {
+                first: 0..2,
+                second: 1..2
+            }),
+            builder.try_add_selection(Interval::new(0, 2, "國民").unwrap())
+        );
+        assert_eq!(
+            Err(SequenceError::SelectionOutOfRange {
+                start: 1,
+                end: 3,
+                len: 2
+            }),
+            builder.try_add_selection(Interval::new(1, 3, "民大").unwrap())
+        );
+        assert_eq!(
+            Err(SequenceError::PhraseLengthMismatch {
+                start: 0,
+                end: 1,
+                chars: 2
+            }),
+            builder.try_add_selection(Interval::new(0, 1, "國民").unwrap())
+        );
+        assert_eq!(
+            Err(SequenceError::BreakOutOfRange { pos: 3, len: 2 }),
+            builder.try_add_break(3)
+        );
+        let seq = builder.build();
+        assert_eq!(vec![Interval::new(1, 2, "民").unwrap()], seq.selections);
+        assert!(seq.breaks.is_empty());
+    }
+
+    #[test]
+    fn segmentation_distance_counts_boundaries() {
+        let pairs = vec![
+            Interval::new(0, 2, "國民").unwrap(),
+            Interval::new(2, 4, "大會").unwrap(),
+            Interval::new(4, 6, "代表").unwrap(),
+        ];
+        let triples = vec![
+            Interval::new(0, 3, "國民大").unwrap(),
+            Interval::new(3, 6, "會代表").unwrap(),
+        ];
+        assert_eq!(3, segmentation_distance(&pairs, &triples));
+        assert_eq!(3, segmentation_distance(&triples, &pairs));
+        assert_eq!(0, segmentation_distance(&pairs, &pairs));
+    }
+
+    #[test]
+    fn valid_tiling() {
+        let intervals = vec![
+            Interval::new(0, 2, "國民").unwrap(),
+            Interval::new(2, 3, "大").unwrap(),
+            Interval::new(3, 4, "會").unwrap(),
+        ];
+        assert!(is_valid_tiling(&intervals, 4));
+        assert!(is_valid_tiling(&[], 0));
+    }
+
+    #[test]
+    fn tiling_with_gap() {
+        let intervals = vec![
+            Interval::new(0, 2, "國民").unwrap(),
+            Interval::new(3, 4, "會").unwrap(),
+        ];
+        assert!(!is_valid_tiling(&intervals, 4));
+        assert!(!is_valid_tiling(&intervals[..1], 4));
+    }
+
+    #[test]
+    fn tiling_with_overlap() {
+        let intervals = vec![
+            Interval::new(0, 2, "國民").unwrap(),
+            Interval::new(1, 4, "民大會").unwrap(),
+        ];
+        assert!(!is_valid_tiling(&intervals, 4));
+    }
+
+    #[test]
+    fn track_intervals_near_max_id() {
+        let prev = vec![TrackedInterval {
+            id: u64::MAX - 1,
+            interval: Interval::new(0, 2, "國民").unwrap(),
+        }];
+        let tracked = track_intervals(
+            &prev,
+            vec![
+                Interval::new(0, 2, "國民").unwrap(),
+                Interval::new(2, 3, "大").unwrap(),
+                Interval::new(3, 4, "會").unwrap(),
+            ],
+        );
+        let ids: Vec<_> = tracked.iter().map(|it| it.id).collect();
+        assert_eq!(vec![u64::MAX - 1, u64::MAX, u64::MAX], ids);
+    }
+
+    #[test]
+    fn slice_mid_buffer() {
+        let seq = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![
+                Interval {
+                    start: 1,
+                    end: 3,
+                    phrase: "民大".to_string(),
+                    unknown: false,
+                },
+                Interval {
+                    start: 4,
+                    end: 6,
+                    phrase: "代表".to_string(),
+                    unknown: false,
+                },
+            ],
+            breaks: vec![Break(1), Break(2), Break(4), Break(6)],
+        };
+        let slice = seq.slice(2..6);
+        assert_eq!(
+            vec![
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            slice.syllables
+        );
+        assert_eq!(
+            vec![Interval {
+                start: 2,
+                end: 4,
+                phrase: "代表".to_string(),
+                unknown: false,
+            }],
+            slice.selections
+        );
+        assert_eq!(vec![Break(0), Break(2), Break(4)], slice.breaks);
+    }
+}
@@ -0,0 +1,48 @@
+//! Canonical display forms of phrases.
+
+use std::collections::HashMap;
+
+/// A map from phrase variants to the form shown in conversion results.
+///
+/// Some phrases have variant forms, such as 台 and 臺, and a deployment may
+/// want the output normalized to one of them. The conversion engine still
+/// scores the phrases found in the dictionary and only rewrites the phrase of
+/// each output interval that has an alias.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::AliasMap;
+///
+/// let mut aliases = AliasMap::new();
+/// aliases.insert("台灣", "臺灣");
+/// assert_eq!(Some("臺灣"), aliases.get("台灣"));
+/// assert_eq!(None, aliases.get("臺灣"));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct AliasMap {
+    aliases: HashMap<String, String>,
+}
+
+impl AliasMap {
+    /// Creates an empty `AliasMap`.
+    pub fn new() -> AliasMap {
+        AliasMap::default()
+    }
+    /// Sets `canonical` as the form shown for `phrase`.
+    pub fn insert(&mut self, phrase: impl Into<String>, canonical: impl Into<String>) {
+        self.aliases.insert(phrase.into(), canonical.into());
+    }
+    /// Removes the alias of `phrase`.
+    pub fn remove(&mut self, phrase: &str) {
+        self.aliases.remove(phrase);
+    }
+    /// Returns the canonical form of `phrase`, if it has an alias.
+    pub fn get(&self, phrase: &str) -> Option<&str> {
+        self.aliases.get(phrase).map(String::as_str)
+    }
+    /// Returns whether no phrase has an alias.
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+}
@@ -0,0 +1,68 @@
+//! User preference deltas blended into phrase scoring.
+
+use std::collections::HashMap;
+
+/// The default bound of the deltas stored in a [`UserBias`].
+pub const DEFAULT_MAX_USER_BIAS: i32 = 500;
+
+/// A map from phrase to an additive score delta representing the preference
+/// of the user.
+///
+/// The delta is added to the frequency of the phrase when the conversion
+/// engine compares homophones, and to the frequency part of the path score.
+/// Deltas are clamped to `-max_delta..=max_delta` so a preference nudges the
+/// choice when the frequency gap is small, but cannot override a phrase that
+/// is much more common.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::UserBias;
+///
+/// let mut bias = UserBias::new(100);
+/// bias.insert("戴錶", 150);
+/// assert_eq!(100, bias.get("戴錶"));
+/// assert_eq!(0, bias.get("代表"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct UserBias {
+    max_delta: i32,
+    deltas: HashMap<String, i32>,
+}
+
+impl Default for UserBias {
+    fn default() -> UserBias {
+        UserBias::new(DEFAULT_MAX_USER_BIAS)
+    }
+}
+
+impl UserBias {
+    /// Creates an empty `UserBias` with deltas bounded by `max_delta`.
+    pub fn new(max_delta: i32) -> UserBias {
+        UserBias {
+            max_delta: max_delta.saturating_abs(),
+            deltas: HashMap::new(),
+        }
+    }
+    /// Sets the delta of `phrase`, clamped to the configured bound.
+    pub fn insert(&mut self, phrase: impl Into<String>, delta: i32) {
+        let delta = delta.clamp(-self.max_delta, self.max_delta);
+        self.deltas.insert(phrase.into(), delta);
+    }
+    /// Removes the delta of `phrase`.
+    pub fn remove(&mut self, phrase: &str) {
+        self.deltas.remove(phrase);
+    }
+    /// Returns the delta of `phrase`, or 0 if there is none.
+    pub fn get(&self, phrase: &str) -> i32 {
+        self.deltas.get(phrase).copied().unwrap_or(0)
+    }
+    /// Returns the bound of the deltas.
+    pub fn max_delta(&self) -> i32 {
+        self.max_delta
+    }
+    /// Returns whether no phrase has a delta.
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+}
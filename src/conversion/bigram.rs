@@ -0,0 +1,71 @@
+//! Context bonuses between adjacent phrases.
+
+use std::collections::HashMap;
+
+/// A map from a pair of adjacent phrases to an additive score bonus.
+///
+/// Phrase frequencies alone can't tell that some phrases often appear
+/// together. When the conversion engine places `next` right after `prev` in a
+/// phrasing, the bonus of the pair is added to the score of the phrasing. A
+/// negative bonus discourages the pair.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::Bigram;
+///
+/// let mut bigram = Bigram::new();
+/// bigram.insert("測試", "儀器", 1000);
+/// assert_eq!(1000, bigram.get("測試", "儀器"));
+/// assert_eq!(0, bigram.get("儀器", "測試"));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Bigram {
+    bonuses: HashMap<String, HashMap<String, i32>>,
+}
+
+impl Bigram {
+    /// Creates an empty `Bigram`.
+    pub fn new() -> Bigram {
+        Bigram::default()
+    }
+    /// Sets the bonus of `next` following `prev`.
+    pub fn insert(&mut self, prev: impl Into<String>, next: impl Into<String>, bonus: i32) {
+        self.bonuses
+            .entry(prev.into())
+            .or_default()
+            .insert(next.into(), bonus);
+    }
+    /// Removes the bonus of `next` following `prev`.
+    pub fn remove(&mut self, prev: &str, next: &str) {
+        if let Some(nexts) = self.bonuses.get_mut(prev) {
+            nexts.remove(next);
+            if nexts.is_empty() {
+                self.bonuses.remove(prev);
+            }
+        }
+    }
+    /// Returns the bonus of `next` following `prev`, or 0 if there is none.
+    pub fn get(&self, prev: &str, next: &str) -> i32 {
+        self.bonuses
+            .get(prev)
+            .and_then(|nexts| nexts.get(next))
+            .copied()
+            .unwrap_or(0)
+    }
+    /// Returns the bonuses with every pair swapped, for phrasings built from
+    /// the end.
+    pub(crate) fn reversed(&self) -> Bigram {
+        let mut reversed = Bigram::new();
+        for (prev, nexts) in &self.bonuses {
+            for (next, &bonus) in nexts {
+                reversed.insert(next.clone(), prev.clone(), bonus);
+            }
+        }
+        reversed
+    }
+    /// Returns whether no pair has a bonus.
+    pub fn is_empty(&self) -> bool {
+        self.bonuses.is_empty()
+    }
+}
@@ -1,457 +1,8953 @@
 use std::{
-    collections::HashMap,
-    fmt::{Debug, Display},
-    ops::Neg,
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, BTreeSet, HashMap, HashSet},
+    fmt::{Debug, Display, Write},
+    hash::{Hash, Hasher},
+    ops::{Deref, DerefMut, Neg, Range},
     rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use tracing::trace;
 
 use crate::{
-    dictionary::{Dictionary, Phrase},
-    zhuyin::Syllable,
+    dictionary::{Dictionary, DictionarySource, Phrase, Phrases},
+    zhuyin::{Bopomofo, Syllable, SyllablePattern},
 };
 
-use super::{Break, ChineseSequence, ConversionEngine, Interval};
+use super::{
+    is_valid_tiling, rendered_width, track_intervals, AliasMap, Bigram, Break, ChineseSequence,
+    CommitError, ConversionEngine, ConversionError, FallbackRenderer, FrequencyOverlay,
+    HomophoneMemory, InternedInterval, Interval, PhraseInterner, PinTextError, SelectionPriority,
+    SimplifiedMapper, SoftBreak, SpanCache, TrackedInterval, UserBias, ZhuyinFallback,
+    MAX_SEQUENCE_LEN,
+};
+
+/// A function returning the extra score of a phrase placed at `start..end`.
+pub type PhraseCost = Box<dyn Fn(&Phrase<'_>, usize, usize) -> i32 + Send + Sync>;
+
+/// A function returning the score of a complete phrasing given by a language
+/// model, for example a log probability.
+pub type LanguageModel = Box<dyn Fn(&[Interval]) -> f64 + Send + Sync>;
+
+/// A function returning the divisor applied to the frequency of a single
+/// character phrase placed at `start..end` in a sequence of `len` syllables.
+pub type SingleCharPenalty = Box<dyn Fn(usize, usize, usize) -> u32 + Send + Sync>;
+
+/// The divisor applied to the frequency of single character phrases by
+/// default.
+pub const DEFAULT_SINGLE_CHAR_PENALTY: u32 = 512;
+
+/// The seed used to break ties between phrasings by default.
+pub const DEFAULT_SEED: u64 = 0;
 
 /// TODO: doc
-#[derive(Debug)]
-pub struct ChewingConversionEngine {
-    dict: Rc<dyn Dictionary>,
+///
+/// The engine is generic over the pointer to the dictionary. The default
+/// uses [`Rc`] for single threaded applications. Use
+/// [`SyncChewingConversionEngine`] to share one engine across threads.
+pub struct ChewingConversionEngine<P = Rc<dyn Dictionary>> {
+    dict: P,
+    max_phrase_len: usize,
+    config: ConversionConfig,
+    phrase_cost: Option<PhraseCost>,
+    single_char_penalty: Option<SingleCharPenalty>,
+    user_bias: UserBias,
+    bigram: Bigram,
+    anti_bigram: HashSet<(String, String)>,
+    blacklist: HashSet<String>,
+    language_model: Option<LanguageModel>,
+    aliases: AliasMap,
+    overlay: FrequencyOverlay,
+    categories: HashMap<String, i32>,
+    fallback_renderer: Option<Box<dyn FallbackRenderer + Send + Sync>>,
+    homophone_memory: HomophoneMemory,
+    length_index: Option<HashMap<Syllable, BTreeSet<usize>>>,
+    shared_cache: Option<Arc<SpanCache>>,
+    atomic_phrases: HashMap<Vec<Syllable>, String>,
 }
 
-impl ConversionEngine for ChewingConversionEngine {
-    fn convert(&self, segment: &ChineseSequence) -> Vec<Interval> {
-        if segment.syllables.is_empty() {
-            return vec![];
-        }
-        let intervals = self.find_intervals(segment);
-        self.find_best_path(segment.syllables.len(), intervals)
+/// A [`ChewingConversionEngine`] that is `Send` and `Sync`.
+///
+/// # Examples
+///
+/// ```
+/// use std::{collections::HashMap, sync::Arc, thread};
+///
+/// use chewing::conversion::{ChineseSequence, ConversionEngine, SyncChewingConversionEngine};
+///
+/// let engine = Arc::new(SyncChewingConversionEngine::new_sync(Arc::new(HashMap::new())));
+/// let worker = Arc::clone(&engine);
+/// thread::spawn(move || {
+///     let seq = ChineseSequence { syllables: vec![], selections: vec![], breaks: vec![] };
+///     assert!(worker.convert(&seq).unwrap().is_empty());
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+pub type SyncChewingConversionEngine = ChewingConversionEngine<Arc<dyn Dictionary + Send + Sync>>;
+
+impl<P: Debug> Debug for ChewingConversionEngine<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChewingConversionEngine")
+            .field("dict", &self.dict)
+            .field("max_phrase_len", &self.max_phrase_len)
+            .field("config", &self.config)
+            .field("phrase_cost", &self.phrase_cost.as_ref().map(|_| ".."))
+            .field(
+                "single_char_penalty",
+                &self.single_char_penalty.as_ref().map(|_| ".."),
+            )
+            .field("user_bias", &self.user_bias)
+            .field("bigram", &self.bigram)
+            .field("anti_bigram", &self.anti_bigram)
+            .field("blacklist", &self.blacklist)
+            .field(
+                "language_model",
+                &self.language_model.as_ref().map(|_| ".."),
+            )
+            .field("aliases", &self.aliases)
+            .field("overlay", &self.overlay)
+            .field("categories", &self.categories)
+            .field(
+                "fallback_renderer",
+                &self.fallback_renderer.as_ref().map(|_| ".."),
+            )
+            .field("homophone_memory", &self.homophone_memory)
+            .field("length_index", &self.length_index.as_ref().map(|_| ".."))
+            .field("shared_cache", &self.shared_cache)
+            .field("atomic_phrases", &self.atomic_phrases)
+            .finish()
     }
+}
 
-    fn convert_next(&self, segment: &ChineseSequence, next: usize) -> Vec<Interval> {
-        if segment.syllables.is_empty() {
-            return vec![];
+/// How phrase frequencies contribute to the score of a phrasing.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FreqRule {
+    /// Sum the raw frequency of every phrase.
+    ///
+    /// Frequencies of single character phrases are reduced so they don't
+    /// outweigh longer phrases.
+    #[default]
+    Raw,
+    /// Sum the logarithm of the frequency once per syllable of the phrase.
+    ///
+    /// A single phrase with a very high frequency no longer dominates the
+    /// score, so long segments only win when the other rules favor them.
+    LogPerSyllable,
+}
+
+/// How the rules are combined into the score of a phrasing.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScoreMode {
+    /// Balance the phrase lengths, their variance, and the frequencies.
+    #[default]
+    Balanced,
+    /// Prefer the phrasing with the fewest phrases.
+    ///
+    /// The number of phrases outweighs every other rule, which only break
+    /// ties between phrasings with the same number of phrases.
+    FewestSegments,
+}
+
+/// The tunable settings of a [`ChewingConversionEngine`].
+///
+/// The engine keeps its settings in one `ConversionConfig` so the active
+/// configuration can be inspected with
+/// [`config`][ChewingConversionEngine::config] and restored with
+/// [`with_config`][ChewingConversionEngine::with_config]. Each field is also
+/// set by the `with_*` method of the same name. Settings holding
+/// dictionary-like data or callbacks, such as the user bias or the phrase
+/// cost, are not part of the configuration.
+///
+/// # Examples
+///
+/// ```
+/// use std::{collections::HashMap, rc::Rc};
+///
+/// use chewing::{conversion::ChewingConversionEngine, dictionary::Dictionary};
+///
+/// let dict: Rc<dyn Dictionary> = Rc::new(HashMap::new());
+/// let engine = ChewingConversionEngine::new(dict.clone()).with_freq_cap(1000);
+/// let restored = ChewingConversionEngine::new(dict).with_config(engine.config().clone());
+/// assert_eq!(1000, restored.config().freq_cap);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionConfig {
+    /// Whether user selections are pinned into the result.
+    pub pin_selections: bool,
+    /// The rule used to score phrase frequencies.
+    pub freq_rule: FreqRule,
+    /// How the rules are combined into the score of a phrasing.
+    pub score_mode: ScoreMode,
+    /// Whether ties are broken by the width consistency of the phrases.
+    pub prefer_consistent_width: bool,
+    /// Whether ties are broken by preferring the earliest boundary.
+    pub prefer_early_boundary: bool,
+    /// Whether a toneless last syllable matches any tone.
+    pub trailing_toneless: bool,
+    /// The weight of the language model score, if a model is set.
+    pub language_model_weight: f64,
+    /// The lowest dictionary frequency of the phrases that can be chosen.
+    pub min_freq: u32,
+    /// The highest phrase frequency counted in the score of a phrasing.
+    pub freq_cap: u32,
+    /// The lowest frequency of every interval in the score of a phrasing.
+    pub freq_smoothing: u32,
+    /// The frequency of the placeholder phrases of unknown syllables.
+    pub unknown_syllable_freq: u32,
+    /// How many times more frequent than its parts a phrase must be to be
+    /// chosen, if set.
+    pub merge_gain_ratio: Option<f64>,
+    /// The weight of the phrase frequency for each phrase length.
+    pub length_weights: Option<Arc<[f64]>>,
+    /// Whether phrasings with similar phrase lengths are preferred.
+    pub length_variance: bool,
+    /// Whether the phrases of the dictionary are validated during
+    /// conversion.
+    pub checked: bool,
+    /// The seed used to break ties between phrasings.
+    pub seed: u64,
+}
+
+impl Default for ConversionConfig {
+    fn default() -> ConversionConfig {
+        ConversionConfig {
+            pin_selections: false,
+            freq_rule: FreqRule::default(),
+            score_mode: ScoreMode::default(),
+            prefer_consistent_width: false,
+            prefer_early_boundary: false,
+            trailing_toneless: false,
+            language_model_weight: 0.0,
+            min_freq: 0,
+            freq_cap: u32::MAX,
+            freq_smoothing: 0,
+            unknown_syllable_freq: 0,
+            merge_gain_ratio: None,
+            length_weights: None,
+            length_variance: true,
+            checked: false,
+            seed: DEFAULT_SEED,
         }
-        let mut graph = Graph::default();
-        let paths = self.find_all_paths(&mut graph, segment, 0, segment.syllables.len(), None);
-        let mut trimmed_paths = self.trim_paths(paths);
-        trimmed_paths.sort();
-        trimmed_paths
-            .into_iter()
-            .rev()
-            .cycle()
-            .nth(next)
-            .map(|p| p.intervals)
-            .expect("should have path")
-            .into_iter()
-            .map(|it| it.into())
-            .collect()
     }
 }
 
-impl ChewingConversionEngine {
-    /// TODO: doc
-    pub fn new(dict: Rc<dyn Dictionary>) -> ChewingConversionEngine {
-        ChewingConversionEngine { dict }
+impl<P> ConversionEngine for ChewingConversionEngine<P>
+where
+    P: Deref,
+    P::Target: Dictionary,
+{
+    fn convert(&self, segment: &ChineseSequence) -> Result<Vec<Interval>, ConversionError> {
+        let intervals = self.convert_adjusted(segment.into(), None, |_| {})?;
+        debug_assert!(is_valid_tiling(&intervals, segment.syllables.len()));
+        Ok(intervals)
     }
 
-    fn find_best_phrase(
+    fn convert_next(
         &self,
-        start: usize,
-        syllables: &[Syllable],
-        selections: &[Interval],
-        breaks: &[Break],
-    ) -> Option<Rc<Phrase<'_>>> {
-        let end = start + syllables.len();
+        segment: &ChineseSequence,
+        next: usize,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let intervals = self.convert_next_cached(segment, next, &mut ConversionCache::new())?;
+        debug_assert!(is_valid_tiling(&intervals, segment.syllables.len()));
+        Ok(intervals)
+    }
+}
 
-        for br in breaks.iter() {
-            if br.0 > start && br.0 < end {
-                // There exists a break point that forbids connecting these
-                // syllables.
-                return None;
+/// Returns the highest sum of frequencies of the phrases in `freqs` that
+/// split `start..end` into at least two parts.
+fn best_split_freq(freqs: &HashMap<(usize, usize), u64>, start: usize, end: usize) -> u64 {
+    // best[i] is the highest frequency sum of phrases covering start..start+i,
+    // counting the whole span only when it is shorter than start..end.
+    let len = end - start;
+    let mut best: Vec<Option<u64>> = vec![None; len + 1];
+    best[0] = Some(0);
+    for to in 1..=len {
+        for from in 0..to {
+            if from == 0 && to == len {
+                continue;
             }
-        }
-
-        let mut max_freq = 0;
-        let mut best_phrase = None;
-        'next_phrase: for phrase in self.dict.lookup_phrase(syllables) {
-            // If there exists a user selected interval which is a
-            // sub-interval of this phrase but the substring is
-            // different then we can skip this phrase.
-            for selection in selections.iter() {
-                debug_assert!(!selection.phrase.is_empty());
-                if start <= selection.start && end >= selection.end {
-                    let offset = selection.start - start;
-                    let len = selection.end - selection.start;
-                    let substring: String =
-                        phrase.as_str().chars().skip(offset).take(len).collect();
-                    if substring != selection.phrase {
-                        continue 'next_phrase;
-                    }
+            if let (Some(prefix), Some(freq)) = (best[from], freqs.get(&(start + from, start + to)))
+            {
+                let sum = prefix.saturating_add(*freq);
+                if best[to].map_or(true, |best| sum > best) {
+                    best[to] = Some(sum);
                 }
             }
-
-            // If there are phrases that can satisfy all the constraints
-            // then pick the one with highest frequency.
-            if best_phrase.is_none() || phrase.freq() > max_freq {
-                max_freq = phrase.freq();
-                best_phrase = Some(Rc::new(phrase));
-            }
         }
+    }
+    best[len].unwrap_or(0)
+}
 
-        best_phrase
+/// Writes `intervals` to the front of `out` if they all fit.
+fn write_intervals(
+    out: &mut [Interval],
+    intervals: impl ExactSizeIterator<Item = Interval>,
+) -> Result<usize, ConversionError> {
+    let needed = intervals.len();
+    if needed > out.len() {
+        return Err(ConversionError::BufferTooSmall {
+            needed,
+            capacity: out.len(),
+        });
     }
-    fn find_intervals(&self, seq: &ChineseSequence) -> Vec<PossibleInterval<'_>> {
-        let mut intervals = vec![];
-        for begin in 0..seq.syllables.len() {
-            for end in begin..=seq.syllables.len() {
-                if let Some(phrase) = self.find_best_phrase(
-                    begin,
-                    &seq.syllables[begin..end],
-                    &seq.selections,
-                    &seq.breaks,
-                ) {
-                    intervals.push(PossibleInterval {
-                        start: begin,
-                        end,
-                        phrase,
-                    });
-                }
-            }
-        }
-        intervals
+    for (slot, interval) in out.iter_mut().zip(intervals) {
+        *slot = interval;
     }
-    /// Calculate the best path with dynamic programming.
-    ///
-    /// Assume P(x,y) is the highest score phrasing result from x to y. The
-    /// following is formula for P(x,y):
-    ///
-    /// P(x,y) = MAX( P(x,y-1)+P(y-1,y), P(x,y-2)+P(y-2,y), ... )
-    ///
-    /// While P(x,y-1) is stored in highest_score array, and P(y-1,y) is
-    /// interval end at y. In this formula, x is always 0.
-    ///
-    /// The format of highest_score array is described as following:
-    ///
-    /// highest_score[0] = P(0,0)
-    /// highest_score[1] = P(0,1)
-    /// ...
-    /// highest_score[y-1] = P(0,y-1)
-    fn find_best_path(&self, len: usize, mut intervals: Vec<PossibleInterval<'_>>) -> Vec<Interval> {
-        let mut highest_score = vec![PossiblePath::default(); len + 1];
-
-        // The interval shall be sorted by the increase order of end.
-        intervals.sort_by(|a, b| a.end.cmp(&b.end));
+    Ok(needed)
+}
 
-        for interval in intervals.into_iter() {
-            let start = interval.start;
-            let end = interval.end;
+/// A "did you mean" suggestion of
+/// [`suggest_corrections`][ChewingConversionEngine::suggest_corrections].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Correction {
+    /// Position of the first syllable of the span.
+    pub start: usize,
+    /// Position after the last syllable of the span.
+    pub end: usize,
+    /// The syllables of the span as typed.
+    pub original: Vec<Syllable>,
+    /// The syllables of the suggested phrase.
+    pub corrected: Vec<Syllable>,
+    /// The suggested phrase.
+    pub phrase: String,
+}
 
-            let mut candidate_path = highest_score[start].clone();
-            candidate_path.intervals.push(interval);
+/// The timings of [`benchmark`][ChewingConversionEngine::benchmark].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    /// How many times every sequence was converted.
+    pub iterations: usize,
+    /// The time spent converting all sequences in all iterations.
+    pub total: Duration,
+    /// The number of dictionary lookups of all conversions, if counted.
+    pub lookups: Option<usize>,
+    /// The measurements of each sequence, in the order of the input.
+    pub sequences: Vec<SequenceBench>,
+}
 
-            if highest_score[end].score() < candidate_path.score() {
-                highest_score[end] = candidate_path;
-            }
+impl BenchReport {
+    /// Returns the average time of a single conversion.
+    pub fn average(&self) -> Duration {
+        let count = self.iterations.saturating_mul(self.sequences.len());
+        match u32::try_from(count) {
+            Ok(0) => Duration::ZERO,
+            Ok(count) => self.total / count,
+            Err(_) => Duration::from_secs_f64(self.total.as_secs_f64() / count as f64),
         }
-
-        highest_score
-            .pop()
-            .expect("highest_score has at least one element")
-            .intervals
-            .into_iter()
-            .map(|interval| interval.into())
-            .collect()
     }
-
-    fn find_all_paths<'g>(
-        &'g self,
-        graph: &mut Graph<'g>,
-        sequence: &ChineseSequence,
-        start: usize,
-        target: usize,
-        prefix: Option<PossiblePath<'g>>,
-    ) -> Vec<PossiblePath<'g>> {
-        if start == target {
-            return vec![prefix.expect("should have prefix")];
-        }
-        let mut result = vec![];
-        for end in start..=target {
-            let entry = graph.entry((start, end));
-            if let Some(phrase) = entry.or_insert_with(|| {
-                self.find_best_phrase(
-                    start,
-                    &sequence.syllables[start..end],
-                    &sequence.selections,
-                    &sequence.breaks,
-                )
-            }) {
-                let mut prefix = prefix.clone().unwrap_or_default();
-                prefix.intervals.push(PossibleInterval {
-                    start,
-                    end,
-                    phrase: phrase.clone(),
-                });
-                result.append(&mut self.find_all_paths(graph, sequence, end, target, Some(prefix)));
-            }
-        }
-        result
+    /// Returns the number of syllables converted per second.
+    pub fn syllables_per_second(&self) -> f64 {
+        let syllables: usize = self.sequences.iter().map(|seq| seq.syllables).sum();
+        throughput(syllables, self.iterations, self.total)
     }
+}
 
-    /// Trim some paths that were part of other paths
-    ///
-    /// Ported from original C implementation, but the original algorithm seems wrong.
-    fn trim_paths<'a>(&self, paths: Vec<PossiblePath<'a>>) -> Vec<PossiblePath<'a>> {
-        let mut trimmed_paths: Vec<PossiblePath<'_>> = vec![];
-        for candidate in paths.into_iter() {
-            trace!("Trim check {}", candidate);
-            let mut drop_candidate = false;
-            let mut keeper = vec![];
-            for p in trimmed_paths.into_iter() {
-                if drop_candidate || p.contains(&candidate) {
-                    drop_candidate = true;
-                    trace!("  Keep {}", p);
-                    keeper.push(p);
-                    continue;
-                }
-                if candidate.contains(&p) {
-                    trace!("  Drop {}", p);
-                    continue;
-                }
-                trace!("  Keep {}", p);
-                keeper.push(p);
-            }
-            if !drop_candidate {
-                trace!("  Keep {}", candidate);
-                keeper.push(candidate);
-            }
-            trimmed_paths = keeper;
-        }
-        trimmed_paths
+/// The timings of one sequence in a [`BenchReport`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceBench {
+    /// The number of syllables of the sequence.
+    pub syllables: usize,
+    /// The time spent converting the sequence in all iterations.
+    pub total: Duration,
+    /// The number of dictionary lookups of all iterations, if counted.
+    pub lookups: Option<usize>,
+}
+
+impl SequenceBench {
+    /// Returns the number of syllables converted per second.
+    pub fn syllables_per_second(&self, iterations: usize) -> f64 {
+        throughput(self.syllables, iterations, self.total)
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-struct PossibleInterval<'a> {
-    start: usize,
-    end: usize,
-    phrase: Rc<Phrase<'a>>,
+/// The phrase the user is probably typing, returned by
+/// [`convert_with_preview`][ChewingConversionEngine::convert_with_preview].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preview {
+    /// Position of the first syllable the phrase starts at. The phrase
+    /// covers the syllables from there to the end and continues beyond.
+    pub start: usize,
+    /// The predicted phrase.
+    pub phrase: String,
 }
 
-impl PossibleInterval<'_> {
-    fn contains(&self, other: &PossibleInterval<'_>) -> bool {
-        self.start <= other.start && self.end >= other.end
+/// The results of [`convert`][ConversionEngine::convert] and
+/// [`convert_next`][ConversionEngine::convert_next] of the same sequence,
+/// returned by [`reconcile`][ChewingConversionEngine::reconcile].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconciliation {
+    /// The phrasing chosen by the dynamic programming of `convert`.
+    pub best: Vec<Interval>,
+    /// The first candidate of `convert_next`.
+    pub next: Vec<Interval>,
+    /// The scores of `best` and `next`, in this order, when they differ.
+    pub scores: Option<(PhrasingScore, PhrasingScore)>,
+}
+
+impl Reconciliation {
+    /// Returns whether both methods chose the same phrasing.
+    pub fn is_consistent(&self) -> bool {
+        self.best == self.next
     }
-    fn len(&self) -> usize {
-        self.end - self.start
+}
+
+/// The scores of a phrasing in a [`Reconciliation`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhrasingScore {
+    /// The rule based score the dynamic programming of `convert` compares,
+    /// as returned by
+    /// [`score_segmentation`][ChewingConversionEngine::score_segmentation].
+    pub dp: Option<i64>,
+    /// The score `convert_next` ranks whole paths with, the rule based score
+    /// plus the weighted [language model][ChewingConversionEngine::with_language_model]
+    /// score.
+    pub path: Option<f64>,
+}
+
+/// Returns the syllables per second of converting `syllables` syllables
+/// `iterations` times in `total`.
+fn throughput(syllables: usize, iterations: usize, total: Duration) -> f64 {
+    let secs = total.as_secs_f64();
+    if secs == 0.0 {
+        return 0.0;
     }
+    syllables as f64 * iterations as f64 / secs
 }
 
-impl From<PossibleInterval<'_>> for Interval {
-    fn from(value: PossibleInterval<'_>) -> Self {
-        Interval {
-            start: value.start,
-            end: value.end,
-            phrase: value.phrase.to_string(),
+/// A reusable cache of the best phrase found for each span of a sequence.
+///
+/// Holding a `ConversionCache` across consecutive
+/// [`convert_next_cached`][ChewingConversionEngine::convert_next_cached] calls
+/// avoids repeating the dictionary lookups when the user cycles through the
+/// candidates of the same sequence. The cache remembers the sequence it was
+/// filled for and clears itself automatically when used with a different
+/// sequence.
+#[derive(Debug, Default)]
+pub struct ConversionCache {
+    key: Option<Rc<CacheKey>>,
+    graph: Rc<Graph>,
+}
+
+/// The sequence a [`ConversionCache`] was filled for.
+#[derive(Debug, PartialEq)]
+struct CacheKey {
+    syllables: Vec<Syllable>,
+    selections: Vec<Interval>,
+    breaks: Vec<Break>,
+}
+
+impl CacheKey {
+    fn new(sequence: &ChineseSequence) -> CacheKey {
+        CacheKey {
+            syllables: sequence.syllables.clone(),
+            selections: sequence.selections.clone(),
+            breaks: sequence.breaks.clone(),
         }
     }
+    fn matches(&self, sequence: &ChineseSequence) -> bool {
+        self.syllables == sequence.syllables
+            && self.selections == sequence.selections
+            && self.breaks == sequence.breaks
+    }
 }
 
-#[derive(Default, Clone, Eq)]
-struct PossiblePath<'a> {
-    intervals: Vec<PossibleInterval<'a>>,
+/// A saved state of a [`ConversionCache`].
+///
+/// Snapshots share the cached phrases with the cache they were taken from,
+/// so taking one is cheap. The cache copies its phrases only when it is
+/// modified after a snapshot.
+#[derive(Debug, Clone)]
+pub struct CacheSnapshot {
+    key: Option<Rc<CacheKey>>,
+    graph: Rc<Graph>,
 }
 
-impl Debug for PossiblePath<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("PossiblePath")
-            .field("score()", &self.score())
-            .field("intervals", &self.intervals)
-            .finish()
+impl ConversionCache {
+    /// Creates a new empty `ConversionCache`.
+    pub fn new() -> ConversionCache {
+        ConversionCache::default()
+    }
+    /// Removes all cached phrases.
+    pub fn clear(&mut self) {
+        self.key = None;
+        self.graph = Rc::default();
+    }
+    /// Saves the current state of the cache.
+    ///
+    /// Use [`restore`][ConversionCache::restore] to roll back to this state,
+    /// for example to undo an edit, without repeating the lookups.
+    pub fn checkpoint(&self) -> CacheSnapshot {
+        CacheSnapshot {
+            key: self.key.clone(),
+            graph: Rc::clone(&self.graph),
+        }
+    }
+    /// Restores the state saved by [`checkpoint`][ConversionCache::checkpoint].
+    pub fn restore(&mut self, snapshot: CacheSnapshot) {
+        self.key = snapshot.key;
+        self.graph = snapshot.graph;
+    }
+    /// Returns whether the cache holds no phrases.
+    pub fn is_empty(&self) -> bool {
+        self.graph.is_empty()
+    }
+    fn prepare(&mut self, sequence: &ChineseSequence) -> &mut Graph {
+        if !self.is_filled_for(sequence) {
+            self.clear();
+            self.key = Some(Rc::new(CacheKey::new(sequence)));
+        }
+        Rc::make_mut(&mut self.graph)
+    }
+    /// Keeps the cached phrases but marks them as filled for `sequence`.
+    fn rekey(&mut self, sequence: &ChineseSequence) {
+        if !self.is_filled_for(sequence) {
+            self.key = Some(Rc::new(CacheKey::new(sequence)));
+        }
+    }
+    fn is_filled_for(&self, sequence: &ChineseSequence) -> bool {
+        self.key.as_ref().map_or(false, |key| key.matches(sequence))
     }
 }
 
-impl PossiblePath<'_> {
-    fn score(&self) -> i32 {
-        let mut score = 0;
-        score += 1000 * self.rule_largest_sum();
-        score += 1000 * self.rule_largest_avgwordlen();
-        score += 100 * self.rule_smallest_lenvariance();
-        score += self.rule_largest_freqsum();
-        score
+impl SyncChewingConversionEngine {
+    /// Creates a new engine sharing `dict` between threads.
+    pub fn new_sync(dict: Arc<dyn Dictionary + Send + Sync>) -> SyncChewingConversionEngine {
+        ChewingConversionEngine::new(dict)
     }
+}
 
-    /// Copied from IsRecContain to trim some paths
-    fn contains(&self, other: &Self) -> bool {
-        let mut big = 0;
-        for sml in 0..other.intervals.len() {
-            loop {
-                if big < self.intervals.len()
-                    && self.intervals[big].start < other.intervals[sml].end
-                {
-                    if self.intervals[big].contains(&other.intervals[sml]) {
-                        break;
-                    }
+impl<P> ChewingConversionEngine<P>
+where
+    P: DerefMut,
+    P::Target: Dictionary,
+{
+    /// Converts the sequence and learns the result as accepted by the user.
+    ///
+    /// Every phrase of the result, except the unknown syllables, is updated in
+    /// the [mutable dictionary][Dictionary::as_mut_dict] with a user frequency
+    /// above its homophones, so later conversions of the same syllables prefer
+    /// the committed phrases, including the ones the user selected.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence cannot be converted, if the
+    /// dictionary cannot be updated, or if it is read-only.
+    pub fn convert_and_commit(
+        &mut self,
+        segment: &ChineseSequence,
+    ) -> Result<Vec<Interval>, CommitError> {
+        let intervals = self.convert(segment)?;
+        for interval in intervals.iter().filter(|interval| !interval.unknown) {
+            let syllables = match segment.syllables_for(interval) {
+                Some(syllables) => syllables,
+                None => continue,
+            };
+            // Aliased output is committed as the dictionary phrase it came from.
+            let selected = self
+                .find_output_phrase(
+                    syllables,
+                    interval.end == segment.syllables.len(),
+                    &interval.phrase,
+                )
+                .map_or_else(
+                    || interval.phrase.clone(),
+                    |phrase| phrase.as_str().to_owned(),
+                );
+            let mut phrase = Phrase::new(selected.as_str(), 0);
+            let mut max_freq = 0;
+            let mut time = 0;
+            for candidate in self.dict.lookup_phrase(syllables) {
+                time = time.max(candidate.last_used().map_or(0, |it| it.saturating_add(1)));
+                if candidate.as_str() == selected {
+                    phrase = candidate.into_owned();
                 } else {
-                    return false;
+                    max_freq = max_freq.max(candidate.freq().saturating_add(1));
                 }
-                big += 1;
             }
+            let user_freq = phrase.freq().max(max_freq);
+            self.dict
+                .as_mut_dict()
+                .ok_or(CommitError::ReadOnly)?
+                .update(syllables, phrase, user_freq, time)?;
         }
-        true
+        self.max_phrase_len = self.dict.max_phrase_len().max(1);
+        Ok(intervals)
     }
+}
 
-    fn rule_largest_sum(&self) -> i32 {
-        let mut score = 0;
-        for interval in &self.intervals {
-            score += interval.end - interval.start;
+impl<P> ChewingConversionEngine<P>
+where
+    P: Deref,
+    P::Target: Dictionary,
+{
+    /// TODO: doc
+    pub fn new(dict: P) -> Self {
+        ChewingConversionEngine {
+            // Single syllables are always looked up so the fallback phrase
+            // can be used.
+            max_phrase_len: dict.max_phrase_len().max(1),
+            dict,
+            config: ConversionConfig::default(),
+            phrase_cost: None,
+            single_char_penalty: None,
+            user_bias: UserBias::default(),
+            bigram: Bigram::default(),
+            anti_bigram: HashSet::new(),
+            blacklist: HashSet::new(),
+            language_model: None,
+            aliases: AliasMap::default(),
+            overlay: FrequencyOverlay::default(),
+            categories: HashMap::new(),
+            fallback_renderer: None,
+            homophone_memory: HomophoneMemory::default(),
+            length_index: None,
+            shared_cache: None,
+            atomic_phrases: HashMap::new(),
         }
-        score as i32
     }
 
-    fn rule_largest_avgwordlen(&self) -> i32 {
-        if self.intervals.is_empty() {
-            return 0;
+    /// Same as [`new`][Self::new] but validates the phrases of the
+    /// dictionary during conversion.
+    ///
+    /// A dictionary phrase must have one character per syllable. The normal
+    /// engine skips inconsistent phrases, and asserts in debug builds, while
+    /// this engine makes [`convert`][ConversionEngine::convert] return
+    /// [`ConversionError::InconsistentPhrase`]. Validation looks up every
+    /// span of the sequence once more, so it is meant for testing
+    /// dictionaries.
+    pub fn new_checked(dict: P) -> Self {
+        ChewingConversionEngine {
+            config: ConversionConfig {
+                checked: true,
+                ..ConversionConfig::default()
+            },
+            ..Self::new(dict)
         }
-        // Constant factor 6=1*2*3, to keep value as integer
-        6 * self.rule_largest_sum()
-            / i32::try_from(self.intervals.len()).expect("number of intervals should be small")
     }
 
-    fn rule_smallest_lenvariance(&self) -> i32 {
-        let len = self.intervals.len();
-        let mut score = 0;
-        // kcwu: heuristic? why variance no square function?
-        for i in 0..len {
-            for j in i + 1..len {
-                let interval_1 = &self.intervals[i];
-                let interval_2 = &self.intervals[j];
-                score += interval_1.len().abs_diff(interval_2.len());
-            }
-        }
-        i32::try_from(score).expect("score should fit in i32").neg()
+    /// Returns the current settings of the engine.
+    pub fn config(&self) -> &ConversionConfig {
+        &self.config
     }
 
-    fn rule_largest_freqsum(&self) -> i32 {
-        let mut score = 0;
-        for interval in &self.intervals {
-            let reduction_factor = if interval.len() == 1 { 512 } else { 1 };
-            score += interval.phrase.freq() / reduction_factor;
-        }
-        i32::try_from(score).expect("score should fit in i32")
+    /// Replaces every setting of the engine with `config`.
+    ///
+    /// Settings outside [`ConversionConfig`], like the user bias, are kept.
+    pub fn with_config(mut self, config: ConversionConfig) -> Self {
+        self.config = config;
+        self
     }
-}
 
-impl PartialEq for PossiblePath<'_> {
-    fn eq(&self, other: &Self) -> bool {
-        self.score() == other.score()
+    /// Sets whether user selections are pinned into the result.
+    ///
+    /// By default a selection only rejects phrases that contradict it, so a
+    /// longer phrase that contains the selected characters may still replace
+    /// the selected interval. When pinned, every selection appears as its own
+    /// interval in the result of [`convert`][ConversionEngine::convert]
+    /// regardless of the score.
+    pub fn with_pin_selections(mut self, pin_selections: bool) -> Self {
+        self.config.pin_selections = pin_selections;
+        self
     }
-}
 
-impl PartialOrd for PossiblePath<'_> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.score().partial_cmp(&other.score())
+    /// Sets the rule used to score phrase frequencies.
+    pub fn with_freq_rule(mut self, freq_rule: FreqRule) -> Self {
+        self.config.freq_rule = freq_rule;
+        self
     }
-}
 
-impl Ord for PossiblePath<'_> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.score().cmp(&other.score())
+    /// Sets how the rules are combined into the score of a phrasing.
+    ///
+    /// Breaks and selections constrain the phrasings the same way in every
+    /// mode.
+    pub fn with_score_mode(mut self, score_mode: ScoreMode) -> Self {
+        self.config.score_mode = score_mode;
+        self
     }
-}
 
-impl Display for PossiblePath<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "#PossiblePath({}", self.score())?;
-        for interval in &self.intervals {
-            write!(
-                f,
-                " ({} {} '{})",
-                interval.start, interval.end, interval.phrase
-            )?;
+    /// Sets a function that adds domain specific knowledge to the score.
+    ///
+    /// The function receives each candidate phrase with its `start` and
+    /// `end` position and returns a value added to the score of any phrasing
+    /// using it. A negative value penalizes the phrase.
+    pub fn with_phrase_cost<F>(mut self, phrase_cost: F) -> Self
+    where
+        F: Fn(&Phrase<'_>, usize, usize) -> i32 + Send + Sync + 'static,
+    {
+        self.phrase_cost = Some(Box::new(phrase_cost));
+        self
+    }
+
+    /// Sets a function that decides how much single character phrases are
+    /// penalized depending on their position.
+    ///
+    /// The function receives the `start` and `end` position of the phrase
+    /// and the length of the sequence, and returns the divisor applied to
+    /// the phrase frequency under [`FreqRule::Raw`]. A single character is
+    /// often correct at a sentence boundary, so a lighter penalty there can
+    /// help. The default divides by [`DEFAULT_SINGLE_CHAR_PENALTY`]
+    /// everywhere.
+    ///
+    /// The penalty is not applied once
+    /// [length weights][Self::with_length_weights] are set, since the weight
+    /// of single syllable phrases takes its place.
+    pub fn with_single_char_penalty<F>(mut self, single_char_penalty: F) -> Self
+    where
+        F: Fn(usize, usize, usize) -> u32 + Send + Sync + 'static,
+    {
+        self.single_char_penalty = Some(Box::new(single_char_penalty));
+        self
+    }
+
+    /// Sets the preference of the user blended into the phrase frequencies.
+    pub fn with_user_bias(mut self, user_bias: UserBias) -> Self {
+        self.user_bias = user_bias;
+        self
+    }
+
+    /// Sets the bonuses added when two phrases are adjacent in a phrasing.
+    ///
+    /// With a non-empty `bigram` the dynamic programming keeps the best path
+    /// ending with every interval instead of only the best path ending at
+    /// every position, so the bonus of the previous phrase can be applied.
+    pub fn with_bigram(mut self, bigram: Bigram) -> Self {
+        self.bigram = bigram;
+        for (prev, next) in &self.anti_bigram {
+            self.bigram
+                .insert(prev.clone(), next.clone(), ANTI_BIGRAM_PENALTY);
         }
-        write!(f, ")")?;
+        self
+    }
+
+    /// Sets the pairs of phrases that should never be adjacent in a
+    /// phrasing.
+    ///
+    /// A phrasing placing the second phrase of a pair right after the first
+    /// one gets a penalty outweighing any other rule, so it is only chosen
+    /// when every phrasing contains a forbidden pair. The pairs take
+    /// precedence over the bonuses of [`with_bigram`][Self::with_bigram].
+    pub fn with_anti_bigram<I, S>(mut self, pairs: I) -> Self
+    where
+        I: IntoIterator<Item = (S, S)>,
+        S: Into<String>,
+    {
+        for (prev, next) in pairs {
+            let (prev, next) = (prev.into(), next.into());
+            self.bigram
+                .insert(prev.clone(), next.clone(), ANTI_BIGRAM_PENALTY);
+            self.anti_bigram.insert((prev, next));
+        }
+        self
+    }
+
+    /// Sets the phrases that are never selected, regardless of frequency.
+    ///
+    /// Other phrases or single characters are used for the syllables of a
+    /// blacklisted phrase instead.
+    pub fn with_blacklist(mut self, blacklist: HashSet<String>) -> Self {
+        self.blacklist = blacklist;
+        self
+    }
+
+    /// Sets whether to break ties by the width consistency of the phrases.
+    ///
+    /// When enabled and two phrasings have the same score, the one with
+    /// fewer boundaries between a half-width (ASCII) and a full-width
+    /// character wins, e.g. `１號|樓` is preferred over `1|號樓`.
+    pub fn with_prefer_consistent_width(mut self, prefer_consistent_width: bool) -> Self {
+        self.config.prefer_consistent_width = prefer_consistent_width;
+        self
+    }
+
+    /// Sets whether to break ties by preferring the earliest boundary.
+    ///
+    /// When enabled and two phrasings have the same score, the one whose
+    /// first boundary is leftmost wins, then the next boundary is compared
+    /// and so on, e.g. `國|民大會|代表` is preferred over `國民|大|會代表`.
+    /// This lets the user lock the left part of the sentence sooner. It is
+    /// compared after width consistency and before the
+    /// [seed][Self::with_seed].
+    pub fn with_prefer_early_boundary(mut self, prefer_early_boundary: bool) -> Self {
+        self.config.prefer_early_boundary = prefer_early_boundary;
+        self
+    }
+
+    /// Sets the seed used to break ties between phrasings.
+    ///
+    /// Two phrasings with the same score are ordered by a hash of the
+    /// phrasings keyed with `seed`, which varies the choice between seeds but
+    /// always gives the same result for the same seed. The default seed is
+    /// [`DEFAULT_SEED`], so results are reproducible unless a seed is set.
+    /// Width consistency, when enabled by
+    /// [`with_prefer_consistent_width`][Self::with_prefer_consistent_width],
+    /// and early boundaries, when enabled by
+    /// [`with_prefer_early_boundary`][Self::with_prefer_early_boundary], are
+    /// compared before the seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.config.seed = seed;
+        self
+    }
+
+    /// Sets how syllables without any phrase are shown.
+    ///
+    /// The placeholder intervals of such syllables use the text of the
+    /// renderer and are marked as unknown. The default is
+    /// [`ZhuyinFallback`].
+    pub fn with_fallback_renderer<R>(mut self, renderer: R) -> Self
+    where
+        R: FallbackRenderer + Send + Sync + 'static,
+    {
+        self.fallback_renderer = Some(Box::new(renderer));
+        self
+    }
+
+    /// Sets whether the last syllable of the sequence matches any tone when
+    /// it has no tone yet.
+    ///
+    /// While the user is still composing, the last syllable often lacks the
+    /// tone. Enabling this gives a reasonable preview by matching that
+    /// syllable with every tone. The other syllables still match exactly.
+    pub fn with_trailing_toneless(mut self, trailing_toneless: bool) -> Self {
+        self.config.trailing_toneless = trailing_toneless;
+        self
+    }
+
+    /// Sets a language model that reranks the candidate phrasings.
+    ///
+    /// Each complete candidate phrasing is ranked by its rule based score
+    /// plus `weight` times the score returned by `language_model`. The model
+    /// sees whole phrasings and can't be split per interval, so only the
+    /// candidates cycled by [`ConversionEngine::convert_next`] and returned
+    /// by [`convert_candidates`][Self::convert_candidates] are reranked.
+    /// [`ConversionEngine::convert`] ignores the model.
+    pub fn with_language_model<F>(mut self, language_model: F, weight: f64) -> Self
+    where
+        F: Fn(&[Interval]) -> f64 + Send + Sync + 'static,
+    {
+        self.language_model = Some(Box::new(language_model));
+        self.config.language_model_weight = weight;
+        self
+    }
+
+    /// Sets the canonical forms shown for phrases with variants.
+    ///
+    /// Phrases are still scored by their dictionary form, only the phrases
+    /// of the resulting intervals are rewritten.
+    pub fn with_aliases(mut self, aliases: AliasMap) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// Sets the lowest dictionary frequency of the phrases that can be
+    /// chosen.
+    ///
+    /// Rarer phrases are skipped so their syllables are covered by shorter,
+    /// better known phrases instead. A syllable only covered by rare single
+    /// characters still uses the most frequent of them.
+    pub fn with_min_freq(mut self, min_freq: u32) -> Self {
+        self.config.min_freq = min_freq;
+        self
+    }
+
+    /// Sets the highest phrase frequency counted in the score of a phrasing.
+    ///
+    /// Without a cap an outlier frequency of a noisy dictionary outweighs the
+    /// other rules and the phrase swallows its neighbors. The cap only
+    /// applies to the score, homophones are still chosen by their full
+    /// frequency.
+    pub fn with_freq_cap(mut self, freq_cap: u32) -> Self {
+        self.config.freq_cap = freq_cap;
+        self
+    }
+
+    /// Sets the lowest frequency of every interval when scoring a phrasing.
+    ///
+    /// Placeholder intervals of unknown syllables and rare phrases often
+    /// count as frequency 0, so phrasings in sparse parts of the dictionary
+    /// tie and the winner depends on the search order. Smoothing raises the
+    /// frequency of each phrase to at least `smoothing` before the
+    /// [frequency rule][Self::with_freq_rule] and the single character
+    /// penalty apply, so phrases above it keep their frequency.
+    pub fn with_freq_smoothing(mut self, smoothing: u32) -> Self {
+        self.config.freq_smoothing = smoothing;
+        self
+    }
+
+    /// Sets the frequency of the placeholder phrases of unknown syllables.
+    ///
+    /// Syllables without any single character phrase in the dictionary are
+    /// converted to a placeholder of frequency 0 by default, so a
+    /// low-frequency phrase containing the syllable nearly always beats
+    /// leaving it unknown. A nonzero baseline lets the placeholder compete
+    /// with such phrases. Unlike [`with_freq_smoothing`][Self::with_freq_smoothing]
+    /// this only affects the placeholders, not the dictionary phrases. The
+    /// placeholder is a single character, so the
+    /// [single character penalty][Self::with_single_char_penalty] applies.
+    pub fn with_unknown_syllable_freq(mut self, freq: u32) -> Self {
+        self.config.unknown_syllable_freq = freq;
+        self
+    }
+
+    /// Only allows a phrase that is `ratio` times more frequent than its
+    /// parts.
+    ///
+    /// The parts of a phrase are the best way to split its syllables into
+    /// shorter phrases, scored by the sum of their frequencies. A phrase
+    /// whose frequency is below `ratio` times that sum is not chosen, so a
+    /// long phrase that is only marginally more frequent doesn't override
+    /// clearer short ones. Phrases that cannot be split into any known
+    /// shorter phrase and selected phrases are always allowed.
+    pub fn with_merge_gain_ratio(mut self, ratio: f64) -> Self {
+        self.config.merge_gain_ratio = Some(ratio);
+        self
+    }
+
+    /// Sets the weight of the phrase frequency for each phrase length.
+    ///
+    /// Under [`FreqRule::Raw`] the frequency of a phrase of `n` syllables is
+    /// multiplied by `weights[n - 1]` before it is added to the score.
+    /// Phrases longer than the weights use the last weight. This replaces the
+    /// [single character penalty][Self::with_single_char_penalty], which is
+    /// ignored while weights are set, even if it was set after them; the
+    /// default behavior is the same as the weights `[1.0 / 512.0, 1.0]`.
+    pub fn with_length_weights(mut self, weights: Vec<f64>) -> Self {
+        self.config.length_weights = Some(weights.into());
+        self
+    }
+
+    /// Sets whether phrasings with similar phrase lengths are preferred.
+    ///
+    /// The rule is enabled by default. Text mixing long technical terms with
+    /// short function words often segments better without it.
+    pub fn with_length_variance(mut self, length_variance: bool) -> Self {
+        self.config.length_variance = length_variance;
+        self
+    }
+
+    /// Sets the locale specific frequencies used instead of the dictionary
+    /// frequencies.
+    ///
+    /// The dictionary is not changed, phrases missing from the overlay keep
+    /// their dictionary frequency.
+    pub fn with_overlay(mut self, overlay: FrequencyOverlay) -> Self {
+        self.overlay = overlay;
+        self
+    }
+
+    /// Sets the homophone choices remembered in the session.
+    ///
+    /// A span with exactly the remembered syllables uses the remembered
+    /// phrase as if it were selected, when the dictionary has it and it fits
+    /// the selections and breaks.
+    pub fn with_homophone_memory(mut self, homophone_memory: HomophoneMemory) -> Self {
+        self.homophone_memory = homophone_memory;
+        self
+    }
+
+    /// Indexes the phrase lengths of the dictionary by their first syllable.
+    ///
+    /// With the index, spans of several syllables are only looked up when
+    /// the dictionary has a phrase of that length starting with the same
+    /// syllable, which saves most lookups on sparse dictionaries. Building
+    /// the index reads every entry of the dictionary once, so it goes stale
+    /// if the dictionary changes afterwards.
+    pub fn with_length_index(mut self) -> Self {
+        let mut index: HashMap<Syllable, BTreeSet<usize>> = HashMap::new();
+        for (syllables, _) in self.dict.entries() {
+            if let Some(first) = syllables.first() {
+                index.entry(*first).or_default().insert(syllables.len());
+            }
+        }
+        self.length_index = Some(index);
+        self
+    }
+
+    /// Shares the dictionary lookups of the engine through `cache`.
+    ///
+    /// Every engine given the same cache answers the lookups of spans
+    /// already cached by any of them without asking the dictionary. All of
+    /// them must use the same dictionary.
+    pub fn with_shared_cache(mut self, cache: Arc<SpanCache>) -> Self {
+        self.shared_cache = Some(cache);
+        self
+    }
+
+    /// Registers `phrase` as an atomic phrase of `syllables`.
+    ///
+    /// Wherever the syllables appear in a sequence, they are converted as
+    /// one interval with `phrase`, whatever the dictionary and the scores
+    /// say. This is meant for proper nouns and brand names that must never
+    /// be split, and is stronger than any frequency boost. Matches crossing
+    /// a break or overlapping a selection are ignored, and when matches
+    /// overlap the leftmost, then longest, one wins. The phrase should have
+    /// one character per syllable.
+    ///
+    /// Atomic phrases constrain the conversions built on the interval
+    /// lattice, like [`ConversionEngine::convert`], but not the candidates
+    /// cycled by [`ConversionEngine::convert_next`].
+    pub fn with_atomic_phrase(mut self, syllables: &[Syllable], phrase: impl Into<String>) -> Self {
+        if !syllables.is_empty() {
+            self.atomic_phrases
+                .insert(syllables.to_vec(), phrase.into());
+        }
+        self
+    }
+
+    /// Returns the homophone choices remembered in the session, to remember
+    /// what the user chooses while the session goes on.
+    pub fn homophone_memory_mut(&mut self) -> &mut HomophoneMemory {
+        &mut self.homophone_memory
+    }
+
+    /// Installs a chosen segmentation on the sequence so later conversions
+    /// reproduce it.
+    ///
+    /// The phrases of `intervals` are added as selections, replacing the
+    /// selections they overlap. Use this after the user picks a candidate of
+    /// [`convert_next`][ConversionEngine::convert_next] for the whole
+    /// sentence. The segmentation is reproduced exactly when the engine pins
+    /// selections, see [`with_pin_selections`][Self::with_pin_selections];
+    /// otherwise the selections only keep the chosen characters and the
+    /// boundaries may still move.
+    pub fn pin_segmentation(&self, segment: &mut ChineseSequence, intervals: &[Interval]) {
+        for selection in super::segmentation_to_selections(intervals) {
+            segment.add_selection(selection);
+        }
+    }
+
+    /// Selects the phrase `text` for the syllables starting at `start`.
+    ///
+    /// The span covers one syllable per character of `text` and is only
+    /// selected if the dictionary has `text` as a phrase for its syllables.
+    /// The selection replaces the selections it overlaps, like
+    /// [`ChineseSequence::add_selection`]. This lets UI actions that only
+    /// know the wanted output, for example pasting over a region, become
+    /// selections.
+    pub fn pin_text(
+        &self,
+        segment: &mut ChineseSequence,
+        start: usize,
+        text: &str,
+    ) -> Result<(), PinTextError> {
+        let end = start + text.chars().count();
+        let found = end > start
+            && segment
+                .syllables
+                .get(start..end)
+                .map_or(false, |syllables| {
+                    self.lookup_phrase(syllables, end == segment.syllables.len())
+                        .any(|phrase| phrase.as_str() == text)
+                });
+        if !found {
+            return Err(PinTextError {
+                start,
+                text: text.to_string(),
+            });
+        }
+        segment.add_selection(Interval {
+            start,
+            end,
+            phrase: text.to_string(),
+            unknown: false,
+        });
         Ok(())
     }
-}
-
-type Graph<'a> = HashMap<(usize, usize), Option<Rc<Phrase<'a>>>>;
-
-#[cfg(test)]
-mod tests {
-    use std::{collections::HashMap, rc::Rc};
-
-    use crate::{
-        conversion::{Break, ChineseSequence, ConversionEngine, Interval},
-        dictionary::{Dictionary, Phrase},
-        syl,
-        zhuyin::Bopomofo::*,
-    };
 
-    use super::{ChewingConversionEngine, PossibleInterval, PossiblePath};
+    /// Adds `bonus` to the score of the phrases tagged with `category`.
+    ///
+    /// A negative bonus suppresses the category. Phrases without a category,
+    /// or with a category that has no bonus, are scored as usual.
+    pub fn with_category_bonus(mut self, category: impl Into<String>, bonus: i32) -> Self {
+        self.categories.insert(category.into(), bonus);
+        self
+    }
+
+    /// Same as [`ConversionEngine::convert_next`] but reuses the phrases
+    /// stored in `cache` from previous calls with the same sequence.
+    pub fn convert_next_cached(
+        &self,
+        segment: &ChineseSequence,
+        next: usize,
+        cache: &mut ConversionCache,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        if segment.syllables.is_empty() {
+            return Ok(vec![]);
+        }
+        check_len(segment.syllables.len())?;
+        let graph = cache.prepare(segment);
+        let path = nth_candidate(self.find_candidate_paths(graph, segment), next)?;
+        Ok(path
+            .intervals
+            .into_iter()
+            .map(|it| self.to_interval(it))
+            .collect())
+    }
+
+    /// Converts the sequence after the user selected `changed`, reusing the
+    /// phrases cached for the sequence before the selection.
+    ///
+    /// `segment` is the sequence `cache` was last used with. The selection
+    /// replaces any selection it overlaps and only the spans overlapping
+    /// those selections are looked up again. The result is the same as
+    /// [`ConversionEngine::convert`] on the sequence with the selection, and
+    /// `cache` is left ready for that sequence.
+    pub fn convert_after_selection(
+        &self,
+        segment: &ChineseSequence,
+        changed: &Interval,
+        cache: &mut ConversionCache,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let len = segment.syllables.len();
+        if changed.start >= changed.end || changed.end > len {
+            return self.convert(segment);
+        }
+        check_len(len)?;
+        let overlaps =
+            |start: usize, end: usize, other: &Interval| start < other.end && end > other.start;
+        let (replaced, mut selections): (Vec<_>, Vec<_>) = segment
+            .selections
+            .iter()
+            .cloned()
+            .partition(|selection| overlaps(selection.start, selection.end, changed));
+        selections.push(changed.clone());
+        let sequence = ChineseSequence {
+            syllables: segment.syllables.clone(),
+            selections,
+            breaks: segment.breaks.clone(),
+        };
+
+        let graph = cache.prepare(segment);
+        graph.retain(|&(start, end), _| {
+            !overlaps(start, end, changed)
+                && !replaced
+                    .iter()
+                    .any(|selection| overlaps(start, end, selection))
+        });
+        let max_len = self.max_phrase_len();
+        let mut intervals = vec![];
+        for begin in 0..len {
+            for end in begin + 1..=len.min(begin + max_len) {
+                let entry = graph.entry((begin, end)).or_insert_with(|| {
+                    self.find_best_phrase(
+                        begin,
+                        &sequence.syllables[begin..end],
+                        &sequence.selections,
+                        &sequence.breaks,
+                        end == len,
+                    )
+                    .map(|(phrase, unknown)| {
+                        (Rc::new(phrase.as_ref().clone().into_owned()), unknown)
+                    })
+                });
+                if let Some((phrase, unknown)) = entry {
+                    intervals.push(PossibleInterval {
+                        start: begin,
+                        end,
+                        bonus: self.phrase_cost(phrase, begin, end),
+                        freq_divisor: self.freq_divisor(begin, end, len),
+                        unknown: *unknown,
+                        phrase: phrase.clone(),
+                    });
+                }
+            }
+        }
+        cache.rekey(&sequence);
+        self.finish_intervals(SequenceView::from(&sequence), &mut intervals);
+        self.find_best_path(len, intervals)
+    }
+
+    /// Converts the sequence choosing the phrasing from the right end.
+    ///
+    /// The best path is searched from the last syllable backward, so when
+    /// phrasings tie and
+    /// [`with_prefer_early_boundary`][Self::with_prefer_early_boundary] is
+    /// enabled, the one whose boundaries are closest to the right end of the
+    /// sequence wins. This suits editors that insert text before a stable
+    /// context. Breaks and
+    /// selections use the same forward positions as
+    /// [`ConversionEngine::convert`], as do the returned intervals.
+    pub fn convert_reverse(
+        &self,
+        segment: &ChineseSequence,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        if segment.syllables.is_empty() {
+            return Ok(vec![]);
+        }
+        let len = segment.syllables.len();
+        check_len(len)?;
+        // Lookups need the syllables in reading order, so the intervals are
+        // found forward and mirrored for the search.
+        let mut intervals: Vec<_> = self
+            .find_intervals(segment)
+            .into_iter()
+            .map(|interval| PossibleInterval {
+                start: len - interval.end,
+                end: len - interval.start,
+                ..interval
+            })
+            .collect();
+        let mut highest_score = vec![];
+        let bigram = self.bigram.reversed();
+        if !self.fill_highest_score_with(len, &mut intervals, &mut highest_score, &bigram) {
+            return Err(ConversionError::EmptyPath { len });
+        }
+        let path = highest_score
+            .pop()
+            .ok_or(ConversionError::EmptyPath { len })?;
+        Ok(path
+            .intervals
+            .into_iter()
+            .rev()
+            .map(|interval| {
+                let interval = self.to_interval(interval);
+                Interval {
+                    start: len - interval.end,
+                    end: len - interval.start,
+                    ..interval
+                }
+            })
+            .collect())
+    }
+
+    /// Converts the sequence and passes each interval of the result to
+    /// `on_interval` from left to right.
+    ///
+    /// This doesn't stream. The best path is only known once the search
+    /// reaches the end of the sequence, so the callback runs after the whole
+    /// sequence is converted. The returned intervals are the same as
+    /// [`ConversionEngine::convert`].
+    pub fn convert_each(
+        &self,
+        segment: &ChineseSequence,
+        mut on_interval: impl FnMut(&Interval),
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let intervals = self.convert(segment)?;
+        intervals.iter().for_each(&mut on_interval);
+        Ok(intervals)
+    }
+
+    /// Converts a stream of syllables into committed intervals.
+    ///
+    /// The syllables after the last committed interval are buffered and
+    /// converted again as each one arrives. The leading interval of the
+    /// buffer is yielded and removed from the buffer once it stays the same
+    /// for `lookahead` more syllables, or once the buffer holds `lookahead`
+    /// syllables more than the longest phrase, so stable segments are
+    /// produced before the input is consumed and each conversion stays
+    /// short. The rest of the buffer is converted and yielded when the input
+    /// ends. The positions of the intervals count from the start of the
+    /// stream.
+    ///
+    /// A conversion error is yielded and ends the stream.
+    pub fn segment_stream<'a, I>(
+        &'a self,
+        syllables: I,
+        lookahead: usize,
+    ) -> impl Iterator<Item = Result<Interval, ConversionError>> + 'a
+    where
+        I: IntoIterator<Item = Syllable>,
+        I::IntoIter: 'a,
+    {
+        let convert_pending = move |pending: &[Syllable]| {
+            self.convert(&ChineseSequence {
+                syllables: pending.to_vec(),
+                selections: vec![],
+                breaks: vec![],
+            })
+        };
+        let shift = |interval: Interval, offset: usize| Interval {
+            start: interval.start + offset,
+            end: interval.end + offset,
+            ..interval
+        };
+        let window = self
+            .max_phrase_len()
+            .saturating_add(lookahead)
+            .min(MAX_SEQUENCE_LEN);
+        let mut syllables = syllables.into_iter();
+        let mut pending = vec![];
+        let mut offset = 0;
+        let mut leading = None;
+        let mut stable = 0;
+        let mut rest = vec![].into_iter();
+        let mut finished = false;
+        std::iter::from_fn(move || loop {
+            if let Some(interval) = rest.next() {
+                return Some(Ok(interval));
+            }
+            if finished {
+                return None;
+            }
+            let syllable = match syllables.next() {
+                Some(syllable) => syllable,
+                None => {
+                    finished = true;
+                    match convert_pending(&pending) {
+                        Ok(intervals) => {
+                            rest = intervals
+                                .into_iter()
+                                .map(|interval| shift(interval, offset))
+                                .collect::<Vec<_>>()
+                                .into_iter();
+                        }
+                        Err(error) => return Some(Err(error)),
+                    }
+                    continue;
+                }
+            };
+            pending.push(syllable);
+            let first = match convert_pending(&pending) {
+                Ok(intervals) => intervals.into_iter().next(),
+                Err(error) => {
+                    finished = true;
+                    return Some(Err(error));
+                }
+            };
+            if first.is_some() && first == leading {
+                stable += 1;
+            } else {
+                leading = first;
+                stable = 0;
+            }
+            if stable >= lookahead || pending.len() >= window {
+                if let Some(interval) = leading.take() {
+                    pending.drain(..interval.end);
+                    let committed = shift(interval, offset);
+                    offset = committed.end;
+                    stable = 0;
+                    return Some(Ok(committed));
+                }
+            }
+        })
+    }
+
+    /// Converts borrowed syllables with the given selections and breaks.
+    ///
+    /// This is the same as [`ConversionEngine::convert`] on a
+    /// [`ChineseSequence`] made of the arguments, for callers that keep
+    /// their syllables in a larger buffer and pass a sub-slice. The slices
+    /// are looked up in place rather than copied into a sequence. Positions
+    /// of the selections, the breaks and the result are relative to the
+    /// start of `syllables`.
+    pub fn convert_slice(
+        &self,
+        syllables: &[Syllable],
+        selections: &[Interval],
+        breaks: &[Break],
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let intervals = self.convert_adjusted(
+            SequenceView {
+                syllables,
+                selections,
+                breaks,
+            },
+            None,
+            |_| {},
+        )?;
+        debug_assert!(is_valid_tiling(&intervals, syllables.len()));
+        Ok(intervals)
+    }
+
+    /// Converts the sequence into intervals whose phrases are shared through
+    /// `interner`.
+    ///
+    /// This returns the same phrasing as [`ConversionEngine::convert`]. Reuse
+    /// the interner across the conversions of a document so identical
+    /// phrases are stored once.
+    pub fn convert_interned(
+        &self,
+        segment: &ChineseSequence,
+        interner: &mut PhraseInterner,
+    ) -> Result<Vec<InternedInterval>, ConversionError> {
+        Ok(self
+            .convert(segment)?
+            .iter()
+            .map(|interval| InternedInterval::new(interval, interner))
+            .collect())
+    }
+
+    /// Same as [`ConversionEngine::convert`] but also returns the
+    /// [`DictionarySource`] of each chosen phrase.
+    ///
+    /// With a [`LayeredDictionary`][crate::dictionary::LayeredDictionary] the
+    /// source tells which layer provided the phrase. Selections, unknown
+    /// syllables, and phrases from a single dictionary report the default
+    /// source.
+    pub fn convert_with_sources(
+        &self,
+        segment: &ChineseSequence,
+    ) -> Result<Vec<(Interval, DictionarySource)>, ConversionError> {
+        let len = segment.syllables.len();
+        let intervals = self.convert(segment)?;
+        Ok(intervals
+            .into_iter()
+            .map(|interval| {
+                let selected = segment.selections.iter().any(|selection| {
+                    selection.start == interval.start && selection.end == interval.end
+                });
+                let source = if interval.unknown || selected {
+                    DictionarySource::default()
+                } else {
+                    self.find_output_phrase(
+                        &segment.syllables[interval.start..interval.end],
+                        interval.end == len,
+                        &interval.phrase,
+                    )
+                    .map_or_else(DictionarySource::default, |phrase| phrase.source())
+                };
+                (interval, source)
+            })
+            .collect())
+    }
+
+    /// Converts the sequence into the caller's buffer and returns the number
+    /// of intervals written.
+    ///
+    /// Only the first intervals of `out` are overwritten, the rest are left
+    /// untouched. If the best phrasing has more intervals than `out` holds,
+    /// [`ConversionError::BufferTooSmall`] is returned and `out` is not
+    /// modified. Use [`convert_max_segments`][Self::convert_max_segments] to
+    /// search for a phrasing that fits instead.
+    ///
+    /// The result is the one of [`convert`][ConversionEngine::convert]. The
+    /// intervals are written straight from the best path, and the size of
+    /// `out` is checked before any of them is built.
+    pub fn convert_into(
+        &self,
+        segment: &ChineseSequence,
+        out: &mut [Interval],
+    ) -> Result<usize, ConversionError> {
+        self.convert_adjusted_with(
+            segment.into(),
+            None,
+            |_| {},
+            |intervals| write_intervals(out, intervals),
+        )
+    }
+
+    /// Returns up to `n` best phrasings of the sequence.
+    ///
+    /// The phrasings are the same ones cycled by
+    /// [`ConversionEngine::convert_next`], in the same order.
+    pub fn convert_candidates(&self, segment: &ChineseSequence, n: usize) -> Vec<Vec<Interval>> {
+        if segment.syllables.is_empty() || n == 0 {
+            return vec![];
+        }
+        self.find_candidate_paths(&mut Graph::default(), segment)
+            .into_iter()
+            .take(n)
+            .map(|p| {
+                p.intervals
+                    .into_iter()
+                    .map(|it| self.to_interval(it))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Returns up to `n` best phrasings of the sequence using beam search.
+    ///
+    /// Instead of enumerating every possible path, only the `beam` highest
+    /// scoring partial paths are kept at each position. This bounds the time
+    /// and memory needed for long sequences, at the cost of possibly missing
+    /// some paths when `beam` is small. The results are ordered from the
+    /// highest score to the lowest.
+    pub fn convert_nbest(
+        &self,
+        segment: &ChineseSequence,
+        n: usize,
+        beam: usize,
+    ) -> Vec<Vec<Interval>> {
+        if segment.syllables.is_empty() || n == 0 {
+            return vec![];
+        }
+        let len = segment.syllables.len();
+        let beam = beam.max(n);
+        let mut intervals = self.find_intervals(segment);
+        intervals.sort_by_key(|it| it.end);
+
+        let mut beams: Vec<Vec<PossiblePath<'_>>> = vec![vec![]; len + 1];
+        beams[0].push(self.new_path());
+        for interval in intervals {
+            let mut candidates: Vec<_> = beams[interval.start]
+                .iter()
+                .map(|path| {
+                    let mut path = path.clone();
+                    path.intervals.push(interval.clone());
+                    path
+                })
+                .collect();
+            let paths = &mut beams[interval.end];
+            paths.append(&mut candidates);
+            paths.sort_by(|a, b| b.cmp(a));
+            paths.truncate(beam);
+        }
+
+        beams
+            .pop()
+            .into_iter()
+            .flatten()
+            .take(n)
+            .map(|path| {
+                path.intervals
+                    .into_iter()
+                    .map(|it| self.to_interval(it))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Returns the second best phrasing of the sequence, or `None` if the
+    /// sequence has only one phrasing.
+    ///
+    /// The dynamic programming of [`convert`][ConversionEngine::convert]
+    /// keeps the two best paths at each position instead of one, which is
+    /// much cheaper than enumerating every phrasing. As with the candidates
+    /// of [`convert_next`][ConversionEngine::convert_next], a path that only
+    /// splits the phrases of a kept path is skipped.
+    pub fn convert_second_best(&self, segment: &ChineseSequence) -> Option<Vec<Interval>> {
+        let len = segment.syllables.len();
+        if len == 0 || check_len(len).is_err() {
+            return None;
+        }
+        let mut intervals = self.find_intervals(segment);
+        intervals.sort_by_key(|it| it.end);
+        dedup_intervals(&mut intervals);
+
+        let mut top: Vec<Vec<PossiblePath<'_>>> = vec![vec![]; len + 1];
+        top[0].push(self.new_path());
+        for interval in intervals {
+            let candidates: Vec<_> = top[interval.start]
+                .iter()
+                .map(|path| self.extend_path(path, interval.clone()))
+                .collect();
+            let paths = &mut top[interval.end];
+            for candidate in candidates {
+                if paths.iter().any(|path| path.contains(&candidate)) {
+                    continue;
+                }
+                paths.retain(|path| !candidate.contains(path));
+                let rank = paths
+                    .iter()
+                    .position(|path| self.is_better(path, &candidate))
+                    .unwrap_or(paths.len());
+                paths.insert(rank, candidate);
+                paths.truncate(2);
+            }
+        }
+        top.pop()
+            .and_then(|mut paths| (paths.len() == 2).then(|| paths.remove(1)))
+            .map(|path| {
+                path.intervals
+                    .into_iter()
+                    .map(|it| self.to_interval(it))
+                    .collect()
+            })
+    }
+
+    /// Converts only the part of the sequence after the first `committed`
+    /// syllables.
+    ///
+    /// The committed prefix is treated as fixed: no phrase may cross the
+    /// boundary at `committed` and no intervals are generated inside the
+    /// prefix. The returned intervals use positions of the whole sequence and
+    /// cover `committed..len`.
+    pub fn convert_suffix(
+        &self,
+        segment: &ChineseSequence,
+        committed: usize,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let len = segment.syllables.len();
+        if committed >= len {
+            return Ok(vec![]);
+        }
+        let suffix = segment.slice(committed..len);
+        Ok(self
+            .convert(&suffix)?
+            .into_iter()
+            .map(|interval| Interval {
+                start: interval.start + committed,
+                end: interval.end + committed,
+                ..interval
+            })
+            .collect())
+    }
+
+    /// Converts and commits the first `upto` syllables of the sequence.
+    ///
+    /// Returns the intervals of the committed prefix and the uncommitted
+    /// suffix as a new sequence, with its selections and breaks re-based to
+    /// start at `0`. No phrase crosses `upto`, so converting the returned
+    /// suffix gives the rest of the phrasing.
+    pub fn commit_prefix(
+        &self,
+        segment: &ChineseSequence,
+        upto: usize,
+    ) -> Result<(Vec<Interval>, ChineseSequence), ConversionError> {
+        let len = segment.syllables.len();
+        let upto = upto.min(len);
+        let committed = self.convert(&segment.slice(0..upto))?;
+        Ok((committed, segment.slice(upto..len)))
+    }
+
+    /// Converts every sequence of `sequences` independently.
+    ///
+    /// The result is the same as calling [`convert`][ConversionEngine::convert]
+    /// on each sequence, but the scratch buffers used by the conversion are
+    /// reused across the sequences instead of being allocated every time.
+    pub fn convert_batch(
+        &self,
+        sequences: &[ChineseSequence],
+    ) -> Result<Vec<Vec<Interval>>, ConversionError> {
+        let mut scratch = Scratch::default();
+        sequences
+            .iter()
+            .map(|seq| self.convert_with_scratch(seq, &mut scratch))
+            .collect()
+    }
+
+    fn convert_with_scratch<'a>(
+        &'a self,
+        segment: &ChineseSequence,
+        scratch: &mut Scratch<'a>,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        if segment.syllables.is_empty() {
+            return Ok(vec![]);
+        }
+        check_len(segment.syllables.len())?;
+        scratch.intervals.clear();
+        self.find_intervals_into(segment, &mut scratch.intervals);
+        self.find_best_path_in(
+            segment.syllables.len(),
+            &mut scratch.intervals,
+            &mut scratch.highest_score,
+        )
+    }
+
+    /// Returns every interval the search of
+    /// [`convert`][ConversionEngine::convert] chooses from, ordered by
+    /// position.
+    ///
+    /// Each span with a dictionary match has one interval with its best
+    /// phrase. Syllables without any phrase have a placeholder interval
+    /// marked as unknown, so a complete path always exists. External tools
+    /// can build their own search over this lattice.
+    pub fn possible_intervals(&self, segment: &ChineseSequence) -> Vec<Interval> {
+        let mut intervals: Vec<_> = self
+            .find_intervals(segment)
+            .into_iter()
+            .map(|interval| self.to_interval(interval))
+            .collect();
+        intervals.sort_by_key(|interval| (interval.start, interval.end));
+        intervals
+    }
+
+    /// Returns the number of complete phrasings of the sequence.
+    ///
+    /// The phrasings are counted over the intervals found by the conversion
+    /// without enumerating them, so this is cheap even when the count is
+    /// huge. The count saturates at `usize::MAX`. Callers can use it to
+    /// decide whether exhaustive search is affordable or a beam should be
+    /// used instead.
+    pub fn estimate_paths(&self, segment: &ChineseSequence) -> usize {
+        let len = segment.syllables.len();
+        let mut intervals = self.find_intervals(segment);
+        intervals.sort_by_key(|interval| interval.end);
+        let mut paths = vec![0usize; len + 1];
+        paths[0] = 1;
+        for interval in &intervals {
+            paths[interval.end] = paths[interval.end].saturating_add(paths[interval.start]);
+        }
+        if len == 0 {
+            0
+        } else {
+            paths[len]
+        }
+    }
+
+    /// Returns the table computed by the dynamic programming of
+    /// [`convert`][ConversionEngine::convert].
+    ///
+    /// The table has one entry per position from `0` to the length of the
+    /// sequence, holding the position, the best score, and the best path
+    /// reaching it. The last entry is the result of `convert`. This is meant
+    /// for inspecting and debugging the conversion algorithm.
+    pub fn score_table(&self, segment: &ChineseSequence) -> Vec<(usize, i64, Vec<Interval>)> {
+        let mut intervals = self.find_intervals(segment);
+        let mut highest_score = vec![];
+        self.fill_highest_score(segment.syllables.len(), &mut intervals, &mut highest_score);
+        highest_score
+            .into_iter()
+            .enumerate()
+            .map(|(pos, path)| {
+                let score = path.score();
+                let intervals = path
+                    .intervals
+                    .into_iter()
+                    .map(|it| self.to_interval(it))
+                    .collect();
+                (pos, score, intervals)
+            })
+            .collect()
+    }
+
+    /// Returns the lattice of the sequence as a Graphviz DOT graph.
+    ///
+    /// The nodes are the positions `0..=len` between syllables and every
+    /// interval of [`possible_intervals`][Self::possible_intervals] is an
+    /// edge labeled with its phrase and frequency. The edges of the result
+    /// of [`convert`][ConversionEngine::convert] are drawn in red. This is
+    /// meant for debugging segmentation decisions, for example with
+    /// `dot -Tsvg`.
+    pub fn lattice_dot(&self, segment: &ChineseSequence) -> String {
+        let len = segment.syllables.len();
+        let mut intervals = self.find_intervals(segment);
+        intervals.sort_by_key(|interval| (interval.start, interval.end));
+        let best = self.convert(segment).unwrap_or_default();
+        let mut dot = String::from("digraph lattice {\n    rankdir=LR;\n");
+        for pos in 0..=len {
+            let _ = writeln!(dot, "    {};", pos);
+        }
+        for interval in intervals {
+            let freq = interval.phrase.freq();
+            let interval = self.to_interval(interval);
+            let label = format!("{} ({})", interval.phrase, freq)
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"");
+            let style = if best.contains(&interval) {
+                ", color=red, penwidth=2"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                dot,
+                "    {} -> {} [label=\"{}\"{}];",
+                interval.start, interval.end, label, style
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Converts the sequence and returns a confidence for each interval.
+    ///
+    /// The confidence is the margin between the weight of the chosen phrase
+    /// and the next best phrase for the same syllables, relative to the
+    /// weight of the chosen phrase. Phrases are weighed as conversion chooses
+    /// among them: blacklisted phrases are skipped, and the user bias and
+    /// category bonuses are added to the frequency. It is `1.0` when there is
+    /// no alternative and approaches `0.0` when the alternative is as heavy
+    /// as the chosen phrase.
+    pub fn convert_scored(
+        &self,
+        segment: &ChineseSequence,
+    ) -> Result<Vec<(Interval, f32)>, ConversionError> {
+        let len = segment.syllables.len();
+        Ok(self
+            .convert(segment)?
+            .into_iter()
+            .map(|interval| {
+                let syllables = &segment.syllables[interval.start..interval.end];
+                let mut weight = 0;
+                let mut runner_up = None;
+                for phrase in self.lookup_phrase(syllables, interval.end == len) {
+                    if !self.is_candidate_phrase(&phrase, syllables) {
+                        continue;
+                    }
+                    if phrase.as_str() == interval.phrase
+                        || self.aliases.get(phrase.as_str()) == Some(interval.phrase.as_str())
+                    {
+                        weight = self.phrase_weight(&phrase, None);
+                    } else {
+                        runner_up = runner_up.max(Some(self.phrase_weight(&phrase, None)));
+                    }
+                }
+                let confidence = match runner_up {
+                    None => 1.0,
+                    Some(_) if weight <= 0 => 0.0,
+                    Some(runner_up) => (weight - runner_up).max(0) as f32 / weight as f32,
+                };
+                (interval, confidence)
+            })
+            .collect())
+    }
+
+    /// Returns the selections of the sequence that no phrase can satisfy.
+    ///
+    /// A selection of several syllables is only kept by a dictionary phrase
+    /// covering it whose characters agree with it. When no span around the
+    /// selection has such a phrase, for example because the selection
+    /// contradicts every enclosing phrase, conversion silently uses other
+    /// phrases for its syllables. Single syllable selections always fall back
+    /// to the selected character, so they are never reported. Selections
+    /// out of range of the syllables are reported too.
+    pub fn unsatisfiable_selections(&self, segment: &ChineseSequence) -> Vec<Interval> {
+        let len = segment.syllables.len();
+        let max_len = self.max_phrase_len();
+        segment
+            .selections
+            .iter()
+            .filter(|selection| {
+                if selection.start >= selection.end || selection.end > len {
+                    return true;
+                }
+                if selection.end - selection.start == 1 {
+                    return false;
+                }
+                let first = selection.end.saturating_sub(max_len);
+                !(first..=selection.start).any(|start| {
+                    (selection.end..=len.min(start + max_len)).any(|end| {
+                        self.find_best_phrase(
+                            start,
+                            &segment.syllables[start..end],
+                            &segment.selections,
+                            &segment.breaks,
+                            end == len,
+                        )
+                        .is_some()
+                    })
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the breaks of the sequence that change the conversion.
+    ///
+    /// A break is effective when converting without it gives a different
+    /// result. Breaks placed at a boundary the conversion would choose anyway
+    /// are left out. This converts the sequence once more for every break.
+    pub fn effective_breaks(&self, segment: &ChineseSequence) -> Vec<Break> {
+        let expected = self.convert(segment).ok();
+        segment
+            .breaks
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| {
+                let without = ChineseSequence {
+                    syllables: segment.syllables.clone(),
+                    selections: segment.selections.clone(),
+                    breaks: segment
+                        .breaks
+                        .iter()
+                        .enumerate()
+                        .filter(|(other, _)| other != index)
+                        .map(|(_, br)| *br)
+                        .collect(),
+                };
+                self.convert(&without).ok() != expected
+            })
+            .map(|(_, br)| *br)
+            .collect()
+    }
+
+    /// Converts the sequence and returns the intervals whose phrase barely
+    /// beat another phrase for the same syllables.
+    ///
+    /// An interval is ambiguous when another phrase of its span weighs at
+    /// most `margin` less than the chosen phrase, counting the same user
+    /// bias and category bonuses as the conversion. These are the likely
+    /// errors a proofreading tool can highlight.
+    pub fn ambiguous_spans(
+        &self,
+        segment: &ChineseSequence,
+        margin: i32,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        Ok(self
+            .convert(segment)?
+            .into_iter()
+            .filter(|interval| {
+                let syllables = &segment.syllables[interval.start..interval.end];
+                let at_end = interval.end == segment.syllables.len();
+                let chosen = self.find_output_phrase(syllables, at_end, &interval.phrase);
+                let weight = chosen
+                    .as_ref()
+                    .map_or(0, |phrase| self.phrase_weight(phrase, None));
+                self.lookup_phrase(syllables, at_end)
+                    .filter(|phrase| {
+                        self.is_candidate_phrase(phrase, syllables)
+                            && chosen
+                                .as_ref()
+                                .map_or(true, |chosen| chosen.as_str() != phrase.as_str())
+                    })
+                    .map(|phrase| self.phrase_weight(&phrase, None))
+                    .max()
+                    .map_or(false, |runner_up| weight - runner_up <= i64::from(margin))
+            })
+            .collect())
+    }
+
+    /// Suggests phrases for the spans that only match the dictionary with
+    /// other tones.
+    ///
+    /// From the start of the sequence, the longest span without any phrase
+    /// for the typed syllables but with a phrase whose syllables differ only
+    /// by their tones is reported, then the search continues after it. The
+    /// suggested phrase has the fewest changed tones, then the highest
+    /// frequency. Spans bisected by a break are skipped.
+    ///
+    /// The tone relaxed lookup uses [`Dictionary::lookup_pattern`], which is
+    /// much slower than an exact lookup, so this is meant for proofreading
+    /// rather than for every key press.
+    pub fn suggest_corrections(&self, segment: &ChineseSequence) -> Vec<Correction> {
+        let len = segment.syllables.len();
+        let max_len = self.max_phrase_len();
+        let mut corrections = vec![];
+        let mut start = 0;
+        while start < len {
+            let found = (start + 1..=len.min(start + max_len))
+                .rev()
+                .filter(|&end| !segment.breaks.iter().any(|br| br.0 > start && br.0 < end))
+                .find_map(|end| self.suggest_correction(segment, start, end));
+            match found {
+                Some(correction) => {
+                    start = correction.end;
+                    corrections.push(correction);
+                }
+                None => start += 1,
+            }
+        }
+        corrections
+    }
+
+    /// Converts every sequence `iterations` times and reports the timings.
+    ///
+    /// This is a measurement utility built on
+    /// [`convert`][ConversionEngine::convert] for comparing scoring or
+    /// performance changes. Failed conversions are timed like successful
+    /// ones. Use [`benchmark_with_lookups`][Self::benchmark_with_lookups] to
+    /// also count the dictionary lookups.
+    pub fn benchmark(&self, sequences: &[ChineseSequence], iterations: usize) -> BenchReport {
+        self.run_benchmark(sequences, iterations, None)
+    }
+
+    /// Same as [`benchmark`][Self::benchmark] but also reports the
+    /// dictionary lookups.
+    ///
+    /// `lookups` returns the running count of lookups, usually the
+    /// [`lookups`][crate::dictionary::CountingDictionary::lookups] of a
+    /// [`CountingDictionary`][crate::dictionary::CountingDictionary] the
+    /// engine converts with.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{collections::HashMap, rc::Rc};
+    ///
+    /// use chewing::{
+    ///     conversion::{ChewingConversionEngine, ChineseSequence},
+    ///     dictionary::CountingDictionary,
+    /// };
+    ///
+    /// let dict = Rc::new(CountingDictionary::new(Box::new(HashMap::new())));
+    /// let engine = ChewingConversionEngine::new(dict.clone());
+    /// let seq = ChineseSequence::from_zhuyin_str("ㄘㄜˋ ㄕˋ").unwrap();
+    /// let report = engine.benchmark_with_lookups(&[seq], 2, || dict.lookups());
+    /// assert_eq!(Some(dict.lookups()), report.lookups);
+    /// ```
+    pub fn benchmark_with_lookups<F>(
+        &self,
+        sequences: &[ChineseSequence],
+        iterations: usize,
+        lookups: F,
+    ) -> BenchReport
+    where
+        F: Fn() -> usize,
+    {
+        self.run_benchmark(sequences, iterations, Some(&lookups))
+    }
+
+    fn run_benchmark(
+        &self,
+        sequences: &[ChineseSequence],
+        iterations: usize,
+        lookups: Option<&dyn Fn() -> usize>,
+    ) -> BenchReport {
+        let mut report = BenchReport {
+            iterations,
+            total: Duration::ZERO,
+            lookups: lookups.map(|_| 0),
+            sequences: sequences
+                .iter()
+                .map(|seq| SequenceBench {
+                    syllables: seq.syllables.len(),
+                    total: Duration::ZERO,
+                    lookups: lookups.map(|_| 0),
+                })
+                .collect(),
+        };
+        for _ in 0..iterations {
+            for (seq, bench) in sequences.iter().zip(report.sequences.iter_mut()) {
+                let before = lookups.map(|lookups| lookups());
+                let start = Instant::now();
+                let _ = self.convert(seq);
+                bench.total += start.elapsed();
+                if let (Some(lookups), Some(before), Some(count)) =
+                    (lookups, before, bench.lookups.as_mut())
+                {
+                    *count += lookups().saturating_sub(before);
+                }
+            }
+        }
+        report.total = report.sequences.iter().map(|bench| bench.total).sum();
+        if report.lookups.is_some() {
+            report.lookups = Some(
+                report
+                    .sequences
+                    .iter()
+                    .filter_map(|bench| bench.lookups)
+                    .sum(),
+            );
+        }
+        report
+    }
+
+    /// Returns the syllable spans that the conversion of `sequences` had to
+    /// split into single characters.
+    ///
+    /// This is a dry run for checking the coverage of a dictionary against a
+    /// test corpus. Every run of two or more consecutive single syllable
+    /// intervals in the best phrasing, not interrupted by a break, is
+    /// reported if the dictionary has no phrase for the whole run. Such runs
+    /// usually point at a missing phrase. Each span is reported once, in the
+    /// order it is first found. Sequences that fail to convert are skipped.
+    pub fn missing_phrases(&self, sequences: &[ChineseSequence]) -> Vec<Vec<Syllable>> {
+        let mut missing: Vec<Vec<Syllable>> = vec![];
+        for seq in sequences {
+            let intervals = match self.convert(seq) {
+                Ok(intervals) => intervals,
+                Err(_) => continue,
+            };
+            let mut runs = vec![];
+            let mut run_start = None;
+            let mut run_end = 0;
+            for interval in &intervals {
+                let breaks_run = seq.breaks.iter().any(|br| br.0 == interval.start);
+                if interval.len() != 1 || breaks_run {
+                    if let Some(start) = run_start.take() {
+                        runs.push(start..run_end);
+                    }
+                }
+                if interval.len() == 1 {
+                    run_start.get_or_insert(interval.start);
+                    run_end = interval.end;
+                }
+            }
+            if let Some(start) = run_start {
+                runs.push(start..run_end);
+            }
+            for run in runs.into_iter().filter(|run| run.len() >= 2) {
+                let syllables = &seq.syllables[run];
+                if self.dict.lookup_phrase(syllables).next().is_none()
+                    && !missing.iter().any(|span| span == syllables)
+                {
+                    missing.push(syllables.to_vec());
+                }
+            }
+        }
+        missing
+    }
+
+    /// Returns the longest dictionary phrase starting at each position.
+    ///
+    /// The result has one entry per syllable. Each entry is the phrase found
+    /// by the engine for the longest span starting there that is not
+    /// bisected by a break, or `None` if the dictionary has no phrase at all
+    /// starting there. Placeholders of unknown syllables are not reported.
+    /// The phrases are output like conversion results, with the aliases
+    /// applied. Predictive input can use this to show what the user might be
+    /// forming.
+    pub fn longest_matches(&self, segment: &ChineseSequence) -> Vec<Option<Interval>> {
+        let len = segment.syllables.len();
+        let max_len = self.max_phrase_len();
+        (0..len)
+            .map(|start| {
+                (start + 1..=len.min(start + max_len))
+                    .rev()
+                    .find_map(|end| {
+                        let syllables = &segment.syllables[start..end];
+                        let (phrase, unknown) = self.find_best_phrase(
+                            start,
+                            syllables,
+                            &segment.selections,
+                            &segment.breaks,
+                            end == len,
+                        )?;
+                        if unknown {
+                            return None;
+                        }
+                        Some(self.to_interval(PossibleInterval {
+                            start,
+                            end,
+                            bonus: self.phrase_cost(&phrase, start, end),
+                            freq_divisor: self.freq_divisor(start, end, len),
+                            unknown,
+                            phrase,
+                        }))
+                    })
+            })
+            .collect()
+    }
+
+    /// Returns the correction of the `start..end` span, if it has no exact
+    /// phrase but a tone relaxed one.
+    fn suggest_correction(
+        &self,
+        segment: &ChineseSequence,
+        start: usize,
+        end: usize,
+    ) -> Option<Correction> {
+        let original = &segment.syllables[start..end];
+        let usable = |phrase: &Phrase<'_>| {
+            !self.blacklist.contains(phrase.as_str())
+                && phrase.as_str().chars().count() == original.len()
+        };
+        if self
+            .lookup_phrase(original, false)
+            .any(|phrase| usable(&phrase))
+        {
+            return None;
+        }
+        let without_tone = |syllable: &Syllable| {
+            let mut syllable = *syllable;
+            syllable.remove_tone();
+            syllable
+        };
+        let patterns: Vec<_> = original
+            .iter()
+            .map(|syllable| SyllablePattern {
+                tone: None,
+                ..SyllablePattern::from(*syllable)
+            })
+            .collect();
+        self.dict
+            .lookup_pattern(&patterns)
+            .filter(|(syllables, phrase)| {
+                usable(phrase)
+                    && syllables
+                        .iter()
+                        .zip(original)
+                        .all(|(a, b)| without_tone(a) == without_tone(b))
+            })
+            .map(|(syllables, phrase)| {
+                let changed = syllables
+                    .iter()
+                    .zip(original)
+                    .filter(|(a, b)| a != b)
+                    .count();
+                (changed, phrase.freq(), syllables, phrase.to_string())
+            })
+            .min_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)))
+            .map(|(_, _, corrected, phrase)| Correction {
+                start,
+                end,
+                original: original.to_vec(),
+                corrected,
+                phrase,
+            })
+    }
+
+    /// Converts a lattice of candidate syllables.
+    ///
+    /// Each position of `lattice` holds the possible syllables of that
+    /// position, for example when the input method cannot tell ㄕ from ㄙ.
+    /// The lattice is looked up like the syllables of a sequence, and the
+    /// highest frequency phrase of the candidates of each span wins. Spans
+    /// are limited to the longest phrase of the dictionary, and the
+    /// candidates of a span extend the candidates of the span one syllable
+    /// shorter, so every candidate is looked up once.
+    ///
+    /// Returns [`ConversionError::MissingSyllable`] if a position has no
+    /// candidate.
+    pub fn convert_lattice(
+        &self,
+        lattice: &[Vec<Syllable>],
+    ) -> Result<Vec<Interval>, ConversionError> {
+        if lattice.is_empty() {
+            return Ok(vec![]);
+        }
+        if let Some(position) = lattice.iter().position(Vec::is_empty) {
+            return Err(ConversionError::MissingSyllable { position });
+        }
+        let len = lattice.len();
+        check_len(len)?;
+        // Unknown spans fall back to the first candidate of each position.
+        let fallback = ChineseSequence {
+            syllables: lattice.iter().map(|candidates| candidates[0]).collect(),
+            selections: vec![],
+            breaks: vec![],
+        };
+        let mut intervals = vec![];
+        self.find_lattice_intervals(lattice, &mut intervals);
+        self.finish_intervals(SequenceView::from(&fallback), &mut intervals);
+        self.find_best_path(len, intervals)
+    }
+    /// Appends the dictionary intervals of every span of `lattice`.
+    fn find_lattice_intervals<'a>(
+        &'a self,
+        lattice: &[Vec<Syllable>],
+        intervals: &mut Vec<PossibleInterval<'a>>,
+    ) {
+        let len = lattice.len();
+        let max_len = self.max_phrase_len();
+        for begin in 0..len {
+            let mut spans: Vec<Vec<Syllable>> = vec![vec![]];
+            for end in begin + 1..=len.min(begin + max_len) {
+                spans = spans
+                    .iter()
+                    .flat_map(|span| {
+                        lattice[end - 1].iter().map(move |syllable| {
+                            let mut span = span.clone();
+                            span.push(*syllable);
+                            span
+                        })
+                    })
+                    .collect();
+                let best = spans
+                    .iter()
+                    .filter_map(|syllables| {
+                        self.find_best_phrase(begin, syllables, &[], &[], end == len)
+                    })
+                    .fold(None, |best: Option<SpanPhrase<'_>>, found| match best {
+                        Some(best) if best.0.freq() >= found.0.freq() => Some(best),
+                        _ => Some(found),
+                    });
+                if let Some((phrase, unknown)) = best {
+                    intervals.push(PossibleInterval {
+                        start: begin,
+                        end,
+                        bonus: self.phrase_cost(&phrase, begin, end),
+                        freq_divisor: self.freq_divisor(begin, end, len),
+                        unknown,
+                        phrase,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Converts the sequence by greedily taking the longest phrase from the
+    /// left.
+    ///
+    /// From each position the longest phrase found in the dictionary is
+    /// taken, with ties broken by frequency, then conversion continues after
+    /// it. No interval list or dynamic programming is built, so this is
+    /// much faster than [`convert`][ConversionEngine::convert] but can miss
+    /// better phrasings where a shorter phrase leads to a better split of
+    /// the rest of the sequence.
+    pub fn convert_greedy(&self, segment: &ChineseSequence) -> Vec<Interval> {
+        let len = segment.syllables.len();
+        let max_len = self.max_phrase_len();
+        let mut intervals = vec![];
+        let mut start = 0;
+        while start < len {
+            let found = (start + 1..=len.min(start + max_len))
+                .rev()
+                .find_map(|end| {
+                    self.find_best_phrase(
+                        start,
+                        &segment.syllables[start..end],
+                        &segment.selections,
+                        &segment.breaks,
+                        end == len,
+                    )
+                    .map(|(phrase, unknown)| (end, phrase, unknown))
+                });
+            let (end, phrase, unknown) = found.unwrap_or_else(|| {
+                let placeholder = self.fallback_phrase(&segment.syllables[start]);
+                (start + 1, Rc::new(placeholder), true)
+            });
+            intervals.push(Interval {
+                start,
+                end,
+                phrase: self
+                    .aliases
+                    .get(phrase.as_str())
+                    .unwrap_or(phrase.as_str())
+                    .to_string(),
+                unknown,
+            });
+            start = end;
+        }
+        intervals
+    }
+
+    /// Converts the sequence, giving up on the full search after `budget`.
+    ///
+    /// The dictionary lookups for the intervals run until the budget is
+    /// exceeded. The syllables reached by then are converted with the usual
+    /// dynamic programming and the remaining suffix falls back to
+    /// [`convert_greedy`][Self::convert_greedy]. The result always covers the
+    /// whole sequence, and equals [`convert`][ConversionEngine::convert] when
+    /// the budget is not exceeded.
+    pub fn convert_within(
+        &self,
+        segment: &ChineseSequence,
+        budget: Duration,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let len = segment.syllables.len();
+        if len == 0 {
+            return Ok(vec![]);
+        }
+        check_len(len)?;
+        let deadline = Instant::now() + budget;
+        let mut intervals = vec![];
+        let reached =
+            self.find_intervals_until(segment.into(), Some(deadline), None, &mut intervals);
+        if reached == len {
+            self.finish_intervals(segment.into(), &mut intervals);
+            return self.find_best_path(len, intervals);
+        }
+        trace!("Conversion budget exceeded at {}", reached);
+        intervals.retain(|interval| interval.end <= reached);
+        let prefix = segment.slice(0..reached);
+        self.finish_intervals(SequenceView::from(&prefix), &mut intervals);
+        let mut result = self.find_best_path(reached, intervals)?;
+        result.extend(
+            self.convert_greedy(&segment.slice(reached..len))
+                .into_iter()
+                .map(|interval| Interval {
+                    start: interval.start + reached,
+                    end: interval.end + reached,
+                    ..interval
+                }),
+        );
+        Ok(result)
+    }
+
+    /// Re-converts the runs of single character intervals of a phrasing.
+    ///
+    /// User edits often leave a fragmented phrasing behind. Every run of
+    /// two or more consecutive single character intervals in `intervals` is
+    /// converted again on its own, so characters are merged where the
+    /// dictionary has a phrase for them. Longer intervals, intervals that
+    /// match a selection, and single characters next to a break are kept as
+    /// is.
+    pub fn recombine(
+        &self,
+        segment: &ChineseSequence,
+        intervals: &[Interval],
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let mut result = vec![];
+        let mut run: Vec<&Interval> = vec![];
+        for interval in intervals {
+            let protected = interval.len() != 1
+                || segment
+                    .selections
+                    .iter()
+                    .any(|sel| sel.start == interval.start && sel.end == interval.end)
+                || segment
+                    .breaks
+                    .iter()
+                    .any(|br| br.0 == interval.start || br.0 == interval.end);
+            if protected || run.last().map_or(false, |last| last.end != interval.start) {
+                self.recombine_run(segment, &mut run, &mut result)?;
+            }
+            if protected {
+                result.push(interval.clone());
+            } else {
+                run.push(interval);
+            }
+        }
+        self.recombine_run(segment, &mut run, &mut result)?;
+        Ok(result)
+    }
+
+    /// Converts the syllables under `run` and appends the result.
+    fn recombine_run(
+        &self,
+        segment: &ChineseSequence,
+        run: &mut Vec<&Interval>,
+        result: &mut Vec<Interval>,
+    ) -> Result<(), ConversionError> {
+        match (run.first(), run.last()) {
+            (Some(first), Some(last)) if run.len() > 1 => {
+                let (start, end) = (first.start, last.end);
+                result.extend(self.convert(&segment.slice(start..end))?.into_iter().map(
+                    |interval| Interval {
+                        start: interval.start + start,
+                        end: interval.end + start,
+                        ..interval
+                    },
+                ));
+            }
+            _ => result.extend(run.iter().map(|&interval| interval.clone())),
+        }
+        run.clear();
+        Ok(())
+    }
+
+    /// Returns the best phrase for `syllables` starting at `start`.
+    ///
+    /// This applies the same rules the engine uses for every span: no break
+    /// may lie inside the span, phrases contradicting `selections` are
+    /// skipped, and the most frequent remaining phrase wins.
+    pub fn best_phrase(
+        &self,
+        start: usize,
+        syllables: &[Syllable],
+        selections: &[Interval],
+        breaks: &[Break],
+    ) -> Option<Phrase<'static>> {
+        self.find_best_phrase(start, syllables, selections, breaks, false)
+            .map(|(phrase, _)| phrase.as_ref().clone().into_owned())
+    }
+
+    /// Returns the phrases the user can select for the `start..end` span of
+    /// the sequence, ordered from the most frequent.
+    ///
+    /// This is the source of the candidate window rather than a conversion:
+    /// every phrase of the dictionary for the syllables is returned except
+    /// the blacklisted ones. Nothing is returned if the span is empty, out of
+    /// range, or bisected by a break.
+    pub fn candidates_for_span(
+        &self,
+        segment: &ChineseSequence,
+        start: usize,
+        end: usize,
+    ) -> Vec<Phrase<'static>> {
+        if start >= end || end > segment.syllables.len() {
+            return vec![];
+        }
+        if segment.breaks.iter().any(|br| br.0 > start && br.0 < end) {
+            return vec![];
+        }
+        let mut phrases: Vec<_> = self
+            .lookup_phrase(&segment.syllables[start..end], false)
+            .filter(|phrase| !self.blacklist.contains(phrase.as_str()))
+            .map(Phrase::into_owned)
+            .collect();
+        phrases.sort_by_key(|phrase| Reverse(phrase.freq()));
+        phrases
+    }
+
+    /// Returns the candidate phrasings of the sequence that differ only in
+    /// the `start..end` span, ordered from the highest score.
+    ///
+    /// Only the paths within the span are enumerated and trimmed, which is
+    /// much cheaper than enumerating the whole sentence with
+    /// [`ConversionEngine::convert_next`] when the candidate menu is opened
+    /// for one span. The rest of the sequence keeps the best conversion with
+    /// breaks at `start` and `end`. Nothing is returned if the span is empty
+    /// or out of range.
+    pub fn span_candidates(
+        &self,
+        segment: &ChineseSequence,
+        start: usize,
+        end: usize,
+    ) -> Vec<Vec<Interval>> {
+        if start >= end || end > segment.syllables.len() {
+            return vec![];
+        }
+        let mut breaks = segment.breaks.clone();
+        breaks.extend([Break(start), Break(end)]);
+        let fixed_segment = ChineseSequence {
+            syllables: segment.syllables.clone(),
+            selections: segment.selections.clone(),
+            breaks,
+        };
+        let fixed = match self.convert(&fixed_segment) {
+            Ok(fixed) => fixed,
+            Err(_) => return vec![],
+        };
+        let mut paths = vec![];
+        self.visit_paths(
+            &mut Graph::default(),
+            segment,
+            start,
+            end,
+            None,
+            &mut |path| Self::trim_insert(&mut paths, path),
+        );
+        paths.sort();
+        paths.reverse();
+        paths
+            .into_iter()
+            .map(|path| {
+                let before = fixed.iter().filter(|it| it.end <= start).cloned();
+                let after = fixed.iter().filter(|it| it.start >= end).cloned();
+                before
+                    .chain(path.intervals.into_iter().map(|it| self.to_interval(it)))
+                    .chain(after)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Converts the sequence with the intervals on both sides of `pos`
+    /// merged into one.
+    ///
+    /// This is the IME action joining the segment before the cursor with the
+    /// next one. The merged span uses its most frequent dictionary phrase, or
+    /// the concatenated phrases of the two intervals if the dictionary has
+    /// none, and the rest of the sequence is converted again around it.
+    /// Breaks and selections inside the merged span are ignored. If `pos` is
+    /// not a boundary between two intervals the conversion is returned
+    /// unchanged.
+    ///
+    /// The sequence is not modified. To keep the join, add the merged
+    /// interval of the result to the selections.
+    pub fn join_at(
+        &self,
+        segment: &ChineseSequence,
+        pos: usize,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let intervals = self.convert(segment)?;
+        let left = match intervals.iter().position(|interval| interval.end == pos) {
+            Some(left) if left + 1 < intervals.len() => left,
+            _ => return Ok(intervals),
+        };
+        let (start, end) = (intervals[left].start, intervals[left + 1].end);
+        let mut sequence = ChineseSequence {
+            syllables: segment.syllables.clone(),
+            selections: segment
+                .selections
+                .iter()
+                .filter(|selection| selection.end <= start || selection.start >= end)
+                .cloned()
+                .collect(),
+            breaks: segment
+                .breaks
+                .iter()
+                .filter(|br| br.0 <= start || br.0 >= end)
+                .copied()
+                .collect(),
+        };
+        let phrase = match self.candidates_for_span(&sequence, start, end).first() {
+            Some(phrase) => phrase.as_str().to_owned(),
+            None => format!("{}{}", intervals[left].phrase, intervals[left + 1].phrase),
+        };
+        let joined = Interval {
+            start,
+            end,
+            phrase,
+            unknown: false,
+        };
+        sequence.selections.push(joined.clone());
+        let mut possible = self.find_intervals(&sequence);
+        Self::pin_intervals(&[joined], &mut possible);
+        self.find_best_path(sequence.syllables.len(), possible)
+    }
+
+    /// Converts the sequence with the interval at `interval_index` broken
+    /// into single syllables.
+    ///
+    /// This is the IME action for a wrong phrase: breaks are inserted between
+    /// all syllables of the interval and the sequence is converted again, so
+    /// the neighboring intervals can take a better phrasing around the new
+    /// breaks. Selections overlapping the interval are ignored. If the index
+    /// is out of range the conversion is returned unchanged.
+    ///
+    /// The sequence is not modified. To keep the split, add the breaks to
+    /// the sequence.
+    pub fn split_at(
+        &self,
+        segment: &ChineseSequence,
+        interval_index: usize,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let intervals = self.convert(segment)?;
+        let (start, end) = match intervals.get(interval_index) {
+            Some(interval) => (interval.start, interval.end),
+            None => return Ok(intervals),
+        };
+        let mut breaks = segment.breaks.clone();
+        breaks.extend((start + 1..end).map(Break));
+        let sequence = ChineseSequence {
+            syllables: segment.syllables.clone(),
+            selections: segment
+                .selections
+                .iter()
+                .filter(|selection| selection.end <= start || selection.start >= end)
+                .cloned()
+                .collect(),
+            breaks,
+        };
+        self.convert(&sequence)
+    }
+
+    /// Converts the sequence, preferring among the competitive phrasings the
+    /// one whose [rendered width][rendered_width] is nearest to
+    /// `target_cells`.
+    ///
+    /// The candidates are the best 16 phrasings of
+    /// [`convert_nbest`][Self::convert_nbest] that score at most 500 below
+    /// the result of [`convert`][ConversionEngine::convert]. The margin is
+    /// less than the weight of the length based rules, so the width only
+    /// outweighs differences of frequency and length variance. The result of
+    /// `convert` is kept unless another candidate is strictly closer to the
+    /// target, and among equally close candidates the higher score wins.
+    pub fn convert_to_width(
+        &self,
+        segment: &ChineseSequence,
+        target_cells: usize,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let best = self.convert(segment)?;
+        let distance = |intervals: &[Interval]| {
+            intervals
+                .iter()
+                .map(|interval| rendered_width(&interval.phrase))
+                .sum::<usize>()
+                .abs_diff(target_cells)
+        };
+        let closest = self.score_segmentation(segment, &best).and_then(|score| {
+            self.convert_nbest(segment, WIDTH_CANDIDATES, WIDTH_CANDIDATES * 4)
+                .into_iter()
+                .filter(|candidate| {
+                    self.score_segmentation(segment, candidate)
+                        .map_or(false, |it| it >= score.saturating_sub(WIDTH_SCORE_MARGIN))
+                })
+                .min_by_key(|candidate| distance(candidate))
+        });
+        Ok(match closest {
+            Some(closest) if distance(&closest) < distance(&best) => closest,
+            _ => best,
+        })
+    }
+
+    /// Converts the sequence into at most `max` intervals.
+    ///
+    /// This is the best phrasing among those with no more than `max`
+    /// phrases, for displays that can only show a few segments. Returns
+    /// `None` if no such phrasing exists, for example when the sequence is
+    /// longer than `max` times the longest phrase of the dictionary.
+    pub fn convert_max_segments(
+        &self,
+        segment: &ChineseSequence,
+        max: usize,
+    ) -> Option<Vec<Interval>> {
+        let len = segment.syllables.len();
+        if len == 0 {
+            return Some(vec![]);
+        }
+        if check_len(len).is_err() || len > max.saturating_mul(self.max_phrase_len()) {
+            return None;
+        }
+        // A phrasing never has more phrases than syllables.
+        let max = max.min(len);
+        let mut intervals = self.find_intervals(segment);
+        intervals.sort_by_key(|it| it.end);
+        dedup_intervals(&mut intervals);
+
+        // best[pos][count] is the best path reaching pos with count phrases.
+        let mut best: Vec<Vec<Option<PossiblePath<'_>>>> = vec![vec![None; max + 1]; len + 1];
+        best[0][0] = Some(self.new_path());
+        for interval in intervals {
+            for count in 0..max {
+                let candidate = match &best[interval.start][count] {
+                    Some(path) => self.extend_path(path, interval.clone()),
+                    None => continue,
+                };
+                let slot = &mut best[interval.end][count + 1];
+                if slot
+                    .as_ref()
+                    .map_or(true, |current| self.is_better(current, &candidate))
+                {
+                    *slot = Some(candidate);
+                }
+            }
+        }
+        let mut result: Option<PossiblePath<'_>> = None;
+        for path in best.pop()?.into_iter().flatten() {
+            if result
+                .as_ref()
+                .map_or(true, |current| self.is_better(current, &path))
+            {
+                result = Some(path);
+            }
+        }
+        result.map(|path| {
+            path.intervals
+                .into_iter()
+                .map(|it| self.to_interval(it))
+                .collect()
+        })
+    }
+
+    /// Returns the score this engine gives to a phrasing of the sequence,
+    /// for example one produced by another engine.
+    ///
+    /// The intervals must cover the whole sequence in order. The phrase of
+    /// each interval is looked up in the dictionary to recover its
+    /// frequency, a placeholder for a syllable missing from the dictionary
+    /// scores as if the engine chose it. Returns `None` if the intervals
+    /// don't cover the sequence or a phrase isn't in the dictionary.
+    pub fn score_segmentation(
+        &self,
+        segment: &ChineseSequence,
+        intervals: &[Interval],
+    ) -> Option<i64> {
+        self.segmentation_path(segment, intervals)
+            .map(|path| path.score())
+    }
+
+    /// Converts the sequence with both [`convert`][ConversionEngine::convert]
+    /// and [`convert_next`][ConversionEngine::convert_next] and explains any
+    /// difference with the scores of both phrasings.
+    ///
+    /// `convert` keeps the best scoring path to each position, which can
+    /// miss the best complete path because the rules averaging over the
+    /// phrases don't add up per interval. `convert_next` enumerates complete
+    /// paths, drops those splitting the phrases of another, and reranks them
+    /// with the language model. A `dp` score of `next` above the one of
+    /// `best` points to the former, a higher `path` score to the latter.
+    pub fn reconcile(&self, segment: &ChineseSequence) -> Result<Reconciliation, ConversionError> {
+        let best = self.convert(segment)?;
+        let next = self.convert_next(segment, 0)?;
+        let scores = if best == next {
+            None
+        } else {
+            Some((
+                self.phrasing_score(segment, &best),
+                self.phrasing_score(segment, &next),
+            ))
+        };
+        Ok(Reconciliation { best, next, scores })
+    }
+    fn phrasing_score(&self, segment: &ChineseSequence, intervals: &[Interval]) -> PhrasingScore {
+        let dp = self.score_segmentation(segment, intervals);
+        let path = dp.map(|score| self.ranking_score(score, intervals));
+        PhrasingScore { dp, path }
+    }
+
+    /// Returns the values of the scoring rules for a phrasing of the
+    /// sequence.
+    ///
+    /// The values are the total length, the average phrase length times 6,
+    /// the negated length variance, and the frequency sum, in this order. The
+    /// score of a phrasing weighs them with 1000, 1000, 100 and 1, plus the
+    /// bonuses of the engine. Pairing the features of the converted phrasing
+    /// with the ones of alternatives, for example built from
+    /// [`candidates_for_span`][Self::candidates_for_span], gives training
+    /// data for better weights. Returns `None` in the same cases as
+    /// [`score_segmentation`][Self::score_segmentation].
+    pub fn feature_vector(
+        &self,
+        segment: &ChineseSequence,
+        intervals: &[Interval],
+    ) -> Option<[i32; 4]> {
+        self.segmentation_path(segment, intervals)
+            .map(|path| path.features())
+    }
+
+    /// Rebuilds the path of a phrasing of the sequence for scoring.
+    fn segmentation_path(
+        &self,
+        segment: &ChineseSequence,
+        intervals: &[Interval],
+    ) -> Option<PossiblePath<'_>> {
+        let len = segment.syllables.len();
+        let mut path = self.new_path();
+        let mut pos = 0;
+        for interval in intervals {
+            if interval.start != pos || interval.end <= pos || interval.end > len {
+                return None;
+            }
+            let syllables = &segment.syllables[interval.start..interval.end];
+            let phrase = self
+                .find_output_phrase(syllables, interval.end == len, &interval.phrase)
+                .or_else(|| {
+                    let placeholder = self.fallback_phrase(&syllables[0]);
+                    Some(placeholder).filter(|it| {
+                        interval.unknown && syllables.len() == 1 && it.as_str() == interval.phrase
+                    })
+                })?;
+            let possible = PossibleInterval {
+                start: interval.start,
+                end: interval.end,
+                bonus: self.phrase_cost(&phrase, interval.start, interval.end),
+                freq_divisor: self.freq_divisor(interval.start, interval.end, len),
+                unknown: interval.unknown,
+                phrase: Rc::new(phrase),
+            };
+            path = self.extend_path(&path, possible);
+            pos = interval.end;
+        }
+        if pos != len {
+            return None;
+        }
+        Some(path)
+    }
+
+    /// Converts the sequence and pairs each interval with up to `k` other
+    /// phrases for the same span, ordered from the most frequent.
+    ///
+    /// The alternatives are the ones
+    /// [`candidates_for_span`][Self::candidates_for_span] returns, so a menu
+    /// can be shown without another lookup call. They are output like the
+    /// intervals, with the aliases applied, and phrases with the same output
+    /// are listed once.
+    pub fn convert_with_alternatives(
+        &self,
+        segment: &ChineseSequence,
+        k: usize,
+    ) -> Result<Vec<(Interval, Vec<String>)>, ConversionError> {
+        Ok(self
+            .convert(segment)?
+            .into_iter()
+            .map(|interval| {
+                let mut seen = HashSet::from([interval.phrase.clone()]);
+                let alternatives = self
+                    .candidates_for_span(segment, interval.start, interval.end)
+                    .into_iter()
+                    .map(|phrase| {
+                        self.aliases
+                            .get(phrase.as_str())
+                            .map_or_else(|| String::from(phrase), str::to_string)
+                    })
+                    .filter(|output| seen.insert(output.clone()))
+                    .take(k)
+                    .collect();
+                (interval, alternatives)
+            })
+            .collect())
+    }
+
+    /// Converts the sequence and assigns identities to the intervals that
+    /// are carried forward from `prev`, the result of the previous call.
+    ///
+    /// Unchanged segments keep their identity across edits and only new
+    /// segments get fresh ones, see [`track_intervals`].
+    pub fn convert_tracked(
+        &self,
+        segment: &ChineseSequence,
+        prev: &[TrackedInterval],
+    ) -> Result<Vec<TrackedInterval>, ConversionError> {
+        Ok(track_intervals(prev, self.convert(segment)?))
+    }
+
+    /// Converts the sequence and previews the phrase being typed at the end.
+    ///
+    /// When the best phrasing ends with two or more single syllable
+    /// intervals, the user is probably in the middle of typing a longer
+    /// phrase. For the longest trailing run of those syllables that starts
+    /// a phrase of [`predict`][Self::predict], the most frequent phrase is
+    /// returned as a [`Preview`] of the syllables from the run on. The
+    /// phrase is longer than the syllables it previews, so it is returned
+    /// next to the intervals instead of as one. Runs bisected by a break or
+    /// overlapping a selection are not previewed. The intervals are the
+    /// result of [`convert`][ConversionEngine::convert].
+    pub fn convert_with_preview(
+        &self,
+        segment: &ChineseSequence,
+    ) -> Result<(Vec<Interval>, Option<Preview>), ConversionError> {
+        let intervals = self.convert(segment)?;
+        let len = segment.syllables.len();
+        let run = intervals
+            .iter()
+            .rev()
+            .take_while(|interval| interval.len() == 1)
+            .count();
+        let first = intervals.len() - run;
+        let preview = (first..intervals.len().saturating_sub(1)).find_map(|i| {
+            let start = intervals[i].start;
+            if segment.breaks.iter().any(|br| br.0 > start && br.0 < len)
+                || segment.selections.iter().any(|sel| sel.end > start)
+            {
+                return None;
+            }
+            let phrase = self
+                .predict(&segment.syllables[start..])
+                .into_iter()
+                .next()?;
+            Some(Preview { start, phrase })
+        });
+        Ok((intervals, preview))
+    }
+
+    /// Returns the phrases that start with `syllables` and extend beyond
+    /// them, ordered from the most frequent.
+    ///
+    /// This is meant for predicting the phrase the user is typing. Each
+    /// phrase is returned once, blacklisted phrases are skipped. Frequencies
+    /// are read through the [overlay][Self::with_overlay], as in conversion.
+    pub fn predict(&self, syllables: &[Syllable]) -> Vec<String> {
+        if syllables.is_empty() {
+            return vec![];
+        }
+        let mut phrases: Vec<_> = self
+            .dict
+            .lookup_phrase_with_prefix(syllables)
+            .filter(|phrase| phrase.as_str().chars().count() > syllables.len())
+            .filter(|phrase| !self.blacklist.contains(phrase.as_str()))
+            .map(|phrase| self.overlaid(phrase))
+            .collect();
+        phrases.sort_by_key(|phrase| Reverse(phrase.freq()));
+        let mut seen = HashSet::new();
+        phrases
+            .into_iter()
+            .map(String::from)
+            .filter(|phrase| seen.insert(phrase.clone()))
+            .collect()
+    }
+
+    /// Returns the number of distinct phrasings
+    /// [`convert_next`][ConversionEngine::convert_next] cycles through.
+    pub fn candidate_count(&self, segment: &ChineseSequence) -> usize {
+        if segment.syllables.is_empty() {
+            return 0;
+        }
+        self.find_candidate_paths(&mut Graph::default(), segment)
+            .len()
+    }
+
+    /// Converts the sequence and maps the result to Simplified Chinese.
+    ///
+    /// Conversion runs on the traditional dictionary as usual, then `mapper`
+    /// rewrites the output phrases.
+    pub fn convert_simplified(
+        &self,
+        segment: &ChineseSequence,
+        mapper: &SimplifiedMapper,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let mut intervals = self.convert(segment)?;
+        mapper.map_intervals(&mut intervals);
+        Ok(intervals)
+    }
+
+    /// Converts the sequence with selections of different priorities.
+    ///
+    /// [`SelectionPriority::Locked`] selections are hard constraints: they
+    /// are added to the selections of the sequence and always kept as whole
+    /// intervals, as with
+    /// [`with_pin_selections`][Self::with_pin_selections]. [`SelectionPriority::Suggested`]
+    /// selections are soft hints: the suggested phrase at its position gets
+    /// a bonus, so it is kept unless another phrasing is clearly better.
+    /// Suggested phrases match the dictionary directly or through their
+    /// alias. Suggestions overlapping a hard selection or crossing a break,
+    /// and suggested phrases missing from the dictionary, are ignored.
+    pub fn convert_with_priorities(
+        &self,
+        segment: &ChineseSequence,
+        selections: &[(Interval, SelectionPriority)],
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let len = segment.syllables.len();
+        let locked: Vec<_> = selections
+            .iter()
+            .filter(|(_, priority)| *priority == SelectionPriority::Locked)
+            .map(|(selection, _)| selection.clone())
+            .collect();
+        let mut hard = segment.selections.clone();
+        hard.extend(locked.iter().cloned());
+        let segment = ChineseSequence {
+            syllables: segment.syllables.clone(),
+            selections: hard,
+            breaks: segment.breaks.clone(),
+        };
+        self.convert_adjusted(SequenceView::from(&segment), None, |intervals| {
+            Self::pin_intervals(&locked, intervals);
+            for (suggestion, _) in selections
+                .iter()
+                .filter(|(_, priority)| *priority == SelectionPriority::Suggested)
+            {
+                let (start, end) = (suggestion.start, suggestion.end);
+                if start >= end
+                    || end > len
+                    || segment.breaks.iter().any(|br| br.0 > start && br.0 < end)
+                    || segment
+                        .selections
+                        .iter()
+                        .any(|selection| selection.start < end && selection.end > start)
+                {
+                    continue;
+                }
+                let syllables = &segment.syllables[start..end];
+                let phrase =
+                    match self.find_output_phrase(syllables, end == len, &suggestion.phrase) {
+                        Some(phrase) => phrase,
+                        None => continue,
+                    };
+                if let Some(interval) = intervals.iter_mut().find(|it| {
+                    it.start == start && it.end == end && it.phrase.as_str() == phrase.as_str()
+                }) {
+                    interval.bonus = interval.bonus.saturating_add(SUGGESTED_SELECTION_BONUS);
+                    continue;
+                }
+                intervals.push(PossibleInterval {
+                    start,
+                    end,
+                    bonus: self
+                        .phrase_cost(&phrase, start, end)
+                        .saturating_add(SUGGESTED_SELECTION_BONUS),
+                    freq_divisor: self.freq_divisor(start, end, len),
+                    unknown: false,
+                    phrase: Rc::new(phrase.into_owned()),
+                });
+            }
+        })
+    }
+
+    /// Converts the sequence with temporary score deltas for some phrases.
+    ///
+    /// The delta of each phrase in `boosts` is added to its frequency when
+    /// choosing among homophones and to the score of the phrasings using it,
+    /// like a [`UserBias`] without bound. Nothing is kept after the call,
+    /// which suits previews of an interpretation.
+    pub fn convert_with_boost(
+        &self,
+        segment: &ChineseSequence,
+        boosts: &HashMap<String, i32>,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        self.convert_adjusted(segment.into(), Some(boosts), |_| {})
+    }
+
+    /// Converts the sequence with the probability of a boundary before each
+    /// syllable.
+    ///
+    /// `probs[i]` is the probability of a boundary before the syllable at
+    /// `i`, from a statistical segmenter for example. Every phrasing is
+    /// rewarded for each boundary above 0.5 it keeps and penalized for each
+    /// boundary below 0.5, so confident boundaries act like soft breaks.
+    /// Positions without a probability are neutral.
+    pub fn convert_with_boundary_probs(
+        &self,
+        segment: &ChineseSequence,
+        probs: &[f32],
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let len = segment.syllables.len();
+        self.convert_adjusted(segment.into(), None, |intervals| {
+            for interval in intervals.iter_mut() {
+                if let Some(prob) = probs.get(interval.end).filter(|_| interval.end < len) {
+                    let bonus = (prob.clamp(0.0, 1.0) - 0.5) * BOUNDARY_PROB_WEIGHT;
+                    interval.bonus = interval.bonus.saturating_add(bonus as i32);
+                }
+            }
+        })
+    }
+
+    /// Converts the sequence while trying to keep the boundaries listed in
+    /// `hint`.
+    ///
+    /// Intervals that start and end on a hinted boundary (or at either end of
+    /// the sequence) without crossing another hinted boundary receive a small
+    /// bonus. This keeps a previous segmentation stable when the user edits
+    /// the buffer, unless another phrasing is clearly better.
+    pub fn convert_with_segmentation(
+        &self,
+        segment: &ChineseSequence,
+        hint: &[usize],
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let len = segment.syllables.len();
+        let is_boundary = |pos: usize| pos == 0 || pos == len || hint.contains(&pos);
+        self.convert_adjusted(segment.into(), None, |intervals| {
+            for interval in intervals.iter_mut() {
+                if is_boundary(interval.start)
+                    && is_boundary(interval.end)
+                    && !hint
+                        .iter()
+                        .any(|&pos| pos > interval.start && pos < interval.end)
+                {
+                    interval.bonus += SEGMENTATION_HINT_BONUS;
+                }
+            }
+        })
+    }
+
+    /// Converts the sequence with the text of some spans given verbatim.
+    ///
+    /// Each `(start, end)` key of `overrides` forces an interval with the
+    /// given text, like a pinned selection that doesn't need to match any
+    /// phrase of the dictionary. The rest of the sequence is converted as
+    /// usual. Overrides that are empty, out of range, or overlap an earlier
+    /// override are ignored.
+    pub fn convert_with_overrides(
+        &self,
+        segment: &ChineseSequence,
+        overrides: &HashMap<(usize, usize), String>,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        let len = segment.syllables.len();
+        let mut spans: Vec<_> = overrides
+            .iter()
+            .filter(|((start, end), _)| start < end && *end <= len)
+            .collect();
+        spans.sort_by_key(|(span, _)| **span);
+        let mut forced: Vec<Interval> = vec![];
+        for (&(start, end), text) in spans {
+            if forced.last().map_or(true, |prev| prev.end <= start) {
+                forced.push(Interval {
+                    start,
+                    end,
+                    phrase: text.clone(),
+                    unknown: false,
+                });
+            }
+        }
+        self.convert_adjusted(segment.into(), None, |intervals| {
+            intervals.retain(|interval| {
+                forced
+                    .iter()
+                    .all(|it| interval.end <= it.start || interval.start >= it.end)
+            });
+            intervals.extend(forced.into_iter().map(|it| PossibleInterval {
+                start: it.start,
+                end: it.end,
+                phrase: Rc::new(Phrase::new(it.phrase, 0)),
+                bonus: 0,
+                freq_divisor: 1,
+                unknown: false,
+            }));
+        })
+    }
+
+    /// Converts the sequence while discouraging phrases that span the soft
+    /// breaks.
+    ///
+    /// The penalty of every soft break strictly inside an interval is
+    /// subtracted from the score of the interval. The hard breaks and the
+    /// selections of the sequence are honored as usual.
+    pub fn convert_with_soft_breaks(
+        &self,
+        segment: &ChineseSequence,
+        soft_breaks: &[SoftBreak],
+    ) -> Result<Vec<Interval>, ConversionError> {
+        self.convert_adjusted(segment.into(), None, |intervals| {
+            for interval in intervals.iter_mut() {
+                for &SoftBreak(pos, penalty) in soft_breaks {
+                    if pos > interval.start && pos < interval.end {
+                        interval.bonus = interval.bonus.saturating_sub(penalty);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Converts the sequence as the continuation of already committed text.
+    ///
+    /// The last interval of `context` is the previous phrase of the first
+    /// interval of the sequence for the bigram bonuses of
+    /// [`with_bigram`][Self::with_bigram]. Unlike a break, the context only
+    /// changes the scores, the sequence is still free to choose any
+    /// phrasing.
+    pub fn convert_with_context(
+        &self,
+        segment: &ChineseSequence,
+        context: &[Interval],
+    ) -> Result<Vec<Interval>, ConversionError> {
+        self.convert_adjusted(segment.into(), None, |intervals| {
+            if let Some(prev) = context.last() {
+                for interval in intervals.iter_mut().filter(|interval| interval.start == 0) {
+                    let bonus = self.bigram.get(&prev.phrase, interval.phrase.as_str());
+                    interval.bonus = interval.bonus.saturating_add(bonus);
+                }
+            }
+        })
+    }
+
+    /// Returns how stable the conversion is while the sequence is typed.
+    ///
+    /// Each prefix of the sequence is converted, and the share of its
+    /// boundaries between intervals that are kept when one more syllable is
+    /// added is averaged over all prefixes. A score of 1.0 means no boundary
+    /// ever moves, lower scores mean more flickering segmentation.
+    pub fn stability(&self, segment: &ChineseSequence) -> Result<f32, ConversionError> {
+        self.stability_by(segment, |sequence, _| self.convert(sequence))
+    }
+
+    /// Same as [`stability`][ChewingConversionEngine::stability] but each
+    /// prefix is converted with the boundaries of the previous prefix as the
+    /// segmentation hint, see
+    /// [`convert_with_segmentation`][ChewingConversionEngine::convert_with_segmentation].
+    pub fn stability_with_segmentation(
+        &self,
+        segment: &ChineseSequence,
+    ) -> Result<f32, ConversionError> {
+        self.stability_by(segment, |sequence, hint| {
+            self.convert_with_segmentation(sequence, hint)
+        })
+    }
+
+    fn stability_by(
+        &self,
+        segment: &ChineseSequence,
+        mut convert: impl FnMut(&ChineseSequence, &[usize]) -> Result<Vec<Interval>, ConversionError>,
+    ) -> Result<f32, ConversionError> {
+        let len = segment.syllables.len();
+        check_len(len)?;
+        if len < 2 {
+            return Ok(1.0);
+        }
+        let boundaries = |intervals: &[Interval]| -> Vec<usize> {
+            match intervals.split_last() {
+                Some((_, rest)) => rest.iter().map(|interval| interval.end).collect(),
+                None => vec![],
+            }
+        };
+        let prefix = |end: usize| ChineseSequence {
+            syllables: segment.syllables[..end].to_vec(),
+            selections: segment
+                .selections
+                .iter()
+                .filter(|selection| selection.end <= end)
+                .cloned()
+                .collect(),
+            breaks: segment
+                .breaks
+                .iter()
+                .filter(|br| br.0 < end)
+                .copied()
+                .collect(),
+        };
+        let mut previous: Vec<usize> = vec![];
+        let mut total = 0.0;
+        for end in 1..=len {
+            let current = boundaries(&convert(&prefix(end), &previous)?);
+            if end > 1 {
+                total += if previous.is_empty() {
+                    1.0
+                } else {
+                    let kept = previous.iter().filter(|pos| current.contains(pos)).count();
+                    kept as f32 / previous.len() as f32
+                };
+            }
+            previous = current;
+        }
+        Ok(total / (len - 1) as f32)
+    }
+
+    /// Looks up the phrases of `syllables`.
+    ///
+    /// When `at_end` is set, the span ends the sequence, and trailing toneless
+    /// matching is enabled, a last syllable without a tone also matches the
+    /// same syllable with any tone.
+    fn lookup_phrase(&self, syllables: &[Syllable], at_end: bool) -> Phrases<'_, '_> {
+        let phrases = self.lookup_dict_phrase(syllables, at_end);
+        if self.overlay.is_empty() {
+            return phrases;
+        }
+        Box::new(phrases.map(move |phrase| self.overlaid(phrase)))
+    }
+    /// Replaces the frequency of the phrase with the one in the overlay, if
+    /// any.
+    fn overlaid<'a>(&self, phrase: Phrase<'a>) -> Phrase<'a> {
+        match self.overlay.get(phrase.as_str()) {
+            Some(freq) => phrase.with_freq(freq),
+            None => phrase,
+        }
+    }
+    fn lookup_dict_phrase(&self, syllables: &[Syllable], at_end: bool) -> Phrases<'_, '_> {
+        match syllables.split_last() {
+            Some((last, _)) if at_end && self.config.trailing_toneless && last.tone().is_none() => {
+                let mut variants = vec![syllables.to_vec()];
+                for tone in [
+                    Bopomofo::TONE5,
+                    Bopomofo::TONE2,
+                    Bopomofo::TONE3,
+                    Bopomofo::TONE4,
+                ] {
+                    let mut variant = syllables.to_vec();
+                    if let Some(last) = variant.last_mut() {
+                        last.update(tone);
+                    }
+                    variants.push(variant);
+                }
+                Box::new(
+                    variants
+                        .into_iter()
+                        .flat_map(move |variant| self.cached_lookup(&variant).collect::<Vec<_>>()),
+                )
+            }
+            _ => self.cached_lookup(syllables),
+        }
+    }
+    /// Looks up the dictionary through the shared cache, if any.
+    fn cached_lookup(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        match &self.shared_cache {
+            Some(cache) => {
+                let phrases = cache.get_or_insert_with(syllables, || {
+                    self.dict
+                        .lookup_phrase(syllables)
+                        .map(Phrase::into_owned)
+                        .collect()
+                });
+                Box::new((0..phrases.len()).map(move |i| phrases[i].clone()))
+            }
+            None => self.dict.lookup_phrase(syllables),
+        }
+    }
+    fn find_best_phrase(
+        &self,
+        start: usize,
+        syllables: &[Syllable],
+        selections: &[Interval],
+        breaks: &[Break],
+        at_end: bool,
+    ) -> Option<SpanPhrase<'_>> {
+        self.find_best_phrase_boosted(start, syllables, selections, breaks, at_end, None)
+    }
+    /// Same as `find_best_phrase` but adds the deltas of `boosts` to the
+    /// phrase frequencies.
+    fn find_best_phrase_boosted(
+        &self,
+        start: usize,
+        syllables: &[Syllable],
+        selections: &[Interval],
+        breaks: &[Break],
+        at_end: bool,
+        boosts: Option<&HashMap<String, i32>>,
+    ) -> Option<SpanPhrase<'_>> {
+        let end = start + syllables.len();
+
+        if !self.is_plausible_span(syllables) {
+            return None;
+        }
+
+        for br in breaks.iter() {
+            if br.0 > start && br.0 < end {
+                // There exists a break point that forbids connecting these
+                // syllables.
+                return None;
+            }
+        }
+
+        // Syllables are matched exactly, a neutral tone syllable only
+        // matches entries with the neutral tone.
+        let mut max_freq = 0;
+        let mut best_phrase = None;
+        let mut rare_phrase: Option<(i64, Phrase<'_>)> = None;
+        let remembered = self.homophone_memory.get(syllables);
+        'next_phrase: for phrase in self.lookup_phrase(syllables, at_end) {
+            // The selection checks below compare characters by syllable
+            // position, which needs one character per syllable.
+            debug_assert!(
+                phrase.as_str().chars().count() == syllables.len(),
+                "dictionary phrase {} doesn't have {} characters",
+                phrase,
+                syllables.len()
+            );
+            if !self.is_candidate_phrase(&phrase, syllables) {
+                continue 'next_phrase;
+            }
+            // If there exists a user selected interval which is a
+            // sub-interval of this phrase but the substring is
+            // different then we can skip this phrase. Selections may come
+            // from the output, so the alias of the phrase matches too.
+            let output = self.aliases.get(phrase.as_str());
+            for selection in selections.iter() {
+                debug_assert!(!selection.phrase.is_empty());
+                if start <= selection.start && end >= selection.end {
+                    let offset = selection.start - start;
+                    let len = selection.end - selection.start;
+                    let matches = |text: &str| {
+                        text.chars()
+                            .skip(offset)
+                            .take(len)
+                            .eq(selection.phrase.chars())
+                    };
+                    if !matches(phrase.as_str()) && !output.map_or(false, matches) {
+                        continue 'next_phrase;
+                    }
+                }
+            }
+
+            // A remembered choice acts like a selection of the span.
+            if remembered == Some(phrase.as_str()) {
+                return Some((Rc::new(phrase), false));
+            }
+
+            // If there are phrases that can satisfy all the constraints
+            // then pick the one with highest frequency.
+            let freq = self.phrase_weight(&phrase, boosts);
+            if phrase.freq() < self.config.min_freq {
+                // Rare single characters are still better than no character.
+                if syllables.len() == 1 && rare_phrase.as_ref().map_or(true, |(f, _)| freq > *f) {
+                    rare_phrase = Some((freq, phrase));
+                }
+                continue 'next_phrase;
+            }
+            if best_phrase.is_none() || freq > max_freq {
+                max_freq = freq;
+                best_phrase = Some(Rc::new(phrase));
+            }
+        }
+
+        if best_phrase.is_none() {
+            best_phrase = rare_phrase.map(|(_, phrase)| Rc::new(phrase));
+        }
+        if best_phrase.is_none() && syllables.len() == 1 {
+            // Nothing in the dictionary can be used for this syllable. Fall
+            // back to the selected character, or the syllable itself, so a
+            // complete path always exists.
+            let selected = selections
+                .iter()
+                .find(|selection| selection.start <= start && selection.end >= end)
+                .and_then(|selection| selection.phrase.chars().nth(start - selection.start))
+                .map(|ch| Phrase::new(String::from(ch), 0));
+            return Some(match selected {
+                Some(phrase) => (Rc::new(phrase), false),
+                None => (Rc::new(self.fallback_phrase(&syllables[0])), true),
+            });
+        }
+
+        best_phrase.map(|phrase| (phrase, false))
+    }
+    /// Returns whether the phrase can be chosen for the syllables, which
+    /// needs one character per syllable and a phrase not blacklisted.
+    fn is_candidate_phrase(&self, phrase: &Phrase<'_>, syllables: &[Syllable]) -> bool {
+        phrase.as_str().chars().count() == syllables.len()
+            && !self.blacklist.contains(phrase.as_str())
+    }
+    /// Returns the weight homophones are ranked by, the frequency of the
+    /// phrase with the user bias, category bonus and `boosts` added.
+    fn phrase_weight(&self, phrase: &Phrase<'_>, boosts: Option<&HashMap<String, i32>>) -> i64 {
+        i64::from(phrase.freq())
+            + i64::from(self.user_bias.get(phrase.as_str()))
+            + i64::from(self.category_bonus(phrase))
+            + i64::from(boost_of(boosts, phrase))
+    }
+    /// Returns the user supplied extra score of the phrase.
+    ///
+    /// This includes the bias of the user toward the phrase.
+    fn phrase_cost(&self, phrase: &Phrase<'_>, start: usize, end: usize) -> i32 {
+        self.user_bias.get(phrase.as_str())
+            + self.category_bonus(phrase)
+            + self
+                .phrase_cost
+                .as_ref()
+                .map_or(0, |cost| cost(phrase, start, end))
+    }
+    /// Returns an error if the dictionary has a phrase of the sequence with
+    /// a different number of characters than syllables.
+    fn check_phrases(&self, seq: SequenceView<'_>) -> Result<(), ConversionError> {
+        let len = seq.syllables.len();
+        for begin in 0..len {
+            for end in begin + 1..=len.min(begin + self.max_phrase_len()) {
+                let inconsistent = self
+                    .lookup_phrase(&seq.syllables[begin..end], end == len)
+                    .find(|phrase| phrase.as_str().chars().count() != end - begin);
+                if let Some(phrase) = inconsistent {
+                    return Err(ConversionError::InconsistentPhrase {
+                        phrase: phrase.as_str().to_owned(),
+                        len: end - begin,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Returns the bonus of the category of the phrase.
+    fn category_bonus(&self, phrase: &Phrase<'_>) -> i32 {
+        phrase
+            .category()
+            .and_then(|category| self.categories.get(category))
+            .copied()
+            .unwrap_or(0)
+    }
+    /// Returns the divisor of the frequency of a phrase at `start..end`.
+    fn freq_divisor(&self, start: usize, end: usize, len: usize) -> u32 {
+        if end - start != 1 || self.config.length_weights.is_some() {
+            return 1;
+        }
+        self.single_char_penalty
+            .as_ref()
+            .map_or(DEFAULT_SINGLE_CHAR_PENALTY, |penalty| {
+                penalty(start, end, len)
+            })
+            .max(1)
+    }
+    /// Returns an empty path scored with the rules of the engine.
+    fn new_path<'a>(&self) -> PossiblePath<'a> {
+        PossiblePath::new(
+            self.config.freq_rule,
+            self.config.score_mode,
+            self.config.length_variance,
+            self.config.freq_cap,
+            self.config.freq_smoothing,
+            self.config.length_weights.clone(),
+        )
+    }
+    /// Returns the number of syllables of the longest span worth looking up.
+    ///
+    /// The length is read from the dictionary when the engine is created.
+    fn max_phrase_len(&self) -> usize {
+        self.max_phrase_len
+    }
+    fn find_intervals(&self, seq: &ChineseSequence) -> Vec<PossibleInterval<'_>> {
+        let mut intervals = vec![];
+        self.find_intervals_into(seq, &mut intervals);
+        intervals
+    }
+    /// Same as `find_intervals` but appends to an existing vector.
+    fn find_intervals_into<'a>(
+        &'a self,
+        seq: &ChineseSequence,
+        intervals: &mut Vec<PossibleInterval<'a>>,
+    ) {
+        self.find_intervals_until(seq.into(), None, None, intervals);
+        self.finish_intervals(seq.into(), intervals);
+    }
+    /// Appends the dictionary intervals starting at each position until
+    /// `deadline` passes, and returns the first position not looked up.
+    fn find_intervals_until<'a>(
+        &'a self,
+        seq: SequenceView<'_>,
+        deadline: Option<Instant>,
+        boosts: Option<&HashMap<String, i32>>,
+        intervals: &mut Vec<PossibleInterval<'a>>,
+    ) -> usize {
+        let max_len = self.max_phrase_len();
+        for begin in 0..seq.syllables.len() {
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                return begin;
+            }
+            for end in begin + 1..=seq.syllables.len().min(begin + max_len) {
+                if let Some((phrase, unknown)) = self.find_best_phrase_boosted(
+                    begin,
+                    &seq.syllables[begin..end],
+                    seq.selections,
+                    seq.breaks,
+                    end == seq.syllables.len(),
+                    boosts,
+                ) {
+                    intervals.push(PossibleInterval {
+                        start: begin,
+                        end,
+                        bonus: self
+                            .phrase_cost(&phrase, begin, end)
+                            .saturating_add(boost_of(boosts, &phrase)),
+                        freq_divisor: self.freq_divisor(begin, end, seq.syllables.len()),
+                        unknown,
+                        phrase,
+                    });
+                }
+            }
+        }
+        seq.syllables.len()
+    }
+    /// Prunes weak merges and applies the pinned selections and atomic phrases.
+    fn finish_intervals(&self, seq: SequenceView<'_>, intervals: &mut Vec<PossibleInterval<'_>>) {
+        self.prune_weak_merges(seq, intervals);
+        if self.config.pin_selections {
+            Self::pin_intervals(seq.selections, intervals);
+        }
+        self.force_atomic_phrases(seq, intervals);
+    }
+    /// Removes the phrases that are not frequent enough compared to their
+    /// parts under the merge gain ratio.
+    fn prune_weak_merges(&self, seq: SequenceView<'_>, intervals: &mut Vec<PossibleInterval<'_>>) {
+        let ratio = match self.config.merge_gain_ratio {
+            Some(ratio) => ratio,
+            None => return,
+        };
+        let mut freqs: HashMap<(usize, usize), u64> = HashMap::new();
+        for interval in intervals.iter().filter(|interval| !interval.unknown) {
+            let freq = freqs.entry((interval.start, interval.end)).or_default();
+            *freq = (*freq).max(u64::from(interval.phrase.freq()));
+        }
+        let weak: HashSet<(usize, usize)> = intervals
+            .iter()
+            .filter(|interval| interval.end - interval.start > 1 && !interval.unknown)
+            .filter(|interval| {
+                !seq.selections
+                    .iter()
+                    .any(|sel| sel.start == interval.start && sel.end == interval.end)
+            })
+            .filter(|interval| {
+                let parts = best_split_freq(&freqs, interval.start, interval.end);
+                parts > 0 && f64::from(interval.phrase.freq()) < ratio * parts as f64
+            })
+            .map(|interval| (interval.start, interval.end))
+            .collect();
+        intervals.retain(|interval| !weak.contains(&(interval.start, interval.end)));
+    }
+    /// Replaces the intervals overlapping a match of an atomic phrase with
+    /// one interval of the atomic phrase.
+    fn force_atomic_phrases(
+        &self,
+        seq: SequenceView<'_>,
+        intervals: &mut Vec<PossibleInterval<'_>>,
+    ) {
+        if self.atomic_phrases.is_empty() {
+            return;
+        }
+        let len = seq.syllables.len();
+        let overlaps =
+            |start: usize, end: usize, other: &Interval| start < other.end && end > other.start;
+        let mut matches: Vec<(usize, usize, &str)> = vec![];
+        let mut start = 0;
+        while start < len {
+            let found = self
+                .atomic_phrases
+                .iter()
+                .filter(|(syllables, _)| seq.syllables[start..].starts_with(syllables))
+                .map(|(syllables, phrase)| (start + syllables.len(), phrase.as_str()))
+                .filter(|&(end, _)| {
+                    !seq.breaks.iter().any(|br| br.0 > start && br.0 < end)
+                        && !seq.selections.iter().any(|sel| overlaps(start, end, sel))
+                })
+                .max_by_key(|&(end, _)| end);
+            match found {
+                Some((end, phrase)) => {
+                    matches.push((start, end, phrase));
+                    start = end;
+                }
+                None => start += 1,
+            }
+        }
+        for (start, end, phrase) in matches {
+            intervals.retain(|interval| interval.end <= start || interval.start >= end);
+            intervals.push(PossibleInterval {
+                start,
+                end,
+                phrase: Rc::new(Phrase::new(phrase.to_string(), 0)),
+                bonus: 0,
+                freq_divisor: 1,
+                unknown: false,
+            });
+        }
+    }
+    /// Returns whether the length index allows a phrase for the syllables.
+    ///
+    /// Single syllables are always plausible so the fallback phrase can be
+    /// used.
+    fn is_plausible_span(&self, syllables: &[Syllable]) -> bool {
+        match (&self.length_index, syllables.first()) {
+            (Some(index), Some(first)) if syllables.len() > 1 => index
+                .get(first)
+                .map_or(false, |lengths| lengths.contains(&syllables.len())),
+            _ => true,
+        }
+    }
+    /// Returns the placeholder phrase of a syllable without phrases.
+    fn fallback_phrase(&self, syllable: &Syllable) -> Phrase<'static> {
+        let text = match &self.fallback_renderer {
+            Some(renderer) => renderer.render(syllable),
+            None => ZhuyinFallback.render(syllable),
+        };
+        Phrase::new(text, self.config.unknown_syllable_freq)
+    }
+    /// Keeps only the intervals that agree with the selection boundaries.
+    ///
+    /// Intervals overlapping a selection are removed unless they match the
+    /// selection exactly, so the best path has to route through every
+    /// selection. A selection missing from the dictionary is added as is.
+    fn pin_intervals(selections: &[Interval], intervals: &mut Vec<PossibleInterval<'_>>) {
+        intervals.retain(|interval| {
+            selections.iter().all(|selection| {
+                (interval.start == selection.start && interval.end == selection.end)
+                    || interval.end <= selection.start
+                    || interval.start >= selection.end
+            })
+        });
+        for selection in selections {
+            if !intervals
+                .iter()
+                .any(|it| it.start == selection.start && it.end == selection.end)
+            {
+                intervals.push(PossibleInterval {
+                    start: selection.start,
+                    end: selection.end,
+                    phrase: Rc::new(Phrase::new(selection.phrase.clone(), 0)),
+                    bonus: 0,
+                    freq_divisor: 1,
+                    unknown: false,
+                });
+            }
+        }
+    }
+    /// Runs the steps of [`ConversionEngine::convert`], calling `adjust` on
+    /// the intervals before the best path is searched.
+    ///
+    /// The phrases are chosen with `boosts` added to their frequency. Pinned
+    /// selections covering the whole sequence are returned without calling
+    /// `adjust`.
+    fn convert_adjusted<'a, F>(
+        &'a self,
+        segment: SequenceView<'_>,
+        boosts: Option<&HashMap<String, i32>>,
+        adjust: F,
+    ) -> Result<Vec<Interval>, ConversionError>
+    where
+        F: FnOnce(&mut Vec<PossibleInterval<'a>>),
+    {
+        self.convert_adjusted_with(segment, boosts, adjust, |intervals| Ok(intervals.collect()))
+    }
+    /// Same as `convert_adjusted` but passes the intervals of the result to
+    /// `output` as an iterator that builds each interval when it is read.
+    fn convert_adjusted_with<'a, F, O, R>(
+        &'a self,
+        segment: SequenceView<'_>,
+        boosts: Option<&HashMap<String, i32>>,
+        adjust: F,
+        output: O,
+    ) -> Result<R, ConversionError>
+    where
+        F: FnOnce(&mut Vec<PossibleInterval<'a>>),
+        O: FnOnce(&mut dyn ExactSizeIterator<Item = Interval>) -> Result<R, ConversionError>,
+    {
+        let len = segment.syllables.len();
+        if len == 0 {
+            return output(&mut std::iter::empty());
+        }
+        check_len(len)?;
+        if self.config.checked {
+            self.check_phrases(segment)?;
+        }
+        if let Some(selections) = self.pinned_selections(segment) {
+            return output(
+                &mut selections
+                    .into_iter()
+                    .map(|selection| self.pinned_interval(selection)),
+            );
+        }
+        let mut intervals = vec![];
+        self.find_intervals_until(segment, None, boosts, &mut intervals);
+        self.finish_intervals(segment, &mut intervals);
+        adjust(&mut intervals);
+        let path = self.best_path_in(len, &mut intervals, &mut vec![])?;
+        output(
+            &mut path
+                .intervals
+                .into_iter()
+                .map(|interval| self.to_interval(interval)),
+        )
+    }
+    /// Calculate the best path with dynamic programming.
+    ///
+    /// Assume P(x,y) is the highest score phrasing result from x to y. The
+    /// following is formula for P(x,y):
+    ///
+    /// P(x,y) = MAX( P(x,y-1)+P(y-1,y), P(x,y-2)+P(y-2,y), ... )
+    ///
+    /// While P(x,y-1) is stored in highest_score array, and P(y-1,y) is
+    /// interval end at y. In this formula, x is always 0.
+    ///
+    /// The format of highest_score array is described as following:
+    ///
+    /// highest_score[0] = P(0,0)
+    /// highest_score[1] = P(0,1)
+    /// ...
+    /// highest_score[y-1] = P(0,y-1)
+    fn find_best_path(
+        &self,
+        len: usize,
+        mut intervals: Vec<PossibleInterval<'_>>,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        self.find_best_path_in(len, &mut intervals, &mut vec![])
+    }
+    /// Same as `find_best_path` but uses `intervals` and `highest_score` as
+    /// scratch buffers. `intervals` is drained.
+    fn find_best_path_in<'a>(
+        &self,
+        len: usize,
+        intervals: &mut Vec<PossibleInterval<'a>>,
+        highest_score: &mut Vec<PossiblePath<'a>>,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        Ok(self
+            .best_path_in(len, intervals, highest_score)?
+            .intervals
+            .into_iter()
+            .map(|interval| self.to_interval(interval))
+            .collect())
+    }
+    /// Same as `find_best_path_in` but returns the path before its intervals
+    /// are output.
+    fn best_path_in<'a>(
+        &self,
+        len: usize,
+        intervals: &mut Vec<PossibleInterval<'a>>,
+        highest_score: &mut Vec<PossiblePath<'a>>,
+    ) -> Result<PossiblePath<'a>, ConversionError> {
+        check_len(len)?;
+        if !self.fill_highest_score(len, intervals, highest_score) {
+            return Err(ConversionError::EmptyPath { len });
+        }
+        highest_score
+            .pop()
+            .ok_or(ConversionError::EmptyPath { len })
+    }
+    /// Returns the selections as the result if they are pinned and cover the
+    /// whole sequence, which fully determines the phrasing.
+    ///
+    /// Unpinned selections may still be merged into longer phrases, so the
+    /// full search is needed for them.
+    fn pinned_selections<'s>(&self, seq: SequenceView<'s>) -> Option<Vec<&'s Interval>> {
+        if !self.config.pin_selections {
+            return None;
+        }
+        let mut selections: Vec<_> = seq.selections.iter().collect();
+        selections.sort_by_key(|selection| selection.start);
+        let mut pos = 0;
+        for selection in &selections {
+            if selection.start != pos || selection.end <= pos {
+                return None;
+            }
+            pos = selection.end;
+        }
+        if pos != seq.syllables.len() {
+            return None;
+        }
+        Some(selections)
+    }
+    /// Converts a pinned selection to the output, applying the aliases.
+    fn pinned_interval(&self, selection: &Interval) -> Interval {
+        Interval {
+            start: selection.start,
+            end: selection.end,
+            phrase: self
+                .aliases
+                .get(&selection.phrase)
+                .unwrap_or(&selection.phrase)
+                .to_string(),
+            unknown: false,
+        }
+    }
+    /// Returns the dictionary phrase under `syllables` that is output as
+    /// `output`, directly or through its alias.
+    ///
+    /// When several phrases are output the same, the one conversion would
+    /// choose is returned, so the output maps back to the phrase it came
+    /// from.
+    fn find_output_phrase(
+        &self,
+        syllables: &[Syllable],
+        at_end: bool,
+        output: &str,
+    ) -> Option<Phrase<'_>> {
+        self.lookup_phrase(syllables, at_end)
+            .filter(|phrase| {
+                self.is_candidate_phrase(phrase, syllables)
+                    && (phrase.as_str() == output
+                        || self.aliases.get(phrase.as_str()) == Some(output))
+            })
+            .fold(None, |best: Option<Phrase<'_>>, phrase| match best {
+                Some(best)
+                    if self.phrase_weight(&best, None) >= self.phrase_weight(&phrase, None) =>
+                {
+                    Some(best)
+                }
+                _ => Some(phrase),
+            })
+    }
+    /// Converts an interval of a path to the output, applying the aliases.
+    fn to_interval(&self, interval: PossibleInterval<'_>) -> Interval {
+        let mut interval = Interval::from(interval);
+        if let Some(canonical) = self.aliases.get(&interval.phrase) {
+            interval.phrase = canonical.to_string();
+        }
+        interval
+    }
+    /// Runs the dynamic programming of `find_best_path`, leaving the best
+    /// path ending at each position in `highest_score`.
+    ///
+    /// Returns whether any path reaches the end of the sequence.
+    fn fill_highest_score<'a>(
+        &self,
+        len: usize,
+        intervals: &mut Vec<PossibleInterval<'a>>,
+        highest_score: &mut Vec<PossiblePath<'a>>,
+    ) -> bool {
+        self.fill_highest_score_with(len, intervals, highest_score, &self.bigram)
+    }
+    /// Same as `fill_highest_score` but takes the bigram bonuses from
+    /// `bigram`.
+    fn fill_highest_score_with<'a>(
+        &self,
+        len: usize,
+        intervals: &mut Vec<PossibleInterval<'a>>,
+        highest_score: &mut Vec<PossiblePath<'a>>,
+        bigram: &Bigram,
+    ) -> bool {
+        highest_score.clear();
+        highest_score.resize(len + 1, self.new_path());
+
+        // The interval shall be sorted by the increase order of end.
+        intervals.sort_by(|a, b| a.end.cmp(&b.end));
+        dedup_intervals(intervals);
+
+        // With bigrams the score of an interval depends on the previous
+        // one, so the best path ending with each interval is kept per
+        // position.
+        let track_context = !bigram.is_empty();
+        let mut ending_paths: Vec<Vec<PossiblePath<'a>>> = vec![];
+        if track_context {
+            ending_paths.resize(len + 1, vec![]);
+            ending_paths[0].push(self.new_path());
+        }
+
+        let mut reachable = vec![false; len + 1];
+        reachable[0] = true;
+        for interval in intervals.drain(..) {
+            let start = interval.start;
+            let end = interval.end;
+
+            // Paths can only be extended from positions some path reaches.
+            if !reachable[start] {
+                continue;
+            }
+            // The first path reaching a position always replaces the empty
+            // placeholder, even with a negative score.
+            let first_path = !reachable[end];
+            reachable[end] = true;
+
+            let candidate_path = if track_context {
+                let mut best: Option<PossiblePath<'a>> = None;
+                for path in &ending_paths[start] {
+                    let candidate = Self::extend_path_with(bigram, path, interval.clone());
+                    if best
+                        .as_ref()
+                        .map_or(true, |b| self.is_better(b, &candidate))
+                    {
+                        best = Some(candidate);
+                    }
+                }
+                match best {
+                    Some(best) => {
+                        ending_paths[end].push(best.clone());
+                        best
+                    }
+                    None => continue,
+                }
+            } else {
+                Self::extend_path_with(bigram, &highest_score[start], interval)
+            };
+
+            if first_path || self.is_better(&highest_score[end], &candidate_path) {
+                highest_score[end] = candidate_path;
+            }
+        }
+        reachable[len]
+    }
+    /// Returns `path` followed by `interval`, with the bigram bonus of the
+    /// last phrase of `path` and `interval` applied.
+    fn extend_path<'a>(
+        &self,
+        path: &PossiblePath<'a>,
+        interval: PossibleInterval<'a>,
+    ) -> PossiblePath<'a> {
+        Self::extend_path_with(&self.bigram, path, interval)
+    }
+    /// Same as `extend_path` but takes the bonuses from `bigram`.
+    fn extend_path_with<'a>(
+        bigram: &Bigram,
+        path: &PossiblePath<'a>,
+        mut interval: PossibleInterval<'a>,
+    ) -> PossiblePath<'a> {
+        let mut path = path.clone();
+        if let Some(prev) = path.intervals.last() {
+            let bonus = bigram.get(prev.phrase.as_str(), interval.phrase.as_str());
+            interval.bonus = interval.bonus.saturating_add(bonus);
+        }
+        path.intervals.push(interval);
+        path
+    }
+    /// Returns whether `candidate` should replace `current` as the best path.
+    fn is_better(&self, current: &PossiblePath<'_>, candidate: &PossiblePath<'_>) -> bool {
+        match current.score().cmp(&candidate.score()) {
+            std::cmp::Ordering::Less => true,
+            std::cmp::Ordering::Equal => {
+                if self.config.prefer_consistent_width {
+                    let (current_width, candidate_width) = (
+                        current.rule_consistent_width(),
+                        candidate.rule_consistent_width(),
+                    );
+                    if current_width != candidate_width {
+                        return candidate_width > current_width;
+                    }
+                }
+                if self.config.prefer_early_boundary {
+                    let (current_ends, candidate_ends) =
+                        (current.boundaries(), candidate.boundaries());
+                    if current_ends != candidate_ends {
+                        return candidate_ends < current_ends;
+                    }
+                }
+                candidate.tie_break_key(self.config.seed) > current.tie_break_key(self.config.seed)
+            }
+            std::cmp::Ordering::Greater => false,
+        }
+    }
+
+    #[cfg(test)]
+    fn find_all_paths(
+        &self,
+        graph: &mut Graph,
+        sequence: &ChineseSequence,
+        start: usize,
+        target: usize,
+        prefix: Option<PossiblePath<'static>>,
+    ) -> Vec<PossiblePath<'static>> {
+        let mut result = vec![];
+        self.visit_paths(graph, sequence, start, target, prefix, &mut |path| {
+            result.push(path)
+        });
+        result
+    }
+
+    /// Returns whether the phrasing found by the dynamic programming has the
+    /// highest score of all the phrasings enumerated by brute force.
+    ///
+    /// Enumeration is exponential in the length, so only use short
+    /// sequences.
+    #[cfg(test)]
+    fn verify_dp_optimality(&self, sequence: &ChineseSequence) -> bool {
+        let len = sequence.syllables.len();
+        let best = self
+            .convert(sequence)
+            .ok()
+            .and_then(|intervals| self.score_segmentation(sequence, &intervals));
+        let brute_force = self
+            .find_all_paths(&mut Graph::default(), sequence, 0, len, None)
+            .iter()
+            .map(PossiblePath::score)
+            .max();
+        best.is_some() && best == brute_force
+    }
+
+    /// Calls `visit` with every complete path from `start` to `target`.
+    ///
+    /// Paths are produced one by one so callers can reduce them on the fly
+    /// instead of holding every path in memory.
+    fn visit_paths(
+        &self,
+        graph: &mut Graph,
+        sequence: &ChineseSequence,
+        start: usize,
+        target: usize,
+        prefix: Option<PossiblePath<'static>>,
+        visit: &mut dyn FnMut(PossiblePath<'static>),
+    ) {
+        let max_len = self.max_phrase_len();
+        self.visit_paths_up_to(graph, sequence, start..target, max_len, prefix, visit);
+    }
+
+    /// Calls `visit` with every complete path over `span` made of phrases of
+    /// at most `max_len` syllables.
+    fn visit_paths_up_to(
+        &self,
+        graph: &mut Graph,
+        sequence: &ChineseSequence,
+        span: Range<usize>,
+        max_len: usize,
+        prefix: Option<PossiblePath<'static>>,
+        visit: &mut dyn FnMut(PossiblePath<'static>),
+    ) {
+        let Range { start, end: target } = span;
+        if start == target {
+            if let Some(prefix) = prefix {
+                visit(prefix);
+            }
+            return;
+        }
+        for end in start + 1..=target.min(start + max_len) {
+            let entry = graph.entry((start, end));
+            if let Some((phrase, unknown)) = entry.or_insert_with(|| {
+                self.find_best_phrase(
+                    start,
+                    &sequence.syllables[start..end],
+                    &sequence.selections,
+                    &sequence.breaks,
+                    end == sequence.syllables.len(),
+                )
+                .map(|(phrase, unknown)| (Rc::new(phrase.as_ref().clone().into_owned()), unknown))
+            }) {
+                let prefix = prefix.clone().unwrap_or_else(|| self.new_path());
+                let interval = PossibleInterval {
+                    start,
+                    end,
+                    phrase: phrase.clone(),
+                    bonus: self.phrase_cost(phrase, start, end),
+                    freq_divisor: self.freq_divisor(start, end, sequence.syllables.len()),
+                    unknown: *unknown,
+                };
+                let prefix = self.extend_path(&prefix, interval);
+                self.visit_paths_up_to(graph, sequence, end..target, max_len, Some(prefix), visit);
+            }
+        }
+    }
+
+    /// Returns the trimmed paths ordered from the highest score to the lowest.
+    fn find_candidate_paths(
+        &self,
+        graph: &mut Graph,
+        segment: &ChineseSequence,
+    ) -> Vec<PossiblePath<'static>> {
+        // Trim while the paths are enumerated so only the retained paths are
+        // kept in memory.
+        let mut trimmed_paths = vec![];
+        self.visit_paths(
+            graph,
+            segment,
+            0,
+            segment.syllables.len(),
+            None,
+            &mut |path| Self::trim_insert(&mut trimmed_paths, path),
+        );
+        trimmed_paths.sort();
+        trimmed_paths.reverse();
+        if self.config.prefer_early_boundary {
+            // The sort is stable so other ties keep their order.
+            trimmed_paths.sort_by(|a, b| {
+                b.score()
+                    .cmp(&a.score())
+                    .then_with(|| a.boundaries().cmp(&b.boundaries()))
+            });
+        }
+        if self.language_model.is_some() {
+            let mut ranked: Vec<_> = trimmed_paths
+                .into_iter()
+                .map(|path| {
+                    let intervals: Vec<Interval> = path
+                        .intervals
+                        .iter()
+                        .cloned()
+                        .map(|it| self.to_interval(it))
+                        .collect();
+                    (self.ranking_score(path.score(), &intervals), path)
+                })
+                .collect();
+            // The sort is stable so ties keep the rule based order.
+            ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+            trimmed_paths = ranked.into_iter().map(|(_, path)| path).collect();
+        }
+        trimmed_paths
+    }
+
+    /// Trim some paths that were part of other paths
+    ///
+    /// Ported from original C implementation, but the original algorithm seems wrong.
+    #[cfg(test)]
+    fn trim_paths<'a>(&self, paths: Vec<PossiblePath<'a>>) -> Vec<PossiblePath<'a>> {
+        let mut trimmed_paths: Vec<PossiblePath<'_>> = vec![];
+        for candidate in paths.into_iter() {
+            Self::trim_insert(&mut trimmed_paths, candidate);
+        }
+        trimmed_paths
+    }
+
+    /// Returns the score candidate paths are ranked by, the rule based
+    /// `score` plus the weighted language model score of `intervals`.
+    fn ranking_score(&self, score: i64, intervals: &[Interval]) -> f64 {
+        match &self.language_model {
+            Some(language_model) => {
+                score as f64 + self.config.language_model_weight * language_model(intervals)
+            }
+            None => score as f64,
+        }
+    }
+
+    /// Adds `candidate` to the trimmed paths, one step of `trim_paths`.
+    fn trim_insert<'a>(trimmed_paths: &mut Vec<PossiblePath<'a>>, candidate: PossiblePath<'a>) {
+        trace!("Trim check {}", candidate);
+        let mut drop_candidate = false;
+        let mut keeper = vec![];
+        for p in trimmed_paths.drain(..) {
+            if drop_candidate || p.contains(&candidate) {
+                drop_candidate = true;
+                trace!("  Keep {}", p);
+                keeper.push(p);
+                continue;
+            }
+            if candidate.contains(&p) {
+                trace!("  Drop {}", p);
+                continue;
+            }
+            trace!("  Keep {}", p);
+            keeper.push(p);
+        }
+        if !drop_candidate {
+            trace!("  Keep {}", candidate);
+            keeper.push(candidate);
+        }
+        *trimmed_paths = keeper;
+    }
+}
+
+/// Returns the delta of `phrase` in the boosts of one conversion.
+fn boost_of(boosts: Option<&HashMap<String, i32>>, phrase: &Phrase<'_>) -> i32 {
+    boosts
+        .and_then(|boosts| boosts.get(phrase.as_str()))
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Score penalty of each interval in [`ScoreMode::FewestSegments`], large
+/// enough to outweigh the other rules.
+const FEWEST_SEGMENTS_PENALTY: i64 = -(1 << 48);
+
+/// Number of phrasings [`ChewingConversionEngine::convert_to_width`] chooses
+/// from.
+const WIDTH_CANDIDATES: usize = 16;
+
+/// How far below the best score [`ChewingConversionEngine::convert_to_width`]
+/// accepts a phrasing for its width.
+const WIDTH_SCORE_MARGIN: i64 = 500;
+
+/// Bigram bonus of a forbidden pair of adjacent phrases.
+const ANTI_BIGRAM_PENALTY: i32 = i32::MIN;
+
+/// Score bonus for an interval aligned with a segmentation hint.
+const SEGMENTATION_HINT_BONUS: i32 = 100;
+
+/// Score bonus for the phrase of a suggested selection.
+const SUGGESTED_SELECTION_BONUS: i32 = 1000;
+
+/// Score bonus for a boundary of probability 1 above a neutral boundary,
+/// scaled by the excess of the probability over 0.5.
+const BOUNDARY_PROB_WEIGHT: f32 = 20_000.0;
+
+/// The borrowed syllables, selections and breaks of a sequence, so the
+/// conversion steps can run on sub-slices of a larger buffer.
+#[derive(Clone, Copy)]
+struct SequenceView<'a> {
+    syllables: &'a [Syllable],
+    selections: &'a [Interval],
+    breaks: &'a [Break],
+}
+
+impl<'a> From<&'a ChineseSequence> for SequenceView<'a> {
+    fn from(seq: &'a ChineseSequence) -> Self {
+        SequenceView {
+            syllables: &seq.syllables,
+            selections: &seq.selections,
+            breaks: &seq.breaks,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct PossibleInterval<'a> {
+    start: usize,
+    end: usize,
+    phrase: Rc<Phrase<'a>>,
+    /// Extra score added on top of the rule based score.
+    bonus: i32,
+    /// Divisor of the phrase frequency under [`FreqRule::Raw`].
+    freq_divisor: u32,
+    /// Whether the phrase is a placeholder for a syllable missing from the
+    /// dictionary.
+    unknown: bool,
+}
+
+impl PossibleInterval<'_> {
+    fn contains(&self, other: &PossibleInterval<'_>) -> bool {
+        self.start <= other.start && self.end >= other.end
+    }
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+impl From<PossibleInterval<'_>> for Interval {
+    fn from(value: PossibleInterval<'_>) -> Self {
+        Interval {
+            start: value.start,
+            end: value.end,
+            phrase: value.phrase.to_string(),
+            unknown: value.unknown,
+        }
+    }
+}
+
+/// Removes the repeated intervals of `intervals`, which must be sorted by
+/// `end`, keeping the first of each.
+///
+/// Equal intervals share the same `end`, so only intervals within one run of
+/// the same `end` are compared and the order of the rest is kept.
+fn dedup_intervals(intervals: &mut Vec<PossibleInterval<'_>>) {
+    let mut kept: Vec<PossibleInterval<'_>> = Vec::with_capacity(intervals.len());
+    let mut run_start = 0;
+    for interval in intervals.drain(..) {
+        if kept.last().map_or(false, |last| last.end != interval.end) {
+            run_start = kept.len();
+        }
+        if !kept[run_start..].contains(&interval) {
+            kept.push(interval);
+        }
+    }
+    *intervals = kept;
+}
+
+/// Rejects sequences too long to be scored.
+fn check_len(len: usize) -> Result<(), ConversionError> {
+    if len > MAX_SEQUENCE_LEN {
+        return Err(ConversionError::ScoreOverflow {
+            len,
+            max: MAX_SEQUENCE_LEN,
+        });
+    }
+    Ok(())
+}
+
+/// Returns the `next`-th path of `paths`, cycling when `next` is past the
+/// end.
+fn nth_candidate(
+    paths: Vec<PossiblePath<'_>>,
+    next: usize,
+) -> Result<PossiblePath<'_>, ConversionError> {
+    paths
+        .into_iter()
+        .cycle()
+        .nth(next)
+        .ok_or(ConversionError::MissingCandidate)
+}
+
+/// Buffers reused between conversions by `convert_batch`.
+#[derive(Default)]
+struct Scratch<'a> {
+    intervals: Vec<PossibleInterval<'a>>,
+    highest_score: Vec<PossiblePath<'a>>,
+}
+
+#[derive(Default, Clone)]
+struct PossiblePath<'a> {
+    intervals: Vec<PossibleInterval<'a>>,
+    freq_rule: FreqRule,
+    score_mode: ScoreMode,
+    length_variance: bool,
+    freq_cap: u32,
+    freq_smoothing: u32,
+    length_weights: Option<Arc<[f64]>>,
+}
+
+impl Debug for PossiblePath<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PossiblePath")
+            .field("score()", &self.score())
+            .field("intervals", &self.intervals)
+            .finish()
+    }
+}
+
+impl PossiblePath<'_> {
+    fn new(
+        freq_rule: FreqRule,
+        score_mode: ScoreMode,
+        length_variance: bool,
+        freq_cap: u32,
+        freq_smoothing: u32,
+        length_weights: Option<Arc<[f64]>>,
+    ) -> Self {
+        PossiblePath {
+            intervals: vec![],
+            freq_rule,
+            score_mode,
+            length_variance,
+            freq_cap,
+            freq_smoothing,
+            length_weights,
+        }
+    }
+
+    /// Returns the score of the path.
+    ///
+    /// The score is accumulated in `i64` and saturates instead of
+    /// overflowing, so dictionaries with very large frequencies still produce
+    /// a comparable score.
+    fn score(&self) -> i64 {
+        let bonus: i64 = self.intervals.iter().map(|it| i64::from(it.bonus)).sum();
+        [
+            1000 * i64::from(self.rule_largest_sum()),
+            1000 * i64::from(self.rule_largest_avgwordlen()),
+            100 * i64::from(self.rule_smallest_lenvariance()),
+            self.rule_largest_freqsum(),
+            bonus,
+            self.rule_fewest_segments(),
+        ]
+        .into_iter()
+        .fold(0, i64::saturating_add)
+    }
+
+    /// Returns a large penalty per interval in [`ScoreMode::FewestSegments`].
+    fn rule_fewest_segments(&self) -> i64 {
+        match self.score_mode {
+            ScoreMode::Balanced => 0,
+            ScoreMode::FewestSegments => {
+                (self.intervals.len() as i64).saturating_mul(FEWEST_SEGMENTS_PENALTY)
+            }
+        }
+    }
+
+    /// Copied from IsRecContain to trim some paths
+    fn contains(&self, other: &Self) -> bool {
+        let mut big = 0;
+        for sml in 0..other.intervals.len() {
+            loop {
+                if big < self.intervals.len()
+                    && self.intervals[big].start < other.intervals[sml].end
+                {
+                    if self.intervals[big].contains(&other.intervals[sml]) {
+                        break;
+                    }
+                } else {
+                    return false;
+                }
+                big += 1;
+            }
+        }
+        true
+    }
+
+    /// Returns the end positions of the intervals from left to right.
+    fn boundaries(&self) -> Vec<usize> {
+        self.intervals.iter().map(|interval| interval.end).collect()
+    }
+    /// Returns a pseudo random key of the phrasing for breaking ties.
+    fn tie_break_key(&self, seed: u64) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        for interval in &self.intervals {
+            (interval.start, interval.end, interval.phrase.as_str()).hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+    /// Returns the values of the rules weighed by [`score`][Self::score].
+    fn features(&self) -> [i32; 4] {
+        [
+            self.rule_largest_sum(),
+            self.rule_largest_avgwordlen(),
+            self.rule_smallest_lenvariance(),
+            i32::try_from(self.rule_largest_freqsum()).unwrap_or(i32::MAX),
+        ]
+    }
+
+    fn rule_largest_sum(&self) -> i32 {
+        let mut score = 0;
+        for interval in &self.intervals {
+            score += interval.end - interval.start;
+        }
+        score as i32
+    }
+
+    fn rule_largest_avgwordlen(&self) -> i32 {
+        if self.intervals.is_empty() {
+            return 0;
+        }
+        // Constant factor 6=1*2*3, to keep value as integer
+        // Sequences are at most MAX_SEQUENCE_LEN long so the counts fit.
+        6 * self.rule_largest_sum() / i32::try_from(self.intervals.len()).unwrap_or(i32::MAX)
+    }
+
+    fn rule_smallest_lenvariance(&self) -> i32 {
+        if !self.length_variance {
+            return 0;
+        }
+        // kcwu: heuristic? why variance no square function?
+        //
+        // The sum of the length differences of every pair is computed from
+        // the sorted lengths: each length differs from all shorter ones by
+        // its multiple minus their running sum.
+        let mut lengths: Vec<_> = self.intervals.iter().map(|it| it.len()).collect();
+        lengths.sort_unstable();
+        let mut score = 0;
+        let mut shorter = 0;
+        for (i, len) in lengths.into_iter().enumerate() {
+            score += len * i - shorter;
+            shorter += len;
+        }
+        i32::try_from(score).unwrap_or(i32::MAX).neg()
+    }
+
+    /// Returns minus the number of interval boundaries between a half-width
+    /// and a full-width character.
+    fn rule_consistent_width(&self) -> i32 {
+        let mut score = 0;
+        for pair in self.intervals.windows(2) {
+            let left = pair[0].phrase.as_str().chars().last();
+            let right = pair[1].phrase.as_str().chars().next();
+            if let (Some(left), Some(right)) = (left, right) {
+                if left.is_ascii() != right.is_ascii() {
+                    score -= 1;
+                }
+            }
+        }
+        score
+    }
+
+    fn rule_largest_freqsum(&self) -> i64 {
+        let mut score: i64 = 0;
+        for interval in &self.intervals {
+            let freq = interval
+                .phrase
+                .freq()
+                .min(self.freq_cap)
+                .max(self.freq_smoothing);
+            let contribution = match self.freq_rule {
+                FreqRule::Raw => match &self.length_weights {
+                    Some(weights) => {
+                        let weight = weights
+                            .get(interval.len() - 1)
+                            .or_else(|| weights.last())
+                            .copied()
+                            .unwrap_or(1.0);
+                        (f64::from(freq) * weight) as i64
+                    }
+                    None => i64::from(freq / interval.freq_divisor),
+                },
+                FreqRule::LogPerSyllable => {
+                    // Number of significant bits is floor(log2(freq)) + 1.
+                    let log_freq = u32::BITS - freq.leading_zeros();
+                    i64::from(log_freq) * interval.len() as i64
+                }
+            };
+            score = score.saturating_add(contribution);
+        }
+        score
+    }
+}
+
+impl PartialEq for PossiblePath<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.score() == other.score()
+    }
+}
+
+impl Eq for PossiblePath<'_> {}
+
+impl PartialOrd for PossiblePath<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.score().partial_cmp(&other.score())
+    }
+}
+
+impl Ord for PossiblePath<'_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.score().cmp(&other.score())
+    }
+}
+
+impl Display for PossiblePath<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#PossiblePath({}", self.score())?;
+        for interval in &self.intervals {
+            write!(
+                f,
+                " ({} {} '{})",
+                interval.start, interval.end, interval.phrase
+            )?;
+        }
+        write!(f, ")")?;
+        Ok(())
+    }
+}
+
+/// The phrase chosen for a span, and whether it is the placeholder of an
+/// unknown syllable.
+type SpanPhrase<'a> = (Rc<Phrase<'a>>, bool);
+
+type Graph = HashMap<(usize, usize), Option<SpanPhrase<'static>>>;
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        collections::{HashMap, HashSet},
+        rc::Rc,
+        sync::Arc,
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        conversion::{
+            AliasMap, Bigram, Break, ChineseSequence, ConversionEngine, ConversionError,
+            FrequencyOverlay, Interval, PhraseInterner, PinTextError, SelectionPriority,
+            SimplifiedMapper, SoftBreak, SpanCache, UserBias, MAX_SEQUENCE_LEN,
+        },
+        dictionary::{
+            CountingDictionary, Dictionary, DictionaryInfo, DictionaryMut, DictionarySource,
+            LayeredDictionary, Phrase, Phrases, SqliteDictionary,
+        },
+        syl,
+        zhuyin::{Bopomofo::*, Syllable},
+    };
+
+    use super::{
+        dedup_intervals, nth_candidate, ChewingConversionEngine, ConversionCache, ConversionConfig,
+        Correction, FreqRule, Graph, PossibleInterval, PossiblePath, Preview, ScoreMode, Scratch,
+        SyncChewingConversionEngine, DEFAULT_SEED, DEFAULT_SINGLE_CHAR_PENALTY,
+    };
+
+    /// A dictionary that sleeps on every lookup.
+    #[derive(Debug)]
+    struct SlowDictionary {
+        inner: Rc<dyn Dictionary>,
+        delay: Duration,
+    }
+
+    impl Dictionary for SlowDictionary {
+        fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+            thread::sleep(self.delay);
+            self.inner.lookup_phrase(syllables)
+        }
+
+        fn max_phrase_len(&self) -> usize {
+            self.inner.max_phrase_len()
+        }
+
+        fn about(&self) -> DictionaryInfo {
+            self.inner.about()
+        }
+
+        fn as_mut_dict(&mut self) -> Option<&mut dyn DictionaryMut> {
+            None
+        }
+    }
+
+    fn test_dictionary() -> Rc<dyn Dictionary> {
+        Rc::new(test_map())
+    }
+
+    fn test_map() -> HashMap<Vec<Syllable>, Vec<Phrase<'static>>> {
+        HashMap::from([
+            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
+            (vec![syl![M, I, EN, TONE2]], vec![("民", 1).into()]),
+            (vec![syl![D, A, TONE4]], vec![("大", 1).into()]),
+            (vec![syl![H, U, EI, TONE4]], vec![("會", 1).into()]),
+            (vec![syl![D, AI, TONE4]], vec![("代", 1).into()]),
+            (vec![syl![B, I, AU, TONE3]], vec![("表", 1).into()]),
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 200).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 200).into()],
+            ),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 200).into(), ("戴錶", 100).into()],
+            ),
+            (vec![syl![X, I, EN]], vec![("心", 1).into()]),
+            (
+                vec![syl![K, U, TONE4], syl![I, EN]],
+                vec![("庫音", 300).into()],
+            ),
+            (
+                vec![syl![X, I, EN], syl![K, U, TONE4], syl![I, EN]],
+                vec![("新酷音", 200).into()],
+            ),
+            (
+                vec![syl![C, E, TONE4], syl![SH, TONE4], syl![I, TONE2]],
+                vec![("測試儀", 42).into()],
+            ),
+            (
+                vec![syl![C, E, TONE4], syl![SH, TONE4]],
+                vec![("測試", 9318).into()],
+            ),
+            (
+                vec![syl![I, TONE2], syl![X, I, A, TONE4]],
+                vec![("一下", 10576).into()],
+            ),
+            (vec![syl![X, I, A, TONE4]], vec![("下", 10576).into()]),
+        ])
+    }
+
+    #[test]
+    fn convert_empty_sequence() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(Vec::<Interval>::new(), engine.convert(&sequence).unwrap());
+    }
+
+    #[test]
+    fn convert_simple_chinese_sequence() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "國民".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 2,
+                    end: 4,
+                    phrase: "大會".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 4,
+                    end: 6,
+                    phrase: "代表".to_string(),
+                    unknown: false
+                },
+            ],
+            engine.convert(&sequence).unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_chinese_sequence_with_breaks() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![Break(1), Break(5)],
+        };
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 1,
+                    phrase: "國".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 1,
+                    end: 2,
+                    phrase: "民".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 2,
+                    end: 4,
+                    phrase: "大會".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 4,
+                    end: 5,
+                    phrase: "代".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 5,
+                    end: 6,
+                    phrase: "表".to_string(),
+                    unknown: false
+                },
+            ],
+            engine.convert(&sequence).unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_chinese_sequence_with_good_selection() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![Interval {
+                start: 4,
+                end: 6,
+                phrase: "戴錶".to_string(),
+                unknown: false,
+            }],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "國民".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 2,
+                    end: 4,
+                    phrase: "大會".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 4,
+                    end: 6,
+                    phrase: "戴錶".to_string(),
+                    unknown: false
+                },
+            ],
+            engine.convert(&sequence).unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_chinese_sequence_with_substring_selection() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![syl![X, I, EN], syl![K, U, TONE4], syl![I, EN]],
+            selections: vec![Interval {
+                start: 1,
+                end: 3,
+                phrase: "酷音".to_string(),
+                unknown: false,
+            }],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 3,
+                phrase: "新酷音".to_string(),
+                unknown: false
+            },],
+            engine.convert(&sequence).unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_cycle_alternatives() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "測試".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 2,
+                    end: 4,
+                    phrase: "一下".to_string(),
+                    unknown: false
+                }
+            ],
+            engine.convert_next(&sequence, 0).unwrap()
+        );
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 3,
+                    phrase: "測試儀".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 3,
+                    end: 4,
+                    phrase: "下".to_string(),
+                    unknown: false
+                }
+            ],
+            engine.convert_next(&sequence, 1).unwrap()
+        );
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "測試".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 2,
+                    end: 4,
+                    phrase: "一下".to_string(),
+                    unknown: false
+                }
+            ],
+            engine.convert_next(&sequence, 2).unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_with_segmentation_hint() {
+        let dict = Rc::new(HashMap::from([
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 2000).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 2000).into()],
+            ),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 4000).into()],
+            ),
+            (
+                vec![
+                    syl![G, U, O, TONE2],
+                    syl![M, I, EN, TONE2],
+                    syl![D, A, TONE4],
+                ],
+                vec![("國民大", 1000).into()],
+            ),
+            (
+                vec![
+                    syl![H, U, EI, TONE4],
+                    syl![D, AI, TONE4],
+                    syl![B, I, AU, TONE3],
+                ],
+                vec![("會代表", 1000).into()],
+            ),
+        ]));
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        // Both segmentations have the same score, the naive conversion keeps
+        // the first one found.
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 3,
+                    phrase: "國民大".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 3,
+                    end: 6,
+                    phrase: "會代表".to_string(),
+                    unknown: false
+                },
+            ],
+            engine.convert(&sequence).unwrap()
+        );
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "國民".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 2,
+                    end: 4,
+                    phrase: "大會".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 4,
+                    end: 6,
+                    phrase: "代表".to_string(),
+                    unknown: false
+                },
+            ],
+            engine
+                .convert_with_segmentation(&sequence, &[2, 4])
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn segmentation_hint_improves_stability() {
+        let dict = Rc::new(HashMap::from([
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 2000).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 2000).into()],
+            ),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 4000).into()],
+            ),
+            (
+                vec![
+                    syl![G, U, O, TONE2],
+                    syl![M, I, EN, TONE2],
+                    syl![D, A, TONE4],
+                ],
+                vec![("國民大", 1000).into()],
+            ),
+            (
+                vec![
+                    syl![H, U, EI, TONE4],
+                    syl![D, AI, TONE4],
+                    syl![B, I, AU, TONE3],
+                ],
+                vec![("會代表", 1000).into()],
+            ),
+        ]));
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let naive = engine.stability(&sequence).unwrap();
+        let hinted = engine.stability_with_segmentation(&sequence).unwrap();
+        assert!(naive < 1.0);
+        assert!(hinted > naive, "{hinted} <= {naive}");
+    }
+
+    #[test]
+    fn seed_breaks_ties_reproducibly() {
+        let dict = Rc::new(HashMap::from([
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 2000).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 2000).into()],
+            ),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 4000).into()],
+            ),
+            (
+                vec![
+                    syl![G, U, O, TONE2],
+                    syl![M, I, EN, TONE2],
+                    syl![D, A, TONE4],
+                ],
+                vec![("國民大", 1000).into()],
+            ),
+            (
+                vec![
+                    syl![H, U, EI, TONE4],
+                    syl![D, AI, TONE4],
+                    syl![B, I, AU, TONE3],
+                ],
+                vec![("會代表", 1000).into()],
+            ),
+        ]));
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let convert = |seed: u64| {
+            ChewingConversionEngine::new(dict.clone())
+                .with_seed(seed)
+                .convert(&sequence)
+                .unwrap()
+        };
+        for seed in 0..8 {
+            assert_eq!(convert(seed), convert(seed));
+        }
+        assert_eq!(
+            convert(DEFAULT_SEED),
+            ChewingConversionEngine::new(dict.clone())
+                .convert(&sequence)
+                .unwrap()
+        );
+        let results: HashSet<_> = (0..32).map(convert).collect();
+        assert_eq!(2, results.len());
+    }
+
+    #[test]
+    fn span_candidates_match_whole_sentence_candidates() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let span = |intervals: Vec<Interval>| -> Option<Vec<Interval>> {
+            let slice: Vec<_> = intervals
+                .into_iter()
+                .filter(|it| it.start >= 4 && it.end <= 6)
+                .collect();
+            let covered: usize = slice.iter().map(|it| it.end - it.start).sum();
+            (covered == 2).then_some(slice)
+        };
+        let whole: HashSet<_> = engine
+            .find_candidate_paths(&mut Graph::default(), &sequence)
+            .into_iter()
+            .map(|path| path.intervals.into_iter().map(Interval::from).collect())
+            .filter_map(span)
+            .collect();
+        let local = engine.span_candidates(&sequence, 4, 6);
+        assert!(!local.is_empty());
+        let fixed = local[0].clone();
+        for candidate in &local {
+            assert_eq!(
+                fixed.iter().filter(|it| it.end <= 4).collect::<Vec<_>>(),
+                candidate
+                    .iter()
+                    .filter(|it| it.end <= 4)
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(
+                fixed.iter().filter(|it| it.start >= 6).collect::<Vec<_>>(),
+                candidate
+                    .iter()
+                    .filter(|it| it.start >= 6)
+                    .collect::<Vec<_>>()
+            );
+        }
+        let local: HashSet<_> = local.into_iter().filter_map(span).collect();
+        assert_eq!(whole, local);
+        assert!(engine.span_candidates(&sequence, 6, 6).is_empty());
+    }
+
+    #[test]
+    fn freq_cap_limits_outlier_phrase() {
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::from([
+            (
+                vec![syl![C, E, TONE4], syl![SH, TONE4]],
+                vec![("測試", 1000).into()],
+            ),
+            (
+                vec![syl![I, TONE2], syl![X, I, A, TONE4]],
+                vec![("一下", 1000).into()],
+            ),
+            (
+                vec![syl![C, E, TONE4], syl![SH, TONE4], syl![I, TONE2]],
+                vec![("測試儀", 10_000_000).into()],
+            ),
+            (vec![syl![X, I, A, TONE4]], vec![("下", 1).into()]),
+        ]));
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |engine: ChewingConversionEngine| -> Vec<String> {
+            engine
+                .convert(&sequence)
+                .unwrap()
+                .into_iter()
+                .map(|it| it.phrase)
+                .collect()
+        };
+        assert_eq!(
+            vec!["測試儀", "下"],
+            phrases(ChewingConversionEngine::new(dict.clone()))
+        );
+        assert_eq!(
+            vec!["測試", "一下"],
+            phrases(ChewingConversionEngine::new(dict).with_freq_cap(1000))
+        );
+    }
+
+    #[test]
+    fn segment_stream_commits_stable_segments_early() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let syllables = vec![
+            syl![G, U, O, TONE2],
+            syl![M, I, EN, TONE2],
+            syl![D, A, TONE4],
+            syl![H, U, EI, TONE4],
+            syl![D, AI, TONE4],
+            syl![B, I, AU, TONE3],
+        ];
+        let consumed = Cell::new(0);
+        let mut stream = engine.segment_stream(
+            syllables
+                .iter()
+                .copied()
+                .inspect(|_| consumed.set(consumed.get() + 1)),
+            2,
+        );
+        let first = stream.next().unwrap().unwrap();
+        assert_eq!(
+            ("國民", 0, 2),
+            (first.phrase.as_str(), first.start, first.end)
+        );
+        assert!(consumed.get() < syllables.len());
+        let mut segments = vec![first];
+        segments.extend(stream.map(Result::unwrap));
+        let sequence = ChineseSequence {
+            syllables: syllables.clone(),
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(engine.convert(&sequence).unwrap(), segments);
+
+        // The buffer never grows past the lookahead after the longest phrase.
+        let long = syllables.repeat(30);
+        consumed.set(0);
+        let mut stream = engine.segment_stream(
+            long.iter()
+                .copied()
+                .inspect(|_| consumed.set(consumed.get() + 1)),
+            100,
+        );
+        let first = stream.next().unwrap().unwrap();
+        assert!(consumed.get() <= engine.max_phrase_len() + 100);
+        assert_eq!(0, first.start);
+        let mut end = first.end;
+        for interval in stream {
+            let interval = interval.unwrap();
+            assert_eq!(end, interval.start);
+            end = interval.end;
+        }
+        assert_eq!(long.len(), end);
+    }
+
+    #[test]
+    fn custom_fallback_renderer() {
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![Z, U, EI, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        assert_eq!("ㄗㄨㄟˇ", engine.convert(&sequence).unwrap()[1].phrase);
+
+        let engine = ChewingConversionEngine::new(test_dictionary())
+            .with_fallback_renderer(|_: &Syllable| String::from("?"));
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "國民".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 2,
+                    end: 3,
+                    phrase: "?".to_string(),
+                    unknown: true
+                },
+            ],
+            engine.convert(&sequence).unwrap()
+        );
+    }
+
+    #[test]
+    fn boundary_probs_split_phrase() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        assert_eq!(
+            vec!["國民", "大會", "代表"],
+            phrases(engine.convert_with_boundary_probs(&sequence, &[]).unwrap())
+        );
+        let probs = [0.5, 0.5, 0.5, 1.0, 0.5, 0.5];
+        assert_eq!(
+            vec!["國民", "大", "會", "代表"],
+            phrases(
+                engine
+                    .convert_with_boundary_probs(&sequence, &probs)
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn ambiguous_spans_within_margin() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![Interval {
+                start: 4,
+                end: 6,
+                phrase: "代表".to_string(),
+                unknown: false
+            }],
+            engine.ambiguous_spans(&sequence, 100).unwrap()
+        );
+        assert!(engine.ambiguous_spans(&sequence, 99).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ambiguous_spans_weigh_aliased_phrases() {
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let mut aliases = AliasMap::new();
+        aliases.insert("代表", "代錶");
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_aliases(aliases);
+        assert!(engine.ambiguous_spans(&sequence, 99).unwrap().is_empty());
+
+        let mut user_bias = UserBias::default();
+        user_bias.insert("戴錶", 50);
+        let engine = engine.with_user_bias(user_bias);
+        assert_eq!(
+            vec!["代錶"],
+            engine
+                .ambiguous_spans(&sequence, 50)
+                .unwrap()
+                .into_iter()
+                .map(|interval| interval.phrase)
+                .collect::<Vec<_>>()
+        );
+        assert!(engine.ambiguous_spans(&sequence, 49).unwrap().is_empty());
+    }
+
+    #[test]
+    fn homophone_memory_defaults_to_learned_choice() {
+        let mut engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!("代表", engine.convert(&sequence).unwrap()[0].phrase);
+
+        engine
+            .homophone_memory_mut()
+            .insert(&sequence.syllables, "戴錶");
+        assert_eq!("戴錶", engine.convert(&sequence).unwrap()[0].phrase);
+
+        let longer = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!("戴錶", engine.convert(&longer).unwrap()[1].phrase);
+    }
+
+    #[test]
+    fn pinned_segmentation_is_reproduced() {
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_pin_selections(true);
+        let mut sequence = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let chosen = engine.convert_next(&sequence, 1).unwrap();
+        assert_ne!(engine.convert(&sequence).unwrap(), chosen);
+
+        engine.pin_segmentation(&mut sequence, &chosen);
+        assert_eq!(chosen, sequence.selections);
+        assert_eq!(chosen, engine.convert(&sequence).unwrap());
+    }
+
+    #[test]
+    fn convert_and_commit_learns_selected_phrase() {
+        let mut dict = SqliteDictionary::open_in_memory().unwrap();
+        for (syllables, phrases) in test_map() {
+            for phrase in phrases {
+                dict.insert(&syllables, phrase).unwrap();
+            }
+        }
+        let mut engine = ChewingConversionEngine::new(Box::new(dict));
+        let syllables = vec![
+            syl![G, U, O, TONE2],
+            syl![M, I, EN, TONE2],
+            syl![D, AI, TONE4],
+            syl![B, I, AU, TONE3],
+        ];
+        let selected = ChineseSequence {
+            syllables: syllables.clone(),
+            selections: vec![Interval::new(2, 4, "戴錶").unwrap()],
+            breaks: vec![],
+        };
+        let plain = ChineseSequence {
+            syllables,
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!("代表", engine.convert(&plain).unwrap()[1].phrase);
+
+        let committed = engine.convert_and_commit(&selected).unwrap();
+        assert_eq!(engine.convert(&selected).unwrap(), committed);
+        assert_eq!(committed, engine.convert(&plain).unwrap());
+        assert!(engine.homophone_memory_mut().is_empty());
+    }
+
+    #[test]
+    fn convert_and_commit_learns_aliased_phrase() {
+        let mut dict = SqliteDictionary::open_in_memory().unwrap();
+        for (syllables, phrases) in test_map() {
+            for phrase in phrases {
+                dict.insert(&syllables, phrase).unwrap();
+            }
+        }
+        let mut aliases = AliasMap::new();
+        aliases.insert("戴錶", "戴表");
+        let mut engine = ChewingConversionEngine::new(Box::new(dict)).with_aliases(aliases);
+        let syllables = vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]];
+        let selected = ChineseSequence {
+            syllables: syllables.clone(),
+            selections: vec![Interval::new(0, 2, "戴表").unwrap()],
+            breaks: vec![],
+        };
+        engine.convert_and_commit(&selected).unwrap();
+        let learned: Vec<_> = engine
+            .dict
+            .lookup_phrase(&syllables)
+            .map(|phrase| phrase.as_str().to_owned())
+            .collect();
+        assert_eq!(vec!["戴錶", "代表"], learned);
+    }
+
+    #[test]
+    fn convert_borrowed_sub_slice() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let buffer = [
+            syl![G, U, O, TONE2],
+            syl![M, I, EN, TONE2],
+            syl![D, A, TONE4],
+            syl![H, U, EI, TONE4],
+            syl![D, AI, TONE4],
+            syl![B, I, AU, TONE3],
+        ];
+        let selection = Interval {
+            start: 2,
+            end: 4,
+            phrase: "戴錶".to_string(),
+            unknown: false,
+        };
+        let intervals = engine
+            .convert_slice(&buffer[2..], std::slice::from_ref(&selection), &[])
+            .unwrap();
+        assert_eq!(
+            engine
+                .convert(&ChineseSequence {
+                    syllables: buffer[2..].to_vec(),
+                    selections: vec![selection],
+                    breaks: vec![],
+                })
+                .unwrap(),
+            intervals
+        );
+        assert_eq!(
+            vec!["大會", "戴錶"],
+            intervals
+                .iter()
+                .map(|it| it.phrase.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn possible_intervals_of_lattice() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![syl![X, I, EN], syl![K, U, TONE4], syl![I, EN]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let lattice: Vec<_> = engine
+            .possible_intervals(&sequence)
+            .into_iter()
+            .map(|it| (it.start, it.end, it.phrase, it.unknown))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, 1, "心".to_string(), false),
+                (0, 3, "新酷音".to_string(), false),
+                (1, 2, "ㄎㄨˋ".to_string(), true),
+                (1, 3, "庫音".to_string(), false),
+                (2, 3, "ㄧㄣ".to_string(), true),
+            ],
+            lattice
+        );
+    }
+
+    #[test]
+    fn length_index_skips_impossible_spans() {
+        let dict = Rc::new(CountingDictionary::new(Box::new(test_map())));
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let naive = ChewingConversionEngine::new(dict.clone())
+            .convert(&sequence)
+            .unwrap();
+        let naive_lookups = dict.lookups();
+        dict.reset();
+
+        let indexed = ChewingConversionEngine::new(dict.clone())
+            .with_length_index()
+            .convert(&sequence)
+            .unwrap();
+        assert_eq!(naive, indexed);
+        assert!(dict.lookups() < naive_lookups);
+    }
+
+    #[test]
+    fn suggest_correction_for_wrong_tone() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![Correction {
+                start: 2,
+                end: 4,
+                original: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE4]],
+                corrected: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                phrase: "代表".to_string(),
+            }],
+            engine.suggest_corrections(&sequence)
+        );
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert!(engine.suggest_corrections(&sequence).is_empty());
+    }
+
+    #[test]
+    fn second_best_matches_next_candidate() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            Some(engine.convert_next(&sequence, 1).unwrap()),
+            engine.convert_second_best(&sequence)
+        );
+        let single = ChineseSequence {
+            syllables: vec![syl![C, E, TONE4]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(None, engine.convert_second_best(&single));
+    }
+
+    #[test]
+    fn boost_applies_to_one_conversion() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let boosts = HashMap::from([("戴錶".to_string(), 200)]);
+        assert_eq!(
+            "戴錶",
+            engine.convert_with_boost(&sequence, &boosts).unwrap()[1].phrase
+        );
+        assert_eq!("代表", engine.convert(&sequence).unwrap()[1].phrase);
+        assert_eq!(
+            "代表",
+            engine
+                .convert_with_boost(&sequence, &HashMap::new())
+                .unwrap()[1]
+                .phrase
+        );
+    }
+
+    #[test]
+    fn dp_is_optimal_on_examples() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let examples = [
+            vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            vec![syl![X, I, EN], syl![K, U, TONE4], syl![I, EN]],
+            vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+        ];
+        for syllables in examples {
+            let sequence = ChineseSequence {
+                syllables,
+                selections: vec![],
+                breaks: vec![],
+            };
+            assert!(engine.verify_dp_optimality(&sequence), "{:?}", sequence);
+        }
+    }
+
+    #[test]
+    fn locked_and_suggested_selections() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |selections: &[(Interval, SelectionPriority)]| -> Vec<String> {
+            engine
+                .convert_with_priorities(&sequence, selections)
+                .unwrap()
+                .into_iter()
+                .map(|it| it.phrase)
+                .collect()
+        };
+        let da = Interval::new(2, 3, "大").unwrap();
+        assert_eq!(
+            vec!["國民", "大會", "代表"],
+            phrases(&[(da.clone(), SelectionPriority::Suggested)])
+        );
+        assert_eq!(
+            vec!["國民", "大", "會", "代表"],
+            phrases(&[(da, SelectionPriority::Locked)])
+        );
+        let watch = Interval::new(4, 6, "戴錶").unwrap();
+        assert_eq!(
+            vec!["國民", "大會", "戴錶"],
+            phrases(&[(watch, SelectionPriority::Suggested)])
+        );
+    }
+
+    #[test]
+    fn suggested_selection_matches_alias() {
+        let mut aliases = AliasMap::new();
+        aliases.insert("戴錶", "戴表");
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_aliases(aliases);
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let suggestion = Interval::new(0, 2, "戴表").unwrap();
+        assert_eq!(
+            vec![suggestion.clone()],
+            engine
+                .convert_with_priorities(&sequence, &[(suggestion, SelectionPriority::Suggested)])
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn estimate_paths_counts_all_phrasings() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let all_paths = engine.find_all_paths(&mut Graph::default(), &sequence, 0, 6, None);
+        assert_eq!(8, all_paths.len());
+        assert_eq!(all_paths.len(), engine.estimate_paths(&sequence));
+    }
+
+    #[test]
+    fn effective_breaks_skip_natural_boundaries() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![Break(2), Break(5)],
+        };
+        assert_eq!(vec![Break(5)], engine.effective_breaks(&sequence));
+    }
+
+    #[test]
+    fn convert_interned_shares_phrases() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![Break(2)],
+        };
+        let mut interner = PhraseInterner::new();
+        let intervals = engine.convert_interned(&sequence, &mut interner).unwrap();
+        assert_eq!(2, intervals.len());
+        assert_eq!("代表", &*intervals[0].phrase);
+        assert!(Rc::ptr_eq(&intervals[0].phrase, &intervals[1].phrase));
+        assert_eq!(1, interner.len());
+        assert_eq!(
+            engine.convert(&sequence).unwrap(),
+            intervals
+                .into_iter()
+                .map(Interval::from)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn config_reflects_settings() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        assert_eq!(&ConversionConfig::default(), engine.config());
+
+        let engine = ChewingConversionEngine::new_checked(test_dictionary())
+            .with_pin_selections(true)
+            .with_freq_rule(FreqRule::LogPerSyllable)
+            .with_score_mode(ScoreMode::FewestSegments)
+            .with_prefer_consistent_width(true)
+            .with_prefer_early_boundary(true)
+            .with_trailing_toneless(true)
+            .with_language_model(|_| 0.0, 0.5)
+            .with_min_freq(10)
+            .with_freq_cap(1000)
+            .with_freq_smoothing(3)
+            .with_length_weights(vec![0.5, 1.0])
+            .with_length_variance(false)
+            .with_seed(42);
+        let config = engine.config();
+        assert!(config.pin_selections);
+        assert_eq!(FreqRule::LogPerSyllable, config.freq_rule);
+        assert_eq!(ScoreMode::FewestSegments, config.score_mode);
+        assert!(config.prefer_consistent_width);
+        assert!(config.prefer_early_boundary);
+        assert!(config.trailing_toneless);
+        assert_eq!(0.5, config.language_model_weight);
+        assert_eq!(10, config.min_freq);
+        assert_eq!(1000, config.freq_cap);
+        assert_eq!(3, config.freq_smoothing);
+        assert_eq!(Some(&[0.5, 1.0][..]), config.length_weights.as_deref());
+        assert!(!config.length_variance);
+        assert!(config.checked);
+        assert_eq!(42, config.seed);
+
+        let restored = ChewingConversionEngine::new(test_dictionary()).with_config(config.clone());
+        assert_eq!(config, restored.config());
+    }
+
+    #[test]
+    fn unsatisfiable_selections_are_reported() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let selection = |start, end, phrase: &str| Interval {
+            start,
+            end,
+            phrase: phrase.to_string(),
+            unknown: false,
+        };
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![
+                selection(0, 2, "國民"),
+                selection(2, 4, "大惠"),
+                selection(4, 6, "戴錶"),
+            ],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![selection(2, 4, "大惠")],
+            engine.unsatisfiable_selections(&sequence)
+        );
+    }
+
+    #[test]
+    fn shared_cache_skips_repeated_lookups() {
+        let dict = Rc::new(CountingDictionary::new(Box::new(test_map())));
+        let cache = Arc::new(SpanCache::new(64));
+        let first = ChewingConversionEngine::new(dict.clone() as Rc<dyn Dictionary>)
+            .with_shared_cache(cache.clone());
+        let second = ChewingConversionEngine::new(dict.clone() as Rc<dyn Dictionary>)
+            .with_shared_cache(cache.clone());
+        let sequence = |syllables| ChineseSequence {
+            syllables,
+            selections: vec![],
+            breaks: vec![],
+        };
+        let expected = ChewingConversionEngine::new(test_dictionary())
+            .convert(&sequence(vec![
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ]))
+            .unwrap();
+
+        first
+            .convert(&sequence(vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ]))
+            .unwrap();
+        let lookups = dict.lookups();
+        assert_eq!(lookups as u64, cache.misses());
+        let result = second
+            .convert(&sequence(vec![
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ]))
+            .unwrap();
+        assert_eq!(expected, result);
+        assert_eq!(lookups, dict.lookups());
+        assert!(cache.hits() > 0);
+    }
+
+    #[test]
+    fn span_cache_evicts_least_recently_used() {
+        let cache = SpanCache::new(2);
+        let get = |syllable| {
+            cache.get_or_insert_with(&[syllable], || vec![("測", 1).into()]);
+        };
+        get(syl![C, E, TONE4]);
+        get(syl![SH, TONE4]);
+        get(syl![C, E, TONE4]);
+        get(syl![D, A, TONE4]);
+        assert_eq!(2, cache.len());
+        assert_eq!((1, 3), (cache.hits(), cache.misses()));
+        get(syl![C, E, TONE4]);
+        assert_eq!((2, 3), (cache.hits(), cache.misses()));
+        get(syl![SH, TONE4]);
+        assert_eq!((2, 4), (cache.hits(), cache.misses()));
+    }
+
+    #[test]
+    fn atomic_phrase_is_never_split() {
+        let syllables = [
+            syl![G, U, O, TONE2],
+            syl![M, I, EN, TONE2],
+            syl![D, A, TONE4],
+        ];
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |engine: &ChewingConversionEngine, sequence: &ChineseSequence| {
+            engine
+                .convert(sequence)
+                .unwrap()
+                .into_iter()
+                .map(|interval| interval.phrase)
+                .collect::<Vec<_>>()
+        };
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        assert_eq!(vec!["國民", "大會", "代表"], phrases(&engine, &sequence));
+
+        let engine = engine.with_atomic_phrase(&syllables, "郭民達");
+        assert_eq!(vec!["郭民達", "會", "代表"], phrases(&engine, &sequence));
+
+        let broken = ChineseSequence {
+            syllables: sequence.syllables.clone(),
+            selections: vec![],
+            breaks: vec![Break(2)],
+        };
+        assert_eq!(vec!["國民", "大會", "代表"], phrases(&engine, &broken));
+    }
+
+    #[test]
+    fn convert_with_sources_reports_layer() {
+        let mut sys_dict = HashMap::new();
+        sys_dict.insert(
+            vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+            vec![("大會", 200).into()],
+        );
+        sys_dict.insert(
+            vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            vec![("代表", 100).into()],
+        );
+        let mut user_dict = HashMap::new();
+        user_dict.insert(
+            vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            vec![("戴錶", 300).into()],
+        );
+        let dict = LayeredDictionary::new(vec![Box::new(sys_dict), Box::new(user_dict)], vec![]);
+        let engine = ChewingConversionEngine::new(Rc::new(dict));
+        let sys = ChineseSequence {
+            syllables: vec![
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            Ok(vec![
+                (
+                    Interval {
+                        start: 0,
+                        end: 2,
+                        phrase: "大會".to_string(),
+                        unknown: false,
+                    },
+                    DictionarySource::SYSTEM
+                ),
+                (
+                    Interval {
+                        start: 2,
+                        end: 4,
+                        phrase: "戴錶".to_string(),
+                        unknown: false,
+                    },
+                    DictionarySource(1)
+                ),
+            ]),
+            engine.convert_with_sources(&sys)
+        );
+    }
+
+    #[test]
+    fn soft_break_penalty_discourages_spanning() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sys = ChineseSequence {
+            syllables: vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            Ok(vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "大會".to_string(),
+                unknown: false,
+            }]),
+            engine.convert_with_soft_breaks(&sys, &[SoftBreak(1, 10)])
+        );
+        assert_eq!(
+            Ok(vec![
+                Interval {
+                    start: 0,
+                    end: 1,
+                    phrase: "大".to_string(),
+                    unknown: false,
+                },
+                Interval {
+                    start: 1,
+                    end: 2,
+                    phrase: "會".to_string(),
+                    unknown: false,
+                },
+            ]),
+            engine.convert_with_soft_breaks(&sys, &[SoftBreak(1, 1_000_000)])
+        );
+    }
+
+    #[test]
+    fn lattice_dot_highlights_best_path() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sys = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let dot = engine.lattice_dot(&sys);
+        assert!(dot.starts_with("digraph lattice {"));
+        let nodes = dot
+            .lines()
+            .filter(|line| line.trim_end_matches(';').trim().parse::<usize>().is_ok())
+            .count();
+        assert_eq!(7, nodes);
+        assert!(dot.contains("    0 -> 2 [label=\"國民 (200)\", color=red, penwidth=2];"));
+        assert!(dot.contains("    2 -> 4 [label=\"大會 (200)\", color=red, penwidth=2];"));
+        assert!(dot.contains("    4 -> 6 [label=\"代表 (200)\", color=red, penwidth=2];"));
+        assert!(dot.contains("    0 -> 1 [label=\"國 (1)\"];"));
+    }
+
+    #[test]
+    fn unknown_syllable_freq_competes_with_phrases() {
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::from([
+            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
+            (vec![syl![M, I, EN, TONE2]], vec![("民", 1).into()]),
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 10).into()],
+            ),
+            (
+                vec![syl![M, I, EN, TONE2], syl![D, A, TONE4]],
+                vec![("民大", 50).into()],
+            ),
+        ]));
+        let sys = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!(
+            vec!["國", "民大"],
+            engine
+                .convert(&sys)
+                .unwrap()
+                .iter()
+                .map(|interval| interval.phrase.as_str())
+                .collect::<Vec<_>>()
+        );
+        let engine = ChewingConversionEngine::new(dict).with_unknown_syllable_freq(51200);
+        let intervals = engine.convert(&sys).unwrap();
+        assert_eq!(
+            vec!["國民", "ㄉㄚˋ"],
+            intervals
+                .iter()
+                .map(|interval| interval.phrase.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(intervals[1].unknown);
+    }
+
+    #[test]
+    fn longest_matches_per_position() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sys = ChineseSequence {
+            syllables: vec![syl![X, I, EN], syl![K, U, TONE4], syl![I, EN]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let matches = engine.longest_matches(&sys);
+        assert_eq!(3, matches.len());
+        assert_eq!(
+            Some(Interval {
+                start: 0,
+                end: 3,
+                phrase: "新酷音".to_string(),
+                unknown: false,
+            }),
+            matches[0]
+        );
+        assert_eq!(
+            Some(Interval {
+                start: 1,
+                end: 3,
+                phrase: "庫音".to_string(),
+                unknown: false,
+            }),
+            matches[1]
+        );
+
+        let sys = ChineseSequence {
+            breaks: vec![Break(2)],
+            ..sys
+        };
+        assert_eq!(
+            Some(Interval {
+                start: 0,
+                end: 1,
+                phrase: "心".to_string(),
+                unknown: false,
+            }),
+            engine.longest_matches(&sys)[0]
+        );
+
+        let mut aliases = AliasMap::new();
+        aliases.insert("心", "芯");
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_aliases(aliases);
+        assert_eq!(
+            Some(Interval::new(0, 1, "芯").unwrap()),
+            engine.longest_matches(&sys)[0]
+        );
+    }
+
+    #[test]
+    fn missing_phrases_reports_split_pair() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sys = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![X, I, EN],
+                syl![X, I, A, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![vec![syl![X, I, EN], syl![X, I, A, TONE4]]],
+            engine.missing_phrases(&[sys])
+        );
+
+        let sys = ChineseSequence {
+            syllables: vec![syl![X, I, EN], syl![X, I, A, TONE4]],
+            selections: vec![],
+            breaks: vec![Break(1)],
+        };
+        assert!(engine.missing_phrases(&[sys]).is_empty());
+    }
+
+    #[test]
+    fn convert_into_fixed_buffer() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sys = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let mut out = vec![Interval::default(); 3];
+        assert_eq!(Ok(3), engine.convert_into(&sys, &mut out));
+        assert_eq!(engine.convert(&sys).unwrap(), out);
+
+        let mut out = vec![Interval::default(); 2];
+        assert_eq!(
+            Err(ConversionError::BufferTooSmall {
+                needed: 3,
+                capacity: 2
+            }),
+            engine.convert_into(&sys, &mut out)
+        );
+        assert_eq!(vec![Interval::default(); 2], out);
+
+        let engine = engine.with_pin_selections(true);
+        let pinned = ChineseSequence {
+            selections: vec![
+                Interval::new(0, 4, "國民大會").unwrap(),
+                Interval::new(4, 6, "戴錶").unwrap(),
+            ],
+            ..sys
+        };
+        assert_eq!(
+            Err(ConversionError::BufferTooSmall {
+                needed: 2,
+                capacity: 1
+            }),
+            engine.convert_into(&pinned, &mut out[..1])
+        );
+        assert_eq!(Ok(2), engine.convert_into(&pinned, &mut out));
+        assert_eq!(pinned.selections, out);
+    }
+
+    #[test]
+    fn convert_with_overrides_emits_text_verbatim() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sys = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let overrides = HashMap::from([((2, 4), "10/14".to_string())]);
+        assert_eq!(
+            Ok(vec![
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "國民".to_string(),
+                    unknown: false,
+                },
+                Interval {
+                    start: 2,
+                    end: 4,
+                    phrase: "10/14".to_string(),
+                    unknown: false,
+                },
+                Interval {
+                    start: 4,
+                    end: 6,
+                    phrase: "代表".to_string(),
+                    unknown: false,
+                },
+            ]),
+            engine.convert_with_overrides(&sys, &overrides)
+        );
+    }
+
+    #[test]
+    fn merge_gain_ratio_prevents_marginal_merge() {
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::from([
+            (vec![syl![G, U, O, TONE2]], vec![("國", 100).into()]),
+            (vec![syl![M, I, EN, TONE2]], vec![("民", 100).into()]),
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 250).into()],
+            ),
+        ]));
+        let sys = ChineseSequence {
+            syllables: vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |engine: &ChewingConversionEngine<Rc<dyn Dictionary>>| {
+            engine
+                .convert(&sys)
+                .unwrap()
+                .into_iter()
+                .map(|interval| interval.phrase)
+                .collect::<Vec<_>>()
+        };
+        let strict = ChewingConversionEngine::new(dict.clone()).with_merge_gain_ratio(1.5);
+        assert_eq!(vec!["國", "民"], phrases(&strict));
+        let loose = ChewingConversionEngine::new(dict).with_merge_gain_ratio(1.0);
+        assert_eq!(vec!["國民"], phrases(&loose));
+    }
+
+    #[test]
+    fn pin_text_selects_matching_span() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let mut sys = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![Interval {
+                start: 1,
+                end: 2,
+                phrase: "民".to_string(),
+                unknown: false,
+            }],
+            breaks: vec![],
+        };
+        assert_eq!(
+            Err(PinTextError {
+                start: 1,
+                text: "民大".to_string()
+            }),
+            engine.pin_text(&mut sys, 1, "民大")
+        );
+        engine.pin_text(&mut sys, 0, "國民").unwrap();
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "國民".to_string(),
+                unknown: false,
+            }],
+            sys.selections
+        );
+        assert_eq!("國民", engine.convert(&sys).unwrap()[0].phrase);
+
+        engine.pin_text(&mut sys, 4, "戴錶").unwrap();
+        assert_eq!(
+            vec!["國民", "大會", "戴錶"],
+            engine
+                .convert(&sys)
+                .unwrap()
+                .iter()
+                .map(|interval| interval.phrase.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn benchmark_counts_are_consistent() {
+        let dict = Rc::new(CountingDictionary::new(Box::new(HashMap::from([
+            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
+            (vec![syl![M, I, EN, TONE2]], vec![("民", 1).into()]),
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 200).into()],
+            ),
+        ]))));
+        let engine = ChewingConversionEngine::new(dict.clone());
+        let sequences = vec![
+            ChineseSequence {
+                syllables: vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                selections: vec![],
+                breaks: vec![],
+            },
+            ChineseSequence {
+                syllables: vec![syl![G, U, O, TONE2]],
+                selections: vec![],
+                breaks: vec![],
+            },
+        ];
+        let report = engine.benchmark_with_lookups(&sequences, 3, || dict.lookups());
+        assert_eq!(3, report.iterations);
+        assert_eq!(
+            vec![2, 1],
+            report
+                .sequences
+                .iter()
+                .map(|bench| bench.syllables)
+                .collect::<Vec<_>>()
+        );
+        let per_sequence: usize = report
+            .sequences
+            .iter()
+            .filter_map(|bench| bench.lookups)
+            .sum();
+        assert_eq!(Some(per_sequence), report.lookups);
+        assert_eq!(Some(dict.lookups()), report.lookups);
+        assert!(report.sequences.iter().all(|bench| bench.lookups > Some(0)));
+        assert_eq!(
+            report.total,
+            report.sequences.iter().map(|bench| bench.total).sum()
+        );
+
+        let report = engine.benchmark(&sequences, 1);
+        assert_eq!(None, report.lookups);
+        assert!(report.sequences.iter().all(|bench| bench.lookups.is_none()));
+    }
+
+    #[test]
+    fn convert_with_preview_marks_tentative_phrase() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sys = ChineseSequence {
+            syllables: vec![syl![X, I, EN], syl![K, U, TONE4]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let (intervals, preview) = engine.convert_with_preview(&sys).unwrap();
+        assert_eq!(engine.convert(&sys).unwrap(), intervals);
+        assert_eq!(2, intervals.len());
+        assert!(crate::conversion::is_valid_tiling(&intervals, 2));
+        assert_eq!(
+            Some(Preview {
+                start: 0,
+                phrase: "新酷音".to_string()
+            }),
+            preview
+        );
+
+        let sys = ChineseSequence {
+            breaks: vec![Break(1)],
+            ..sys
+        };
+        assert_eq!(None, engine.convert_with_preview(&sys).unwrap().1);
+    }
+
+    #[test]
+    fn convert_tracked_keeps_unchanged_ids() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let mut sys = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let first = engine.convert_tracked(&sys, &[]).unwrap();
+        let phrases: Vec<_> = first.iter().map(|t| t.interval.phrase.as_str()).collect();
+        assert_eq!(vec!["國民", "大會", "代"], phrases);
+
+        sys.syllables.push(syl![B, I, AU, TONE3]);
+        let second = engine.convert_tracked(&sys, &first).unwrap();
+        let phrases: Vec<_> = second.iter().map(|t| t.interval.phrase.as_str()).collect();
+        assert_eq!(vec!["國民", "大會", "代表"], phrases);
+        assert_eq!(first[0].id, second[0].id);
+        assert_eq!(first[1].id, second[1].id);
+        assert!(first.iter().all(|t| t.id != second[2].id));
+    }
+
+    #[test]
+    fn convert_next_reuses_cache() {
+        let dict = Rc::new(CountingDictionary::new(Box::new(test_map())));
+        let engine = ChewingConversionEngine::new(dict.clone());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let mut cache = ConversionCache::new();
+        for next in 0..4 {
+            assert_eq!(
+                engine.convert_next(&sequence, next).unwrap(),
+                engine
+                    .convert_next_cached(&sequence, next, &mut cache)
+                    .unwrap()
+            );
+        }
+        dict.reset();
+        for next in 0..4 {
+            engine
+                .convert_next_cached(&sequence, next, &mut cache)
+                .unwrap();
+        }
+        assert_eq!(0, dict.lookups());
+
+        cache.clear();
+        for next in 0..4 {
+            engine
+                .convert_next_cached(&sequence, next, &mut cache)
+                .unwrap();
+        }
+        // One lookup for each visited (start, end) pair
+        assert_eq!(cache.graph.len(), dict.lookups_by_key().len());
+        assert!(dict.lookups_by_key().values().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn conversion_cache_checkpoint_and_restore() {
+        let dict = Rc::new(CountingDictionary::new(Box::new(test_map())));
+        let engine = ChewingConversionEngine::new(dict.clone());
+        let mut cache = ConversionCache::new();
+        let mut sequence = ChineseSequence {
+            syllables: vec![syl![C, E, TONE4], syl![SH, TONE4]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let expected = engine
+            .convert_next_cached(&sequence, 0, &mut cache)
+            .unwrap();
+        let snapshot = cache.checkpoint();
+
+        sequence.syllables.push(syl![I, TONE2]);
+        sequence.syllables.push(syl![X, I, A, TONE4]);
+        let edited = engine
+            .convert_next_cached(&sequence, 0, &mut cache)
+            .unwrap();
+        assert_ne!(expected, edited);
+
+        cache.restore(snapshot.clone());
+        sequence.syllables.truncate(2);
+        dict.reset();
+        assert_eq!(
+            expected,
+            engine
+                .convert_next_cached(&sequence, 0, &mut cache)
+                .unwrap()
+        );
+        assert_eq!(0, dict.lookups());
+        assert_eq!(snapshot.graph.len(), cache.graph.len());
+    }
+
+    #[test]
+    fn conversion_cache_invalidates_on_change() {
+        let dict = Rc::new(CountingDictionary::new(Box::new(test_map())));
+        let engine = ChewingConversionEngine::new(dict.clone());
+        let mut sequence = ChineseSequence {
+            syllables: vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let mut cache = ConversionCache::new();
+        engine
+            .convert_next_cached(&sequence, 0, &mut cache)
+            .unwrap();
+        sequence.breaks.push(Break(1));
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 1,
+                    phrase: "國".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 1,
+                    end: 2,
+                    phrase: "民".to_string(),
+                    unknown: false
+                },
+            ],
+            engine
+                .convert_next_cached(&sequence, 0, &mut cache)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_simplified_chinese_sequence() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let mapper = SimplifiedMapper::new([('國', '国'), ('會', '会')]);
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "国民".to_string(),
+                unknown: false
+            }],
+            engine.convert_simplified(&sequence, &mapper).unwrap()
+        );
+    }
+
+    #[test]
+    fn candidate_count_matches_cycle_length() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let count = engine.candidate_count(&sequence);
+        let mut seen = vec![];
+        for next in 0..count * 2 {
+            let result = engine.convert_next(&sequence, next).unwrap();
+            if !seen.contains(&result) {
+                seen.push(result);
+            }
+        }
+        assert_eq!(2, count);
+        assert_eq!(seen.len(), count);
+        assert_eq!(
+            engine.convert_next(&sequence, 0).unwrap(),
+            engine.convert_next(&sequence, count).unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_syllable_missing_from_dictionary() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![syl![G, U, O, TONE2], syl![B, A]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let expected = vec![
+            Interval {
+                start: 0,
+                end: 1,
+                phrase: "國".to_string(),
+                unknown: false,
+            },
+            Interval {
+                start: 1,
+                end: 2,
+                phrase: "ㄅㄚ".to_string(),
+                unknown: true,
+            },
+        ];
+        assert_eq!(expected, engine.convert(&sequence).unwrap());
+        assert_eq!(Ok(expected), engine.convert_next(&sequence, 0));
+        assert_eq!(1, engine.candidate_count(&sequence));
+    }
+
+    #[test]
+    fn convert_nbest_agrees_with_full_enumeration() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let mut paths = engine.find_all_paths(
+            &mut Graph::default(),
+            &sequence,
+            0,
+            sequence.syllables.len(),
+            None,
+        );
+        paths.sort_by(|a, b| b.cmp(a));
+        let expected: Vec<Vec<Interval>> = paths
+            .into_iter()
+            .take(3)
+            .map(|p| p.intervals.into_iter().map(|it| it.into()).collect())
+            .collect();
+        assert_eq!(expected, engine.convert_nbest(&sequence, 3, 8));
+        assert_eq!(
+            engine.convert(&sequence).unwrap(),
+            engine.convert_nbest(&sequence, 1, 1)[0]
+        );
+    }
+
+    struct StoredConversion {
+        best: Vec<Interval>,
+        alternatives: Vec<Vec<Interval>>,
+    }
+
+    fn convert_and_store(sequence: &ChineseSequence) -> StoredConversion {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        StoredConversion {
+            best: engine.convert(sequence).unwrap(),
+            alternatives: engine.convert_candidates(sequence, 2),
+        }
+    }
+
+    #[test]
+    fn owned_results_outlive_engine() {
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let stored = convert_and_store(&sequence);
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "代表".to_string(),
+                unknown: false
+            }],
+            stored.best
+        );
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let count = engine.candidate_count(&sequence).min(2);
+        let expected: Vec<_> = (0..count)
+            .map(|next| engine.convert_next(&sequence, next).unwrap())
+            .collect();
+        assert_eq!(expected, stored.alternatives);
+    }
+
+    #[test]
+    fn conversion_skips_spans_longer_than_max_phrase_len() {
+        let dict = test_dictionary();
+        assert_eq!(3, dict.max_phrase_len());
+        let dict = Rc::new(CountingDictionary::new(Box::new(test_map())));
+        let engine = ChewingConversionEngine::new(dict.clone());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        engine.convert(&sequence).unwrap();
+        engine.convert_next(&sequence, 1).unwrap();
+        assert!(dict.lookups() > 0);
+        assert!(dict.lookups_by_key().keys().all(|key| key.len() <= 3));
+    }
+
+    #[test]
+    fn pin_selections_keeps_single_character() {
+        let dict = test_dictionary();
+        let sys_seq = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![Interval {
+                start: 0,
+                end: 1,
+                phrase: "代".to_string(),
+                unknown: false,
+            }],
+            breaks: vec![],
+        };
+
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "代表".to_string(),
+                unknown: false
+            }],
+            engine.convert(&sys_seq).unwrap()
+        );
+
+        let engine = ChewingConversionEngine::new(dict).with_pin_selections(true);
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 1,
+                    phrase: "代".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 1,
+                    end: 2,
+                    phrase: "表".to_string(),
+                    unknown: false
+                }
+            ],
+            engine.convert(&sys_seq).unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_suffix_keeps_committed_prefix() {
+        let dict = Rc::new(HashMap::from([
+            (vec![syl![C, E, TONE4]], vec![("測", 1).into()]),
+            (vec![syl![SH, TONE4]], vec![("試", 1).into()]),
+            (vec![syl![I, TONE2]], vec![("儀", 1).into()]),
+            (
+                vec![syl![C, E, TONE4], syl![SH, TONE4], syl![I, TONE2]],
+                vec![("測試儀", 42).into()],
+            ),
+        ]));
+        let engine = ChewingConversionEngine::new(dict);
+        let sys_seq = ChineseSequence {
+            syllables: vec![syl![C, E, TONE4], syl![SH, TONE4], syl![I, TONE2]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 3,
+                phrase: "測試儀".to_string(),
+                unknown: false
+            }],
+            engine.convert(&sys_seq).unwrap()
+        );
+        assert_eq!(
+            vec![Interval {
+                start: 2,
+                end: 3,
+                phrase: "儀".to_string(),
+                unknown: false
+            }],
+            engine.convert_suffix(&sys_seq, 2).unwrap()
+        );
+        assert_eq!(
+            Vec::<Interval>::new(),
+            engine.convert_suffix(&sys_seq, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn log_freq_rule_prefers_balanced_split() {
+        let dict = Rc::new(HashMap::from([
+            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
+            (vec![syl![M, I, EN, TONE2]], vec![("民", 1).into()]),
+            (vec![syl![D, A, TONE4]], vec![("大", 1).into()]),
+            (vec![syl![H, U, EI, TONE4]], vec![("會", 1).into()]),
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 1000).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 1000).into()],
+            ),
+            (
+                vec![
+                    syl![M, I, EN, TONE2],
+                    syl![D, A, TONE4],
+                    syl![H, U, EI, TONE4],
+                ],
+                vec![("民大會", 100000).into()],
+            ),
+        ]));
+        let sys_seq = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 1,
+                    phrase: "國".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 1,
+                    end: 4,
+                    phrase: "民大會".to_string(),
+                    unknown: false
+                },
+            ],
+            engine.convert(&sys_seq).unwrap()
+        );
+
+        let engine = ChewingConversionEngine::new(dict).with_freq_rule(FreqRule::LogPerSyllable);
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "國民".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 2,
+                    end: 4,
+                    phrase: "大會".to_string(),
+                    unknown: false
+                },
+            ],
+            engine.convert(&sys_seq).unwrap()
+        );
+    }
+
+    #[test]
+    fn neutral_tone_matches_only_neutral_tone_entries() {
+        let dict = Rc::new(HashMap::from([
+            (
+                vec![syl![D, U, ENG], syl![X, I, TONE5]],
+                vec![("東西", 100).into()],
+            ),
+            (vec![syl![D, U, ENG]], vec![("冬", 1).into()]),
+            (vec![syl![X, I]], vec![("西", 1).into()]),
+        ]));
+        let engine = ChewingConversionEngine::new(dict);
+        let neutral = ChineseSequence {
+            syllables: vec![syl![D, U, ENG], syl![X, I, TONE5]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "東西".to_string(),
+                unknown: false
+            }],
+            engine.convert(&neutral).unwrap()
+        );
+        let toneless = ChineseSequence {
+            syllables: vec![syl![D, U, ENG], syl![X, I]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 1,
+                    phrase: "冬".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 1,
+                    end: 2,
+                    phrase: "西".to_string(),
+                    unknown: false
+                }
+            ],
+            engine.convert(&toneless).unwrap()
+        );
+    }
+
+    #[test]
+    fn phrase_cost_flips_path() {
+        let dict = test_dictionary();
+        let sys_seq = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "代表".to_string(),
+                unknown: false
+            }],
+            engine.convert(&sys_seq).unwrap()
+        );
+        let engine = ChewingConversionEngine::new(dict).with_phrase_cost(|phrase, start, end| {
+            if phrase.as_str() == "代表" && (start, end) == (0, 2) {
+                -100000
+            } else {
+                0
+            }
+        });
+        let expected = vec![
+            Interval {
+                start: 0,
+                end: 1,
+                phrase: "代".to_string(),
+                unknown: false,
+            },
+            Interval {
+                start: 1,
+                end: 2,
+                phrase: "表".to_string(),
+                unknown: false,
+            },
+        ];
+        assert_eq!(expected, engine.convert(&sys_seq).unwrap());
+    }
+
+    #[test]
+    fn convert_batch_matches_individual_conversion() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequences = vec![
+            ChineseSequence {
+                syllables: vec![
+                    syl![G, U, O, TONE2],
+                    syl![M, I, EN, TONE2],
+                    syl![D, A, TONE4],
+                    syl![H, U, EI, TONE4],
+                    syl![D, AI, TONE4],
+                    syl![B, I, AU, TONE3],
+                ],
+                selections: vec![],
+                breaks: vec![],
+            },
+            ChineseSequence {
+                syllables: vec![syl![C, E, TONE4], syl![SH, TONE4], syl![I, TONE2]],
+                selections: vec![],
+                breaks: vec![],
+            },
+            ChineseSequence {
+                syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                selections: vec![],
+                breaks: vec![Break(1)],
+            },
+        ];
+        let expected: Vec<_> = sequences
+            .iter()
+            .map(|seq| engine.convert(seq).unwrap())
+            .collect();
+        assert_eq!(expected, engine.convert_batch(&sequences).unwrap());
+
+        let mut scratch = Scratch::default();
+        for seq in &sequences {
+            engine.convert_with_scratch(seq, &mut scratch).unwrap();
+        }
+        let capacity = (
+            scratch.intervals.capacity(),
+            scratch.highest_score.capacity(),
+        );
+        for seq in &sequences {
+            engine.convert_with_scratch(seq, &mut scratch).unwrap();
+        }
+        assert_eq!(
+            capacity,
+            (
+                scratch.intervals.capacity(),
+                scratch.highest_score.capacity()
+            )
+        );
+    }
+
+    #[test]
+    fn user_bias_flips_close_homophones() {
+        let sys_seq = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let mut bias = UserBias::new(300);
+        bias.insert("戴錶", 1000);
+
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        assert_eq!("代表", engine.convert(&sys_seq).unwrap()[0].phrase);
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_user_bias(bias.clone());
+        assert_eq!("戴錶", engine.convert(&sys_seq).unwrap()[0].phrase);
+
+        let dict = Rc::new(HashMap::from([
+            (vec![syl![D, AI, TONE4]], vec![("代", 1).into()]),
+            (vec![syl![B, I, AU, TONE3]], vec![("表", 1).into()]),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 10000).into(), ("戴錶", 100).into()],
+            ),
+        ]));
+        let engine = ChewingConversionEngine::new(dict).with_user_bias(bias);
+        assert_eq!("代表", engine.convert(&sys_seq).unwrap()[0].phrase);
+    }
+
+    #[test]
+    fn convert_covers_syllable_missing_in_the_middle() {
+        let dict = Rc::new(HashMap::from([
+            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
+            (vec![syl![D, A, TONE4]], vec![("大", 1).into()]),
+        ]));
+        let engine = ChewingConversionEngine::new(dict);
+        let sys_seq = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 1,
+                    phrase: "國".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 1,
+                    end: 2,
+                    phrase: "ㄇㄧㄣˊ".to_string(),
+                    unknown: true
+                },
+                Interval {
+                    start: 2,
+                    end: 3,
+                    phrase: "大".to_string(),
+                    unknown: false
+                },
+            ],
+            engine.convert(&sys_seq).unwrap()
+        );
+    }
+
+    #[test]
+    fn score_table_ends_with_convert_result() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sys_seq = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let table = engine.score_table(&sys_seq);
+        assert_eq!(7, table.len());
+        assert_eq!((0, 0, vec![]), table[0]);
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "國民".to_string(),
+                unknown: false
+            }],
+            table[2].2
+        );
+        let (pos, _, path) = table.last().unwrap();
+        assert_eq!(6, *pos);
+        assert_eq!(&engine.convert(&sys_seq).unwrap(), path);
+    }
+
+    #[test]
+    fn convert_scored_reflects_competing_homophone() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sys_seq = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let scored = engine.convert_scored(&sys_seq).unwrap();
+        assert_eq!(2, scored.len());
+        assert_eq!("國民", scored[0].0.phrase);
+        assert_eq!(1.0, scored[0].1);
+        assert_eq!("代表", scored[1].0.phrase);
+        assert!(scored[1].1 < 1.0);
+        assert!((scored[1].1 - 0.5).abs() < f32::EPSILON);
+
+        let mut user_bias = UserBias::default();
+        user_bias.insert("戴錶", 50);
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_user_bias(user_bias);
+        let scored = engine.convert_scored(&sys_seq).unwrap();
+        assert_eq!("代表", scored[1].0.phrase);
+        assert!((scored[1].1 - 0.25).abs() < f32::EPSILON);
+
+        let engine = ChewingConversionEngine::new(test_dictionary())
+            .with_blacklist(HashSet::from(["戴錶".to_string()]));
+        let scored = engine.convert_scored(&sys_seq).unwrap();
+        assert_eq!("代表", scored[1].0.phrase);
+        assert_eq!(1.0, scored[1].1);
+    }
+
+    #[test]
+    fn convert_concurrently_with_shared_engine() {
+        let dict: Arc<dyn Dictionary + Send + Sync> = Arc::new(HashMap::from([
+            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
+            (vec![syl![M, I, EN, TONE2]], vec![("民", 1).into()]),
+            (vec![syl![D, A, TONE4]], vec![("大", 1).into()]),
+            (vec![syl![H, U, EI, TONE4]], vec![("會", 1).into()]),
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 200).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 200).into()],
+            ),
+        ]));
+        let engine = Arc::new(SyncChewingConversionEngine::new_sync(dict));
+        let sys_seq = Arc::new(ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        });
+        let expected = vec![
+            Interval {
+                start: 0,
+                end: 2,
+                phrase: "國民".to_string(),
+                unknown: false,
+            },
+            Interval {
+                start: 2,
+                end: 4,
+                phrase: "大會".to_string(),
+                unknown: false,
+            },
+        ];
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let engine = Arc::clone(&engine);
+                let sys_seq = Arc::clone(&sys_seq);
+                thread::spawn(move || engine.convert(&sys_seq).unwrap())
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(expected, handle.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn blacklisted_phrase_is_never_selected() {
+        let dict = Rc::new(HashMap::from([
+            (vec![syl![D, AI, TONE4]], vec![("代", 1).into()]),
+            (vec![syl![B, I, AU, TONE3]], vec![("表", 1).into()]),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 200).into(), ("戴錶", 1000).into()],
+            ),
+        ]));
+        let sys_seq = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!("戴錶", engine.convert(&sys_seq).unwrap()[0].phrase);
+        let engine =
+            ChewingConversionEngine::new(dict).with_blacklist(HashSet::from(["戴錶".to_string()]));
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "代表".to_string(),
+                unknown: false
+            }],
+            engine.convert(&sys_seq).unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_lattice_resolves_ambiguous_syllable() {
+        let dict = Rc::new(HashMap::from([
+            (vec![syl![SH, TONE4]], vec![("是", 10).into()]),
+            (vec![syl![S, TONE4]], vec![("四", 20).into()]),
+            (vec![syl![J, I, AN, TONE4]], vec![("件", 1).into()]),
+            (
+                vec![syl![SH, TONE4], syl![J, I, AN, TONE4]],
+                vec![("事件", 100).into()],
+            ),
+        ]));
+        let engine = ChewingConversionEngine::new(dict);
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "事件".to_string(),
+                unknown: false
+            }],
+            engine
+                .convert_lattice(&[
+                    vec![syl![S, TONE4], syl![SH, TONE4]],
+                    vec![syl![J, I, AN, TONE4]]
+                ])
+                .unwrap()
+        );
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 1,
+                phrase: "四".to_string(),
+                unknown: false
+            }],
+            engine
+                .convert_lattice(&[vec![syl![SH, TONE4], syl![S, TONE4]]])
+                .unwrap()
+        );
+        assert_eq!(
+            Err(ConversionError::MissingSyllable { position: 1 }),
+            engine.convert_lattice(&[vec![syl![SH, TONE4]], vec![]])
+        );
+    }
+
+    #[test]
+    fn prefer_consistent_width_breaks_ties() {
+        let dict = Rc::new(HashMap::from([
+            (vec![syl![I]], vec![("1", 0).into()]),
+            (vec![syl![H, AU, TONE4]], vec![("號", 0).into()]),
+            (vec![syl![L, OU, TONE2]], vec![("樓", 0).into()]),
+            (
+                vec![syl![I], syl![H, AU, TONE4]],
+                vec![("１號", 100).into()],
+            ),
+            (
+                vec![syl![H, AU, TONE4], syl![L, OU, TONE2]],
+                vec![("號樓", 100).into()],
+            ),
+        ]));
+        let sys_seq = ChineseSequence {
+            syllables: vec![syl![I], syl![H, AU, TONE4], syl![L, OU, TONE2]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let to_string = |intervals: Vec<Interval>| {
+            intervals
+                .into_iter()
+                .map(|it| it.phrase)
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!("1|號樓", to_string(engine.convert(&sys_seq).unwrap()));
+        let engine = ChewingConversionEngine::new(dict).with_prefer_consistent_width(true);
+        assert_eq!("１號|樓", to_string(engine.convert(&sys_seq).unwrap()));
+    }
+
+    #[test]
+    fn convert_to_width_prefers_closest_width() {
+        let dict = |wide_freq: u32| {
+            Rc::new(HashMap::from([
+                (vec![syl![I]], vec![("1", 0).into()]),
+                (vec![syl![H, AU, TONE4]], vec![("號", 0).into()]),
+                (vec![syl![L, OU, TONE2]], vec![("樓", 0).into()]),
+                (
+                    vec![syl![I], syl![H, AU, TONE4]],
+                    vec![("１號", wide_freq).into()],
+                ),
+                (
+                    vec![syl![H, AU, TONE4], syl![L, OU, TONE2]],
+                    vec![("號樓", 700).into()],
+                ),
+            ]))
+        };
+        let sys_seq = ChineseSequence {
+            syllables: vec![syl![I], syl![H, AU, TONE4], syl![L, OU, TONE2]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let to_string = |intervals: Vec<Interval>| {
+            intervals
+                .into_iter()
+                .map(|it| it.phrase)
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        // The wide phrasing scores 400 below the best one.
+        let engine = ChewingConversionEngine::new(dict(300));
+        assert_eq!("1|號樓", to_string(engine.convert(&sys_seq).unwrap()));
+        assert_eq!(
+            "1|號樓",
+            to_string(engine.convert_to_width(&sys_seq, 5).unwrap())
+        );
+        assert_eq!(
+            "１號|樓",
+            to_string(engine.convert_to_width(&sys_seq, 6).unwrap())
+        );
+        assert_eq!(
+            "１號|樓",
+            to_string(engine.convert_to_width(&sys_seq, 10).unwrap())
+        );
+
+        // 600 below is beyond the margin, the width doesn't matter.
+        let engine = ChewingConversionEngine::new(dict(100));
+        assert_eq!(
+            "1|號樓",
+            to_string(engine.convert_to_width(&sys_seq, 6).unwrap())
+        );
+    }
+
+    #[test]
+    fn prefer_early_boundary_breaks_ties() {
+        let (a, b, c, d, e, f) = (
+            syl![G, U, O, TONE2],
+            syl![M, I, EN, TONE2],
+            syl![D, A, TONE4],
+            syl![H, U, EI, TONE4],
+            syl![D, AI, TONE4],
+            syl![B, I, AU, TONE3],
+        );
+        let dict = Rc::new(HashMap::from([
+            (vec![a], vec![("國", 0).into()]),
+            (vec![b], vec![("民", 0).into()]),
+            (vec![c], vec![("大", 0).into()]),
+            (vec![d], vec![("會", 0).into()]),
+            (vec![e], vec![("代", 0).into()]),
+            (vec![f], vec![("表", 0).into()]),
+            (vec![a, b], vec![("國民", 100).into()]),
+            (vec![b, c, d], vec![("民大會", 100).into()]),
+            (vec![d, e, f], vec![("會代表", 100).into()]),
+            (vec![e, f], vec![("代表", 100).into()]),
+        ]));
+        let sys_seq = ChineseSequence {
+            syllables: vec![a, b, c, d, e, f],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let to_string = |intervals: Vec<Interval>| {
+            intervals
+                .into_iter()
+                .map(|it| it.phrase)
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!(
+            "國民|大|會代表",
+            to_string(engine.convert(&sys_seq).unwrap())
+        );
+        let engine = ChewingConversionEngine::new(dict).with_prefer_early_boundary(true);
+        assert_eq!(
+            "國|民大會|代表",
+            to_string(engine.convert(&sys_seq).unwrap())
+        );
+        assert_eq!(
+            "國|民大會|代表",
+            to_string(engine.convert_next(&sys_seq, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn length_weights_scale_frequencies() {
+        let (a, b, c, d) = (
+            syl![G, U, O, TONE2],
+            syl![M, I, EN, TONE2],
+            syl![D, A, TONE4],
+            syl![H, U, EI, TONE4],
+        );
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::from([
+            (vec![a], vec![("國", 0).into()]),
+            (vec![a, b], vec![("國民", 100).into()]),
+            (vec![c, d], vec![("大會", 100).into()]),
+            (vec![b, c, d], vec![("民大會", 250).into()]),
+        ]));
+        let sys_seq = ChineseSequence {
+            syllables: vec![a, b, c, d],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let to_string = |engine: ChewingConversionEngine| {
+            engine
+                .convert(&sys_seq)
+                .unwrap()
+                .into_iter()
+                .map(|it| it.phrase)
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        assert_eq!(
+            "國民|大會",
+            to_string(ChewingConversionEngine::new(dict.clone()))
+        );
+        assert_eq!(
+            "國民|大會",
+            to_string(
+                ChewingConversionEngine::new(dict.clone())
+                    .with_length_weights(vec![1.0 / 512.0, 1.0])
+            )
+        );
+        assert_eq!(
+            "國|民大會",
+            to_string(ChewingConversionEngine::new(dict).with_length_weights(vec![
+                1.0 / 512.0,
+                0.1,
+                1.0
+            ]))
+        );
+    }
+
+    #[test]
+    fn length_weights_replace_single_char_penalty() {
+        let (a, b, c, d) = (
+            syl![G, U, O, TONE2],
+            syl![M, I, EN, TONE2],
+            syl![D, A, TONE4],
+            syl![H, U, EI, TONE4],
+        );
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::from([
+            (vec![a], vec![("國", 10000).into()]),
+            (vec![a, b], vec![("國民", 100).into()]),
+            (vec![c, d], vec![("大會", 100).into()]),
+            (vec![b, c, d], vec![("民大會", 250).into()]),
+        ]));
+        let sys_seq = ChineseSequence {
+            syllables: vec![a, b, c, d],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let to_string = |engine: ChewingConversionEngine| {
+            engine
+                .convert(&sys_seq)
+                .unwrap()
+                .into_iter()
+                .map(|it| it.phrase)
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        assert_eq!(
+            "國民|大會",
+            to_string(ChewingConversionEngine::new(dict.clone()))
+        );
+        assert_eq!(
+            "國|民大會",
+            to_string(
+                ChewingConversionEngine::new(dict.clone()).with_single_char_penalty(|_, _, _| 1)
+            )
+        );
+        assert_eq!(
+            "國民|大會",
+            to_string(
+                ChewingConversionEngine::new(dict)
+                    .with_length_weights(vec![1.0 / 512.0, 1.0])
+                    .with_single_char_penalty(|_, _, _| 1)
+            )
+        );
+    }
+
+    #[test]
+    fn freq_smoothing_breaks_sparse_ties() {
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::from([(
+            vec![syl![C, E, TONE4], syl![SH, TONE4]],
+            vec![("測試", 600).into()],
+        )]));
+        let sys_seq = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let lengths = |engine: ChewingConversionEngine| {
+            engine
+                .convert(&sys_seq)
+                .unwrap()
+                .into_iter()
+                .map(|it| it.end - it.start)
+                .collect::<Vec<_>>()
+        };
+        // Eight unknown single syllables score the same as six unknown
+        // syllables followed by the phrase, the default seed picks the
+        // placeholders. The smoothed placeholders are still divided by the
+        // single character penalty, so raising every phrase to the smoothing
+        // favours the phrase.
+        assert_eq!(
+            vec![1, 1, 1, 1, 1, 1, 1, 1],
+            lengths(ChewingConversionEngine::new(dict.clone()))
+        );
+        assert_eq!(
+            vec![1, 1, 1, 1, 1, 1, 2],
+            lengths(ChewingConversionEngine::new(dict).with_freq_smoothing(1024))
+        );
+    }
+
+    #[test]
+    fn convert_greedy_longest_match() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sys_seq = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            engine.convert(&sys_seq).unwrap(),
+            engine.convert_greedy(&sys_seq)
+        );
+
+        // Greedy takes the longest phrase 測試儀 first while the DP finds
+        // the more frequent split 測試 一下.
+        let sys_seq = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        assert_eq!(
+            vec!["測試儀", "下"],
+            phrases(engine.convert_greedy(&sys_seq))
+        );
+        assert_eq!(
+            vec!["測試", "一下"],
+            phrases(engine.convert(&sys_seq).unwrap())
+        );
+    }
+
+    #[test]
+    fn large_frequencies_do_not_overflow() {
+        let big = i32::MAX as u32 - 1;
+        let dict = Rc::new(HashMap::from([
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", big).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", big).into()],
+            ),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", u32::MAX).into(), ("戴錶", big).into()],
+            ),
+            (
+                vec![syl![H, U, EI, TONE4], syl![D, AI, TONE4]],
+                vec![("會代", 1).into()],
+            ),
+        ]));
+        let engine = ChewingConversionEngine::new(dict);
+        let sys_seq = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases: Vec<_> = engine
+            .convert(&sys_seq)
+            .unwrap()
+            .into_iter()
+            .map(|it| it.phrase)
+            .collect();
+        assert_eq!(vec!["國民", "大會", "代表"], phrases);
+        let table = engine.score_table(&sys_seq);
+        assert!(table[6].1 > i64::from(i32::MAX));
+        assert!(table[6].1 > table[4].1);
+    }
+
+    #[test]
+    fn best_phrase_for_span() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let phrase = engine
+            .best_phrase(0, &[syl![D, AI, TONE4], syl![B, I, AU, TONE3]], &[], &[])
+            .unwrap();
+        assert_eq!("代表", phrase.as_str());
+        assert_eq!(200, phrase.freq());
+        assert_eq!(
+            None,
+            engine.best_phrase(
+                0,
+                &[syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                &[],
+                &[Break(1)]
+            )
+        );
+    }
+
+    #[test]
+    fn trimming_during_enumeration_bounds_retained_paths() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let len = sequence.syllables.len();
+        let all_paths = engine.find_all_paths(&mut Graph::default(), &sequence, 0, len, None);
+        let mut expected = engine.trim_paths(all_paths.clone());
+        expected.sort();
+        expected.reverse();
+
+        let mut peak = 0;
+        let mut trimmed = vec![];
+        engine.visit_paths(
+            &mut Graph::default(),
+            &sequence,
+            0,
+            len,
+            None,
+            &mut |path| {
+                ChewingConversionEngine::<Rc<dyn Dictionary>>::trim_insert(&mut trimmed, path);
+                peak = peak.max(trimmed.len());
+            },
+        );
+        assert!(peak < all_paths.len());
+
+        let actual = engine.find_candidate_paths(&mut Graph::default(), &sequence);
+        assert_eq!(expected.len(), actual.len());
+        for (expected, actual) in expected.iter().zip(actual.iter()) {
+            assert_eq!(expected.intervals, actual.intervals);
+        }
+    }
+
+    #[test]
+    fn position_aware_single_char_penalty() {
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::from([
+            (vec![syl![D, A, TONE4]], vec![("大", 2000).into()]),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 100).into()],
+            ),
+            (
+                vec![syl![H, U, EI, TONE4], syl![D, AI, TONE4]],
+                vec![("會代", 90).into()],
+            ),
+        ]));
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, A, TONE4], syl![H, U, EI, TONE4], syl![D, AI, TONE4]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let flat = ChewingConversionEngine::new(dict.clone());
+        assert_eq!(
+            vec!["大會", "ㄉㄞˋ"],
+            flat.convert(&sequence)
+                .unwrap()
+                .into_iter()
+                .map(|it| it.phrase)
+                .collect::<Vec<_>>()
+        );
+        let position_aware =
+            ChewingConversionEngine::new(dict).with_single_char_penalty(|start, _end, _len| {
+                if start == 0 {
+                    1
+                } else {
+                    DEFAULT_SINGLE_CHAR_PENALTY
+                }
+            });
+        assert_eq!(
+            vec!["大", "會代"],
+            position_aware
+                .convert(&sequence)
+                .unwrap()
+                .into_iter()
+                .map(|it| it.phrase)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    fn long_sequence() -> ChineseSequence {
+        ChineseSequence {
+            syllables: [
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ]
+            .repeat(4),
+            selections: vec![],
+            breaks: vec![],
+        }
+    }
+
+    fn assert_covers(len: usize, intervals: &[Interval]) {
+        let mut pos = 0;
+        for interval in intervals {
+            assert_eq!(pos, interval.start);
+            assert!(interval.end > interval.start);
+            pos = interval.end;
+        }
+        assert_eq!(len, pos);
+    }
+
+    #[test]
+    fn convert_within_budget_covers_input() {
+        let engine = ChewingConversionEngine::new(Rc::new(SlowDictionary {
+            inner: test_dictionary(),
+            delay: Duration::from_millis(2),
+        }));
+        let sequence = long_sequence();
+        let result = engine
+            .convert_within(&sequence, Duration::from_millis(1))
+            .unwrap();
+        assert_covers(sequence.syllables.len(), &result);
+    }
+
+    #[test]
+    fn convert_within_falls_back_to_greedy() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = long_sequence();
+        assert_eq!(
+            engine.convert_greedy(&sequence),
+            engine.convert_within(&sequence, Duration::ZERO).unwrap()
+        );
+        assert_eq!(
+            engine.convert(&sequence).unwrap(),
+            engine
+                .convert_within(&sequence, Duration::from_secs(60))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn recombine_single_characters() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![X, I, EN],
+                syl![K, U, TONE4],
+                syl![I, EN],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![Break(4)],
+        };
+        let single = |start: usize, phrase: &str| Interval {
+            start,
+            end: start + 1,
+            phrase: phrase.to_string(),
+            unknown: false,
+        };
+        let fragmented = vec![
+            single(0, "心"),
+            single(1, "ㄎㄨˋ"),
+            single(2, "ㄧㄣ"),
+            single(3, "代"),
+            single(4, "表"),
+        ];
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 3,
+                    phrase: "新酷音".to_string(),
+                    unknown: false
+                },
+                single(3, "代"),
+                single(4, "表"),
+            ],
+            engine.recombine(&sequence, &fragmented).unwrap()
+        );
+    }
+
+    #[test]
+    fn unreachable_end_is_empty_path_error() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let phrase = Rc::new(Phrase::new("測試", 0));
+        let intervals = vec![
+            PossibleInterval {
+                start: 0,
+                end: 2,
+                phrase: phrase.clone(),
+                bonus: 0,
+                freq_divisor: 1,
+                unknown: false,
+            },
+            PossibleInterval {
+                start: 1,
+                end: 3,
+                phrase,
+                bonus: 0,
+                freq_divisor: 1,
+                unknown: false,
+            },
+        ];
+        assert_eq!(
+            Err(ConversionError::EmptyPath { len: 3 }),
+            engine.find_best_path(3, intervals)
+        );
+    }
+
+    #[test]
+    fn too_long_sequence_is_score_overflow_error() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, A, TONE4]; MAX_SEQUENCE_LEN + 1],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let expected = Err(ConversionError::ScoreOverflow {
+            len: MAX_SEQUENCE_LEN + 1,
+            max: MAX_SEQUENCE_LEN,
+        });
+        assert_eq!(expected, engine.convert(&sequence));
+        assert_eq!(expected, engine.convert_next(&sequence, 0));
+    }
+
+    #[test]
+    fn no_paths_is_missing_candidate_error() {
+        assert_eq!(
+            Err(ConversionError::MissingCandidate),
+            nth_candidate(vec![], 0)
+        );
+    }
+
+    #[test]
+    fn predict_completions() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        assert_eq!(vec!["國民"], engine.predict(&[syl![G, U, O, TONE2]]));
+        assert_eq!(vec!["代表", "戴錶"], engine.predict(&[syl![D, AI, TONE4]]));
+        assert!(engine
+            .predict(&[syl![G, U, O, TONE2], syl![M, I, EN, TONE2]])
+            .is_empty());
+
+        let mut overlay = FrequencyOverlay::new();
+        overlay.insert("戴錶", 300);
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_overlay(overlay);
+        assert_eq!(vec!["戴錶", "代表"], engine.predict(&[syl![D, AI, TONE4]]));
+    }
+
+    #[test]
+    fn duplicate_intervals_are_removed() {
+        let dict = test_dictionary();
+        let engine = ChewingConversionEngine::new(dict);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let len = sequence.syllables.len();
+        let intervals = engine.find_intervals(&sequence);
+        let unique = intervals.len();
+
+        // Looking up the same spans twice yields every interval twice.
+        let mut repeated = intervals.clone();
+        repeated.extend(intervals.iter().cloned());
+        repeated.sort_by_key(|it| it.end);
+        dedup_intervals(&mut repeated);
+        assert_eq!(unique, repeated.len());
+
+        let mut doubled = intervals.clone();
+        doubled.extend(intervals);
+        assert_eq!(
+            engine.convert(&sequence).unwrap(),
+            engine.find_best_path(len, doubled).unwrap()
+        );
+    }
+
+    #[test]
+    fn bigram_flips_segmentation() {
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::from([
+            (
+                vec![syl![C, E, TONE4], syl![SH, TONE4]],
+                vec![("測試", 9318).into()],
+            ),
+            (
+                vec![syl![C, E, TONE4], syl![SH, TONE4], syl![I, TONE2]],
+                vec![("測試儀", 20000).into()],
+            ),
+            (
+                vec![syl![I, TONE2], syl![CH, I, TONE4]],
+                vec![("儀器", 10).into()],
+            ),
+            (vec![syl![CH, I, TONE4]], vec![("器", 1).into()]),
+        ]));
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![CH, I, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!(
+            vec!["測試儀", "器"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+
+        let mut bigram = Bigram::new();
+        bigram.insert("測試", "儀器", 20000);
+        let engine = ChewingConversionEngine::new(dict).with_bigram(bigram);
+        assert_eq!(
+            vec!["測試", "儀器"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+        assert_eq!(
+            vec!["測試", "儀器"],
+            phrases(engine.convert_next(&sequence, 0).unwrap())
+        );
+        assert_eq!(
+            vec!["測試", "儀器"],
+            phrases(engine.convert_reverse(&sequence).unwrap())
+        );
+    }
+
+    #[test]
+    fn trailing_toneless_syllable_matches_any_tone() {
+        let sequence = ChineseSequence {
+            syllables: vec![syl![C, E, TONE4], syl![SH]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        assert_ne!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "測試".to_string(),
+                unknown: false
+            }],
+            engine.convert(&sequence).unwrap()
+        );
+
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_trailing_toneless(true);
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "測試".to_string(),
+                unknown: false
+            }],
+            engine.convert(&sequence).unwrap()
+        );
+
+        // Only the last syllable is matched without the tone.
+        let sequence = ChineseSequence {
+            syllables: vec![syl![C, E], syl![SH, TONE4]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(2, engine.convert(&sequence).unwrap().len());
+    }
+
+    #[test]
+    fn commit_prefix_returns_suffix_sequence() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![Interval {
+                start: 4,
+                end: 6,
+                phrase: "戴錶".to_string(),
+                unknown: false,
+            }],
+            breaks: vec![Break(5)],
+        };
+        let (committed, rest) = engine.commit_prefix(&sequence, 4).unwrap();
+        assert_eq!(
+            vec![
+                Interval {
+                    start: 0,
+                    end: 2,
+                    phrase: "國民".to_string(),
+                    unknown: false
+                },
+                Interval {
+                    start: 2,
+                    end: 4,
+                    phrase: "大會".to_string(),
+                    unknown: false
+                },
+            ],
+            committed
+        );
+        assert_eq!(
+            vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            rest.syllables
+        );
+        assert_eq!(
+            vec![Interval {
+                start: 0,
+                end: 2,
+                phrase: "戴錶".to_string(),
+                unknown: false
+            }],
+            rest.selections
+        );
+        assert_eq!(vec![Break(1)], rest.breaks);
+        assert_eq!(
+            engine.convert_suffix(&sequence, 4).unwrap(),
+            engine
+                .convert(&rest)
+                .unwrap()
+                .into_iter()
+                .map(|it| Interval {
+                    start: it.start + 4,
+                    end: it.end + 4,
+                    phrase: it.phrase,
+                    unknown: false
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn fewest_segments_prefers_single_phrase() {
+        let dict: Rc<dyn Dictionary> = Rc::new(HashMap::from([
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 10000).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 10000).into()],
+            ),
+            (
+                vec![
+                    syl![G, U, O, TONE2],
+                    syl![M, I, EN, TONE2],
+                    syl![D, A, TONE4],
+                    syl![H, U, EI, TONE4],
+                ],
+                vec![("國民大會", 1).into()],
+            ),
+        ]));
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!(
+            vec!["國民", "大會"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+
+        let engine = ChewingConversionEngine::new(dict).with_score_mode(ScoreMode::FewestSegments);
+        assert_eq!(
+            vec!["國民大會"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+
+        // Breaks are still respected.
+        let with_break = ChineseSequence {
+            breaks: vec![Break(2)],
+            ..sequence
+        };
+        assert_eq!(
+            vec!["國民", "大會"],
+            phrases(engine.convert(&with_break).unwrap())
+        );
+    }
+
+    #[test]
+    fn convert_after_selection_matches_full_convert() {
+        let dict = Rc::new(CountingDictionary::new(Box::new(test_map())));
+        let engine = ChewingConversionEngine::new(dict.clone());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let selection = Interval {
+            start: 4,
+            end: 6,
+            phrase: "戴錶".to_string(),
+            unknown: false,
+        };
+        let mut cache = ConversionCache::new();
+        engine
+            .convert_next_cached(&sequence, 0, &mut cache)
+            .unwrap();
+        dict.reset();
+        let incremental = engine
+            .convert_after_selection(&sequence, &selection, &mut cache)
+            .unwrap();
+        let relooked = dict.lookups();
+
+        let selected = ChineseSequence {
+            selections: vec![selection],
+            ..sequence
+        };
+        dict.reset();
+        assert_eq!(engine.convert(&selected).unwrap(), incremental);
+        assert!(relooked < dict.lookups());
+        assert_eq!(
+            incremental,
+            engine
+                .convert_next_cached(&selected, 0, &mut cache)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_reverse_matches_forward() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let cases = [
+            (vec![], vec![]),
+            (vec![Break(1), Break(3)], vec![]),
+            (vec![Break(5)], vec![]),
+            (vec![], vec![Interval::new(4, 6, "戴錶").unwrap()]),
+            (vec![], vec![Interval::new(1, 3, "民大").unwrap()]),
+            (vec![Break(2)], vec![Interval::new(3, 4, "會").unwrap()]),
+        ];
+        for (breaks, selections) in cases {
+            let sequence = ChineseSequence {
+                syllables: sequence.syllables.clone(),
+                selections,
+                breaks,
+            };
+            assert_eq!(
+                engine.convert(&sequence).unwrap(),
+                engine.convert_reverse(&sequence).unwrap(),
+                "{:?}",
+                sequence
+            );
+        }
+        assert_eq!(
+            Vec::<Interval>::new(),
+            engine
+                .convert_reverse(&ChineseSequence {
+                    syllables: vec![],
+                    selections: vec![],
+                    breaks: vec![],
+                })
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn language_model_reranks_candidates() {
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        assert_eq!(
+            vec!["測試", "一下"],
+            phrases(engine.convert_next(&sequence, 0).unwrap())
+        );
+
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_language_model(
+            |intervals| {
+                if intervals.iter().any(|it| it.phrase == "測試儀") {
+                    0.0
+                } else {
+                    -1e12
+                }
+            },
+            1.0,
+        );
+        assert_eq!(
+            vec!["測試儀", "下"],
+            phrases(engine.convert_next(&sequence, 0).unwrap())
+        );
+        assert_eq!(
+            vec!["測試儀", "下"],
+            phrases(engine.convert_candidates(&sequence, 1).remove(0))
+        );
+        assert_eq!(
+            vec!["測試", "一下"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+    }
+
+    #[test]
+    fn reconcile_explains_reranked_candidate() {
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![C, E, TONE4],
+                syl![SH, TONE4],
+                syl![I, TONE2],
+                syl![X, I, A, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: &[Interval]| -> Vec<String> {
+            intervals.iter().map(|it| it.phrase.clone()).collect()
+        };
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let reconciliation = engine.reconcile(&sequence).unwrap();
+        assert!(reconciliation.is_consistent());
+        assert_eq!(None, reconciliation.scores);
+
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_language_model(
+            |intervals| {
+                if intervals.iter().any(|it| it.phrase == "測試儀") {
+                    0.0
+                } else {
+                    -1e12
+                }
+            },
+            1.0,
+        );
+        let reconciliation = engine.reconcile(&sequence).unwrap();
+        assert!(!reconciliation.is_consistent());
+        assert_eq!(vec!["測試", "一下"], phrases(&reconciliation.best));
+        assert_eq!(vec!["測試儀", "下"], phrases(&reconciliation.next));
+        let (best, next) = reconciliation.scores.unwrap();
+        assert_eq!(
+            engine.score_segmentation(&sequence, &reconciliation.best),
+            best.dp
+        );
+        assert_eq!(
+            engine.score_segmentation(&sequence, &reconciliation.next),
+            next.dp
+        );
+        assert!(best.dp > next.dp);
+        assert!(best.path < next.path);
+        assert_eq!(Some(next.dp.unwrap() as f64), next.path);
+    }
+
+    #[test]
+    fn aliases_normalize_output_phrases() {
+        let dict = Rc::new(HashMap::from([
+            (
+                vec![syl![T, AI, TONE2], syl![U, AN]],
+                vec![("台灣", 100).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 200).into()],
+            ),
+        ]));
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![T, AI, TONE2],
+                syl![U, AN],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        let mut aliases = AliasMap::new();
+        aliases.insert("台灣", "臺灣");
+        let engine = ChewingConversionEngine::new(dict).with_aliases(aliases);
+        assert_eq!(
+            vec!["臺灣", "大會"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+        assert_eq!(
+            vec!["臺灣", "大會"],
+            phrases(engine.convert_next(&sequence, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn aliased_output_round_trips() {
+        let dict = Rc::new(HashMap::from([
+            (
+                vec![syl![T, AI, TONE2], syl![U, AN]],
+                vec![("臺灣", 10).into(), ("台灣", 100).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 200).into()],
+            ),
+        ]));
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![T, AI, TONE2],
+                syl![U, AN],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let plain = ChewingConversionEngine::new(dict.clone());
+        let plain_output = plain.convert(&sequence).unwrap();
+        let mut aliases = AliasMap::new();
+        aliases.insert("台灣", "臺灣");
+        let engine = ChewingConversionEngine::new(dict).with_aliases(aliases.clone());
+        let output = engine.convert(&sequence).unwrap();
+        assert_eq!("臺灣", output[0].phrase);
+        assert_eq!(
+            plain.score_segmentation(&sequence, &plain_output),
+            engine.score_segmentation(&sequence, &output)
+        );
+
+        // Only the alias of the dictionary phrase is output, pinning it keeps
+        // the dictionary phrase.
+        let dict = Rc::new(HashMap::from([
+            (
+                vec![syl![T, AI, TONE2], syl![U, AN]],
+                vec![("台灣", 100).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 200).into()],
+            ),
+        ]));
+        let engine = ChewingConversionEngine::new(dict).with_aliases(aliases);
+        let output = engine.convert(&sequence).unwrap();
+        let mut pinned = ChineseSequence {
+            syllables: sequence.syllables.clone(),
+            selections: vec![],
+            breaks: vec![],
+        };
+        engine.pin_segmentation(&mut pinned, &output);
+        assert_eq!(output, engine.convert(&pinned).unwrap());
+    }
+
+    #[test]
+    fn min_freq_decomposes_rare_phrase() {
+        let sequence = ChineseSequence {
+            syllables: vec![syl![X, I, EN], syl![K, U, TONE4], syl![I, EN]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        assert_eq!(vec!["新酷音"], phrases(engine.convert(&sequence).unwrap()));
+
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_min_freq(250);
+        assert_eq!(
+            vec!["心", "庫音"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+    }
+
+    #[test]
+    fn length_variance_rule_can_be_disabled() {
+        let syllables = [
+            syl![Z, TONE1],
+            syl![L, I, AU, TONE4],
+            syl![J, I, EH, TONE2],
+            syl![G, OU, TONE4],
+            syl![D, E, TONE5],
+        ];
+        let dict = Rc::new(HashMap::from([
+            (vec![syllables[0]], vec![("資", 1).into()]),
+            (vec![syllables[1]], vec![("料", 1).into()]),
+            (vec![syllables[2]], vec![("結", 1).into()]),
+            (vec![syllables[3]], vec![("構", 1).into()]),
+            (vec![syllables[4]], vec![("的", 1000).into()]),
+            (syllables[0..2].to_vec(), vec![("資料", 500).into()]),
+            (syllables[2..5].to_vec(), vec![("結構的", 500).into()]),
+            (syllables[0..4].to_vec(), vec![("資料結構", 1150).into()]),
+        ]));
+        let sequence = ChineseSequence {
+            syllables: syllables.to_vec(),
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!(
+            vec!["資料", "結構的"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+
+        let engine = ChewingConversionEngine::new(dict).with_length_variance(false);
+        assert_eq!(
+            vec!["資料結構", "的"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+    }
+
+    #[test]
+    fn convert_each_matches_convert() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let mut visited = vec![];
+        let intervals = engine
+            .convert_each(&sequence, |interval| visited.push(interval.clone()))
+            .unwrap();
+        assert_eq!(engine.convert(&sequence).unwrap(), visited);
+        assert_eq!(intervals, visited);
+    }
+
+    #[test]
+    fn overlays_replace_dictionary_frequencies() {
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        let mut first = FrequencyOverlay::new();
+        first.insert("代表", 100);
+        first.insert("戴錶", 300);
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_overlay(first);
+        assert_eq!(vec!["戴錶"], phrases(engine.convert(&sequence).unwrap()));
+
+        let mut second = FrequencyOverlay::new();
+        second.insert("代表", 300);
+        second.insert("戴錶", 100);
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_overlay(second);
+        assert_eq!(vec!["代表"], phrases(engine.convert(&sequence).unwrap()));
+    }
+
+    #[test]
+    fn join_at_merges_adjacent_intervals() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![Break(1)],
+        };
+        assert_eq!(
+            vec!["國", "民", "大會"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+        assert_eq!(
+            vec!["國民", "大會"],
+            phrases(engine.join_at(&sequence, 1).unwrap())
+        );
+        assert_eq!(
+            vec!["國", "民大會"],
+            phrases(engine.join_at(&sequence, 2).unwrap())
+        );
+        assert_eq!(
+            vec!["國", "民", "大會"],
+            phrases(engine.join_at(&sequence, 3).unwrap())
+        );
+    }
+
+    #[test]
+    fn split_at_breaks_interval_into_syllables() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!(
+            vec!["國", "民", "大會", "代表"],
+            phrases(engine.split_at(&sequence, 0).unwrap())
+        );
+        assert_eq!(
+            phrases(engine.convert(&sequence).unwrap()),
+            phrases(engine.split_at(&sequence, 3).unwrap())
+        );
+    }
+
+    #[test]
+    fn category_bonus_flips_choice() {
+        let dict = Rc::new(HashMap::from([(
+            vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            vec![
+                ("代表", 200).into(),
+                Phrase::new("戴錶", 100).with_category("brand"),
+            ],
+        )]));
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        let engine = ChewingConversionEngine::new(dict.clone());
+        assert_eq!(vec!["代表"], phrases(engine.convert(&sequence).unwrap()));
+
+        let engine = ChewingConversionEngine::new(dict.clone()).with_category_bonus("name", 500);
+        assert_eq!(vec!["代表"], phrases(engine.convert(&sequence).unwrap()));
+
+        let engine = ChewingConversionEngine::new(dict).with_category_bonus("brand", 500);
+        assert_eq!(vec!["戴錶"], phrases(engine.convert(&sequence).unwrap()));
+    }
+
+    #[test]
+    fn anti_bigram_avoids_forbidden_pair() {
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        let mut bigram = Bigram::new();
+        bigram.insert("大會", "代表", 1000);
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_bigram(bigram.clone());
+        assert_eq!(
+            vec!["國民", "大會", "代表"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+        // Splitting either phrase scores the same, so keep the boundaries
+        // closest to where the search starts instead of the seeded choice.
+        let engine = ChewingConversionEngine::new(test_dictionary())
+            .with_anti_bigram([("大會", "代表")])
+            .with_bigram(bigram)
+            .with_prefer_early_boundary(true);
+        assert_eq!(
+            vec!["國民", "大", "會", "代表"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+        assert_eq!(
+            vec!["國民", "大會", "代", "表"],
+            phrases(engine.convert_reverse(&sequence).unwrap())
+        );
+    }
+
+    #[test]
+    fn char_spans_follow_literal_length() {
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_pin_selections(true);
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ],
+            selections: vec![Interval::new(0, 2, "ROC").unwrap()],
+            breaks: vec![],
+        };
+        let spans: Vec<_> = engine
+            .convert_char_spans(&sequence)
+            .unwrap()
+            .into_iter()
+            .map(|(chars, interval)| (chars, interval.start..interval.end, interval.phrase))
+            .collect();
+        assert_eq!(
+            vec![
+                (0..3, 0..2, "ROC".to_string()),
+                (3..5, 2..4, "大會".to_string())
+            ],
+            spans
+        );
+    }
+
+    #[test]
+    fn checked_engine_reports_inconsistent_phrase() {
+        let dict = Rc::new(HashMap::from([
+            (vec![syl![D, AI, TONE4]], vec![("代", 1).into()]),
+            (vec![syl![B, I, AU, TONE3]], vec![("表", 1).into()]),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表人", 200).into()],
+            ),
+        ]));
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let engine = ChewingConversionEngine::new_checked(dict);
+        assert_eq!(
+            Err(ConversionError::InconsistentPhrase {
+                phrase: "代表人".to_string(),
+                len: 2
+            }),
+            engine.convert(&sequence)
+        );
+        let error = engine.convert(&sequence).unwrap_err();
+        assert_eq!(
+            Err(error.clone()),
+            engine.convert_with_boundary_probs(&sequence, &[0.0, 1.0])
+        );
+        assert_eq!(
+            Err(error.clone()),
+            engine.convert_with_boost(&sequence, &HashMap::new())
+        );
+        assert_eq!(
+            Err(error.clone()),
+            engine.convert_with_priorities(&sequence, &[])
+        );
+        assert_eq!(
+            Err(error.clone()),
+            engine.convert_with_soft_breaks(&sequence, &[SoftBreak(1, 10)])
+        );
+        assert_eq!(
+            Err(error),
+            engine.convert_with_overrides(&sequence, &HashMap::new())
+        );
+    }
+
+    #[test]
+    fn feature_vector_of_convert_dominates() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        let best = engine
+            .feature_vector(&sequence, &engine.convert(&sequence).unwrap())
+            .unwrap();
+        assert_eq!([6, 12, 0, 600], best);
+        let worse = engine
+            .feature_vector(
+                &sequence,
+                &[
+                    Interval::new(0, 1, "國").unwrap(),
+                    Interval::new(1, 2, "民").unwrap(),
+                    Interval::new(2, 4, "大會").unwrap(),
+                    Interval::new(4, 6, "代表").unwrap(),
+                ],
+            )
+            .unwrap();
+        assert_eq!([6, 9, -4, 400], worse);
+        assert!(best.iter().zip(&worse).all(|(best, worse)| best >= worse));
+        assert_eq!(
+            None,
+            engine.feature_vector(&sequence, &[Interval::new(0, 2, "國民").unwrap()])
+        );
+    }
 
-    fn test_dictionary() -> Rc<dyn Dictionary> {
-        Rc::new(HashMap::from([
-            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
+    #[test]
+    fn committed_context_feeds_bigram() {
+        let dict = Rc::new(HashMap::from([
             (vec![syl![M, I, EN, TONE2]], vec![("民", 1).into()]),
+            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
             (vec![syl![D, A, TONE4]], vec![("大", 1).into()]),
-            (vec![syl![H, U, EI, TONE4]], vec![("會", 1).into()]),
-            (vec![syl![D, AI, TONE4]], vec![("代", 1).into()]),
-            (vec![syl![B, I, AU, TONE3]], vec![("表", 1).into()]),
-            (
-                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
-                vec![("國民", 200).into()],
-            ),
-            (
-                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
-                vec![("大會", 200).into()],
-            ),
-            (
-                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
-                vec![("代表", 200).into(), ("戴錶", 100).into()],
-            ),
-            (vec![syl![X, I, EN]], vec![("心", 1).into()]),
-            (
-                vec![syl![K, U, TONE4], syl![I, EN]],
-                vec![("庫音", 300).into()],
-            ),
-            (
-                vec![syl![X, I, EN], syl![K, U, TONE4], syl![I, EN]],
-                vec![("新酷音", 200).into()],
-            ),
-            (
-                vec![syl![C, E, TONE4], syl![SH, TONE4], syl![I, TONE2]],
-                vec![("測試儀", 42).into()],
-            ),
             (
-                vec![syl![C, E, TONE4], syl![SH, TONE4]],
-                vec![("測試", 9318).into()],
+                vec![syl![M, I, EN, TONE2], syl![G, U, O, TONE2]],
+                vec![("民國", 100).into()],
             ),
             (
-                vec![syl![I, TONE2], syl![X, I, A, TONE4]],
-                vec![("一下", 10576).into()],
+                vec![syl![G, U, O, TONE2], syl![D, A, TONE4]],
+                vec![("國大", 300).into()],
             ),
-            (vec![syl![X, I, A, TONE4]], vec![("下", 10576).into()]),
-        ]))
-    }
-
-    #[test]
-    fn convert_empty_sequence() {
-        let dict = test_dictionary();
-        let engine = ChewingConversionEngine::new(dict);
+        ]));
         let sequence = ChineseSequence {
-            syllables: vec![],
+            syllables: vec![
+                syl![M, I, EN, TONE2],
+                syl![G, U, O, TONE2],
+                syl![D, A, TONE4],
+            ],
             selections: vec![],
             breaks: vec![],
         };
-        assert_eq!(Vec::<Interval>::new(), engine.convert(&sequence));
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
+        };
+        let mut bigram = Bigram::new();
+        bigram.insert("中華", "民國", 1000);
+        let engine = ChewingConversionEngine::new(dict).with_bigram(bigram);
+        assert_eq!(
+            vec!["民", "國大"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+        assert_eq!(
+            vec!["民", "國大"],
+            phrases(engine.convert_with_context(&sequence, &[]).unwrap())
+        );
+        let context = [Interval::new(0, 2, "中華").unwrap()];
+        assert_eq!(
+            vec!["民國", "大"],
+            phrases(engine.convert_with_context(&sequence, &context).unwrap())
+        );
     }
 
     #[test]
-    fn convert_simple_chinese_sequence() {
-        let dict = test_dictionary();
-        let engine = ChewingConversionEngine::new(dict);
+    fn score_segmentation_of_convert_is_highest() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
         let sequence = ChineseSequence {
             syllables: vec![
                 syl![G, U, O, TONE2],
@@ -464,31 +8960,58 @@ mod tests {
             selections: vec![],
             breaks: vec![],
         };
-        assert_eq!(
-            vec![
-                Interval {
-                    start: 0,
-                    end: 2,
-                    phrase: "國民".to_string()
-                },
-                Interval {
-                    start: 2,
-                    end: 4,
-                    phrase: "大會".to_string()
-                },
-                Interval {
-                    start: 4,
-                    end: 6,
-                    phrase: "代表".to_string()
-                },
-            ],
-            engine.convert(&sequence)
-        );
+        let intervals = |spans: &[(usize, usize, &str)]| -> Vec<Interval> {
+            spans
+                .iter()
+                .map(|&(start, end, phrase)| Interval::new(start, end, phrase).unwrap())
+                .collect()
+        };
+        let best = engine
+            .score_segmentation(&sequence, &engine.convert(&sequence).unwrap())
+            .unwrap();
+        let alternatives = [
+            intervals(&[(0, 1, "國"), (1, 2, "民"), (2, 4, "大會"), (4, 6, "代表")]),
+            intervals(&[(0, 2, "國民"), (2, 3, "大"), (3, 4, "會"), (4, 6, "代表")]),
+            intervals(&[(0, 2, "國民"), (2, 4, "大會"), (4, 6, "戴錶")]),
+        ];
+        for alternative in &alternatives {
+            let score = engine.score_segmentation(&sequence, alternative).unwrap();
+            assert!(score < best, "{:?}", alternative);
+        }
+
+        let unknown = intervals(&[(0, 2, "過敏"), (2, 4, "大會"), (4, 6, "代表")]);
+        assert_eq!(None, engine.score_segmentation(&sequence, &unknown));
+        let partial = intervals(&[(0, 2, "國民"), (2, 4, "大會")]);
+        assert_eq!(None, engine.score_segmentation(&sequence, &partial));
     }
 
     #[test]
-    fn convert_chinese_sequence_with_breaks() {
-        let dict = test_dictionary();
+    fn convert_max_segments_picks_longer_phrases() {
+        let dict = Rc::new(HashMap::from([
+            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 10000).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 10000).into()],
+            ),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 10000).into()],
+            ),
+            (
+                vec![
+                    syl![M, I, EN, TONE2],
+                    syl![D, A, TONE4],
+                    syl![H, U, EI, TONE4],
+                    syl![D, AI, TONE4],
+                    syl![B, I, AU, TONE3],
+                ],
+                vec![("民大會代表", 1).into()],
+            ),
+        ]));
         let engine = ChewingConversionEngine::new(dict);
         let sequence = ChineseSequence {
             syllables: vec![
@@ -500,44 +9023,33 @@ mod tests {
                 syl![B, I, AU, TONE3],
             ],
             selections: vec![],
-            breaks: vec![Break(1), Break(5)],
+            breaks: vec![],
+        };
+        let phrases = |intervals: Vec<Interval>| -> Vec<String> {
+            intervals.into_iter().map(|it| it.phrase).collect()
         };
         assert_eq!(
-            vec![
-                Interval {
-                    start: 0,
-                    end: 1,
-                    phrase: "國".to_string()
-                },
-                Interval {
-                    start: 1,
-                    end: 2,
-                    phrase: "民".to_string()
-                },
-                Interval {
-                    start: 2,
-                    end: 4,
-                    phrase: "大會".to_string()
-                },
-                Interval {
-                    start: 4,
-                    end: 5,
-                    phrase: "代".to_string()
-                },
-                Interval {
-                    start: 5,
-                    end: 6,
-                    phrase: "表".to_string()
-                },
-            ],
-            engine.convert(&sequence)
+            vec!["國民", "大會", "代表"],
+            phrases(engine.convert(&sequence).unwrap())
+        );
+        assert_eq!(
+            Some(engine.convert(&sequence).unwrap()),
+            engine.convert_max_segments(&sequence, 3)
+        );
+        assert_eq!(
+            Some(vec!["國".to_string(), "民大會代表".to_string()]),
+            engine.convert_max_segments(&sequence, 2).map(phrases)
+        );
+        assert_eq!(None, engine.convert_max_segments(&sequence, 1));
+        assert_eq!(
+            Some(engine.convert(&sequence).unwrap()),
+            engine.convert_max_segments(&sequence, usize::MAX)
         );
     }
 
     #[test]
-    fn convert_chinese_sequence_with_good_selection() {
-        let dict = test_dictionary();
-        let engine = ChewingConversionEngine::new(dict);
+    fn convert_with_alternatives_lists_other_phrases() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
         let sequence = ChineseSequence {
             syllables: vec![
                 syl![G, U, O, TONE2],
@@ -547,117 +9059,129 @@ mod tests {
                 syl![D, AI, TONE4],
                 syl![B, I, AU, TONE3],
             ],
-            selections: vec![Interval {
-                start: 4,
-                end: 6,
-                phrase: "戴錶".to_string(),
-            }],
+            selections: vec![],
             breaks: vec![],
         };
+        let result = engine.convert_with_alternatives(&sequence, 3).unwrap();
         assert_eq!(
             vec![
-                Interval {
-                    start: 0,
-                    end: 2,
-                    phrase: "國民".to_string()
-                },
-                Interval {
-                    start: 2,
-                    end: 4,
-                    phrase: "大會".to_string()
-                },
-                Interval {
-                    start: 4,
-                    end: 6,
-                    phrase: "戴錶".to_string()
-                },
+                (Interval::new(0, 2, "國民").unwrap(), vec![]),
+                (Interval::new(2, 4, "大會").unwrap(), vec![]),
+                (
+                    Interval::new(4, 6, "代表").unwrap(),
+                    vec!["戴錶".to_string()]
+                ),
             ],
-            engine.convert(&sequence)
+            result
         );
+        let result = engine.convert_with_alternatives(&sequence, 0).unwrap();
+        assert!(result
+            .iter()
+            .all(|(_, alternatives)| alternatives.is_empty()));
     }
 
     #[test]
-    fn convert_chinese_sequence_with_substring_selection() {
-        let dict = test_dictionary();
-        let engine = ChewingConversionEngine::new(dict);
+    fn convert_with_alternatives_applies_aliases() {
         let sequence = ChineseSequence {
-            syllables: vec![syl![X, I, EN], syl![K, U, TONE4], syl![I, EN]],
-            selections: vec![Interval {
-                start: 1,
-                end: 3,
-                phrase: "酷音".to_string(),
-            }],
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
             breaks: vec![],
         };
+        let mut aliases = AliasMap::new();
+        aliases.insert("代表", "代錶");
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_aliases(aliases);
         assert_eq!(
-            vec![Interval {
-                start: 0,
-                end: 3,
-                phrase: "新酷音".to_string()
-            },],
-            engine.convert(&sequence)
+            vec![(
+                Interval::new(0, 2, "代錶").unwrap(),
+                vec!["戴錶".to_string()]
+            )],
+            engine.convert_with_alternatives(&sequence, 3).unwrap()
+        );
+
+        let mut aliases = AliasMap::new();
+        aliases.insert("戴錶", "代表");
+        let engine = ChewingConversionEngine::new(test_dictionary()).with_aliases(aliases);
+        assert_eq!(
+            vec![(Interval::new(0, 2, "代表").unwrap(), vec![])],
+            engine.convert_with_alternatives(&sequence, 3).unwrap()
         );
     }
 
     #[test]
-    fn convert_cycle_alternatives() {
-        let dict = test_dictionary();
-        let engine = ChewingConversionEngine::new(dict);
+    fn fully_selected_sequence_skips_lookups() {
+        let dict = Rc::new(CountingDictionary::new(Box::new(test_map())));
+        let engine = ChewingConversionEngine::new(dict.clone()).with_pin_selections(true);
         let sequence = ChineseSequence {
             syllables: vec![
-                syl![C, E, TONE4],
-                syl![SH, TONE4],
-                syl![I, TONE2],
-                syl![X, I, A, TONE4],
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
+            ],
+            selections: vec![
+                Interval::new(4, 6, "戴錶").unwrap(),
+                Interval::new(0, 1, "國").unwrap(),
+                Interval::new(1, 4, "民大會").unwrap(),
             ],
-            selections: vec![],
             breaks: vec![],
         };
+        let result = engine.convert(&sequence).unwrap();
+        assert_eq!(0, dict.lookups());
         assert_eq!(
             vec![
-                Interval {
-                    start: 0,
-                    end: 2,
-                    phrase: "測試".to_string()
-                },
-                Interval {
-                    start: 2,
-                    end: 4,
-                    phrase: "一下".to_string()
-                }
+                Interval::new(0, 1, "國").unwrap(),
+                Interval::new(1, 4, "民大會").unwrap(),
+                Interval::new(4, 6, "戴錶").unwrap(),
             ],
-            engine.convert_next(&sequence, 0)
+            result
         );
-        assert_eq!(
-            vec![
-                Interval {
-                    start: 0,
-                    end: 3,
-                    phrase: "測試儀".to_string()
-                },
-                Interval {
-                    start: 3,
-                    end: 4,
-                    phrase: "下".to_string()
-                }
+        let full = engine
+            .find_best_path(6, engine.find_intervals(&sequence))
+            .unwrap();
+        assert_eq!(full, result);
+
+        let partial = ChineseSequence {
+            selections: vec![Interval::new(4, 6, "戴錶").unwrap()],
+            ..sequence
+        };
+        engine.convert(&partial).unwrap();
+        assert_ne!(0, dict.lookups());
+    }
+
+    #[test]
+    fn candidates_for_span_by_frequency() {
+        let engine = ChewingConversionEngine::new(test_dictionary());
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+                syl![D, AI, TONE4],
+                syl![B, I, AU, TONE3],
             ],
-            engine.convert_next(&sequence, 1)
-        );
+            selections: vec![],
+            breaks: vec![],
+        };
+        let candidates: Vec<_> = engine
+            .candidates_for_span(&sequence, 4, 6)
+            .into_iter()
+            .map(|phrase| (phrase.as_str().to_string(), phrase.freq()))
+            .collect();
         assert_eq!(
-            vec![
-                Interval {
-                    start: 0,
-                    end: 2,
-                    phrase: "測試".to_string()
-                },
-                Interval {
-                    start: 2,
-                    end: 4,
-                    phrase: "一下".to_string()
-                }
-            ],
-            engine.convert_next(&sequence, 2)
+            vec![("代表".to_string(), 200), ("戴錶".to_string(), 100)],
+            candidates
         );
+        assert!(engine.candidates_for_span(&sequence, 4, 7).is_empty());
+
+        let sequence = ChineseSequence {
+            breaks: vec![Break(5)],
+            ..sequence
+        };
+        assert!(engine.candidates_for_span(&sequence, 4, 6).is_empty());
+        assert_eq!(1, engine.candidates_for_span(&sequence, 4, 5).len());
     }
 
     #[test]
@@ -668,13 +9192,25 @@ mod tests {
                     start: 0,
                     end: 2,
                     phrase: Phrase::new("測試", 0).into(),
+                    bonus: 0,
+                    freq_divisor: 1,
+                    unknown: false,
                 },
                 PossibleInterval {
                     start: 2,
                     end: 4,
                     phrase: Phrase::new("一下", 0).into(),
+                    bonus: 0,
+                    freq_divisor: 1,
+                    unknown: false,
                 },
             ],
+            freq_rule: FreqRule::Raw,
+            score_mode: ScoreMode::Balanced,
+            length_variance: true,
+            freq_cap: u32::MAX,
+            freq_smoothing: 0,
+            length_weights: None,
         };
         let path_2 = PossiblePath {
             intervals: vec![
@@ -682,18 +9218,33 @@ mod tests {
                     start: 0,
                     end: 2,
                     phrase: Phrase::new("測試", 0).into(),
+                    bonus: 0,
+                    freq_divisor: 1,
+                    unknown: false,
                 },
                 PossibleInterval {
                     start: 2,
                     end: 3,
                     phrase: Phrase::new("遺", 0).into(),
+                    bonus: 0,
+                    freq_divisor: 1,
+                    unknown: false,
                 },
                 PossibleInterval {
                     start: 3,
                     end: 4,
                     phrase: Phrase::new("下", 0).into(),
+                    bonus: 0,
+                    freq_divisor: 1,
+                    unknown: false,
                 },
             ],
+            freq_rule: FreqRule::Raw,
+            score_mode: ScoreMode::Balanced,
+            length_variance: true,
+            freq_cap: u32::MAX,
+            freq_smoothing: 0,
+            length_weights: None,
         };
         assert!(path_1.contains(&path_2));
     }
@@ -0,0 +1,42 @@
+//! Placeholders of syllables without phrases.
+
+use crate::zhuyin::Syllable;
+
+/// Renders the placeholder phrase of a syllable the dictionary has no phrase
+/// for.
+///
+/// The conversion engine always produces a complete phrasing, so a syllable
+/// without any phrase is converted to a zero frequency placeholder marked as
+/// unknown. [`ZhuyinFallback`] is used by default. Closures taking a
+/// syllable and returning a string also implement the trait.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::{conversion::{FallbackRenderer, ZhuyinFallback}, syl, zhuyin::Bopomofo::*};
+///
+/// assert_eq!("ㄎㄨˋ", ZhuyinFallback.render(&syl![K, U, TONE4]));
+/// ```
+pub trait FallbackRenderer {
+    /// Returns the text shown for `syllable`.
+    fn render(&self, syllable: &Syllable) -> String;
+}
+
+impl<F> FallbackRenderer for F
+where
+    F: Fn(&Syllable) -> String,
+{
+    fn render(&self, syllable: &Syllable) -> String {
+        self(syllable)
+    }
+}
+
+/// The default [`FallbackRenderer`] showing the zhuyin of the syllable.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ZhuyinFallback;
+
+impl FallbackRenderer for ZhuyinFallback {
+    fn render(&self, syllable: &Syllable) -> String {
+        syllable.to_string()
+    }
+}
@@ -0,0 +1,57 @@
+//! Session memory of homophone choices.
+
+use std::collections::HashMap;
+
+use crate::zhuyin::Syllable;
+
+/// A map from syllables to the phrase the user chose for them.
+///
+/// Once the user picks a homophone for some syllables, later conversions of
+/// the exact same syllables default to that phrase as if it were selected.
+/// Unlike a frequency bump this only applies to the same syllables and is
+/// meant to last for one session.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::{conversion::HomophoneMemory, syl, zhuyin::Bopomofo::*};
+///
+/// let mut memory = HomophoneMemory::new();
+/// memory.insert(&[syl![D, AI, TONE4], syl![B, I, AU, TONE3]], "戴錶");
+/// assert_eq!(
+///     Some("戴錶"),
+///     memory.get(&[syl![D, AI, TONE4], syl![B, I, AU, TONE3]])
+/// );
+/// assert_eq!(None, memory.get(&[syl![D, AI, TONE4]]));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct HomophoneMemory {
+    choices: HashMap<Vec<Syllable>, String>,
+}
+
+impl HomophoneMemory {
+    /// Creates an empty `HomophoneMemory`.
+    pub fn new() -> HomophoneMemory {
+        HomophoneMemory::default()
+    }
+    /// Remembers `phrase` as the choice for `syllables`.
+    pub fn insert(&mut self, syllables: &[Syllable], phrase: impl Into<String>) {
+        self.choices.insert(syllables.to_vec(), phrase.into());
+    }
+    /// Forgets the choice for `syllables`.
+    pub fn remove(&mut self, syllables: &[Syllable]) {
+        self.choices.remove(syllables);
+    }
+    /// Returns the phrase chosen for `syllables`, if any.
+    pub fn get(&self, syllables: &[Syllable]) -> Option<&str> {
+        self.choices.get(syllables).map(String::as_str)
+    }
+    /// Forgets every choice, for example when the session ends.
+    pub fn clear(&mut self) {
+        self.choices.clear();
+    }
+    /// Returns whether no choice is remembered.
+    pub fn is_empty(&self) -> bool {
+        self.choices.is_empty()
+    }
+}
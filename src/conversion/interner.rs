@@ -0,0 +1,96 @@
+//! Shared storage for repeated phrase strings.
+
+use std::{collections::HashSet, rc::Rc};
+
+use super::Interval;
+
+/// A set of phrase strings shared between intervals.
+///
+/// Converting a long document produces many intervals with the same common
+/// words. Interning stores each distinct phrase once and hands out cheap
+/// [`Rc<str>`] clones of it, so memory grows with the vocabulary instead of
+/// the length of the text. Keep one interner for the whole batch to share
+/// phrases between conversions.
+///
+/// # Examples
+///
+/// ```
+/// use std::rc::Rc;
+///
+/// use chewing::conversion::PhraseInterner;
+///
+/// let mut interner = PhraseInterner::new();
+/// let a = interner.intern("代表");
+/// let b = interner.intern("代表");
+/// assert!(Rc::ptr_eq(&a, &b));
+/// assert_eq!(1, interner.len());
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct PhraseInterner {
+    phrases: HashSet<Rc<str>>,
+}
+
+impl PhraseInterner {
+    /// Creates an empty `PhraseInterner`.
+    pub fn new() -> PhraseInterner {
+        PhraseInterner::default()
+    }
+    /// Returns the shared string equal to `phrase`, storing it first if it
+    /// was not interned yet.
+    pub fn intern(&mut self, phrase: &str) -> Rc<str> {
+        if let Some(interned) = self.phrases.get(phrase) {
+            return interned.clone();
+        }
+        let interned: Rc<str> = Rc::from(phrase);
+        self.phrases.insert(interned.clone());
+        interned
+    }
+    /// Returns the number of distinct phrases.
+    pub fn len(&self) -> usize {
+        self.phrases.len()
+    }
+    /// Returns whether no phrase was interned.
+    pub fn is_empty(&self) -> bool {
+        self.phrases.is_empty()
+    }
+    /// Forgets every phrase. Strings still held by intervals stay valid.
+    pub fn clear(&mut self) {
+        self.phrases.clear();
+    }
+}
+
+/// An [`Interval`] whose phrase is stored in a [`PhraseInterner`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InternedInterval {
+    /// Start of the interval, inclusive.
+    pub start: usize,
+    /// End of the interval, exclusive.
+    pub end: usize,
+    /// The interned phrase of the interval.
+    pub phrase: Rc<str>,
+    /// Whether the dictionary has nothing for the syllable of the interval.
+    pub unknown: bool,
+}
+
+impl InternedInterval {
+    /// Interns the phrase of `interval`.
+    pub fn new(interval: &Interval, interner: &mut PhraseInterner) -> InternedInterval {
+        InternedInterval {
+            start: interval.start,
+            end: interval.end,
+            phrase: interner.intern(&interval.phrase),
+            unknown: interval.unknown,
+        }
+    }
+}
+
+impl From<InternedInterval> for Interval {
+    fn from(interval: InternedInterval) -> Interval {
+        Interval {
+            start: interval.start,
+            end: interval.end,
+            phrase: interval.phrase.to_string(),
+            unknown: interval.unknown,
+        }
+    }
+}
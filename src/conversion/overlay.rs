@@ -0,0 +1,48 @@
+//! Locale specific phrase frequencies.
+
+use std::collections::HashMap;
+
+/// A table of phrase frequencies that replace the dictionary frequencies.
+///
+/// The same syllables are often written differently by region. Instead of
+/// building a dictionary per locale, a `FrequencyOverlay` can be layered on
+/// top of the base dictionary. Phrases found in the overlay are scored with
+/// the overlay frequency, other phrases keep their dictionary frequency.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::FrequencyOverlay;
+///
+/// let mut overlay = FrequencyOverlay::new();
+/// overlay.insert("影片", 1000);
+/// assert_eq!(Some(1000), overlay.get("影片"));
+/// assert_eq!(None, overlay.get("視頻"));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct FrequencyOverlay {
+    freqs: HashMap<String, u32>,
+}
+
+impl FrequencyOverlay {
+    /// Creates an empty `FrequencyOverlay`.
+    pub fn new() -> FrequencyOverlay {
+        FrequencyOverlay::default()
+    }
+    /// Sets the frequency used for `phrase`.
+    pub fn insert(&mut self, phrase: impl Into<String>, freq: u32) {
+        self.freqs.insert(phrase.into(), freq);
+    }
+    /// Removes `phrase` so it uses the dictionary frequency again.
+    pub fn remove(&mut self, phrase: &str) {
+        self.freqs.remove(phrase);
+    }
+    /// Returns the frequency of `phrase`, if it is in the overlay.
+    pub fn get(&self, phrase: &str) -> Option<u32> {
+        self.freqs.get(phrase).copied()
+    }
+    /// Returns whether the overlay has no phrases.
+    pub fn is_empty(&self) -> bool {
+        self.freqs.is_empty()
+    }
+}
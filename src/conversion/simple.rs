@@ -0,0 +1,67 @@
+//! A conversion engine that picks the most frequent character per syllable.
+
+use std::rc::Rc;
+
+use crate::dictionary::{Dictionary, Phrase};
+
+use super::{ChineseSequence, ConversionEngine, ConversionError, Interval};
+
+/// A conversion engine that converts every syllable on its own.
+///
+/// Each syllable becomes the most frequent single character word found in the
+/// dictionary, or the selected character if a user selection covers it. No
+/// multi-syllable phrases are considered, so the result is always one
+/// interval per syllable. This is mostly useful as a baseline to compare
+/// scoring strategies against.
+#[derive(Debug)]
+pub struct SimpleConversionEngine {
+    dict: Rc<dyn Dictionary>,
+}
+
+impl SimpleConversionEngine {
+    /// Creates a new `SimpleConversionEngine` using `dict` for lookups.
+    pub fn new(dict: Rc<dyn Dictionary>) -> SimpleConversionEngine {
+        SimpleConversionEngine { dict }
+    }
+}
+
+impl ConversionEngine for SimpleConversionEngine {
+    fn convert(&self, segment: &ChineseSequence) -> Result<Vec<Interval>, ConversionError> {
+        Ok(segment
+            .syllables
+            .iter()
+            .enumerate()
+            .map(|(i, syllable)| {
+                let selected = segment
+                    .selections
+                    .iter()
+                    .find(|sel| sel.start <= i && sel.end > i)
+                    .and_then(|sel| sel.phrase.chars().nth(i - sel.start))
+                    .map(String::from);
+                let phrase = selected.or_else(|| {
+                    self.dict
+                        .lookup_word(*syllable)
+                        .fold(None, |best: Option<Phrase<'_>>, phrase| match best {
+                            Some(best) if best.freq() >= phrase.freq() => Some(best),
+                            _ => Some(phrase),
+                        })
+                        .map(|phrase| phrase.as_str().to_string())
+                });
+                Interval {
+                    start: i,
+                    end: i + 1,
+                    unknown: phrase.is_none(),
+                    phrase: phrase.unwrap_or_else(|| syllable.to_string()),
+                }
+            })
+            .collect())
+    }
+
+    fn convert_next(
+        &self,
+        segment: &ChineseSequence,
+        _next: usize,
+    ) -> Result<Vec<Interval>, ConversionError> {
+        self.convert(segment)
+    }
+}
@@ -0,0 +1,51 @@
+//! Post-conversion mapping from Traditional to Simplified Chinese.
+
+use std::collections::HashMap;
+
+use super::Interval;
+
+/// Maps Traditional Chinese characters to their Simplified form.
+///
+/// The mapping is applied character by character on the phrases produced by
+/// a conversion engine. Characters without an entry in the table are kept
+/// untouched. The mapper never affects scoring, conversion still runs on the
+/// traditional dictionary.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::SimplifiedMapper;
+///
+/// let mapper = SimplifiedMapper::new([('國', '国'), ('會', '会')]);
+///
+/// assert_eq!("国民大会", mapper.map_str("國民大會"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SimplifiedMapper {
+    table: HashMap<char, char>,
+}
+
+impl SimplifiedMapper {
+    /// Creates a new `SimplifiedMapper` from a traditional to simplified
+    /// character table.
+    pub fn new<T>(table: T) -> SimplifiedMapper
+    where
+        T: IntoIterator<Item = (char, char)>,
+    {
+        SimplifiedMapper {
+            table: table.into_iter().collect(),
+        }
+    }
+    /// Maps each character of `text` to its simplified form.
+    pub fn map_str(&self, text: &str) -> String {
+        text.chars()
+            .map(|c| self.table.get(&c).copied().unwrap_or(c))
+            .collect()
+    }
+    /// Rewrites the phrases of the intervals in place.
+    pub fn map_intervals(&self, intervals: &mut [Interval]) {
+        for interval in intervals {
+            interval.phrase = self.map_str(&interval.phrase);
+        }
+    }
+}
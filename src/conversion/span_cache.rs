@@ -0,0 +1,138 @@
+//! Dictionary lookups shared between conversions.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use crate::{dictionary::Phrase, zhuyin::Syllable};
+
+/// A least recently used cache of the phrases of syllable spans.
+///
+/// A server converting many requests looks up the same hot spans over and
+/// over. Wrap a `SpanCache` in an [`Arc`] and pass it to
+/// [`with_shared_cache`][super::ChewingConversionEngine::with_shared_cache]
+/// of every engine using the same dictionary, so a span is only looked up
+/// once until it is evicted. The cache holds the phrases of the dictionary
+/// as found, so engines with different settings can share it.
+///
+/// The cache never notices changes of the dictionary. Call
+/// [`clear`][Self::clear] after updating it.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::conversion::SpanCache;
+///
+/// let cache = SpanCache::new(1024);
+/// assert!(cache.is_empty());
+/// assert_eq!(1024, cache.capacity());
+/// ```
+#[derive(Debug)]
+pub struct SpanCache {
+    capacity: usize,
+    inner: Mutex<SpanCacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct SpanCacheInner {
+    entries: HashMap<Vec<Syllable>, (u64, Arc<[Phrase<'static>]>)>,
+    /// The keys of the entries by the clock of their last use.
+    recency: BTreeMap<u64, Vec<Syllable>>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl SpanCache {
+    /// Creates an empty cache holding at most `capacity` spans.
+    pub fn new(capacity: usize) -> SpanCache {
+        SpanCache {
+            capacity,
+            inner: Mutex::new(SpanCacheInner::default()),
+        }
+    }
+    /// Returns the maximum number of spans kept.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    /// Returns the number of spans cached.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+    /// Returns whether no span is cached.
+    pub fn is_empty(&self) -> bool {
+        self.lock().entries.is_empty()
+    }
+    /// Returns the number of lookups answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.lock().hits
+    }
+    /// Returns the number of lookups that went to the dictionary.
+    pub fn misses(&self) -> u64 {
+        self.lock().misses
+    }
+    /// Forgets every span.
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.entries.clear();
+        inner.recency.clear();
+    }
+    /// Returns the phrases of `syllables`, calling `lookup` and caching its
+    /// result if they are not cached yet.
+    pub(crate) fn get_or_insert_with<F>(
+        &self,
+        syllables: &[Syllable],
+        lookup: F,
+    ) -> Arc<[Phrase<'static>]>
+    where
+        F: FnOnce() -> Vec<Phrase<'static>>,
+    {
+        {
+            let mut inner = self.lock();
+            inner.clock += 1;
+            let clock = inner.clock;
+            if let Some((used, phrases)) = inner.entries.get_mut(syllables) {
+                let last_used = std::mem::replace(used, clock);
+                let phrases = phrases.clone();
+                if let Some(key) = inner.recency.remove(&last_used) {
+                    inner.recency.insert(clock, key);
+                }
+                inner.hits += 1;
+                return phrases;
+            }
+            inner.misses += 1;
+        }
+        // The dictionary is looked up without holding the lock so other
+        // threads are not blocked meanwhile.
+        let phrases: Arc<[Phrase<'static>]> = lookup().into();
+        if self.capacity == 0 {
+            return phrases;
+        }
+        let mut inner = self.lock();
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(syllables) {
+            let oldest = inner.recency.keys().next().copied();
+            if let Some(key) = oldest.and_then(|oldest| inner.recency.remove(&oldest)) {
+                inner.entries.remove(&key);
+            }
+        }
+        // Every use gets its own clock, so the recency keys are unique.
+        inner.clock += 1;
+        let clock = inner.clock;
+        if let Some((used, _)) = inner
+            .entries
+            .insert(syllables.to_vec(), (clock, phrases.clone()))
+        {
+            inner.recency.remove(&used);
+        }
+        inner.recency.insert(clock, syllables.to_vec());
+        phrases
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, SpanCacheInner> {
+        // The cache stays consistent even if a thread panicked while holding
+        // the lock, so poisoning is ignored.
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
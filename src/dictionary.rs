@@ -5,20 +5,39 @@ use std::{
     cmp::Ordering,
     collections::{HashMap, HashSet},
     fmt::{Debug, Display},
+    hash::{Hash, Hasher},
     path::Path,
+    sync::Arc,
 };
 
 use thiserror::Error;
 
-use crate::zhuyin::Syllable;
+use crate::zhuyin::{Syllable, SyllablePattern};
 
+pub use counting::CountingDictionary;
+pub use flat::FlatDictionary;
 pub use layered::LayeredDictionary;
+pub use normalized::{NormalizedDictionary, NORMALIZED_MAX_FREQ};
+pub use script::ScriptNormalizer;
+pub use sorted_vec::SortedVecDictionary;
 pub use sqlite::{SqliteDictionary, SqliteDictionaryBuilder, SqliteDictionaryError};
+pub use timed::TimedDictionary;
 pub use trie::{TrieDictionary, TrieDictionaryBuilder, TrieDictionaryStatistics};
+pub use user_phrase::{ParseUserPhraseError, UserPhraseDictionary};
 
+mod counting;
+mod flat;
 mod layered;
+mod normalized;
+mod script;
+mod sorted_vec;
 mod sqlite;
+mod timed;
 mod trie;
+mod user_phrase;
+
+/// The default maximum number of syllables in a phrase.
+pub const DEFAULT_MAX_PHRASE_LEN: usize = 11;
 
 /// The error type which is returned from updating a dictionary.
 #[derive(Error, Debug)]
@@ -97,11 +116,49 @@ pub struct DictionaryInfo {
 ///
 /// assert!(Phrase::new("測", 100) > Phrase::new("冊", 1));
 /// ```
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+///
+/// The [`DictionarySource`] of a phrase is informational and does not take
+/// part in comparisons or hashing.
+#[derive(Clone, Debug)]
 pub struct Phrase<'a> {
     phrase: Cow<'a, str>,
     freq: u32,
     last_used: Option<u64>,
+    category: Option<Arc<str>>,
+    source: DictionarySource,
+}
+
+/// The dictionary a [`Phrase`] was found in.
+///
+/// A [`LayeredDictionary`] tags each phrase with the index of the layer that
+/// provided it, so the base dictionary is layer 0, usually the system
+/// dictionary, and later layers are usually user or session dictionaries.
+/// Phrases from a single dictionary report the default source, layer 0.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::dictionary::{DictionarySource, Phrase};
+///
+/// let phrase = Phrase::new("測", 1);
+/// assert_eq!(DictionarySource::SYSTEM, phrase.source());
+/// assert_eq!(1, phrase.with_source(DictionarySource(1)).source().layer());
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DictionarySource(pub usize);
+
+impl DictionarySource {
+    /// The base layer, usually the system dictionary.
+    pub const SYSTEM: DictionarySource = DictionarySource(0);
+
+    /// Returns the index of the layer.
+    pub fn layer(self) -> usize {
+        self.0
+    }
+    /// Returns whether the source is the base layer.
+    pub fn is_system(self) -> bool {
+        self == DictionarySource::SYSTEM
+    }
 }
 
 impl<'a> Phrase<'a> {
@@ -122,6 +179,8 @@ impl<'a> Phrase<'a> {
             phrase: phrase.into(),
             freq,
             last_used: None,
+            category: None,
+            source: DictionarySource::SYSTEM,
         }
     }
     /// Sets the last used time of the phrase.
@@ -129,6 +188,42 @@ impl<'a> Phrase<'a> {
         self.last_used = Some(last_used);
         self
     }
+    /// Sets the category of the phrase, such as a person name or a place.
+    ///
+    /// Copies of the phrase share the category, so cloning a tagged phrase,
+    /// as most lookups do, doesn't allocate it again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use chewing::dictionary::Phrase;
+    ///
+    /// let phrase = Phrase::new("臺北", 100).with_category("place");
+    ///
+    /// assert_eq!(Some("place"), phrase.category());
+    /// ```
+    pub fn with_category(mut self, category: impl Into<Arc<str>>) -> Phrase<'a> {
+        self.category = Some(category.into());
+        self
+    }
+    /// Returns the category of the phrase, if it is tagged.
+    pub fn category(&self) -> Option<&str> {
+        self.category.as_deref()
+    }
+    /// Sets the dictionary the phrase was found in.
+    pub fn with_source(mut self, source: DictionarySource) -> Phrase<'a> {
+        self.source = source;
+        self
+    }
+    /// Returns the dictionary the phrase was found in.
+    pub fn source(&self) -> DictionarySource {
+        self.source
+    }
+    /// Replaces the frequency of the phrase.
+    pub(crate) fn with_freq(mut self, freq: u32) -> Phrase<'a> {
+        self.freq = freq;
+        self
+    }
     /// Returns the frequency of the phrase.
     ///
     /// # Examples
@@ -182,10 +277,32 @@ impl<'a> Phrase<'a> {
             phrase: Cow::Owned(self.phrase.into_owned()),
             freq: self.freq,
             last_used: self.last_used,
+            category: self.category,
+            source: self.source,
         }
     }
 }
 
+impl PartialEq for Phrase<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.phrase == other.phrase
+            && self.freq == other.freq
+            && self.last_used == other.last_used
+            && self.category == other.category
+    }
+}
+
+impl Eq for Phrase<'_> {}
+
+impl Hash for Phrase<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.phrase.hash(state);
+        self.freq.hash(state);
+        self.last_used.hash(state);
+        self.category.hash(state);
+    }
+}
+
 /// Phrases are compared by their frequency first, followed by their phrase
 /// string.
 impl PartialOrd for Phrase<'_> {
@@ -270,6 +387,24 @@ impl Display for Phrase<'_> {
 /// ```
 pub type Phrases<'a, 'p> = Box<dyn Iterator<Item = Phrase<'p>> + 'a>;
 
+/// Adds `phrase` to `phrases`, keeping the most frequent copy of phrases
+/// with the same string.
+///
+/// Nothing stops a dictionary entry from listing a phrase twice with
+/// different frequencies. Dictionaries collapse the duplicates when they are
+/// built or merged, so lookups stay lazy and see a single candidate. Phrases
+/// keep the position of their first occurrence.
+pub(crate) fn push_unique_phrase<'p>(phrases: &mut Vec<Phrase<'p>>, phrase: Phrase<'p>) {
+    match phrases.iter_mut().find(|it| it.as_str() == phrase.as_str()) {
+        Some(existing) => {
+            if phrase.freq > existing.freq {
+                *existing = phrase;
+            }
+        }
+        None => phrases.push(phrase),
+    }
+}
+
 /// TODO: doc
 pub type DictEntries<'a, 'p> = Box<dyn Iterator<Item = (Vec<Syllable>, Phrase<'p>)> + 'a>;
 
@@ -313,8 +448,103 @@ pub trait Dictionary: Debug {
     /// Returns an iterator to all phrases matched by the syllables, if any. The
     /// result should use a stable order each time for the same input.
     fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_>;
+    /// Returns the frequency stored for `phrase` under `syllables`, or `None`
+    /// if the dictionary doesn't have the pairing.
+    ///
+    /// The default implementation scans
+    /// [`lookup_phrase`][Dictionary::lookup_phrase].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::collections::HashMap;
+    ///
+    /// use chewing::{dictionary::Dictionary, syl, zhuyin::Bopomofo::*};
+    ///
+    /// let mut dict = HashMap::new();
+    /// let syllables = [syl![G, U, O, TONE2], syl![M, I, EN, TONE2]];
+    /// dict.as_mut_dict().unwrap().insert(&syllables, ("國民", 200).into())?;
+    ///
+    /// assert_eq!(Some(200), dict.phrase_freq(&syllables, "國民"));
+    /// assert_eq!(None, dict.phrase_freq(&syllables, "過敏"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn phrase_freq(&self, syllables: &[Syllable], phrase: &str) -> Option<u32> {
+        self.lookup_phrase(syllables)
+            .find(|it| it.as_str() == phrase)
+            .map(|it| it.freq())
+    }
+    /// Returns an iterator to all phrases whose syllables match `patterns`,
+    /// with the syllables they are stored under.
+    ///
+    /// Each pattern matches the syllable at the same position, and only
+    /// phrases with as many syllables as patterns are returned. This is a
+    /// query facility for search interfaces, the default implementation
+    /// scans [`entries`][Dictionary::entries].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::collections::HashMap;
+    ///
+    /// use chewing::{dictionary::Dictionary, syl, zhuyin::{Bopomofo::*, SyllablePattern}};
+    ///
+    /// let mut dict = HashMap::new();
+    /// let dict_mut = dict.as_mut_dict().unwrap();
+    /// dict_mut.insert(&[syl![G, U, O, TONE2], syl![M, I, EN, TONE2]], ("國民", 200).into())?;
+    /// dict_mut.insert(&[syl![G, U, O, TONE4], syl![M, I, EN, TONE3]], ("過敏", 100).into())?;
+    /// dict_mut.insert(&[syl![G, U, O, TONE2]], ("國", 100).into())?;
+    ///
+    /// let phrases: Vec<_> = dict
+    ///     .lookup_pattern(&[syl![G, U, O, TONE2].into(), SyllablePattern::any()])
+    ///     .map(|(_, phrase)| phrase.to_string())
+    ///     .collect();
+    /// assert_eq!(vec!["國民"], phrases);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn lookup_pattern(&self, patterns: &[SyllablePattern]) -> DictEntries<'_, '_> {
+        let patterns = patterns.to_vec();
+        Box::new(self.entries().filter(move |(syllables, _)| {
+            syllables.len() == patterns.len()
+                && patterns
+                    .iter()
+                    .zip(syllables)
+                    .all(|(pattern, syllable)| pattern.matches(syllable))
+        }))
+    }
+    /// Returns an iterator to all phrases whose syllables start with
+    /// `syllables`, including the phrases matching them exactly.
+    ///
+    /// This is used to predict the phrases the user is typing. The default
+    /// implementation scans [`entries`][Dictionary::entries].
+    fn lookup_phrase_with_prefix(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        let prefix = syllables.to_vec();
+        Box::new(
+            self.entries()
+                .filter(move |(key, _)| key.starts_with(&prefix))
+                .map(|(_, phrase)| phrase),
+        )
+    }
     /// Returns an iterator to all phrases in the dictionary.
-    fn entries(&self) -> DictEntries<'_, '_>;
+    ///
+    /// The default implementation returns nothing, for sources that can't
+    /// enumerate their content.
+    fn entries(&self) -> DictEntries<'_, '_> {
+        Box::new(std::iter::empty())
+    }
+    /// Returns the number of syllables of the longest phrase in the
+    /// dictionary.
+    ///
+    /// Conversion engines use this to avoid looking up spans that cannot
+    /// match anything. The default implementation returns
+    /// [`DEFAULT_MAX_PHRASE_LEN`].
+    fn max_phrase_len(&self) -> usize {
+        DEFAULT_MAX_PHRASE_LEN
+    }
     /// Returns information about the dictionary instance.
     fn about(&self) -> DictionaryInfo;
     /// Returns a mutable reference to the dictionary if the underlying
@@ -407,6 +637,14 @@ impl Dictionary for HashMap<Vec<Syllable>, Vec<Phrase<'static>>> {
             .unwrap_or_else(|| Box::new(std::iter::empty()))
     }
 
+    fn phrase_freq(&self, syllables: &[Syllable], phrase: &str) -> Option<u32> {
+        self.get(syllables)?
+            .iter()
+            .filter(|it| it.as_str() == phrase)
+            .map(|it| it.freq())
+            .max()
+    }
+
     fn entries(&self) -> DictEntries<'_, '_> {
         Box::new(
             self.iter()
@@ -414,6 +652,14 @@ impl Dictionary for HashMap<Vec<Syllable>, Vec<Phrase<'static>>> {
         )
     }
 
+    fn max_phrase_len(&self) -> usize {
+        self.iter()
+            .filter(|(_, phrases)| !phrases.is_empty())
+            .map(|(syllables, _)| syllables.len())
+            .max()
+            .unwrap_or(0)
+    }
+
     fn about(&self) -> DictionaryInfo {
         Default::default()
     }
@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::zhuyin::Syllable;
+
+use super::{DictEntries, Dictionary, DictionaryInfo, DictionaryMut, Phrases};
+
+/// A dictionary wrapper that counts the phrase lookups.
+///
+/// This is a measurement tool, for example for
+/// [`benchmark_with_lookups`][crate::conversion::ChewingConversionEngine::benchmark_with_lookups].
+/// Every [`lookup_phrase`][Dictionary::lookup_phrase] call is forwarded to
+/// the inner dictionary and counted, in total and by syllables.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use chewing::{dictionary::{CountingDictionary, Dictionary}, syl, zhuyin::Bopomofo};
+///
+/// let dict = CountingDictionary::new(Box::new(HashMap::new()));
+/// dict.lookup_phrase(&[syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]]);
+/// assert_eq!(1, dict.lookups());
+/// ```
+#[derive(Debug)]
+pub struct CountingDictionary {
+    inner: Box<dyn Dictionary>,
+    lookups: AtomicUsize,
+    lookups_by_key: Mutex<HashMap<Vec<Syllable>, usize>>,
+}
+
+impl CountingDictionary {
+    /// Creates a new `CountingDictionary` wrapping `inner`.
+    pub fn new(inner: Box<dyn Dictionary>) -> CountingDictionary {
+        CountingDictionary {
+            inner,
+            lookups: AtomicUsize::new(0),
+            lookups_by_key: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Returns the number of phrase lookups so far.
+    pub fn lookups(&self) -> usize {
+        self.lookups.load(Ordering::Relaxed)
+    }
+    /// Returns the number of phrase lookups of each syllables looked up so
+    /// far.
+    pub fn lookups_by_key(&self) -> HashMap<Vec<Syllable>, usize> {
+        self.by_key().clone()
+    }
+    /// Resets the number of phrase lookups to zero.
+    pub fn reset(&self) {
+        self.lookups.store(0, Ordering::Relaxed);
+        self.by_key().clear();
+    }
+    fn by_key(&self) -> std::sync::MutexGuard<'_, HashMap<Vec<Syllable>, usize>> {
+        // The counts stay consistent even if a thread panicked while holding
+        // the lock, so poisoning is ignored.
+        self.lookups_by_key
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Dictionary for CountingDictionary {
+    fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        *self.by_key().entry(syllables.to_vec()).or_default() += 1;
+        self.inner.lookup_phrase(syllables)
+    }
+
+    fn entries(&self) -> DictEntries<'_, '_> {
+        self.inner.entries()
+    }
+
+    fn max_phrase_len(&self) -> usize {
+        self.inner.max_phrase_len()
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        self.inner.about()
+    }
+
+    fn as_mut_dict(&mut self) -> Option<&mut dyn DictionaryMut> {
+        None
+    }
+}
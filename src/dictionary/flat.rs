@@ -0,0 +1,423 @@
+use std::{
+    cmp::Ordering,
+    fs,
+    io::{self, Write},
+    ops::Deref,
+    path::Path,
+};
+
+#[cfg(feature = "memmap2")]
+use memmap2::Mmap;
+
+use crate::zhuyin::Syllable;
+
+use super::{
+    push_unique_phrase, DictEntries, Dictionary, DictionaryInfo, DictionaryMut, Phrase, Phrases,
+};
+
+const MAGIC: &[u8; 4] = b"CHMD";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 12;
+const RECORD_SIZE: usize = 16;
+
+/// A read-only dictionary stored in a flat binary format that can be used
+/// without parsing.
+///
+/// With the `memmap2` feature the file is memory mapped when the dictionary
+/// is opened, otherwise it is read into a buffer. Lookups binary search a
+/// sorted index directly in those bytes, so opening a dictionary costs only
+/// mapping the file and validating the index.
+///
+/// # File format
+///
+/// All integers are little endian and all offsets count from the start of the
+/// file.
+///
+/// ```text
+/// header   magic "CHMD" | version: u32 = 1 | count: u32
+/// index    count × { key_offset: u32, key_len: u32,
+///                    phrases_offset: u32, phrases_len: u32 }
+/// keys     key_len × syllable: u16, see Syllable::to_u16
+/// phrases  { freq: u32, len: u32, phrase: [u8; len] }... spanning
+///          phrases_len bytes
+/// ```
+///
+/// The index is sorted by the syllables of each key, compared as `u16`
+/// values, and keys are unique. Phrases are UTF-8.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::collections::HashMap;
+///
+/// use chewing::{dictionary::{Dictionary, FlatDictionary}, syl, zhuyin::Bopomofo};
+///
+/// let syllables = vec![syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]];
+/// let source = HashMap::from([(syllables.clone(), vec![("測", 100).into()])]);
+///
+/// let mut bytes = vec![];
+/// FlatDictionary::write(&source, &mut bytes)?;
+/// let dict = FlatDictionary::from_bytes(bytes)?;
+/// assert_eq!("測", dict.lookup_phrase(&syllables).next().unwrap().as_str());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FlatDictionary {
+    bytes: Bytes,
+    count: usize,
+    max_phrase_len: usize,
+}
+
+#[derive(Debug)]
+enum Bytes {
+    Owned(Vec<u8>),
+    #[cfg(feature = "memmap2")]
+    Mapped(Mmap),
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Bytes::Owned(bytes) => bytes,
+            #[cfg(feature = "memmap2")]
+            Bytes::Mapped(map) => map,
+        }
+    }
+}
+
+impl FlatDictionary {
+    /// Opens the dictionary stored in the file at `path`.
+    ///
+    /// The file is memory mapped with the `memmap2` feature. It must not be
+    /// modified while the dictionary is open.
+    #[cfg(feature = "memmap2")]
+    #[allow(unsafe_code)]
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FlatDictionary> {
+        let file = fs::File::open(path)?;
+        // SAFETY: dictionary files are only replaced, never written in place,
+        // and every offset is validated before it is read.
+        let map = unsafe { Mmap::map(&file)? };
+        FlatDictionary::new(Bytes::Mapped(map))
+    }
+    /// Opens the dictionary stored in the file at `path`.
+    #[cfg(not(feature = "memmap2"))]
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FlatDictionary> {
+        FlatDictionary::from_bytes(fs::read(path)?)
+    }
+    /// Creates a dictionary from the bytes of a dictionary file.
+    ///
+    /// The whole index is validated, so lookups never read out of bounds.
+    pub fn from_bytes(bytes: Vec<u8>) -> io::Result<FlatDictionary> {
+        FlatDictionary::new(Bytes::Owned(bytes))
+    }
+    fn new(bytes: Bytes) -> io::Result<FlatDictionary> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return Err(invalid_data("not a dictionary file"));
+        }
+        if read_u32(&bytes, 4) != Some(VERSION) {
+            return Err(invalid_data("unsupported version"));
+        }
+        let count = read_u32(&bytes, 8).unwrap_or_default() as usize;
+        count
+            .checked_mul(RECORD_SIZE)
+            .and_then(|len| len.checked_add(HEADER_SIZE))
+            .filter(|&end| end <= bytes.len())
+            .ok_or_else(|| invalid_data("truncated index"))?;
+        let dict = FlatDictionary {
+            count,
+            max_phrase_len: 0,
+            bytes,
+        };
+        let mut max_phrase_len = 0;
+        for i in 0..count {
+            let record = dict.record(i);
+            let key = checked_range(record[0], record[1].checked_mul(2), dict.bytes.len())
+                .ok_or_else(|| invalid_data("key out of bounds"))?;
+            if key.is_empty() {
+                return Err(invalid_data("empty key"));
+            }
+            let phrases = checked_range(record[2], Some(record[3]), dict.bytes.len())
+                .ok_or_else(|| invalid_data("phrases out of bounds"))?;
+            validate_phrases(&dict.bytes[phrases])?;
+            if i > 0 && dict.compare_keys(i - 1, i) != Ordering::Less {
+                return Err(invalid_data("index is not sorted"));
+            }
+            max_phrase_len = max_phrase_len.max(record[1] as usize);
+        }
+        Ok(FlatDictionary {
+            max_phrase_len,
+            ..dict
+        })
+    }
+    /// Writes the entries of `dict` in the dictionary file format.
+    ///
+    /// Phrases sharing the same syllables keep the order `dict` returns them.
+    /// Duplicate phrases are written once with their highest frequency.
+    pub fn write<W: Write>(dict: &dyn Dictionary, mut writer: W) -> io::Result<()> {
+        let mut entries: Vec<_> = dict.entries().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut groups: Vec<(Vec<Syllable>, Vec<Phrase<'_>>)> = vec![];
+        for (syllables, phrase) in entries {
+            match groups.last_mut() {
+                Some((last, phrases)) if *last == syllables => push_unique_phrase(phrases, phrase),
+                _ => groups.push((syllables, vec![phrase])),
+            }
+        }
+
+        let too_large = || invalid_data("dictionary too large");
+        let to_u32 = |value: usize| u32::try_from(value).map_err(|_| too_large());
+        let mut index = Vec::with_capacity(groups.len() * RECORD_SIZE);
+        let mut data = vec![];
+        let data_start = HEADER_SIZE + groups.len() * RECORD_SIZE;
+        for (syllables, phrases) in &groups {
+            let key_offset = to_u32(data_start + data.len())?;
+            for syllable in syllables {
+                data.extend_from_slice(&syllable.to_u16().to_le_bytes());
+            }
+            let phrases_offset = data_start + data.len();
+            for phrase in phrases {
+                data.extend_from_slice(&phrase.freq().to_le_bytes());
+                data.extend_from_slice(&to_u32(phrase.as_str().len())?.to_le_bytes());
+                data.extend_from_slice(phrase.as_str().as_bytes());
+            }
+            for value in [
+                key_offset,
+                to_u32(syllables.len())?,
+                to_u32(phrases_offset)?,
+                to_u32(data_start + data.len() - phrases_offset)?,
+            ] {
+                index.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        to_u32(data_start + data.len())?;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&to_u32(groups.len())?.to_le_bytes())?;
+        writer.write_all(&index)?;
+        writer.write_all(&data)?;
+        Ok(())
+    }
+    /// Returns the number of distinct syllable keys.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+    /// Returns whether the dictionary has no entry.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    fn record(&self, i: usize) -> [u32; 4] {
+        let base = HEADER_SIZE + i * RECORD_SIZE;
+        let field = |n: usize| read_u32(&self.bytes, base + n * 4).unwrap_or_default();
+        [field(0), field(1), field(2), field(3)]
+    }
+    fn key(&self, i: usize) -> impl Iterator<Item = u16> + '_ {
+        let record = self.record(i);
+        let start = record[0] as usize;
+        self.bytes[start..start + record[1] as usize * 2]
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+    }
+    fn compare_keys(&self, a: usize, b: usize) -> Ordering {
+        self.key(a).cmp(self.key(b))
+    }
+    fn syllables(&self, i: usize) -> Vec<Syllable> {
+        self.key(i)
+            .filter_map(|unit| Syllable::try_from(unit).ok())
+            .collect()
+    }
+    fn phrases(&self, i: usize) -> PhrasesIter<'_> {
+        let record = self.record(i);
+        let start = record[2] as usize;
+        PhrasesIter {
+            bytes: &self.bytes[start..start + record[3] as usize],
+        }
+    }
+    fn find(&self, syllables: &[Syllable]) -> Option<usize> {
+        let (mut lo, mut hi) = (0, self.count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.key(mid).cmp(syllables.iter().map(Syllable::to_u16)) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+}
+
+struct PhrasesIter<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for PhrasesIter<'a> {
+    type Item = Phrase<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let freq = read_u32(self.bytes, 0)?;
+        let len = read_u32(self.bytes, 4)? as usize;
+        let phrase = std::str::from_utf8(self.bytes.get(8..8 + len)?).ok()?;
+        self.bytes = &self.bytes[8 + len..];
+        Some(Phrase::new(phrase, freq))
+    }
+}
+
+impl Dictionary for FlatDictionary {
+    fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        if syllables.is_empty() || syllables.iter().any(Syllable::is_empty) {
+            return Box::new(std::iter::empty());
+        }
+        match self.find(syllables) {
+            Some(i) => Box::new(self.phrases(i)),
+            None => Box::new(std::iter::empty()),
+        }
+    }
+
+    fn entries(&self) -> DictEntries<'_, '_> {
+        Box::new((0..self.count).flat_map(move |i| {
+            let syllables = self.syllables(i);
+            self.phrases(i)
+                .map(move |phrase| (syllables.clone(), phrase))
+        }))
+    }
+
+    fn max_phrase_len(&self) -> usize {
+        self.max_phrase_len
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        Default::default()
+    }
+
+    fn as_mut_dict(&mut self) -> Option<&mut dyn DictionaryMut> {
+        None
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let raw = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]))
+}
+
+fn checked_range(offset: u32, len: Option<u32>, limit: usize) -> Option<std::ops::Range<usize>> {
+    let start = offset as usize;
+    let end = start.checked_add(len? as usize)?;
+    (end <= limit).then_some(start..end)
+}
+
+fn validate_phrases(mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        let len = read_u32(bytes, 4).ok_or_else(|| invalid_data("truncated phrase"))? as usize;
+        let phrase = bytes
+            .get(8..8 + len)
+            .ok_or_else(|| invalid_data("truncated phrase"))?;
+        std::str::from_utf8(phrase).map_err(|_| invalid_data("phrase is not UTF-8"))?;
+        bytes = &bytes[8 + len..];
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        dictionary::{Dictionary, Phrase},
+        syl,
+        zhuyin::{Bopomofo::*, Syllable},
+    };
+
+    use super::FlatDictionary;
+
+    fn corpus() -> HashMap<Vec<Syllable>, Vec<Phrase<'static>>> {
+        HashMap::from([
+            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
+            (vec![syl![M, I, EN, TONE2]], vec![("民", 1).into()]),
+            (
+                vec![syl![D, AI, TONE4]],
+                vec![("代", 1).into(), ("戴", 2).into()],
+            ),
+            (vec![syl![B, I, AU, TONE3]], vec![("表", 1).into()]),
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 200).into()],
+            ),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 200).into(), ("戴錶", 100).into()],
+            ),
+        ])
+    }
+
+    fn build(dict: &dyn Dictionary) -> FlatDictionary {
+        let mut bytes = vec![];
+        FlatDictionary::write(dict, &mut bytes).unwrap();
+        FlatDictionary::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn same_phrases_as_hash_map() {
+        let reference = corpus();
+        let dict = build(&reference);
+        assert_eq!(reference.len(), dict.len());
+        assert_eq!(2, dict.max_phrase_len());
+        for (syllables, phrases) in &reference {
+            assert_eq!(
+                phrases.clone(),
+                dict.lookup_phrase(syllables)
+                    .map(Phrase::into_owned)
+                    .collect::<Vec<_>>()
+            );
+        }
+        assert_eq!(0, dict.lookup_phrase(&[syl![H, U, EI, TONE4]]).count());
+        assert_eq!(0, dict.lookup_phrase(&[]).count());
+    }
+
+    #[test]
+    fn entries_roundtrip() {
+        let dict = build(&corpus());
+        let copy = build(&dict);
+        assert_eq!(
+            dict.entries().collect::<Vec<_>>(),
+            copy.entries().collect::<Vec<_>>()
+        );
+        assert_eq!(8, dict.entries().count());
+    }
+
+    #[test]
+    fn open_file() -> Result<(), Box<dyn std::error::Error>> {
+        let file = tempfile::NamedTempFile::new()?;
+        FlatDictionary::write(&corpus(), file.as_file())?;
+        let dict = FlatDictionary::open(file.path())?;
+        assert_eq!(
+            "國民",
+            dict.lookup_phrase(&[syl![G, U, O, TONE2], syl![M, I, EN, TONE2]])
+                .next()
+                .unwrap()
+                .as_str()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_data() {
+        assert!(FlatDictionary::from_bytes(b"CHEW".to_vec()).is_err());
+
+        let mut bytes = vec![];
+        FlatDictionary::write(&corpus(), &mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(FlatDictionary::from_bytes(bytes).is_err());
+
+        let empty: HashMap<Vec<Syllable>, Vec<Phrase<'static>>> = HashMap::new();
+        assert!(build(&empty).is_empty());
+    }
+}
@@ -1,12 +1,12 @@
 use std::hash::{Hash, Hasher};
 
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
 
 use crate::zhuyin::Syllable;
 
 use super::{
-    BlockList, DictEntries, Dictionary, DictionaryInfo, DictionaryMut, DictionaryUpdateError,
-    Phrase, Phrases,
+    BlockList, DictEntries, Dictionary, DictionaryInfo, DictionaryMut, DictionarySource,
+    DictionaryUpdateError, Phrase, Phrases,
 };
 
 /// A collection of dictionaries that returns the union of the lookup results.
@@ -75,6 +75,8 @@ impl Dictionary for LayeredDictionary {
     /// Lookup phrases from all underlying dictionaries.
     ///
     /// Phrases are ordered by their first apperance in the underlying dictionaries.
+    /// Each phrase is tagged with the [`DictionarySource`] of the layer that
+    /// provided its frequency.
     ///
     /// Pseudo code
     ///
@@ -91,15 +93,18 @@ impl Dictionary for LayeredDictionary {
     ///       Add phrases <- (phrase, freq)
     /// ```
     fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
-        let (base, layers) = match self.inner.split_first() {
-            Some(d) => d,
-            None => return Box::new(std::iter::empty()),
-        };
         let mut phrases = IndexSet::with_capacity(128);
-        phrases.extend(base.lookup_phrase(syllables).map(LookupPhrase));
-        for d in layers {
+        for (layer, d) in self.inner.iter().enumerate() {
+            let source = DictionarySource(layer);
             for phrase in d.lookup_phrase(syllables) {
-                phrases.replace(LookupPhrase(phrase));
+                let phrase = LookupPhrase(phrase.with_source(source));
+                // A layer may list a phrase twice, keep its most frequent copy.
+                let duplicate = phrases.get(&phrase).map_or(false, |it: &LookupPhrase<'_>| {
+                    it.0.source() == source && it.0.freq() >= phrase.0.freq()
+                });
+                if !duplicate {
+                    phrases.replace(phrase);
+                }
             }
         }
         Box::new(
@@ -110,9 +115,31 @@ impl Dictionary for LayeredDictionary {
         )
     }
 
+    /// Enumerates phrases from all underlying dictionaries.
+    ///
+    /// Like [`lookup_phrase`][Self::lookup_phrase], a phrase found in later
+    /// layers replaces the frequency of the same phrase in earlier layers.
     fn entries(&self) -> DictEntries<'_, '_> {
-        todo!("entries from all layers")
-        // Box::new(std::iter::empty())
+        let mut entries = IndexMap::new();
+        for d in &self.inner {
+            for (syllables, phrase) in d.entries() {
+                entries.insert((syllables, phrase.as_str().to_string()), phrase);
+            }
+        }
+        Box::new(
+            entries
+                .into_iter()
+                .map(|((syllables, _), phrase)| (syllables, phrase))
+                .filter(|(_, phrase)| !self.is_blocked(&phrase.phrase)),
+        )
+    }
+
+    fn max_phrase_len(&self) -> usize {
+        self.inner
+            .iter()
+            .map(|d| d.max_phrase_len())
+            .max()
+            .unwrap_or(0)
     }
 
     fn about(&self) -> DictionaryInfo {
@@ -184,3 +211,112 @@ impl PartialEq for LookupPhrase<'_> {
         self.0.phrase == other.0.phrase
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        rc::Rc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        conversion::{ChewingConversionEngine, ChineseSequence, ConversionEngine},
+        dictionary::{
+            DictEntries, Dictionary, DictionaryInfo, DictionaryMut, Phrase, Phrases,
+            TimedDictionary,
+        },
+        syl,
+        zhuyin::{Bopomofo, Syllable},
+    };
+
+    use super::LayeredDictionary;
+
+    #[test]
+    fn entries_from_all_layers() {
+        let sys_dict = Box::new(HashMap::from([
+            (
+                vec![syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]],
+                vec![("測", 1).into(), ("冊", 1).into(), ("側", 1).into()],
+            ),
+            (
+                vec![
+                    syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4],
+                    syl![Bopomofo::SH, Bopomofo::TONE4],
+                ],
+                vec![("測試", 9318).into()],
+            ),
+        ]));
+        let user_dict = Box::new(HashMap::from([(
+            vec![syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]],
+            vec![("策", 100).into(), ("冊", 100).into()],
+        )]));
+        let user_block_list = Box::new(HashSet::from(["側".to_string()]));
+        let dict = LayeredDictionary::new(vec![sys_dict, user_dict], vec![user_block_list]);
+        let ce4 = vec![syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]];
+        assert_eq!(
+            HashSet::<(_, Phrase<'_>)>::from([
+                (ce4.clone(), ("測", 1).into()),
+                (ce4.clone(), ("冊", 100).into()),
+                (ce4.clone(), ("策", 100).into()),
+                (
+                    vec![
+                        syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4],
+                        syl![Bopomofo::SH, Bopomofo::TONE4],
+                    ],
+                    ("測試", 9318).into()
+                ),
+            ]),
+            dict.entries().collect::<HashSet<_>>()
+        );
+    }
+
+    #[derive(Debug)]
+    struct SlowDictionary(HashMap<Vec<Syllable>, Vec<Phrase<'static>>>);
+
+    impl Dictionary for SlowDictionary {
+        fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+            thread::sleep(Duration::from_secs(2));
+            self.0.lookup_phrase(syllables)
+        }
+        fn entries(&self) -> DictEntries<'_, '_> {
+            self.0.entries()
+        }
+        fn max_phrase_len(&self) -> usize {
+            self.0.max_phrase_len()
+        }
+        fn about(&self) -> DictionaryInfo {
+            Default::default()
+        }
+        fn as_mut_dict(&mut self) -> Option<&mut dyn DictionaryMut> {
+            None
+        }
+    }
+
+    #[test]
+    fn lookup_skips_timed_out_layer() {
+        let ce4 = vec![syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]];
+        let fast = Box::new(HashMap::from([(ce4.clone(), vec![("測", 1).into()])]));
+        let slow = SlowDictionary(HashMap::from([(ce4.clone(), vec![("策", 100).into()])]));
+        let timed = Box::new(TimedDictionary::new(
+            Box::new(slow),
+            Duration::from_millis(10),
+        ));
+        let dict = LayeredDictionary::new(vec![fast, timed], vec![]);
+
+        let start = Instant::now();
+        assert_eq!(
+            vec![Phrase::from(("測", 1))],
+            dict.lookup_phrase(&ce4).collect::<Vec<_>>()
+        );
+        let engine = ChewingConversionEngine::new(Rc::new(dict));
+        let seq = ChineseSequence {
+            syllables: ce4,
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!("測", engine.convert(&seq).unwrap()[0].phrase);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
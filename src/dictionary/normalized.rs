@@ -0,0 +1,182 @@
+use crate::zhuyin::Syllable;
+
+use super::{DictEntries, Dictionary, DictionaryInfo, DictionaryMut, Phrases};
+
+/// The upper bound of normalized frequencies.
+pub const NORMALIZED_MAX_FREQ: u32 = 65535;
+
+/// A dictionary wrapper that rescales phrase frequencies into a common range.
+///
+/// Dictionaries from different sources often use very different frequency
+/// scales. One may use raw corpus counts up to millions while another uses
+/// hand tuned values within a few hundreds. When they are combined with a
+/// [`LayeredDictionary`][super::LayeredDictionary] the bigger numbers would
+/// dominate the scoring. Wrapping each source in a `NormalizedDictionary`
+/// maps its frequencies into `0..=NORMALIZED_MAX_FREQ` using its own maximum
+/// frequency.
+///
+/// The wrapped dictionary is read-only.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use chewing::{dictionary::{Dictionary, NormalizedDictionary}, syl, zhuyin::Bopomofo};
+///
+/// let dict = NormalizedDictionary::new(
+///     Box::new(HashMap::from([
+///         (vec![syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]], vec![("測", 150).into()]),
+///     ])),
+///     300,
+/// );
+/// assert_eq!(
+///     32767,
+///     dict.lookup_word(syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4])
+///         .next()
+///         .unwrap()
+///         .freq()
+/// );
+/// ```
+#[derive(Debug)]
+pub struct NormalizedDictionary {
+    inner: Box<dyn Dictionary>,
+    max_freq: u32,
+}
+
+impl NormalizedDictionary {
+    /// Creates a new `NormalizedDictionary` with a known maximum frequency of
+    /// the underlying dictionary.
+    pub fn new(inner: Box<dyn Dictionary>, max_freq: u32) -> NormalizedDictionary {
+        NormalizedDictionary { inner, max_freq }
+    }
+    /// Creates a new `NormalizedDictionary` by scanning all entries of the
+    /// underlying dictionary for the maximum frequency.
+    pub fn scan(inner: Box<dyn Dictionary>) -> NormalizedDictionary {
+        let max_freq = inner
+            .entries()
+            .map(|(_, phrase)| phrase.freq())
+            .max()
+            .unwrap_or(0);
+        NormalizedDictionary { inner, max_freq }
+    }
+    /// Returns the maximum frequency used as the scale of the underlying
+    /// dictionary.
+    pub fn max_freq(&self) -> u32 {
+        self.max_freq
+    }
+    fn normalize(&self, freq: u32) -> u32 {
+        if self.max_freq == 0 {
+            return freq;
+        }
+        let freq = u64::from(freq.min(self.max_freq));
+        (freq * u64::from(NORMALIZED_MAX_FREQ) / u64::from(self.max_freq)) as u32
+    }
+}
+
+impl Dictionary for NormalizedDictionary {
+    fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        Box::new(self.inner.lookup_phrase(syllables).map(|mut phrase| {
+            phrase.freq = self.normalize(phrase.freq);
+            phrase
+        }))
+    }
+
+    fn entries(&self) -> DictEntries<'_, '_> {
+        Box::new(self.inner.entries().map(|(syllables, mut phrase)| {
+            phrase.freq = self.normalize(phrase.freq);
+            (syllables, phrase)
+        }))
+    }
+
+    fn max_phrase_len(&self) -> usize {
+        self.inner.max_phrase_len()
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        self.inner.about()
+    }
+
+    fn as_mut_dict(&mut self) -> Option<&mut dyn DictionaryMut> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, rc::Rc};
+
+    use crate::{
+        conversion::{ChewingConversionEngine, ChineseSequence, ConversionEngine},
+        dictionary::{Dictionary, LayeredDictionary},
+        syl,
+        zhuyin::Bopomofo::*,
+    };
+
+    use super::NormalizedDictionary;
+
+    fn low_scale_dictionary() -> Box<dyn Dictionary> {
+        Box::new(HashMap::from([
+            (vec![syl![D, AI, TONE4]], vec![("代", 1).into()]),
+            (vec![syl![B, I, AU, TONE3]], vec![("表", 1).into()]),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 200).into()],
+            ),
+            (vec![syl![D, E, TONE5]], vec![("的", 300).into()]),
+        ]))
+    }
+
+    fn high_scale_dictionary() -> Box<dyn Dictionary> {
+        Box::new(HashMap::from([
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("戴錶", 1000).into()],
+            ),
+            (vec![syl![D, E, TONE5]], vec![("得", 5000000).into()]),
+        ]))
+    }
+
+    fn convert(dict: LayeredDictionary) -> String {
+        let engine = ChewingConversionEngine::new(Rc::new(dict));
+        let sequence = ChineseSequence {
+            syllables: vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+            selections: vec![],
+            breaks: vec![],
+        };
+        engine
+            .convert(&sequence)
+            .unwrap()
+            .into_iter()
+            .map(|interval| interval.phrase)
+            .collect()
+    }
+
+    #[test]
+    fn normalize_mixed_scale_dictionaries() {
+        let raw = LayeredDictionary::new(
+            vec![low_scale_dictionary(), high_scale_dictionary()],
+            vec![],
+        );
+        assert_eq!("戴錶", convert(raw));
+
+        let normalized = LayeredDictionary::new(
+            vec![
+                Box::new(NormalizedDictionary::scan(low_scale_dictionary())),
+                Box::new(NormalizedDictionary::scan(high_scale_dictionary())),
+            ],
+            vec![],
+        );
+        assert_eq!("代表", convert(normalized));
+    }
+
+    #[test]
+    fn scan_max_frequency() {
+        let dict = NormalizedDictionary::scan(high_scale_dictionary());
+        assert_eq!(5000000, dict.max_freq());
+        assert_eq!(
+            65535,
+            dict.lookup_word(syl![D, E, TONE5]).next().unwrap().freq()
+        );
+    }
+}
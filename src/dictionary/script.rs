@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+use crate::zhuyin::Syllable;
+
+use super::{
+    push_unique_phrase, DictEntries, Dictionary, DictionaryInfo, DictionaryMut, Phrase, Phrases,
+};
+
+/// A dictionary wrapper that folds the phrases of mixed script dictionaries
+/// into one target script.
+///
+/// Imported dictionaries often mix entries in Traditional and Simplified
+/// Chinese, so the same word shows up twice, e.g. `國民` and `国民`. A
+/// `ScriptNormalizer` maps every character of the phrases found in the
+/// underlying dictionary through a table into the target script, then
+/// collapses the phrases that became equal into the most frequent one.
+/// Conversion on top of it only sees, and outputs, the target script.
+///
+/// Unlike [`SimplifiedMapper`][crate::conversion::SimplifiedMapper], which
+/// rewrites the output of a conversion, this works at lookup time so both
+/// spellings compete as one candidate. The wrapped dictionary is
+/// read-only.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use chewing::{dictionary::{Dictionary, ScriptNormalizer}, syl, zhuyin::Bopomofo};
+///
+/// let dict = ScriptNormalizer::new(
+///     Box::new(HashMap::from([(
+///         vec![syl![Bopomofo::G, Bopomofo::U, Bopomofo::O, Bopomofo::TONE2]],
+///         vec![("国", 100).into(), ("國", 50).into()],
+///     )])),
+///     [('国', '國')],
+/// );
+/// let phrases: Vec<_> = dict
+///     .lookup_word(syl![Bopomofo::G, Bopomofo::U, Bopomofo::O, Bopomofo::TONE2])
+///     .collect();
+/// assert_eq!(1, phrases.len());
+/// assert_eq!("國", phrases[0].as_str());
+/// assert_eq!(100, phrases[0].freq());
+/// ```
+#[derive(Debug)]
+pub struct ScriptNormalizer {
+    inner: Box<dyn Dictionary>,
+    table: HashMap<char, char>,
+}
+
+impl ScriptNormalizer {
+    /// Creates a new `ScriptNormalizer` from a table mapping characters of
+    /// other scripts to the target script. Characters without an entry are
+    /// kept.
+    pub fn new<T>(inner: Box<dyn Dictionary>, table: T) -> ScriptNormalizer
+    where
+        T: IntoIterator<Item = (char, char)>,
+    {
+        ScriptNormalizer {
+            inner,
+            table: table.into_iter().collect(),
+        }
+    }
+    /// Maps each character of `text` to the target script.
+    pub fn normalize_str(&self, text: &str) -> String {
+        text.chars()
+            .map(|c| self.table.get(&c).copied().unwrap_or(c))
+            .collect()
+    }
+    fn normalize<'p>(&self, mut phrase: Phrase<'p>) -> Phrase<'p> {
+        if phrase.as_str().chars().any(|c| self.table.contains_key(&c)) {
+            phrase.phrase = self.normalize_str(phrase.as_str()).into();
+        }
+        phrase
+    }
+}
+
+impl Dictionary for ScriptNormalizer {
+    fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        // Normalizing can turn different phrases into the same one.
+        let mut phrases = vec![];
+        for phrase in self.inner.lookup_phrase(syllables) {
+            push_unique_phrase(&mut phrases, self.normalize(phrase));
+        }
+        Box::new(phrases.into_iter())
+    }
+
+    fn entries(&self) -> DictEntries<'_, '_> {
+        Box::new(
+            self.inner
+                .entries()
+                .map(|(syllables, phrase)| (syllables, self.normalize(phrase))),
+        )
+    }
+
+    fn max_phrase_len(&self) -> usize {
+        self.inner.max_phrase_len()
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        self.inner.about()
+    }
+
+    fn as_mut_dict(&mut self) -> Option<&mut dyn DictionaryMut> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, rc::Rc};
+
+    use crate::{
+        conversion::{ChewingConversionEngine, ChineseSequence, ConversionEngine},
+        dictionary::Dictionary,
+        syl,
+        zhuyin::Bopomofo::*,
+    };
+
+    use super::ScriptNormalizer;
+
+    fn mixed_dictionary() -> Box<dyn Dictionary> {
+        Box::new(HashMap::from([
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("国民", 300).into(), ("國民", 200).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大会", 200).into()],
+            ),
+        ]))
+    }
+
+    fn convert(dict: ScriptNormalizer) -> Vec<String> {
+        let engine = ChewingConversionEngine::new(Rc::new(dict));
+        let sequence = ChineseSequence {
+            syllables: vec![
+                syl![G, U, O, TONE2],
+                syl![M, I, EN, TONE2],
+                syl![D, A, TONE4],
+                syl![H, U, EI, TONE4],
+            ],
+            selections: vec![],
+            breaks: vec![],
+        };
+        engine
+            .convert(&sequence)
+            .unwrap()
+            .into_iter()
+            .map(|interval| interval.phrase)
+            .collect()
+    }
+
+    #[test]
+    fn unify_mixed_scripts() {
+        let traditional = ScriptNormalizer::new(mixed_dictionary(), [('国', '國'), ('会', '會')]);
+        let phrases: Vec<_> = traditional
+            .lookup_phrase(&[syl![G, U, O, TONE2], syl![M, I, EN, TONE2]])
+            .map(|phrase| (phrase.as_str().to_string(), phrase.freq()))
+            .collect();
+        assert_eq!(vec![("國民".to_string(), 300)], phrases);
+        assert_eq!(vec!["國民", "大會"], convert(traditional));
+
+        let simplified = ScriptNormalizer::new(mixed_dictionary(), [('國', '国'), ('會', '会')]);
+        assert_eq!(vec!["国民", "大会"], convert(simplified));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::zhuyin::Syllable;
+
+use super::{
+    push_unique_phrase, DictEntries, Dictionary, DictionaryInfo, DictionaryMut, Phrase, Phrases,
+};
+
+/// A read-only dictionary backed by a vector sorted by syllables.
+///
+/// All entries are stored contiguously and looked up with a binary search,
+/// which is friendlier to the CPU cache than a `HashMap` when the conversion
+/// engine queries many short spans in a hot loop.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::{dictionary::{Dictionary, SortedVecDictionary}, syl, zhuyin::Bopomofo};
+///
+/// let dict = SortedVecDictionary::new(vec![
+///     (vec![syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]], ("測", 100).into()),
+///     (
+///         vec![
+///             syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4],
+///             syl![Bopomofo::SH, Bopomofo::TONE4],
+///         ],
+///         ("測試", 200).into(),
+///     ),
+/// ]);
+/// assert_eq!(2, dict.max_phrase_len());
+/// assert_eq!(
+///     "測",
+///     dict.lookup_word(syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4])
+///         .next()
+///         .unwrap()
+///         .as_str()
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct SortedVecDictionary {
+    entries: Vec<(Vec<Syllable>, Vec<Phrase<'static>>)>,
+    max_phrase_len: usize,
+    info: DictionaryInfo,
+}
+
+impl SortedVecDictionary {
+    /// Creates a new `SortedVecDictionary` from syllables and phrase pairs.
+    ///
+    /// The entries don't need to be sorted. Phrases with the same syllables
+    /// keep their relative order, and duplicate phrases keep their highest
+    /// frequency.
+    pub fn new<I>(entries: I) -> SortedVecDictionary
+    where
+        I: IntoIterator<Item = (Vec<Syllable>, Phrase<'static>)>,
+    {
+        let mut pairs: Vec<_> = entries.into_iter().collect();
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut grouped: Vec<(Vec<Syllable>, Vec<Phrase<'static>>)> = vec![];
+        for (syllables, phrase) in pairs {
+            match grouped.last_mut() {
+                Some((last, phrases)) if *last == syllables => push_unique_phrase(phrases, phrase),
+                _ => grouped.push((syllables, vec![phrase])),
+            }
+        }
+        let max_phrase_len = grouped
+            .iter()
+            .map(|(syllables, _)| syllables.len())
+            .max()
+            .unwrap_or(0);
+        SortedVecDictionary {
+            entries: grouped,
+            max_phrase_len,
+            info: Default::default(),
+        }
+    }
+    /// Creates a new `SortedVecDictionary` with the content of another
+    /// dictionary.
+    pub fn from_dict(dict: &dyn Dictionary) -> SortedVecDictionary {
+        let mut sorted = SortedVecDictionary::new(
+            dict.entries()
+                .map(|(syllables, phrase)| (syllables, phrase.into_owned())),
+        );
+        sorted.info = dict.about();
+        sorted
+    }
+    /// Returns the number of distinct syllable keys.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    /// Returns `true` if the dictionary has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    fn get(&self, syllables: &[Syllable]) -> Option<&[Phrase<'static>]> {
+        if syllables.len() > self.max_phrase_len {
+            return None;
+        }
+        self.entries
+            .binary_search_by(|(key, _)| key.as_slice().cmp(syllables))
+            .ok()
+            .map(|index| self.entries[index].1.as_slice())
+    }
+}
+
+impl Dictionary for SortedVecDictionary {
+    fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        match self.get(syllables) {
+            Some(phrases) => Box::new(phrases.iter().cloned()),
+            None => Box::new(std::iter::empty()),
+        }
+    }
+
+    fn lookup_phrase_with_prefix(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        // Keys with the same prefix are contiguous in the sorted entries.
+        let first = self
+            .entries
+            .partition_point(|(key, _)| key.as_slice() < syllables);
+        let len = self.entries[first..].partition_point(|(key, _)| key.starts_with(syllables));
+        Box::new(
+            self.entries[first..first + len]
+                .iter()
+                .flat_map(|(_, phrases)| phrases.iter().cloned()),
+        )
+    }
+
+    fn entries(&self) -> DictEntries<'_, '_> {
+        Box::new(self.entries.iter().flat_map(|(syllables, phrases)| {
+            phrases
+                .iter()
+                .map(move |phrase| (syllables.clone(), phrase.clone()))
+        }))
+    }
+
+    fn max_phrase_len(&self) -> usize {
+        self.max_phrase_len
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        self.info.clone()
+    }
+
+    fn as_mut_dict(&mut self) -> Option<&mut dyn DictionaryMut> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        dictionary::{Dictionary, LayeredDictionary, Phrase},
+        syl,
+        zhuyin::{Bopomofo::*, Syllable},
+    };
+
+    use super::SortedVecDictionary;
+
+    fn corpus() -> HashMap<Vec<Syllable>, Vec<Phrase<'static>>> {
+        HashMap::from([
+            (vec![syl![G, U, O, TONE2]], vec![("國", 1).into()]),
+            (vec![syl![M, I, EN, TONE2]], vec![("民", 1).into()]),
+            (vec![syl![D, A, TONE4]], vec![("大", 1).into()]),
+            (vec![syl![H, U, EI, TONE4]], vec![("會", 1).into()]),
+            (
+                vec![syl![D, AI, TONE4]],
+                vec![("代", 1).into(), ("戴", 2).into()],
+            ),
+            (vec![syl![B, I, AU, TONE3]], vec![("表", 1).into()]),
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![("國民", 200).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 200).into()],
+            ),
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 200).into(), ("戴錶", 100).into()],
+            ),
+            (
+                vec![
+                    syl![G, U, O, TONE2],
+                    syl![M, I, EN, TONE2],
+                    syl![D, A, TONE4],
+                    syl![H, U, EI, TONE4],
+                ],
+                vec![("國民大會", 300).into()],
+            ),
+        ])
+    }
+
+    #[test]
+    fn same_phrases_as_hash_map() {
+        let map = corpus();
+        let sorted = SortedVecDictionary::from_dict(&map);
+        assert_eq!(map.len(), sorted.len());
+        assert_eq!(map.max_phrase_len(), sorted.max_phrase_len());
+        for syllables in map.keys() {
+            assert_eq!(
+                map.lookup_phrase(syllables).collect::<Vec<_>>(),
+                sorted.lookup_phrase(syllables).collect::<Vec<_>>()
+            );
+        }
+        let mut expected: Vec<_> = map.entries().collect();
+        let mut actual: Vec<_> = sorted.entries().collect();
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn duplicate_phrases_keep_max_freq() {
+        let syllables = vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]];
+        let map = HashMap::from([(
+            syllables.clone(),
+            vec![
+                ("代表", 100).into(),
+                ("戴錶", 150).into(),
+                ("代表", 200).into(),
+            ],
+        )]);
+        let sorted = SortedVecDictionary::from_dict(&map);
+        let layered = LayeredDictionary::new(vec![Box::new(map.clone())], vec![]);
+        assert_eq!(Some(200), map.phrase_freq(&syllables, "代表"));
+        for dict in [&layered as &dyn Dictionary, &sorted] {
+            assert_eq!(
+                vec![Phrase::new("代表", 200), Phrase::new("戴錶", 150)],
+                dict.lookup_phrase(&syllables).collect::<Vec<_>>()
+            );
+            assert_eq!(Some(200), dict.phrase_freq(&syllables, "代表"));
+        }
+    }
+
+    #[test]
+    fn entries_are_sorted_by_syllables() {
+        let sorted = SortedVecDictionary::from_dict(&corpus());
+        let keys: Vec<_> = sorted.entries().map(|(syllables, _)| syllables).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn prefix_lookup_matches_default() {
+        let map = corpus();
+        let sorted = SortedVecDictionary::from_dict(&map);
+        for prefix in [
+            vec![syl![G, U, O, TONE2]],
+            vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+            vec![syl![D, AI, TONE4]],
+            vec![syl![X, I, EN]],
+        ] {
+            let mut expected: Vec<_> = map.lookup_phrase_with_prefix(&prefix).collect();
+            let mut actual: Vec<_> = sorted.lookup_phrase_with_prefix(&prefix).collect();
+            expected.sort();
+            actual.sort();
+            assert_eq!(expected, actual);
+        }
+        assert_eq!(
+            3,
+            sorted
+                .lookup_phrase_with_prefix(&[syl![G, U, O, TONE2]])
+                .count()
+        );
+    }
+
+    #[test]
+    fn absent_keys_are_empty() {
+        let sorted = SortedVecDictionary::from_dict(&corpus());
+        assert_eq!(0, sorted.lookup_word(syl![X, I, EN]).count());
+        assert_eq!(
+            0,
+            sorted
+                .lookup_phrase(&[syl![D, A, TONE4], syl![D, A, TONE4]])
+                .count()
+        );
+        assert_eq!(0, sorted.lookup_phrase(&[syl![D, A, TONE4]; 5]).count());
+        assert!(SortedVecDictionary::default().is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{path::Path, str};
+use std::{path::Path, str, time::Duration};
 
 use rusqlite::{params, Connection, Error as RusqliteError, OpenFlags, OptionalExtension};
 use thiserror::Error;
@@ -80,6 +80,46 @@ impl SqliteDictionary {
         })
     }
 
+    /// Decays the learned frequency of the user phrases toward the frequency
+    /// of the phrase without learning.
+    ///
+    /// The learned boost of each phrase, the part of its user frequency above
+    /// the base frequency, is halved for every `half_life` elapsed between the
+    /// time the phrase was last used and `now`. Times are in the unit passed
+    /// to [`DictionaryMut::update`] and `half_life` counts one unit per
+    /// second. The base frequencies are untouched.
+    ///
+    /// The last used time is not changed, so each call decays the boost by the
+    /// whole time since the last use again.
+    pub fn decay(&self, half_life: Duration, now: u64) -> Result<(), SqliteDictionaryError> {
+        let half_life = half_life.as_secs_f64();
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "SELECT id, freq, user_freq, time
+                FROM dictionary_v1 JOIN userphrase_v2 ON userphrase_id = id
+                WHERE user_freq > freq",
+            )?;
+            let boosted = stmt
+                .query_map([], |row| row.try_into())?
+                .collect::<Result<Vec<(u64, u32, u32, u64)>, _>>()?;
+            let mut update =
+                tx.prepare_cached("UPDATE userphrase_v2 SET user_freq = ? WHERE id = ?")?;
+            for (id, freq, user_freq, time) in boosted {
+                let elapsed = now.saturating_sub(time) as f64;
+                let remaining = if half_life > 0.0 {
+                    0.5_f64.powf(elapsed / half_life)
+                } else {
+                    0.0
+                };
+                let boost = (f64::from(user_freq - freq) * remaining) as u32;
+                update.execute(params![freq + boost, id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
     fn initialize_tables(conn: &Connection) -> Result<(), SqliteDictionaryError> {
         conn.pragma_update(None, "journal_mode", "WAL")?;
         conn.pragma_update(None, "synchronous", "NORMAL")?;
@@ -323,6 +363,19 @@ impl Dictionary for SqliteDictionary {
         )
     }
 
+    fn max_phrase_len(&self) -> usize {
+        let max_len: Option<usize> = self
+            .conn
+            .query_row(
+                "SELECT max(length(syllables)) FROM dictionary_v1",
+                [],
+                |row| row.get(0),
+            )
+            .expect("SQL error");
+        // Each syllable is stored as two bytes
+        max_len.unwrap_or(0) / 2
+    }
+
     fn about(&self) -> DictionaryInfo {
         self.info.clone()
     }
@@ -529,12 +582,13 @@ impl DictionaryBuilder for SqliteDictionaryBuilder {
 
 #[cfg(test)]
 mod tests {
-    use std::error::Error;
+    use std::{error::Error, rc::Rc, time::Duration};
 
     use rusqlite::{params, Connection};
     use tempfile::NamedTempFile;
 
     use crate::{
+        conversion::{ChewingConversionEngine, ChineseSequence, ConversionEngine},
         dictionary::{Dictionary, DictionaryMut, Phrase},
         syl,
         zhuyin::Bopomofo,
@@ -637,4 +691,29 @@ mod tests {
         );
         Ok(())
     }
+    #[test]
+    fn decayed_boost_no_longer_flips_conversion() -> Result<(), Box<dyn Error>> {
+        let mut dict = SqliteDictionary::open_in_memory()?;
+        let syllables = [
+            syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4],
+            syl![Bopomofo::SH, Bopomofo::TONE4],
+        ];
+        dict.insert(&syllables, ("測試", 9318).into())?;
+        dict.insert(&syllables, ("策士", 9000).into())?;
+        dict.update(&syllables, ("策士", 9000).into(), 9900, 0)?;
+        let dict = Rc::new(dict);
+        let engine = ChewingConversionEngine::new(dict.clone());
+        let sequence = ChineseSequence {
+            syllables: syllables.to_vec(),
+            selections: vec![],
+            breaks: vec![],
+        };
+        assert_eq!("策士", engine.convert(&sequence)?[0].phrase);
+
+        dict.decay(Duration::from_secs(100), 200)?;
+        assert_eq!(Some(9225), dict.phrase_freq(&syllables, "策士"));
+        assert_eq!(Some(9318), dict.phrase_freq(&syllables, "測試"));
+        assert_eq!("測試", engine.convert(&sequence)?[0].phrase);
+        Ok(())
+    }
 }
@@ -0,0 +1,171 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::zhuyin::Syllable;
+
+use super::{DictEntries, Dictionary, DictionaryInfo, DictionaryMut, Phrase, Phrases};
+
+type Request = (Vec<Syllable>, mpsc::Sender<Vec<Phrase<'static>>>);
+
+/// A dictionary wrapper that gives up on lookups taking longer than a
+/// timeout.
+///
+/// This is meant for slow layers of a
+/// [`LayeredDictionary`][super::LayeredDictionary], such as network or disk
+/// backed dictionaries. The lookups run on a worker thread that owns the
+/// inner dictionary, so it must be [`Send`]. A
+/// [`lookup_phrase`][Dictionary::lookup_phrase] that doesn't answer within
+/// the timeout returns no phrases, and the layered dictionary uses the
+/// phrases of the other layers. While a timed out lookup is still running,
+/// further lookups return no phrases right away instead of queueing behind
+/// it. A warning is logged the first time a lookup times out.
+///
+/// The wrapper is read only. [`entries`][Dictionary::entries] waits for the
+/// worker to finish its current lookup.
+///
+/// # Examples
+///
+/// ```
+/// use std::{collections::HashMap, time::Duration};
+///
+/// use chewing::{dictionary::{Dictionary, TimedDictionary}, syl, zhuyin::Bopomofo};
+///
+/// let mut dict = HashMap::new();
+/// dict.insert(
+///     vec![syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]],
+///     vec![("測", 1).into()],
+/// );
+/// let timed = TimedDictionary::new(Box::new(dict), Duration::from_secs(1));
+/// assert_eq!(
+///     1,
+///     timed
+///         .lookup_phrase(&[syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]])
+///         .count()
+/// );
+/// ```
+#[derive(Debug)]
+pub struct TimedDictionary {
+    inner: Arc<Mutex<Box<dyn Dictionary + Send>>>,
+    requests: Mutex<mpsc::Sender<Request>>,
+    in_flight: Arc<AtomicUsize>,
+    timeout: Duration,
+    max_phrase_len: usize,
+    info: DictionaryInfo,
+    warned: AtomicBool,
+}
+
+impl TimedDictionary {
+    /// Creates a new `TimedDictionary` looking up `inner` on a worker thread
+    /// with at most `timeout` for each lookup.
+    pub fn new(inner: Box<dyn Dictionary + Send>, timeout: Duration) -> TimedDictionary {
+        let max_phrase_len = inner.max_phrase_len();
+        let info = inner.about();
+        let inner = Arc::new(Mutex::new(inner));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let (requests, receiver) = mpsc::channel::<Request>();
+        let worker = Arc::clone(&inner);
+        let worker_in_flight = Arc::clone(&in_flight);
+        thread::spawn(move || {
+            for (syllables, reply) in receiver {
+                let phrases = match worker.lock() {
+                    Ok(dict) => dict
+                        .lookup_phrase(&syllables)
+                        .map(Phrase::into_owned)
+                        .collect(),
+                    Err(_) => break,
+                };
+                // Finish before replying, so the next lookup after a reply
+                // never sees this request as still running.
+                worker_in_flight.fetch_sub(1, Ordering::SeqCst);
+                let _ = reply.send(phrases);
+            }
+        });
+        TimedDictionary {
+            inner,
+            requests: Mutex::new(requests),
+            in_flight,
+            timeout,
+            max_phrase_len,
+            info,
+            warned: AtomicBool::new(false),
+        }
+    }
+    /// Returns the timeout of each lookup.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+    fn warn_once(&self, reason: &str) {
+        if !self.warned.swap(true, Ordering::Relaxed) {
+            warn!(
+                name = ?self.info.name,
+                timeout = ?self.timeout,
+                "dictionary lookup {}, skipping its phrases",
+                reason
+            );
+        }
+    }
+}
+
+impl Dictionary for TimedDictionary {
+    fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        if self.in_flight.load(Ordering::SeqCst) > 0 {
+            self.warn_once("is still running");
+            return Box::new(std::iter::empty());
+        }
+        let (reply, response) = mpsc::channel();
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let sent = match self.requests.lock() {
+            Ok(requests) => requests.send((syllables.to_vec(), reply)).is_ok(),
+            Err(_) => false,
+        };
+        if !sent {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.warn_once("failed");
+            return Box::new(std::iter::empty());
+        }
+        match response.recv_timeout(self.timeout) {
+            Ok(phrases) => Box::new(phrases.into_iter()),
+            Err(RecvTimeoutError::Timeout) => {
+                self.warn_once("timed out");
+                Box::new(std::iter::empty())
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                self.warn_once("failed");
+                Box::new(std::iter::empty())
+            }
+        }
+    }
+
+    fn entries(&self) -> DictEntries<'_, '_> {
+        match self.inner.lock() {
+            Ok(dict) => Box::new(
+                dict.entries()
+                    .map(|(syllables, phrase)| (syllables, phrase.into_owned()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+            Err(_) => Box::new(std::iter::empty()),
+        }
+    }
+
+    fn max_phrase_len(&self) -> usize {
+        self.max_phrase_len
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        self.info.clone()
+    }
+
+    fn as_mut_dict(&mut self) -> Option<&mut dyn DictionaryMut> {
+        None
+    }
+}
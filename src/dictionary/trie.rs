@@ -17,8 +17,8 @@ use riff::{Chunk, ChunkContents, ChunkId, RIFF_ID};
 use crate::zhuyin::Syllable;
 
 use super::{
-    BuildDictionaryError, Dictionary, DictionaryBuilder, DictionaryInfo, DictionaryMut,
-    DuplicatePhraseError, Phrase, Phrases,
+    BuildDictionaryError, DictEntries, Dictionary, DictionaryBuilder, DictionaryInfo,
+    DictionaryMut, DuplicatePhraseError, Phrase, Phrases,
 };
 
 const DICT_FORMAT: u32 = 0;
@@ -119,7 +119,9 @@ impl<'a> PhraseData<&'a [u8]> {
 /// use chewing::{syl, zhuyin::{Bopomofo, Syllable}};
 /// # use chewing::dictionary::{DictionaryBuilder, TrieDictionaryBuilder};
 /// use chewing::dictionary::{Dictionary, TrieDictionary};
-/// # let mut tempfile = File::create("dict.dat")?;
+/// # let dir = tempfile::tempdir()?;
+/// # let path = dir.path().join("dict.dat");
+/// # let mut tempfile = File::create(&path)?;
 /// # let mut builder = TrieDictionaryBuilder::new();
 /// # builder.insert(&[
 /// #     syl![Bopomofo::Z, Bopomofo::TONE4],
@@ -127,7 +129,7 @@ impl<'a> PhraseData<&'a [u8]> {
 /// # ], ("字典", 0).into());
 /// # builder.write(&mut tempfile)?;
 ///
-/// let mut file = File::open("dict.dat")?;
+/// let mut file = File::open(path)?;
 /// let dict = TrieDictionary::new(&mut file)?;
 ///
 /// // Find the phrase ㄗˋㄉ一ㄢˇ (dictionary)
@@ -147,6 +149,7 @@ pub struct TrieDictionary {
     info: DictionaryInfo,
     dict: Vec<u8>,
     data: Vec<u8>,
+    max_phrase_len: usize,
 }
 
 impl TrieDictionary {
@@ -235,7 +238,46 @@ impl TrieDictionary {
         let data = data_chunk
             .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "expecting data chunk"))?
             .read_contents(&mut stream)?;
-        Ok(TrieDictionary { info, dict, data })
+        let max_phrase_len = Self::read_max_height(&dict)?;
+        Ok(TrieDictionary {
+            info,
+            dict,
+            data,
+            max_phrase_len,
+        })
+    }
+
+    /// Walks the trie index and returns the number of syllables of the
+    /// longest phrase.
+    fn read_max_height(dict: &[u8]) -> io::Result<usize> {
+        if dict.len() < TrieNodePod::SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "dict chunk is too short",
+            ));
+        }
+        let mut max_height = 0;
+        let mut stack = vec![(0, 0)];
+        while let Some((begin, height)) = stack.pop() {
+            let node: &TrieNodePod = from_bytes(&dict[begin..begin + TrieNodePod::SIZE]);
+            if node.child_end() > dict.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "child node out of range",
+                ));
+            }
+            for child_begin in (node.child_begin()..node.child_end()).step_by(TrieNodePod::SIZE) {
+                let child: &TrieNodePod =
+                    from_bytes(&dict[child_begin..child_begin + TrieNodePod::SIZE]);
+                if child.syllable() == 0 {
+                    // The leaf node contains the phrases of this node
+                    max_height = max_height.max(height);
+                } else {
+                    stack.push((child_begin, height + 1));
+                }
+            }
+        }
+        Ok(max_height)
     }
 
     fn read_fmt_version<T>(fmt_chunk: Chunk, mut stream: T) -> io::Result<u32>
@@ -335,8 +377,38 @@ impl Dictionary for TrieDictionary {
         })
     }
 
-    fn entries(&self) -> super::DictEntries<'_, '_> {
-        todo!();
+    fn entries(&self) -> DictEntries<'_, '_> {
+        // Collect the location of every leaf first, the phrases are read
+        // lazily from the data chunk.
+        let mut leaves = vec![];
+        let mut stack = vec![(0, vec![])];
+        while let Some((begin, syllables)) = stack.pop() {
+            let node: &TrieNodePod = from_bytes(&self.dict[begin..begin + TrieNodePod::SIZE]);
+            for child_begin in (node.child_begin()..node.child_end()).step_by(TrieNodePod::SIZE) {
+                let child: &TrieNodePod =
+                    from_bytes(&self.dict[child_begin..child_begin + TrieNodePod::SIZE]);
+                if child.syllable() == 0 {
+                    let leaf: &TrieLeafPod =
+                        from_bytes(&self.dict[child_begin..child_begin + TrieLeafPod::SIZE]);
+                    leaves.push((syllables.clone(), leaf.data_begin()..leaf.data_end()));
+                } else if let Ok(syllable) = Syllable::try_from(child.syllable()) {
+                    let mut syllables = syllables.clone();
+                    syllables.push(syllable);
+                    stack.push((child_begin, syllables));
+                }
+            }
+        }
+        leaves.sort_by(|a, b| a.0.cmp(&b.0));
+        Box::new(leaves.into_iter().flat_map(move |(syllables, range)| {
+            PhrasesIter {
+                bytes: &self.data[range],
+            }
+            .map(move |phrase| (syllables.clone(), phrase))
+        }))
+    }
+
+    fn max_phrase_len(&self) -> usize {
+        self.max_phrase_len
     }
 
     fn about(&self) -> DictionaryInfo {
@@ -362,7 +434,9 @@ impl Dictionary for TrieDictionary {
 /// use chewing::{syl, zhuyin::Bopomofo};
 /// use chewing::dictionary::{DictionaryBuilder, TrieDictionaryBuilder};
 ///
-/// let mut file = File::create("dict.dat")?;
+/// # let dir = tempfile::tempdir()?;
+/// # let path = dir.path().join("dict.dat");
+/// let mut file = File::create(path)?;
 /// let mut builder = TrieDictionaryBuilder::new();
 /// builder.insert(&[
 ///     syl![Bopomofo::Z, Bopomofo::TONE4],
@@ -1197,6 +1271,87 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn tree_max_phrase_len() -> Result<(), Box<dyn std::error::Error>> {
+        let mut builder = TrieDictionaryBuilder::new();
+        builder.insert(
+            &[syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]],
+            ("測", 1).into(),
+        )?;
+        builder.insert(
+            &[
+                syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4],
+                syl![Bopomofo::SH, Bopomofo::TONE4],
+                syl![Bopomofo::I, Bopomofo::TONE2],
+            ],
+            ("測試儀", 1).into(),
+        )?;
+        let mut cursor = Cursor::new(vec![]);
+        builder.write(&mut cursor)?;
+
+        let dict = TrieDictionary::new(&mut cursor)?;
+        assert_eq!(3, dict.max_phrase_len());
+
+        let mut cursor = Cursor::new(vec![]);
+        TrieDictionaryBuilder::new().write(&mut cursor)?;
+        let dict = TrieDictionary::new(&mut cursor)?;
+        assert_eq!(0, dict.max_phrase_len());
+        Ok(())
+    }
+
+    #[test]
+    fn tree_entries() -> Result<(), Box<dyn std::error::Error>> {
+        let mut builder = TrieDictionaryBuilder::new();
+        builder.insert(
+            &[syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]],
+            ("測", 1).into(),
+        )?;
+        builder.insert(
+            &[syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]],
+            ("冊", 2).into(),
+        )?;
+        builder.insert(
+            &[
+                syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4],
+                syl![Bopomofo::SH, Bopomofo::TONE4],
+            ],
+            ("測試", 100).into(),
+        )?;
+        let mut cursor = Cursor::new(vec![]);
+        builder.write(&mut cursor)?;
+
+        let dict = TrieDictionary::new(&mut cursor)?;
+        let mut entries: Vec<_> = dict
+            .entries()
+            .map(|(syllables, phrase)| (syllables, phrase.as_str().to_string(), phrase.freq()))
+            .collect();
+        entries.sort();
+        assert_eq!(
+            vec![
+                (
+                    vec![syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]],
+                    "冊".to_string(),
+                    2
+                ),
+                (
+                    vec![syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4]],
+                    "測".to_string(),
+                    1
+                ),
+                (
+                    vec![
+                        syl![Bopomofo::C, Bopomofo::E, Bopomofo::TONE4],
+                        syl![Bopomofo::SH, Bopomofo::TONE4]
+                    ],
+                    "測試".to_string(),
+                    100
+                ),
+            ],
+            entries
+        );
+        Ok(())
+    }
+
     #[test]
     #[should_panic]
     fn tree_builder_duplicate_phrase_error() {
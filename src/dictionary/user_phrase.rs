@@ -0,0 +1,186 @@
+use std::{io::BufRead, str::FromStr};
+
+use thiserror::Error;
+
+use crate::zhuyin::Syllable;
+
+use super::{
+    DictEntries, Dictionary, DictionaryInfo, DictionaryMut, Phrase, Phrases, SortedVecDictionary,
+};
+
+/// A read-only dictionary loaded from a plaintext file of user phrases.
+///
+/// Each line holds a phrase, its frequency, and the zhuyin of every character
+/// separated by whitespace, the same layout as the `tsi.src` source of the
+/// system dictionary. Empty lines and lines starting with `#` are skipped.
+///
+/// To override the system dictionary put the `UserPhraseDictionary` after it
+/// in a [`LayeredDictionary`][super::LayeredDictionary], where later
+/// dictionaries replace the frequencies of earlier ones.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use chewing::{dictionary::{Dictionary, UserPhraseDictionary}, syl, zhuyin::Bopomofo::*};
+///
+/// let file = "# my phrases\n酷音 100 ㄎㄨˋ ㄧㄣ\n";
+/// let dict = UserPhraseDictionary::from_reader(file.as_bytes())?;
+/// assert_eq!(
+///     Some(100),
+///     dict.phrase_freq(&[syl![K, U, TONE4], syl![I, EN]], "酷音")
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct UserPhraseDictionary {
+    inner: SortedVecDictionary,
+}
+
+impl UserPhraseDictionary {
+    /// Parses the user phrases from `reader`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with the line number of the first line that can't be
+    /// read or parsed.
+    pub fn from_reader(reader: impl BufRead) -> Result<UserPhraseDictionary, ParseUserPhraseError> {
+        let mut entries = vec![];
+        for (index, line) in reader.lines().enumerate() {
+            let line_num = index + 1;
+            let err = |msg: &str| ParseUserPhraseError {
+                line_num,
+                msg: msg.to_string(),
+            };
+            let line = line.map_err(|_| err("unable to read line"))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let phrase = fields.next().ok_or_else(|| err("missing phrase"))?;
+            let freq = fields
+                .next()
+                .ok_or_else(|| err("missing frequency"))?
+                .parse()
+                .map_err(|_| err("invalid frequency"))?;
+            let syllables = fields
+                .map(Syllable::from_str)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| err("invalid zhuyin"))?;
+            if syllables.len() != phrase.chars().count() {
+                return Err(err("zhuyin doesn't match the phrase length"));
+            }
+            entries.push((syllables, Phrase::new(phrase.to_string(), freq)));
+        }
+        Ok(UserPhraseDictionary {
+            inner: SortedVecDictionary::new(entries),
+        })
+    }
+}
+
+impl Dictionary for UserPhraseDictionary {
+    fn lookup_phrase(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        self.inner.lookup_phrase(syllables)
+    }
+
+    fn lookup_phrase_with_prefix(&self, syllables: &[Syllable]) -> Phrases<'_, '_> {
+        self.inner.lookup_phrase_with_prefix(syllables)
+    }
+
+    fn entries(&self) -> DictEntries<'_, '_> {
+        self.inner.entries()
+    }
+
+    fn max_phrase_len(&self) -> usize {
+        self.inner.max_phrase_len()
+    }
+
+    fn about(&self) -> DictionaryInfo {
+        DictionaryInfo {
+            name: Some("User phrases".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn as_mut_dict(&mut self) -> Option<&mut dyn DictionaryMut> {
+        None
+    }
+}
+
+/// Errors returned when parsing a file of user phrases.
+#[derive(Error, Debug)]
+#[error("invalid user phrase at line {line_num}: {msg}")]
+pub struct ParseUserPhraseError {
+    line_num: usize,
+    msg: String,
+}
+
+impl ParseUserPhraseError {
+    /// Returns the line number of the error, starting from 1.
+    pub fn line_num(&self) -> usize {
+        self.line_num
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        dictionary::{Dictionary, LayeredDictionary, Phrase},
+        syl,
+        zhuyin::Bopomofo::*,
+    };
+
+    use super::UserPhraseDictionary;
+
+    #[test]
+    fn user_phrases_override_base() {
+        let base = HashMap::from([
+            (
+                vec![syl![D, AI, TONE4], syl![B, I, AU, TONE3]],
+                vec![("代表", 200).into(), ("戴錶", 100).into()],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![("大會", 200).into()],
+            ),
+        ]);
+        let file = "\
+# phrase freq zhuyin
+戴錶 500 ㄉㄞˋ ㄅㄧㄠˇ
+
+酷音 100 ㄎㄨˋ ㄧㄣ
+";
+        let user = UserPhraseDictionary::from_reader(file.as_bytes()).unwrap();
+        let dict = LayeredDictionary::new(vec![Box::new(base), Box::new(user)], vec![]);
+        let best = |syllables: &[_]| dict.lookup_phrase(syllables).max();
+        assert_eq!(
+            Some(Phrase::new("戴錶", 500)),
+            best(&[syl![D, AI, TONE4], syl![B, I, AU, TONE3]])
+        );
+        assert_eq!(
+            Some(Phrase::new("大會", 200)),
+            best(&[syl![D, A, TONE4], syl![H, U, EI, TONE4]])
+        );
+        assert_eq!(
+            Some(Phrase::new("酷音", 100)),
+            best(&[syl![K, U, TONE4], syl![I, EN]])
+        );
+    }
+
+    #[test]
+    fn malformed_lines_report_line_number() {
+        for (file, line_num) in [
+            ("酷音 100 ㄎㄨˋ ㄧㄣ\n酷音 abc ㄎㄨˋ ㄧㄣ\n", 2),
+            ("# comment\n\n酷音\n", 3),
+            ("酷音 100 ㄎㄨˋ\n", 1),
+            ("酷音 100 ㄎㄨˋ ㄧㄣㄚ\n", 1),
+        ] {
+            let err = UserPhraseDictionary::from_reader(file.as_bytes()).unwrap_err();
+            assert_eq!(line_num, err.line_num(), "{err}");
+        }
+    }
+}
@@ -26,7 +26,7 @@ pub use self::{
     ginyieh::GinYieh,
     hsu::Hsu,
     ibm::Ibm,
-    pinyin::{Pinyin, PinyinVariant},
+    pinyin::{ParsePinyinError, Pinyin, PinyinParser, PinyinVariant},
     standard::Standard,
 };
 
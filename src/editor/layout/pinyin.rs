@@ -1,8 +1,10 @@
 //! Pinyin
 
+use thiserror::Error;
+
 use crate::{
     editor::keymap::{KeyCode, KeyEvent},
-    zhuyin::{Bopomofo, Syllable},
+    zhuyin::{Bopomofo, ParseSyllableError, Syllable, ToneSystem},
 };
 
 use super::{KeyBehavior, SyllableEditor};
@@ -132,156 +134,250 @@ impl SyllableEditor for Pinyin {
             _ => None,
         };
 
-        if let Some(entry) = match self.variant {
-            PinyinVariant::HanyuPinyin => table::HANYU_PINYIN_MAPPING.iter(),
-            PinyinVariant::ThlPinyin => table::THL_PINYIN_MAPPING.iter(),
-            PinyinVariant::Mps2Pinyin => table::MPS2_PINYIN_MAPPING.iter(),
-        }
-        .find(|entry| entry.pinyin == self.key_seq)
-        {
-            self.key_seq.clear();
-            self.syllable = entry.primary;
-            self.syllable_alt = entry.alt;
-            if let Some(tone) = tone {
-                self.syllable.update(tone);
-                self.syllable_alt.update(tone);
+        let key_seq = std::mem::take(&mut self.key_seq);
+        match parse_pinyin(&self.variant, &key_seq) {
+            Some(parsed) => {
+                self.syllable = parsed.primary;
+                self.syllable_alt = parsed.alt;
+                if let Some(tone) = tone {
+                    self.syllable.update(tone);
+                    self.syllable_alt.update(tone);
+                }
+                KeyBehavior::Commit
             }
-            return KeyBehavior::Commit;
+            None => KeyBehavior::Absorb,
         }
+    }
 
-        if let Some(entry) = table::COMMON_MAPPING
-            .iter()
-            .find(|entry| entry.pinyin == self.key_seq)
+    fn is_empty(&self) -> bool {
+        self.key_seq.is_empty()
+    }
+
+    fn remove_last(&mut self) {
+        self.key_seq.pop();
+    }
+
+    fn clear(&mut self) {
+        self.key_seq.clear();
+        self.syllable.clear();
+        self.syllable_alt.clear();
+    }
+
+    fn read(&self) -> Syllable {
+        self.syllable
+    }
+
+    fn key_seq(&self) -> Option<String> {
+        Some(self.key_seq.clone())
+    }
+}
+
+/// Parses pinyin syllables with numeric tones, such as `guo2`.
+///
+/// The letters are mapped like the [`Pinyin`] layout of the same variant
+/// and the optional trailing digit `1` to `5` is the tone. Without a digit
+/// or with `1` the syllable has no tone mark. Ambiguous spellings use the
+/// primary syllable of the layout.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::{
+///     conversion::ChineseSequence,
+///     editor::layout::PinyinParser,
+///     syl,
+///     zhuyin::Bopomofo::*,
+/// };
+///
+/// let seq = ChineseSequence::from_str_with("guo2 min2", &PinyinParser::default()).unwrap();
+/// assert_eq!(vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]], seq.syllables);
+/// ```
+#[derive(Debug, Default)]
+pub struct PinyinParser {
+    variant: PinyinVariant,
+}
+
+impl PinyinParser {
+    /// Creates a parser for the pinyin `variant`.
+    pub fn new(variant: PinyinVariant) -> PinyinParser {
+        PinyinParser { variant }
+    }
+}
+
+/// Error returned by [`PinyinParser`] for text that is not a pinyin
+/// syllable.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid pinyin syllable {text:?}")]
+pub struct ParsePinyinError {
+    /// The offending text.
+    pub text: String,
+}
+
+impl ToneSystem for PinyinParser {
+    fn parse_syllable(&self, text: &str) -> Result<Syllable, ParseSyllableError> {
+        let invalid = || {
+            ParseSyllableError::with_source(ParsePinyinError {
+                text: text.to_string(),
+            })
+        };
+        let (letters, tone) = match text.as_bytes().last() {
+            Some(b'1') => (&text[..text.len() - 1], None),
+            Some(b'2') => (&text[..text.len() - 1], Some(Bopomofo::TONE2)),
+            Some(b'3') => (&text[..text.len() - 1], Some(Bopomofo::TONE3)),
+            Some(b'4') => (&text[..text.len() - 1], Some(Bopomofo::TONE4)),
+            Some(b'5') => (&text[..text.len() - 1], Some(Bopomofo::TONE5)),
+            _ => (text, None),
+        };
+        if letters.is_empty()
+            || letters.len() > MAX_PINYIN_LEN
+            || !letters.bytes().all(|b| b.is_ascii_lowercase())
         {
-            self.key_seq.clear();
-            self.syllable = entry.primary;
-            self.syllable_alt = entry.alt;
-            if let Some(tone) = tone {
-                self.syllable.update(tone);
-                self.syllable_alt.update(tone);
-            }
-            return KeyBehavior::Commit;
+            return Err(invalid());
+        }
+        let parsed = parse_pinyin(&self.variant, letters).ok_or_else(invalid)?;
+        if !parsed.exact || parsed.primary.is_empty() {
+            return Err(invalid());
         }
+        let mut syllable = parsed.primary;
+        if let Some(tone) = tone {
+            syllable.update(tone);
+        }
+        Ok(syllable)
+    }
+}
 
-        let initial = table::INITIAL_MAPPING
-            .iter()
-            .find(|entry| self.key_seq.starts_with(entry.pinyin));
+/// A syllable parsed from pinyin without its tone.
+struct ParsedPinyin {
+    primary: Syllable,
+    alt: Syllable,
+    /// Whether every letter of the pinyin was used.
+    exact: bool,
+}
 
-        let final_seq = match initial {
-            Some(entry) => self.key_seq.trim_start_matches(entry.pinyin),
-            None => &self.key_seq,
-        };
+/// Parses the letters of a pinyin syllable.
+///
+/// Returns `None` if neither an initial nor a final is recognized.
+fn parse_pinyin(variant: &PinyinVariant, key_seq: &str) -> Option<ParsedPinyin> {
+    if let Some(entry) = match variant {
+        PinyinVariant::HanyuPinyin => table::HANYU_PINYIN_MAPPING.iter(),
+        PinyinVariant::ThlPinyin => table::THL_PINYIN_MAPPING.iter(),
+        PinyinVariant::Mps2Pinyin => table::MPS2_PINYIN_MAPPING.iter(),
+    }
+    .find(|entry| entry.pinyin == key_seq)
+    {
+        return Some(ParsedPinyin {
+            primary: entry.primary,
+            alt: entry.alt,
+            exact: true,
+        });
+    }
 
-        let fina = table::FINAL_MAPPING
-            .iter()
-            .find(|entry| final_seq == entry.pinyin);
+    if let Some(entry) = table::COMMON_MAPPING
+        .iter()
+        .find(|entry| entry.pinyin == key_seq)
+    {
+        return Some(ParsedPinyin {
+            primary: entry.primary,
+            alt: entry.alt,
+            exact: true,
+        });
+    }
 
-        if initial.is_none() && fina.is_none() {
-            self.key_seq.clear();
-            return KeyBehavior::Absorb;
-        }
+    let initial = table::INITIAL_MAPPING
+        .iter()
+        .find(|entry| key_seq.starts_with(entry.pinyin));
 
-        let mut initial = initial.map(|i| i.initial);
-        let mut medial = fina.and_then(|f| f.medial);
-        let mut rime = fina.and_then(|f| f.rime);
+    let final_seq = match initial {
+        Some(entry) => key_seq.trim_start_matches(entry.pinyin),
+        None => key_seq,
+    };
 
-        if let Some(Bopomofo::I) = rime {
-            match initial {
-                Some(Bopomofo::ZH) | Some(Bopomofo::CH) | Some(Bopomofo::SH)
-                | Some(Bopomofo::R) | Some(Bopomofo::Z) | Some(Bopomofo::C) | Some(Bopomofo::S) => {
-                    medial.take();
-                    rime.take();
-                }
-                _ => (),
-            }
-        }
+    let fina = table::FINAL_MAPPING
+        .iter()
+        .find(|entry| final_seq == entry.pinyin);
 
-        match initial {
-            Some(Bopomofo::J) | Some(Bopomofo::Q) | Some(Bopomofo::X) => {
-                match (medial, rime) {
-                    (Some(Bopomofo::U), Some(Bopomofo::AN))
-                    | (Some(Bopomofo::U), Some(Bopomofo::EN))
-                    | (Some(Bopomofo::U), None) => {
-                        medial.replace(Bopomofo::IU);
-                    }
-                    _ => (),
-                };
-            }
-            _ => (),
-        }
+    if initial.is_none() && fina.is_none() {
+        return None;
+    }
+    let exact = fina.is_some() || final_seq.is_empty();
 
-        match medial {
-            Some(Bopomofo::I) | Some(Bopomofo::IU) => {
-                match initial {
-                    Some(Bopomofo::S) | Some(Bopomofo::SH) => {
-                        initial.replace(Bopomofo::X);
-                    }
-                    Some(Bopomofo::C) | Some(Bopomofo::CH) => {
-                        initial.replace(Bopomofo::Q);
-                    }
-                    _ => (),
-                };
-            }
-            _ => {
-                if initial == Some(Bopomofo::J) {
-                    initial.replace(Bopomofo::ZH);
-                }
-            }
-        }
+    let mut initial = initial.map(|i| i.initial);
+    let mut medial = fina.and_then(|f| f.medial);
+    let mut rime = fina.and_then(|f| f.rime);
 
+    if let Some(Bopomofo::I) = rime {
         match initial {
-            Some(Bopomofo::B) | Some(Bopomofo::P) | Some(Bopomofo::M) | Some(Bopomofo::F) => {
-                match (medial, rime) {
-                    (Some(Bopomofo::U), Some(Bopomofo::ENG))
-                    | (Some(Bopomofo::U), Some(Bopomofo::O)) => {
-                        medial.take();
-                    }
-                    _ => (),
-                };
+            Some(Bopomofo::ZH) | Some(Bopomofo::CH) | Some(Bopomofo::SH) | Some(Bopomofo::R)
+            | Some(Bopomofo::Z) | Some(Bopomofo::C) | Some(Bopomofo::S) => {
+                medial.take();
+                rime.take();
             }
             _ => (),
         }
+    }
 
-        self.key_seq.clear();
-        let mut builder = Syllable::builder();
-        if let Some(initial) = initial {
-            builder = builder.insert(initial).unwrap();
-        }
-        if let Some(medial) = medial {
-            builder = builder.insert(medial).unwrap();
-        }
-        if let Some(rime) = rime {
-            builder = builder.insert(rime).unwrap();
-        }
-        if let Some(tone) = tone {
-            builder = builder.insert(tone).unwrap();
+    match initial {
+        Some(Bopomofo::J) | Some(Bopomofo::Q) | Some(Bopomofo::X) => {
+            match (medial, rime) {
+                (Some(Bopomofo::U), Some(Bopomofo::AN))
+                | (Some(Bopomofo::U), Some(Bopomofo::EN))
+                | (Some(Bopomofo::U), None) => {
+                    medial.replace(Bopomofo::IU);
+                }
+                _ => (),
+            };
         }
-        self.syllable = builder.build();
-        self.syllable_alt = self.syllable;
-        KeyBehavior::Commit
+        _ => (),
     }
 
-    fn is_empty(&self) -> bool {
-        self.key_seq.is_empty()
+    match medial {
+        Some(Bopomofo::I) | Some(Bopomofo::IU) => {
+            match initial {
+                Some(Bopomofo::S) | Some(Bopomofo::SH) => {
+                    initial.replace(Bopomofo::X);
+                }
+                Some(Bopomofo::C) | Some(Bopomofo::CH) => {
+                    initial.replace(Bopomofo::Q);
+                }
+                _ => (),
+            };
+        }
+        _ => {
+            if initial == Some(Bopomofo::J) {
+                initial.replace(Bopomofo::ZH);
+            }
+        }
     }
 
-    fn remove_last(&mut self) {
-        self.key_seq.pop();
+    match initial {
+        Some(Bopomofo::B) | Some(Bopomofo::P) | Some(Bopomofo::M) | Some(Bopomofo::F) => {
+            match (medial, rime) {
+                (Some(Bopomofo::U), Some(Bopomofo::ENG))
+                | (Some(Bopomofo::U), Some(Bopomofo::O)) => {
+                    medial.take();
+                }
+                _ => (),
+            };
+        }
+        _ => (),
     }
 
-    fn clear(&mut self) {
-        self.key_seq.clear();
-        self.syllable.clear();
-        self.syllable_alt.clear();
+    let mut builder = Syllable::builder();
+    if let Some(initial) = initial {
+        builder = builder.insert(initial).unwrap();
     }
-
-    fn read(&self) -> Syllable {
-        self.syllable
+    if let Some(medial) = medial {
+        builder = builder.insert(medial).unwrap();
     }
-
-    fn key_seq(&self) -> Option<String> {
-        Some(self.key_seq.clone())
+    if let Some(rime) = rime {
+        builder = builder.insert(rime).unwrap();
     }
+    let syllable = builder.build();
+    Some(ParsedPinyin {
+        primary: syllable,
+        alt: syllable,
+        exact,
+    })
 }
 
 struct AmbiguousMapEntry {
@@ -510,3 +606,32 @@ mod table {
         fin!("z", None, None),
     ];
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        conversion::ChineseSequence,
+        syl,
+        zhuyin::{Bopomofo::*, ToneSystem},
+    };
+
+    use super::PinyinParser;
+
+    #[test]
+    fn parse_numeric_tones() {
+        let seq = ChineseSequence::from_str_with("guo2 min2", &PinyinParser::default()).unwrap();
+        assert_eq!(
+            vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+            seq.syllables
+        );
+    }
+
+    #[test]
+    fn reject_malformed_pinyin() {
+        let parser = PinyinParser::default();
+        for text in ["", "2", "gxq2", "Guo2", "guo9", "guo22", "ㄍㄨㄛˊ"] {
+            assert!(parser.parse_syllable(text).is_err(), "{text:?}");
+        }
+        assert!(ChineseSequence::from_str_with("guo2 xq3", &parser).is_err());
+    }
+}
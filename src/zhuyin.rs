@@ -1,7 +1,15 @@
 //! TODO: docs
 
 mod bopomofo;
+mod pattern;
 mod syllable;
+mod tone;
+mod tone_system;
 
 pub use bopomofo::{Bopomofo, BopomofoKind, ParseBopomofoError};
-pub use syllable::{DecodeSyllableError, IntoSyllablesBytes, Syllable, SyllableBuilder};
+pub use pattern::SyllablePattern;
+pub use syllable::{
+    DecodeSyllableError, IntoSyllablesBytes, ParseSyllableError, Syllable, SyllableBuilder,
+};
+pub use tone::Tone;
+pub use tone_system::ToneSystem;
@@ -0,0 +1,122 @@
+use super::{Bopomofo, Syllable, Tone};
+
+/// A pattern matching syllables by some of their components.
+///
+/// Each component left as `None` matches any value, including a missing
+/// component, so the [`Default`] pattern matches every syllable. Patterns
+/// are used to query dictionaries with
+/// [`lookup_pattern`][crate::dictionary::Dictionary::lookup_pattern].
+///
+/// # Examples
+///
+/// ```
+/// use chewing::{syl, zhuyin::{Bopomofo::*, SyllablePattern}};
+///
+/// let pattern = SyllablePattern {
+///     initial: Some(G),
+///     ..Default::default()
+/// };
+/// assert!(pattern.matches(&syl![G, U, O, TONE2]));
+/// assert!(!pattern.matches(&syl![M, I, EN, TONE2]));
+/// assert!(SyllablePattern::any().matches(&syl![M, I, EN, TONE2]));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SyllablePattern {
+    /// The required initial.
+    pub initial: Option<Bopomofo>,
+    /// The required medial.
+    pub medial: Option<Bopomofo>,
+    /// The required rime.
+    pub rime: Option<Bopomofo>,
+    /// The required tone.
+    pub tone: Option<Tone>,
+}
+
+impl SyllablePattern {
+    /// Returns a pattern matching any syllable.
+    pub fn any() -> SyllablePattern {
+        SyllablePattern::default()
+    }
+    /// Returns whether `syllable` has every component required by the
+    /// pattern.
+    pub fn matches(&self, syllable: &Syllable) -> bool {
+        fn matches<T: PartialEq>(required: Option<T>, actual: Option<T>) -> bool {
+            required.map_or(true, |required| actual == Some(required))
+        }
+        matches(self.initial, syllable.initial())
+            && matches(self.medial, syllable.medial())
+            && matches(self.rime, syllable.rime())
+            && matches(self.tone, syllable.tone())
+    }
+}
+
+/// Requires the components of the syllable, so only the syllables with the
+/// same components, and maybe more, match.
+impl From<Syllable> for SyllablePattern {
+    fn from(syllable: Syllable) -> Self {
+        SyllablePattern {
+            initial: syllable.initial(),
+            medial: syllable.medial(),
+            rime: syllable.rime(),
+            tone: syllable.tone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        dictionary::{Dictionary, Phrase},
+        syl,
+        zhuyin::{Bopomofo::*, Tone},
+    };
+
+    use super::SyllablePattern;
+
+    #[test]
+    fn lookup_by_pattern() {
+        let dict = HashMap::from([
+            (
+                vec![syl![G, U, O, TONE2], syl![M, I, EN, TONE2]],
+                vec![Phrase::new("國民", 200)],
+            ),
+            (
+                vec![syl![G, U, O, TONE4], syl![M, I, EN, TONE3]],
+                vec![Phrase::new("過敏", 100)],
+            ),
+            (
+                vec![syl![D, A, TONE4], syl![H, U, EI, TONE4]],
+                vec![Phrase::new("大會", 200)],
+            ),
+            (vec![syl![G, U, O, TONE2]], vec![Phrase::new("國", 100)]),
+        ]);
+        let lookup = |patterns: &[SyllablePattern]| -> Vec<String> {
+            let mut phrases: Vec<_> = dict
+                .lookup_pattern(patterns)
+                .map(|(_, phrase)| phrase.to_string())
+                .collect();
+            phrases.sort();
+            phrases
+        };
+        assert_eq!(
+            vec!["國民"],
+            lookup(&[syl![G, U, O, TONE2].into(), SyllablePattern::any()])
+        );
+        let initial = SyllablePattern {
+            initial: Some(G),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["國民", "過敏"],
+            lookup(&[initial, SyllablePattern::any()])
+        );
+        let fourth_tone = SyllablePattern {
+            tone: Some(Tone::Tone4),
+            ..Default::default()
+        };
+        assert_eq!(vec!["大會"], lookup(&[fourth_tone, fourth_tone]));
+        assert_eq!(vec!["國"], lookup(&[initial]));
+    }
+}
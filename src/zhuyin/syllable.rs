@@ -1,20 +1,45 @@
 use std::{
+    cmp::Ordering,
     fmt::{Display, Write},
     str::FromStr,
 };
 
 use thiserror::Error;
 
-use super::{Bopomofo, BopomofoKind, ParseBopomofoError};
+use super::{Bopomofo, BopomofoKind, ParseBopomofoError, Tone};
 
 /// The consonants and vowels that are taken together to make a single sound.
 ///
 /// <https://en.m.wikipedia.org/wiki/Syllable#Chinese_model>
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
+///
+/// # Ordering
+///
+/// Syllables are ordered by initial, then medial, then rime, then tone. Each
+/// component is compared by its position in the standard bopomofo order and a
+/// missing component sorts before any present one. For example `ㄅ` < `ㄅㄚ` <
+/// `ㄅㄚˊ` < `ㄅㄧ` < `ㄆ`. The neutral tone `˙` sorts before `ˊ`, `ˇ`, and
+/// `ˋ`. This ordering is stable and can be relied on for sorted containers such
+/// as `BTreeMap` and for binary searchable on-disk formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Syllable {
     value: u16,
 }
 
+impl PartialOrd for Syllable {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Syllable {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // The packed value stores initial, medial, rime, and tone indices from
+        // the most significant bits to the least, so comparing the value
+        // compares the components in that order.
+        self.value.cmp(&other.value)
+    }
+}
+
 impl Syllable {
     /// TODO: docs
     pub const fn new() -> Syllable {
@@ -24,7 +49,7 @@ impl Syllable {
     pub const fn builder() -> SyllableBuilder {
         SyllableBuilder::new()
     }
-    /// TODO: docs
+    /// Returns the initial consonant of the syllable, such as `ㄍ`.
     pub const fn initial(&self) -> Option<Bopomofo> {
         let index = self.value >> 9;
         if index == 0 {
@@ -36,7 +61,7 @@ impl Syllable {
             }
         }
     }
-    /// TODO: docs
+    /// Returns the medial of the syllable, one of `ㄧ`, `ㄨ`, or `ㄩ`.
     #[allow(clippy::unusual_byte_groupings)]
     pub const fn medial(&self) -> Option<Bopomofo> {
         let index = (self.value & 0b0000000_11_0000_000) >> 7;
@@ -49,7 +74,8 @@ impl Syllable {
             }
         }
     }
-    /// TODO: docs
+    /// Returns the rime, also called the final, of the syllable, such as
+    /// `ㄛ`.
     #[allow(clippy::unusual_byte_groupings)]
     pub const fn rime(&self) -> Option<Bopomofo> {
         let index = (self.value & 0b0000000_00_1111_000) >> 3;
@@ -62,18 +88,10 @@ impl Syllable {
             }
         }
     }
-    /// TODO: docs
+    /// Returns the tone of the syllable.
     #[allow(clippy::unusual_byte_groupings)]
-    pub const fn tone(&self) -> Option<Bopomofo> {
-        let index = self.value & 0b0000000_00_0000_111;
-        if index == 0 {
-            None
-        } else {
-            match Bopomofo::from_tone(index) {
-                Ok(v) => Some(v),
-                Err(_) => panic!(),
-            }
-        }
+    pub const fn tone(&self) -> Option<Tone> {
+        Tone::from_index(self.value & 0b0000000_00_0000_111)
     }
     /// TODO: docs
     pub fn remove_initial(&mut self) -> Option<Bopomofo> {
@@ -94,12 +112,12 @@ impl Syllable {
         ret
     }
     /// TODO: docs
-    pub fn remove_tone(&mut self) -> Option<Bopomofo> {
+    pub fn remove_tone(&mut self) -> Option<Tone> {
         let ret = self.tone();
         self.value &= 0b1111_1111_1111_1000;
         ret
     }
-    /// TODO: docs
+    /// Returns whether the syllable has no component at all.
     pub fn is_empty(&self) -> bool {
         self.value == 0
     }
@@ -119,6 +137,14 @@ impl Syllable {
     pub fn has_tone(&self) -> bool {
         self.tone().is_some()
     }
+    /// Returns whether the syllable is marked with the neutral tone `˙`.
+    ///
+    /// The neutral tone is a distinct tone. A neutral tone syllable is not
+    /// equal to the same syllable without a tone mark, so dictionary lookups
+    /// with a neutral tone syllable only match neutral tone entries.
+    pub fn is_neutral_tone(&self) -> bool {
+        matches!(self.tone(), Some(Tone::Tone5))
+    }
     /// Returns the `Syllable` encoded in a u16 integer.
     ///
     /// The data layout used:
@@ -151,6 +177,15 @@ impl Syllable {
     pub fn to_le_bytes(&self) -> [u8; 2] {
         self.to_u16().to_le_bytes()
     }
+    /// Replaces the tone of the syllable, or removes it with `None`.
+    pub fn set_tone(&mut self, tone: Option<Tone>) {
+        match tone {
+            Some(tone) => self.update(tone.to_bopomofo()),
+            None => {
+                self.remove_tone();
+            }
+        }
+    }
     /// TODO: docs
     pub fn update(&mut self, bopomofo: Bopomofo) {
         match bopomofo.kind() {
@@ -175,7 +210,7 @@ impl Syllable {
     /// TODO: docs
     pub fn pop(&mut self) -> Option<Bopomofo> {
         if self.tone().is_some() {
-            return self.remove_tone();
+            return self.remove_tone().map(Tone::to_bopomofo);
         }
         if self.rime().is_some() {
             return self.remove_rime();
@@ -235,6 +270,22 @@ impl FromStr for Syllable {
     }
 }
 
+/// Syllables are serialized as their zhuyin string, e.g. `"ㄍㄨㄛˊ"`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Syllable {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Syllable {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 /// TODO: docs
 pub trait IntoSyllablesBytes {
     /// TODO: docs
@@ -256,7 +307,8 @@ where
 
 impl Display for Syllable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for &bopomofo in [&self.initial(), &self.medial(), &self.rime(), &self.tone()] {
+        let tone = self.tone().map(Tone::to_bopomofo);
+        for &bopomofo in [&self.initial(), &self.medial(), &self.rime(), &tone] {
             if let Some(bopomofo) = bopomofo {
                 f.write_char(bopomofo.into())?;
             }
@@ -348,6 +400,52 @@ impl SyllableBuilder {
         };
         Ok(self)
     }
+    /// Inserts the tone of the syllable, same as inserting its tone mark.
+    pub const fn insert_tone(self, tone: Tone) -> Result<SyllableBuilder, BuildSyllableError> {
+        self.insert(tone.to_bopomofo())
+    }
+    /// Removes the last inserted bopomofo, like a backspace in the
+    /// composition buffer.
+    ///
+    /// Components are removed from the tone back to the initial. After
+    /// removing a component the builder accepts it, or any later component,
+    /// again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use chewing::zhuyin::{Bopomofo, Syllable};
+    ///
+    /// let mut builder = Syllable::builder()
+    ///     .insert(Bopomofo::G)?
+    ///     .insert(Bopomofo::U)?
+    ///     .insert(Bopomofo::TONE2)?;
+    /// assert_eq!(Some(Bopomofo::TONE2), builder.pop());
+    /// let syllable = builder.insert(Bopomofo::TONE3)?.build();
+    /// assert_eq!("ㄍㄨˇ", syllable.to_string());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn pop(&mut self) -> Option<Bopomofo> {
+        let mut syllable = Syllable { value: self.value };
+        let removed = syllable.pop();
+        self.value = syllable.value;
+        self.step = if syllable.tone().is_some() {
+            4
+        } else if syllable.rime().is_some() {
+            3
+        } else if syllable.medial().is_some() {
+            2
+        } else if syllable.initial().is_some() {
+            1
+        } else {
+            0
+        };
+        removed
+    }
+    /// Returns whether no bopomofo has been inserted.
+    pub const fn is_empty(&self) -> bool {
+        self.value == 0
+    }
     /// TODO: docs
     pub const fn build(self) -> Syllable {
         Syllable { value: self.value }
@@ -368,12 +466,21 @@ pub struct BuildSyllableError {
     msg: &'static str,
 }
 
+/// Errors returned when parsing a syllable from a string.
 #[derive(Error, Debug)]
 #[error("syllable parse error")]
 pub struct ParseSyllableError {
     source: Box<dyn std::error::Error>,
 }
 
+impl ParseSyllableError {
+    pub(crate) fn with_source(source: impl std::error::Error + 'static) -> ParseSyllableError {
+        ParseSyllableError {
+            source: Box::new(source),
+        }
+    }
+}
+
 impl From<ParseBopomofoError> for ParseSyllableError {
     fn from(value: ParseBopomofoError) -> Self {
         ParseSyllableError {
@@ -407,6 +514,16 @@ impl From<BuildSyllableError> for ParseSyllableError {
 /// assert_eq!("ㄎㄨˋ", syl.to_string());
 /// ```
 ///
+/// The tone can also follow a semicolon as a [`Tone`][crate::zhuyin::Tone]
+/// ```
+/// use chewing::zhuyin::{Bopomofo::{K, U}, Tone};
+/// use chewing::syl;
+///
+/// let syl = syl![K, U; Tone::Tone4];
+///
+/// assert_eq!("ㄎㄨˋ", syl.to_string());
+/// ```
+///
 /// # Panics
 ///
 /// `syl!` can panic if the bopomofos are not well formed.
@@ -415,6 +532,20 @@ impl From<BuildSyllableError> for ParseSyllableError {
 #[macro_export]
 macro_rules! syl {
     () => { $crate::zhuyin::Syllable::new() };
+    ($($bopomofo:expr),+ ; $tone:expr) => {
+        {
+            let mut builder = $crate::zhuyin::Syllable::builder();
+            $(builder = match builder.insert($bopomofo) {
+                Ok(b) => b,
+                Err(_) => panic!("unable to build syllable"),
+            };)+
+            builder = match builder.insert_tone($tone) {
+                Ok(b) => b,
+                Err(_) => panic!("unable to build syllable"),
+            };
+            builder.build()
+        }
+    };
     ($($bopomofo:expr),+) => {
         {
             let mut builder = $crate::zhuyin::Syllable::builder();
@@ -430,7 +561,11 @@ macro_rules! syl {
 #[cfg(test)]
 mod test {
 
-    use super::{Bopomofo, Syllable};
+    use std::collections::HashMap;
+
+    use crate::dictionary::{Dictionary, Phrase};
+
+    use super::{Bopomofo, Syllable, SyllableBuilder, Tone};
 
     #[test]
     fn syllable_hsu_sdf_as_u16() {
@@ -505,6 +640,193 @@ mod test {
         );
     }
 
+    #[test]
+    fn tone_is_separate_from_bopomofo() {
+        use Bopomofo::*;
+        const SYLLABLE: Syllable = syl![S, I, EN; Tone::Tone4];
+        assert_eq!(syl![S, I, EN, TONE4], SYLLABLE);
+        assert_eq!("ㄙㄧㄣˋ", SYLLABLE.to_string());
+        assert_eq!(Some(Tone::Tone4), SYLLABLE.tone());
+
+        for (tone, mark) in [
+            (Tone::Tone1, 'ˉ'),
+            (Tone::Tone2, 'ˊ'),
+            (Tone::Tone3, 'ˇ'),
+            (Tone::Tone4, 'ˋ'),
+            (Tone::Tone5, '˙'),
+        ] {
+            let syl = syl![M, A; tone];
+            assert_eq!(syl![M, A, tone.to_bopomofo()], syl);
+            assert_eq!(Some(tone), syl.tone());
+            assert_eq!(Some(tone), Tone::from_bopomofo(tone.to_bopomofo()));
+            assert_eq!(format!("ㄇㄚ{mark}"), syl.to_string());
+        }
+        assert_eq!(None, Tone::from_bopomofo(M));
+        assert_eq!(5, Tone::Tone1.index());
+        assert_eq!(
+            syl![M, A; Tone::Tone1].to_u16() & 0b111,
+            Tone::Tone1.index()
+        );
+        assert_eq!(1, Tone::Tone5.index());
+
+        let mut syl = syl![M, A, TONE3];
+        syl.set_tone(Some(Tone::Tone5));
+        assert!(syl.is_neutral_tone());
+        syl.set_tone(None);
+        assert_eq!(syl![M, A], syl);
+    }
+
+    #[test]
+    fn lookup_with_tone_syllables() {
+        use Bopomofo::*;
+        let dict = HashMap::from([(
+            vec![syl![C, E, TONE4], syl![SH, TONE4]],
+            vec![Phrase::new("測試", 100)],
+        )]);
+        assert_eq!(
+            vec![Phrase::new("測試", 100)],
+            dict.lookup_phrase(&[syl![C, E; Tone::Tone4], syl![SH; Tone::Tone4]])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn syllable_components() {
+        use Bopomofo::*;
+        let syl = syl![G, U, O, TONE2];
+        assert_eq!(Some(G), syl.initial());
+        assert_eq!(Some(U), syl.medial());
+        assert_eq!(Some(O), syl.rime());
+        assert_eq!(Some(Tone::Tone2), syl.tone());
+        assert!(!syl.is_empty());
+
+        let syl = syl![G];
+        assert_eq!(Some(G), syl.initial());
+        assert_eq!(None, syl.medial());
+        assert_eq!(None, syl.rime());
+        assert_eq!(None, syl.tone());
+        assert!(syl![].is_empty());
+    }
+
+    #[test]
+    fn syllable_canonical_order() {
+        use Bopomofo::*;
+        let mut syllables = vec![
+            syl![P],
+            syl![B, I],
+            syl![B, A, TONE2],
+            syl![B, A],
+            syl![B, A, TONE5],
+            syl![B],
+            syl![I, EN],
+            syl![B, U, O],
+            syl![B, A, TONE4],
+        ];
+        syllables.sort();
+        assert_eq!(
+            vec![
+                syl![I, EN],
+                syl![B],
+                syl![B, A],
+                syl![B, A, TONE5],
+                syl![B, A, TONE2],
+                syl![B, A, TONE4],
+                syl![B, I],
+                syl![B, U, O],
+                syl![P],
+            ],
+            syllables
+        );
+    }
+
+    #[test]
+    fn equal_syllables_compare_equal() {
+        use std::cmp::Ordering;
+
+        let a = syl![Bopomofo::S, Bopomofo::I, Bopomofo::EN, Bopomofo::TONE4];
+        let b: Syllable = "ㄙㄧㄣˋ".parse().unwrap();
+        assert_eq!(Ordering::Equal, a.cmp(&b));
+        assert_eq!(Some(Ordering::Equal), a.partial_cmp(&b));
+    }
+
+    #[test]
+    fn neutral_tone_is_distinct() {
+        use std::collections::HashMap;
+
+        let neutral = syl![Bopomofo::X, Bopomofo::I, Bopomofo::TONE5];
+        let toneless = syl![Bopomofo::X, Bopomofo::I];
+        assert!(neutral.is_neutral_tone());
+        assert!(!toneless.is_neutral_tone());
+        assert!(!syl![Bopomofo::X, Bopomofo::I, Bopomofo::TONE2].is_neutral_tone());
+        assert_eq!(Some(Tone::Tone5), neutral.tone());
+        assert_eq!(neutral, "ㄒㄧ˙".parse().unwrap());
+        assert_ne!(neutral, toneless);
+
+        let map = HashMap::from([(vec![neutral], "西")]);
+        assert_eq!(Some(&"西"), map.get(&vec![neutral]));
+        assert_eq!(None, map.get(&vec![toneless]));
+    }
+
+    #[test]
+    fn compose_syllable_step_by_step() {
+        let builder = Syllable::builder();
+        assert!(builder.is_empty());
+        let builder = builder.insert(Bopomofo::G).unwrap();
+        assert_eq!("ㄍ", builder_text(&builder));
+        let builder = builder.insert(Bopomofo::U).unwrap();
+        assert_eq!("ㄍㄨ", builder_text(&builder));
+        let builder = builder.insert(Bopomofo::O).unwrap();
+        assert_eq!("ㄍㄨㄛ", builder_text(&builder));
+        let mut builder = builder.insert(Bopomofo::TONE2).unwrap();
+        assert_eq!("ㄍㄨㄛˊ", builder_text(&builder));
+
+        assert_eq!(Some(Bopomofo::TONE2), builder.pop());
+        assert_eq!("ㄍㄨㄛ", builder_text(&builder));
+        let syllable = builder.insert(Bopomofo::TONE2).unwrap().build();
+        assert_eq!(
+            syl![Bopomofo::G, Bopomofo::U, Bopomofo::O, Bopomofo::TONE2],
+            syllable
+        );
+    }
+
+    #[test]
+    fn backspace_to_empty() {
+        let mut builder = Syllable::builder()
+            .insert(Bopomofo::G)
+            .unwrap()
+            .insert(Bopomofo::U)
+            .unwrap();
+        assert_eq!(Some(Bopomofo::U), builder.pop());
+        assert_eq!(Some(Bopomofo::G), builder.pop());
+        assert_eq!(None, builder.pop());
+        assert!(builder.is_empty());
+        // A new initial is accepted after removing the old one.
+        let builder = builder.insert(Bopomofo::K).unwrap();
+        assert_eq!("ㄎ", builder_text(&builder));
+    }
+
+    #[test]
+    fn reject_second_medial() {
+        let builder = Syllable::builder()
+            .insert(Bopomofo::G)
+            .unwrap()
+            .insert(Bopomofo::U)
+            .unwrap();
+        assert!(builder.insert(Bopomofo::I).is_err());
+        assert!(Syllable::builder()
+            .insert(Bopomofo::G)
+            .unwrap()
+            .insert(Bopomofo::K)
+            .is_err());
+    }
+
+    fn builder_text(builder: &SyllableBuilder) -> String {
+        Syllable {
+            value: builder.value,
+        }
+        .to_string()
+    }
+
     #[test]
     fn new_and_pop_bopomofo() {
         let mut syl = syl![Bopomofo::S, Bopomofo::I, Bopomofo::EN, Bopomofo::TONE4];
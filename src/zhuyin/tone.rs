@@ -0,0 +1,99 @@
+use std::fmt::{Display, Write};
+
+use super::Bopomofo;
+
+/// The tone of a syllable.
+///
+/// Tones are written with the tone marks of bopomofo, but unlike the
+/// consonants and vowels they are not part of the sound of a syllable.
+/// Keeping them in their own type lets tone insensitive code, such as fuzzy
+/// matching, compare or replace the tone without inspecting bopomofo kinds.
+///
+/// # Examples
+///
+/// ```
+/// use chewing::{syl, zhuyin::{Bopomofo, Tone}};
+///
+/// let syllable = syl![Bopomofo::M, Bopomofo::A; Tone::Tone3];
+/// assert_eq!(Some(Tone::Tone3), syllable.tone());
+/// assert_eq!(Bopomofo::TONE3, Tone::Tone3.to_bopomofo());
+/// assert_eq!("ˇ", Tone::Tone3.to_string());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tone {
+    /// ˉ, the high level tone, usually left unmarked.
+    Tone1,
+    /// ˊ, the rising tone.
+    Tone2,
+    /// ˇ, the low dipping tone.
+    Tone3,
+    /// ˋ, the falling tone.
+    Tone4,
+    /// ˙, the neutral tone.
+    Tone5,
+}
+
+impl Tone {
+    /// Returns the tone mark of the tone.
+    pub const fn to_bopomofo(self) -> Bopomofo {
+        match self {
+            Tone::Tone1 => Bopomofo::TONE1,
+            Tone::Tone2 => Bopomofo::TONE2,
+            Tone::Tone3 => Bopomofo::TONE3,
+            Tone::Tone4 => Bopomofo::TONE4,
+            Tone::Tone5 => Bopomofo::TONE5,
+        }
+    }
+    /// Returns the tone of a tone mark, or `None` for other bopomofo.
+    pub const fn from_bopomofo(bopomofo: Bopomofo) -> Option<Tone> {
+        match bopomofo {
+            Bopomofo::TONE1 => Some(Tone::Tone1),
+            Bopomofo::TONE2 => Some(Tone::Tone2),
+            Bopomofo::TONE3 => Some(Tone::Tone3),
+            Bopomofo::TONE4 => Some(Tone::Tone4),
+            Bopomofo::TONE5 => Some(Tone::Tone5),
+            _ => None,
+        }
+    }
+    /// Returns the index of the tone in the tone bits of a packed syllable.
+    ///
+    /// The neutral tone is 1 and the first tone is 5, as in C libchewing.
+    pub const fn index(self) -> u16 {
+        match self {
+            Tone::Tone5 => 1,
+            Tone::Tone2 => 2,
+            Tone::Tone3 => 3,
+            Tone::Tone4 => 4,
+            Tone::Tone1 => 5,
+        }
+    }
+    /// Returns the tone stored in the tone bits of a packed syllable.
+    pub(super) const fn from_index(index: u16) -> Option<Tone> {
+        match index {
+            1 => Some(Tone::Tone5),
+            2 => Some(Tone::Tone2),
+            3 => Some(Tone::Tone3),
+            4 => Some(Tone::Tone4),
+            5 => Some(Tone::Tone1),
+            _ => None,
+        }
+    }
+}
+
+impl From<Tone> for Bopomofo {
+    fn from(tone: Tone) -> Self {
+        tone.to_bopomofo()
+    }
+}
+
+impl From<Tone> for char {
+    fn from(tone: Tone) -> Self {
+        tone.to_bopomofo().into()
+    }
+}
+
+impl Display for Tone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_char((*self).into())
+    }
+}
@@ -0,0 +1,21 @@
+use super::{ParseSyllableError, Syllable};
+
+/// A romanization or other input scheme that can be parsed into syllables.
+///
+/// The conversion engines work on zhuyin [`Syllable`]s. A `ToneSystem`
+/// maps the text of one syllable written in another scheme, such as
+/// pinyin with numeric tones, onto the same syllables so the input can be
+/// converted with [`ChineseSequence::from_str_with`][crate::conversion::ChineseSequence::from_str_with].
+///
+/// # Examples
+///
+/// ```
+/// use chewing::{editor::layout::PinyinParser, syl, zhuyin::{Bopomofo::*, ToneSystem}};
+///
+/// let parser = PinyinParser::default();
+/// assert_eq!(syl![G, U, O, TONE2], parser.parse_syllable("guo2").unwrap());
+/// ```
+pub trait ToneSystem {
+    /// Parses the text of a single syllable.
+    fn parse_syllable(&self, text: &str) -> Result<Syllable, ParseSyllableError>;
+}